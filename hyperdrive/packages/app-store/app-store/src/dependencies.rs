@@ -0,0 +1,842 @@
+//! Dependency resolution for installs.
+//!
+//! A listing declares the packages its package needs in `properties.dependencies`, each
+//! a package ID optionally pinned to a version hash: `name:publisher.os` or
+//! `name:publisher.os@<version-hash>`. Before a package is installed, its dependencies
+//! are resolved from their chain listings into an [`InstallPlan`]: every package that
+//! isn't installed yet, each after the packages it depends on. Dependencies that
+//! haven't been downloaded are downloaded first, and the plan is applied once they
+//! all arrive. Plans waiting on downloads are persisted, and resumed at startup.
+use crate::{
+    hyperware::process::{
+        chain::OnchainMetadata,
+        downloads::{
+            CancelDownloadRequest, DownloadCompleteRequest, DownloadRequest, LocalDownloadRequest,
+        },
+    },
+    state::State,
+    utils, VFS_TIMEOUT,
+};
+use hyperware_process_lib::{println, vfs, Address, PackageId, Request};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashSet;
+
+/// A dependency as declared in a listing.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dependency {
+    pub package_id: PackageId,
+    /// the version hash the dependency is pinned to, if any
+    pub version_hash: Option<String>,
+}
+
+impl std::str::FromStr for Dependency {
+    type Err = DependencyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (package_id, version_hash) = match s.split_once('@') {
+            Some((package_id, version_hash)) if !version_hash.is_empty() => {
+                (package_id, Some(version_hash.to_string()))
+            }
+            Some(_) => return Err(DependencyError::InvalidDependency(s.to_string())),
+            None => (s, None),
+        };
+        let Ok(package_id) = package_id.parse::<PackageId>() else {
+            return Err(DependencyError::InvalidDependency(s.to_string()));
+        };
+        Ok(Dependency {
+            package_id,
+            version_hash,
+        })
+    }
+}
+
+impl std::fmt::Display for Dependency {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self.version_hash {
+            Some(version_hash) => write!(f, "{}@{version_hash}", self.package_id),
+            None => write!(f, "{}", self.package_id),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DependencyError {
+    InvalidDependency(String),
+    NotListed(PackageId),
+    NoVersion(PackageId),
+    Cycle(Vec<PackageId>),
+    Conflict {
+        package_id: PackageId,
+        required_by: PackageId,
+        wanted: String,
+        found: String,
+    },
+    /// uninstalling `package_id` would break the installed packages that need it
+    Required {
+        package_id: PackageId,
+        dependents: Vec<PackageId>,
+    },
+}
+
+impl std::fmt::Display for DependencyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DependencyError::InvalidDependency(d) => write!(f, "invalid dependency \"{d}\""),
+            DependencyError::NotListed(p) => write!(f, "dependency {p} has no listing"),
+            DependencyError::NoVersion(p) => {
+                write!(f, "listing of {p} has no hash for its current version")
+            }
+            DependencyError::Cycle(cycle) => write!(
+                f,
+                "dependency cycle: {}",
+                cycle
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            ),
+            DependencyError::Conflict {
+                package_id,
+                required_by,
+                wanted,
+                found,
+            } => write!(
+                f,
+                "{required_by} requires version {wanted} of {package_id}, but {found}"
+            ),
+            DependencyError::Required {
+                package_id,
+                dependents,
+            } => write!(
+                f,
+                "{package_id} is required by {}",
+                dependents
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DependencyError {}
+
+/// The dependencies a listing declares.
+pub fn declared_dependencies(
+    metadata: &OnchainMetadata,
+) -> Result<Vec<Dependency>, DependencyError> {
+    metadata
+        .properties
+        .dependencies
+        .iter()
+        .flatten()
+        .map(|dependency| dependency.parse())
+        .collect()
+}
+
+/// Packages to install, each after the packages it depends on. The package the plan
+/// was made for is last.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InstallPlan {
+    pub steps: Vec<PlanStep>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PlanStep {
+    pub package_id: PackageId,
+    pub version_hash: String,
+    /// whether a dependent pinned this version, rather than it being the listing's current one
+    pub pinned: bool,
+    /// whether this version has to be downloaded before it can be installed
+    pub download: bool,
+    /// packages in the plan that depend on this one
+    pub required_by: Vec<PackageId>,
+    metadata: OnchainMetadata,
+}
+
+impl InstallPlan {
+    pub fn to_json(&self) -> serde_json::Value {
+        json!({
+            "steps": self
+                .steps
+                .iter()
+                .map(|step| json!({
+                    "package_id": {
+                        "package_name": step.package_id.package(),
+                        "publisher_node": step.package_id.publisher(),
+                    },
+                    "version_hash": step.version_hash,
+                    "pinned": step.pinned,
+                    "download": step.download,
+                    "required_by": step
+                        .required_by
+                        .iter()
+                        .map(|p| p.to_string())
+                        .collect::<Vec<_>>(),
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl std::fmt::Display for InstallPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for step in &self.steps {
+            write!(f, "\n  {} ({})", step.package_id, step.version_hash)?;
+            if step.download {
+                write!(f, ", download")?;
+            }
+            if !step.required_by.is_empty() {
+                let required_by = step
+                    .required_by
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>();
+                write!(f, ", required by {}", required_by.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An install whose plan is waiting on downloads.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingInstall {
+    plan: InstallPlan,
+    /// (package, version hash) of the downloads not yet complete
+    waiting: HashSet<(PackageId, String)>,
+}
+
+/// Resolve what installing `version_hash` of a package takes. `metadata` is the
+/// package's listing, fetched from chain if not given.
+pub fn resolve(
+    package_id: &crate::hyperware::process::main::PackageId,
+    metadata: Option<OnchainMetadata>,
+    version_hash: &str,
+    state: &State,
+) -> anyhow::Result<InstallPlan> {
+    let metadata = match metadata {
+        Some(metadata) => metadata,
+        None => utils::fetch_package_metadata(package_id)?,
+    };
+    Ok(resolve_with(
+        package_id.clone().to_process_lib(),
+        metadata,
+        version_hash,
+        state,
+        &Chain,
+    )?)
+}
+
+/// Where the resolver finds listings and what's been downloaded.
+trait Lookup {
+    fn listing(&self, package_id: &PackageId) -> Option<OnchainMetadata>;
+    fn is_downloaded(&self, package_id: &PackageId, version_hash: &str) -> bool;
+}
+
+/// Listings from chain, downloads from the vfs.
+struct Chain;
+
+impl Lookup for Chain {
+    fn listing(&self, package_id: &PackageId) -> Option<OnchainMetadata> {
+        utils::fetch_package_metadata(
+            &crate::hyperware::process::main::PackageId::from_process_lib(package_id.clone()),
+        )
+        .ok()
+    }
+
+    fn is_downloaded(&self, package_id: &PackageId, version_hash: &str) -> bool {
+        is_downloaded(package_id, version_hash)
+    }
+}
+
+fn resolve_with(
+    package_id: PackageId,
+    metadata: OnchainMetadata,
+    version_hash: &str,
+    state: &State,
+    lookup: &impl Lookup,
+) -> Result<InstallPlan, DependencyError> {
+    let mut resolver = Resolver {
+        state,
+        lookup,
+        steps: vec![],
+        path: vec![],
+    };
+    resolver.visit(package_id, version_hash.to_string(), false, metadata)?;
+    Ok(InstallPlan {
+        steps: resolver.steps,
+    })
+}
+
+struct Resolver<'a, L: Lookup> {
+    state: &'a State,
+    lookup: &'a L,
+    steps: Vec<PlanStep>,
+    /// packages being resolved, from the package asked for down to the current one
+    path: Vec<PackageId>,
+}
+
+impl<L: Lookup> Resolver<'_, L> {
+    fn visit(
+        &mut self,
+        package_id: PackageId,
+        version_hash: String,
+        pinned: bool,
+        metadata: OnchainMetadata,
+    ) -> Result<(), DependencyError> {
+        // an installed package may pin this one to another version
+        for (dependent, dependencies) in &self.state.dependencies {
+            if !self.state.packages.contains_key(dependent) || *dependent == package_id {
+                continue;
+            }
+            for dependency in dependencies {
+                if dependency.package_id != package_id {
+                    continue;
+                }
+                if let Some(wanted) = &dependency.version_hash {
+                    if *wanted != version_hash {
+                        return Err(DependencyError::Conflict {
+                            package_id,
+                            required_by: dependent.clone(),
+                            wanted: wanted.clone(),
+                            found: format!("{version_hash} would be installed"),
+                        });
+                    }
+                }
+            }
+        }
+
+        self.path.push(package_id.clone());
+        for dependency in declared_dependencies(&metadata)? {
+            self.require(&package_id, dependency)?;
+        }
+        self.path.pop();
+
+        let download = !self.lookup.is_downloaded(&package_id, &version_hash);
+        self.steps.push(PlanStep {
+            package_id,
+            version_hash,
+            pinned,
+            download,
+            required_by: vec![],
+            metadata,
+        });
+        Ok(())
+    }
+
+    fn require(
+        &mut self,
+        dependent: &PackageId,
+        dependency: Dependency,
+    ) -> Result<(), DependencyError> {
+        let Dependency {
+            package_id,
+            version_hash: pin,
+        } = dependency;
+
+        if let Some(start) = self.path.iter().position(|p| *p == package_id) {
+            let mut cycle = self.path[start..].to_vec();
+            cycle.push(package_id);
+            return Err(DependencyError::Cycle(cycle));
+        }
+
+        // already in the plan: the version planned must be the one pinned
+        if let Some(step) = self.steps.iter_mut().find(|s| s.package_id == package_id) {
+            if let Some(pin) = pin {
+                if pin != step.version_hash {
+                    return Err(DependencyError::Conflict {
+                        package_id,
+                        required_by: dependent.clone(),
+                        wanted: pin,
+                        found: format!("{} is planned", step.version_hash),
+                    });
+                }
+            }
+            step.required_by.push(dependent.clone());
+            return Ok(());
+        }
+
+        // already installed: its own dependencies were resolved when it was
+        if let Some(installed) = self.state.packages.get(&package_id) {
+            if let Some(pin) = pin {
+                if pin != installed.our_version_hash {
+                    return Err(DependencyError::Conflict {
+                        package_id,
+                        required_by: dependent.clone(),
+                        wanted: pin,
+                        found: format!("{} is installed", installed.our_version_hash),
+                    });
+                }
+            }
+            return Ok(());
+        }
+
+        let Some(metadata) = self.lookup.listing(&package_id) else {
+            return Err(DependencyError::NotListed(package_id));
+        };
+        let (version_hash, pinned) = match pin {
+            Some(pin) => (pin, true),
+            None => match current_version_hash(&metadata) {
+                Some(version_hash) => (version_hash, false),
+                None => return Err(DependencyError::NoVersion(package_id)),
+            },
+        };
+        self.visit(package_id, version_hash, pinned, metadata)?;
+        self.steps
+            .last_mut()
+            .unwrap()
+            .required_by
+            .push(dependent.clone());
+        Ok(())
+    }
+}
+
+/// the version hash of a listing's current version
+fn current_version_hash(metadata: &OnchainMetadata) -> Option<String> {
+    metadata
+        .properties
+        .code_hashes
+        .iter()
+        .find(|(version, _)| *version == metadata.properties.current_version)
+        .map(|(_, hash)| hash.clone())
+}
+
+fn is_downloaded(package_id: &PackageId, version_hash: &str) -> bool {
+    let Ok(Ok(response)) = utils::vfs_request(
+        format!("/app-store:sys/downloads/{package_id}/{version_hash}.zip"),
+        vfs::VfsAction::Metadata,
+    )
+    .send_and_await_response(VFS_TIMEOUT) else {
+        return false;
+    };
+    matches!(
+        serde_json::from_slice::<vfs::VfsResponse>(response.body()),
+        Ok(vfs::VfsResponse::Metadata(_))
+    )
+}
+
+/// Install a package and whatever it needs that isn't installed, printing the plan
+/// first if that's more than the package itself. Returns whether it was installed now,
+/// rather than once its dependencies download.
+pub fn install_with_dependencies(
+    package_id: &crate::hyperware::process::main::PackageId,
+    metadata: Option<OnchainMetadata>,
+    version_hash: &str,
+    state: &mut State,
+    our_node: &str,
+) -> anyhow::Result<bool> {
+    let plan = resolve(package_id, metadata, version_hash, state)?;
+    if plan.steps.len() > 1 {
+        println!(
+            "installing {}:{} takes:{plan}",
+            package_id.package_name, package_id.publisher_node
+        );
+    }
+    apply_or_download(plan, state, our_node)
+}
+
+/// Check that no installed package depends on `package_id`, so that it can be
+/// uninstalled.
+pub fn check_uninstall(state: &State, package_id: &PackageId) -> Result<(), DependencyError> {
+    let dependents = state.dependents(package_id);
+    if dependents.is_empty() {
+        return Ok(());
+    }
+    Err(DependencyError::Required {
+        package_id: package_id.clone(),
+        dependents,
+    })
+}
+
+/// Install a plan's packages in order. If one fails, the packages this plan newly
+/// installed are uninstalled again, dependents first.
+pub fn apply(plan: &InstallPlan, state: &mut State, our_node: &str) -> anyhow::Result<()> {
+    let mut installed = vec![];
+    for step in &plan.steps {
+        let was_installed = state.packages.contains_key(&step.package_id);
+        let result = utils::install(
+            &crate::hyperware::process::main::PackageId::from_process_lib(step.package_id.clone()),
+            Some(step.metadata.clone()),
+            &step.version_hash,
+            state,
+            our_node,
+        );
+        if !was_installed && state.packages.contains_key(&step.package_id) {
+            installed.push(&step.package_id);
+        }
+        if let Err(e) = result {
+            let our = Address::new(our_node, ("main", "app-store", "sys"));
+            for package_id in installed.into_iter().rev() {
+                if let Err(e) = utils::uninstall(&our, state, package_id) {
+                    println!("couldn't roll back install of {package_id}: {e}");
+                }
+            }
+            return Err(anyhow::anyhow!(
+                "failed to install {}: {e}",
+                step.package_id
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Apply a plan now if everything in it is downloaded. Otherwise start the downloads
+/// it needs and apply it once they complete; returns whether it was applied now.
+pub fn apply_or_download(
+    plan: InstallPlan,
+    state: &mut State,
+    our_node: &str,
+) -> anyhow::Result<bool> {
+    let waiting: HashSet<(PackageId, String)> = plan
+        .steps
+        .iter()
+        .filter(|step| step.download)
+        .map(|step| (step.package_id.clone(), step.version_hash.clone()))
+        .collect();
+    if waiting.is_empty() {
+        apply(&plan, state, our_node)?;
+        return Ok(true);
+    }
+    for step in plan.steps.iter().filter(|step| step.download) {
+        start_download(step)?;
+    }
+    state
+        .pending_installs
+        .push(PendingInstall { plan, waiting });
+    state.persist_to_file()?;
+    Ok(false)
+}
+
+fn start_download(step: &PlanStep) -> anyhow::Result<()> {
+    let download_from = step
+        .metadata
+        .properties
+        .mirrors
+        .first()
+        .cloned()
+        .unwrap_or_else(|| step.package_id.publisher().to_string());
+    Request::to(("our", "downloads", "app-store", "sys"))
+        .body(serde_json::to_vec(&DownloadRequest::LocalDownload(
+            LocalDownloadRequest {
+                package_id: crate::hyperware::process::main::PackageId::from_process_lib(
+                    step.package_id.clone(),
+                ),
+                download_from,
+                desired_version_hash: step.version_hash.clone(),
+            },
+        ))?)
+        .send()?;
+    Ok(())
+}
+
+/// Pick up the installs that were waiting on downloads when we stopped: apply those
+/// whose downloads have all arrived, and ask again for the downloads of the rest.
+pub fn resume_pending_installs(state: &mut State, our_node: &str) {
+    let mut ready = vec![];
+    for mut pending in std::mem::take(&mut state.pending_installs) {
+        pending
+            .waiting
+            .retain(|(package_id, version_hash)| !is_downloaded(package_id, version_hash));
+        if pending.waiting.is_empty() {
+            ready.push(pending.plan);
+            continue;
+        }
+        for step in &pending.plan.steps {
+            if pending
+                .waiting
+                .contains(&(step.package_id.clone(), step.version_hash.clone()))
+            {
+                if let Err(e) = start_download(step) {
+                    println!("couldn't download {}: {e}", step.package_id);
+                }
+            }
+        }
+        state.pending_installs.push(pending);
+    }
+    apply_ready(ready, state, our_node);
+}
+
+/// Advance the installs waiting on a download: apply those it was the last download of,
+/// and drop those it failed for, cancelling the downloads no other install waits on.
+pub fn handle_download_complete(state: &mut State, req: &DownloadCompleteRequest, our_node: &str) {
+    let key = (
+        req.package_id.clone().to_process_lib(),
+        req.version_hash.clone(),
+    );
+    let mut ready = vec![];
+    let mut failed = vec![];
+    let mut i = 0;
+    while i < state.pending_installs.len() {
+        let pending = &mut state.pending_installs[i];
+        if !pending.waiting.remove(&key) || (req.err.is_none() && !pending.waiting.is_empty()) {
+            i += 1;
+            continue;
+        }
+        let pending = state.pending_installs.remove(i);
+        match &req.err {
+            Some(err) => {
+                println!(
+                    "couldn't install {}: failed to download {}: {err:?}",
+                    pending.plan.steps.last().unwrap().package_id,
+                    key.0
+                );
+                failed.push(pending);
+            }
+            None => ready.push(pending.plan),
+        }
+    }
+    for key in failed.into_iter().flat_map(|pending| pending.waiting) {
+        if state
+            .pending_installs
+            .iter()
+            .any(|p| p.waiting.contains(&key))
+        {
+            continue;
+        }
+        let (package_id, version_hash) = key;
+        let _ = Request::to(("our", "downloads", "app-store", "sys"))
+            .body(DownloadRequest::CancelDownload(CancelDownloadRequest {
+                package_id: crate::hyperware::process::main::PackageId::from_process_lib(
+                    package_id,
+                ),
+                version_hash,
+            }))
+            .send();
+    }
+    apply_ready(ready, state, our_node);
+}
+
+fn apply_ready(ready: Vec<InstallPlan>, state: &mut State, our_node: &str) {
+    for plan in ready {
+        let package_id = plan.steps.last().unwrap().package_id.clone();
+        match apply(&plan, state, our_node) {
+            Ok(()) => println!("successfully installed {package_id} and its dependencies"),
+            Err(e) => println!("error installing {package_id}: {e}"),
+        }
+    }
+    if let Err(e) = state.persist_to_file() {
+        println!("couldn't save pending installs: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{hyperware::process::chain::OnchainProperties, state::PackageState};
+    use std::collections::HashMap;
+
+    /// listings and downloads held in memory
+    #[derive(Default)]
+    struct Listings {
+        listings: HashMap<PackageId, OnchainMetadata>,
+        downloaded: HashSet<(PackageId, String)>,
+    }
+
+    impl Listings {
+        fn with(mut self, package: &str, dependencies: &[&str]) -> Self {
+            let package_id: PackageId = package.parse().unwrap();
+            self.listings
+                .insert(package_id.clone(), listing(&package_id, dependencies));
+            self
+        }
+    }
+
+    impl Lookup for Listings {
+        fn listing(&self, package_id: &PackageId) -> Option<OnchainMetadata> {
+            self.listings.get(package_id).cloned()
+        }
+
+        fn is_downloaded(&self, package_id: &PackageId, version_hash: &str) -> bool {
+            self.downloaded
+                .contains(&(package_id.clone(), version_hash.to_string()))
+        }
+    }
+
+    fn listing(package_id: &PackageId, dependencies: &[&str]) -> OnchainMetadata {
+        OnchainMetadata {
+            name: None,
+            description: None,
+            image: None,
+            external_url: None,
+            animation_url: None,
+            properties: OnchainProperties {
+                package_name: package_id.package().to_string(),
+                publisher: package_id.publisher().to_string(),
+                current_version: "1.0.0".to_string(),
+                mirrors: vec![],
+                code_hashes: vec![("1.0.0".to_string(), format!("{package_id}-hash"))],
+                license: None,
+                screenshots: None,
+                wit_version: None,
+                dependencies: Some(dependencies.iter().map(|d| d.to_string()).collect()),
+            },
+        }
+    }
+
+    fn new_state() -> State {
+        State {
+            packages: HashMap::new(),
+            installed_apis: HashSet::new(),
+            is_serving_public: false,
+            dependencies: HashMap::new(),
+            pending_installs: vec![],
+        }
+    }
+
+    fn install(state: &mut State, package: &str, version_hash: &str, dependencies: &[&str]) {
+        let package_id: PackageId = package.parse().unwrap();
+        state.packages.insert(
+            package_id.clone(),
+            PackageState {
+                our_version_hash: version_hash.to_string(),
+                verified: true,
+                caps_approved: true,
+                manifest_hash: None,
+            },
+        );
+        state.dependencies.insert(
+            package_id,
+            dependencies.iter().map(|d| d.parse().unwrap()).collect(),
+        );
+    }
+
+    fn resolve(
+        package: &str,
+        state: &State,
+        listings: &Listings,
+    ) -> Result<InstallPlan, DependencyError> {
+        let package_id: PackageId = package.parse().unwrap();
+        let metadata = listings.listing(&package_id).unwrap();
+        resolve_with(package_id, metadata, "root-hash", state, listings)
+    }
+
+    fn order(plan: &InstallPlan) -> Vec<String> {
+        plan.steps
+            .iter()
+            .map(|s| s.package_id.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn dependencies_parse_with_and_without_a_pin() {
+        let dependency: Dependency = "lib:publisher.os".parse().unwrap();
+        assert_eq!(dependency.package_id.to_string(), "lib:publisher.os");
+        assert_eq!(dependency.version_hash, None);
+
+        let dependency: Dependency = "lib:publisher.os@abc".parse().unwrap();
+        assert_eq!(dependency.version_hash.as_deref(), Some("abc"));
+        assert_eq!(dependency.to_string(), "lib:publisher.os@abc");
+
+        for invalid in ["lib:publisher.os@", "lib", "@abc", ""] {
+            assert!(
+                matches!(
+                    invalid.parse::<Dependency>(),
+                    Err(DependencyError::InvalidDependency(_))
+                ),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn dependencies_come_before_their_dependents() {
+        let listings = Listings::default()
+            .with("app:p.os", &["b:p.os", "a:p.os"])
+            .with("a:p.os", &["b:p.os"])
+            .with("b:p.os", &[]);
+        let plan = resolve("app:p.os", &new_state(), &listings).unwrap();
+        assert_eq!(order(&plan), ["b:p.os", "a:p.os", "app:p.os"]);
+        assert_eq!(plan.steps[0].required_by.len(), 2);
+        assert!(plan.steps.iter().all(|s| s.download));
+    }
+
+    #[test]
+    fn installed_and_downloaded_dependencies_are_reused() {
+        let mut listings = Listings::default()
+            .with("app:p.os", &["a:p.os", "b:p.os"])
+            .with("b:p.os", &[]);
+        listings
+            .downloaded
+            .insert(("b:p.os".parse().unwrap(), "b:p.os-hash".to_string()));
+        let mut state = new_state();
+        install(&mut state, "a:p.os", "a-hash", &[]);
+        let plan = resolve("app:p.os", &state, &listings).unwrap();
+        assert_eq!(order(&plan), ["b:p.os", "app:p.os"]);
+        assert!(!plan.steps[0].download);
+    }
+
+    #[test]
+    fn cycles_are_refused() {
+        let listings = Listings::default()
+            .with("app:p.os", &["a:p.os"])
+            .with("a:p.os", &["b:p.os"])
+            .with("b:p.os", &["a:p.os"]);
+        let Err(DependencyError::Cycle(cycle)) = resolve("app:p.os", &new_state(), &listings)
+        else {
+            panic!("expected a cycle");
+        };
+        let cycle = cycle.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        assert_eq!(cycle, ["a:p.os", "b:p.os", "a:p.os"]);
+    }
+
+    #[test]
+    fn conflicting_pins_are_refused() {
+        // two packages in the plan pin different versions of a third
+        let listings = Listings::default()
+            .with("app:p.os", &["lib:p.os@one", "a:p.os"])
+            .with("a:p.os", &["lib:p.os@two"])
+            .with("lib:p.os", &[]);
+        assert!(matches!(
+            resolve("app:p.os", &new_state(), &listings),
+            Err(DependencyError::Conflict { wanted, .. }) if wanted == "two"
+        ));
+
+        // a pin that disagrees with what's installed
+        let listings = Listings::default().with("app:p.os", &["lib:p.os@two"]);
+        let mut state = new_state();
+        install(&mut state, "lib:p.os", "one", &[]);
+        assert!(matches!(
+            resolve("app:p.os", &state, &listings),
+            Err(DependencyError::Conflict { wanted, .. }) if wanted == "two"
+        ));
+
+        // an installed package pins the one being installed to another version
+        let listings = Listings::default().with("lib:p.os", &[]);
+        let mut state = new_state();
+        install(&mut state, "app:p.os", "app-hash", &["lib:p.os@one"]);
+        assert!(matches!(
+            resolve("lib:p.os", &state, &listings),
+            Err(DependencyError::Conflict { wanted, .. }) if wanted == "one"
+        ));
+    }
+
+    #[test]
+    fn missing_listings_are_reported() {
+        let listings = Listings::default().with("app:p.os", &["gone:p.os"]);
+        assert!(matches!(
+            resolve("app:p.os", &new_state(), &listings),
+            Err(DependencyError::NotListed(p)) if p.to_string() == "gone:p.os"
+        ));
+    }
+
+    #[test]
+    fn required_packages_are_not_uninstalled() {
+        let mut state = new_state();
+        install(&mut state, "lib:p.os", "lib-hash", &[]);
+        install(&mut state, "app:p.os", "app-hash", &["lib:p.os"]);
+        let (lib, app): (PackageId, PackageId) =
+            ("lib:p.os".parse().unwrap(), "app:p.os".parse().unwrap());
+        let Err(e) = check_uninstall(&state, &lib) else {
+            panic!("expected lib to be required");
+        };
+        assert_eq!(e.to_string(), "lib:p.os is required by app:p.os");
+        assert!(check_uninstall(&state, &app).is_ok());
+
+        // once its dependent is gone, it can go too
+        state.packages.remove(&app);
+        assert!(check_uninstall(&state, &lib).is_ok());
+    }
+}
@@ -3,6 +3,7 @@
 //! and sends back http_responses.
 //!
 use crate::{
+    dependencies::{self, DependencyError},
     hyperware::process::{
         chain::{ChainRequest, ChainResponse},
         downloads::{
@@ -39,6 +40,7 @@ pub fn init_frontend(http_server: &mut server::HttpServer, state: &State) {
         // actions
         "/apps/:id/download",     // download a listed app
         "/apps/:id/install",      // install a downloaded app
+        "/apps/:id/install-plan", // what installing an app takes, version hash in query params
         "/downloads/:id/mirror",  // start mirroring a version of a downloaded app
         "/downloads/:id/remove",  // remove a downloaded app
         "/reset",                 // reset chain state, re-index
@@ -300,6 +302,7 @@ if (typeof document !== 'undefined') {
 /// - get detail about a specific app: GET /apps/:id
 /// - get detail about a specific apps downloads: GET /downloads/:id
 /// - get manifest of a specific downloaded app: GET /manifest?id={id}&version_hash={version_hash}
+/// - get the packages installing an app takes: GET /apps/:id/install-plan?version_hash={version_hash}
 /// - remove a downloaded app: POST /downloads/:id/remove
 
 /// - get online/offline mirrors for a listed app: GET /mirrorcheck/:id/:node
@@ -469,8 +472,14 @@ fn serve_paths(
                             format!("Uninstalling apps is not allowed from public UI").into_bytes(),
                         ));
                     }
-                    // uninstall an app
-                    crate::utils::uninstall(our, state, &package_id)?;
+                    // uninstall an app, unless installed apps depend on it
+                    match crate::utils::uninstall(our, state, &package_id) {
+                        Ok(()) => {}
+                        Err(e) if e.is::<DependencyError>() => {
+                            return Ok((StatusCode::CONFLICT, None, e.to_string().into_bytes()));
+                        }
+                        Err(e) => return Err(e),
+                    }
                     println!("successfully uninstalled {:?}", package_id);
                     Ok((
                         StatusCode::NO_CONTENT,
@@ -695,8 +704,38 @@ fn serve_paths(
                 serde_json::to_vec(&DownloadResponse::Success)?,
             ))
         }
+        // GET /apps/:id/install-plan
+        // the dependencies installing an app takes, for the user to review
+        "/apps/:id/install-plan" => {
+            let Ok(package_id) = get_package_id(url_params) else {
+                return Ok((
+                    StatusCode::BAD_REQUEST,
+                    None,
+                    "Missing id".to_string().into_bytes(),
+                ));
+            };
+            let Some(version_hash) = query_params.get("version_hash") else {
+                return Ok((
+                    StatusCode::BAD_REQUEST,
+                    None,
+                    "Missing version_hash in query params.".to_string().into_bytes(),
+                ));
+            };
+            let package_id =
+                crate::hyperware::process::main::PackageId::from_process_lib(package_id);
+            match dependencies::resolve(&package_id, None, version_hash, state) {
+                Ok(plan) => Ok((StatusCode::OK, None, serde_json::to_vec(&plan.to_json())?)),
+                // cycles, conflicts, and bad listings are the user's to see
+                Err(e) if e.is::<DependencyError>() => Ok((
+                    StatusCode::CONFLICT,
+                    None,
+                    serde_json::to_vec(&json!({"error": e.to_string()}))?,
+                )),
+                Err(e) => Err(e),
+            }
+        }
         // POST /apps/:id/install
-        // install a downloaded app
+        // install a downloaded app, and the dependencies it needs
         "/apps/:id/install" => {
             let Ok(package_id) = get_package_id(url_params) else {
                 return Ok((
@@ -719,20 +758,22 @@ fn serve_paths(
             let process_package_id =
                 crate::hyperware::process::main::PackageId::from_process_lib(package_id);
 
-            match crate::utils::install(
+            match dependencies::install_with_dependencies(
                 &process_package_id,
                 None,
                 &version_hash,
                 state,
                 &our.node().to_string(),
             ) {
-                Ok(_) => {
+                Ok(true) => {
                     println!(
                         "successfully installed {}:{}",
                         process_package_id.package_name, process_package_id.publisher_node
                     );
                     Ok((StatusCode::CREATED, None, vec![]))
                 }
+                // installed once its dependencies download
+                Ok(false) => Ok((StatusCode::ACCEPTED, None, vec![])),
                 Err(e) => Ok((
                     StatusCode::SERVICE_UNAVAILABLE,
                     None,
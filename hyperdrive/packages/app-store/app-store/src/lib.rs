@@ -16,6 +16,7 @@
 //! - `state.rs`: Manages the local state of installed packages and their metadata.
 //! - `http_api.rs`: Provides HTTP endpoints for frontend interactions.
 //! - `utils.rs`: Utility functions for app management.
+//! - `dependencies.rs`: Resolves and installs the packages an app depends on.
 //!
 //! ## Interaction Flow:
 //!
@@ -45,10 +46,11 @@ use state::{State, UpdateInfo, Updates};
 wit_bindgen::generate!({
     path: "../target/wit",
     generate_unused_types: true,
-    world: "app-store-sys-v2",
+    world: "app-store-sys-v3",
     additional_derives: [serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
 });
 
+mod dependencies;
 mod http_api;
 pub mod state;
 pub mod utils;
@@ -84,6 +86,7 @@ fn init(our: Address) {
     let mut updates = Updates::load();
 
     http_api::init_frontend(&mut http_server, &state);
+    dependencies::resume_pending_installs(&mut state, &our.node);
 
     loop {
         match await_message() {
@@ -204,7 +207,10 @@ fn handle_message(
                             .packages
                             .get(&process_lib_package_id)
                             .map(|package| package.manifest_hash == Some(manifest_hash.clone()))
-                            .unwrap_or(false);
+                            .unwrap_or(false)
+                            // an update that needs new dependencies waits for the user
+                            && dependencies::resolve(&package_id, None, &version_hash, state)
+                                .is_ok_and(|plan| plan.steps.len() == 1);
 
                         if should_auto_install {
                             if let Err(e) =
@@ -267,6 +273,8 @@ fn handle_message(
                     return Err(anyhow::anyhow!("download complete from non-local node"));
                 }
 
+                dependencies::handle_download_complete(state, &req, &our.node);
+
                 http_server.ws_push_all_channels(
                     "/",
                     http::server::WsMessageType::Text,
@@ -324,14 +332,27 @@ fn handle_local_request(
             metadata,
             version_hash,
         }) => (
-            match utils::install(&package_id, metadata, &version_hash, state, &our.node) {
-                Ok(()) => {
+            match dependencies::install_with_dependencies(
+                &package_id,
+                metadata,
+                &version_hash,
+                state,
+                &our.node,
+            ) {
+                Ok(true) => {
                     println!(
                         "successfully installed {}:{}",
                         package_id.package_name, package_id.publisher_node
                     );
                     LocalResponse::InstallResponse(InstallResponse::Success)
                 }
+                Ok(false) => {
+                    println!(
+                        "downloading dependencies of {}:{}; it will be installed once they arrive",
+                        package_id.package_name, package_id.publisher_node
+                    );
+                    LocalResponse::InstallResponse(InstallResponse::DownloadingDependencies)
+                }
                 Err(e) => {
                     println!("error installing package: {e}");
                     LocalResponse::InstallResponse(InstallResponse::Failure)
@@ -1,4 +1,8 @@
-use crate::{hyperware::process::downloads::DownloadError, utils, VFS_TIMEOUT};
+use crate::{
+    dependencies::{Dependency, PendingInstall},
+    hyperware::process::downloads::DownloadError,
+    utils, VFS_TIMEOUT,
+};
 use hyperware_process_lib::{get_state, hypermap, set_state, vfs, PackageId};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
@@ -72,6 +76,10 @@ pub struct State {
     pub installed_apis: HashSet<PackageId>,
     /// are we serving the public app store?
     pub is_serving_public: bool,
+    /// what each package we installed declared it depends on
+    pub dependencies: HashMap<PackageId, Vec<Dependency>>,
+    /// installs waiting on their dependencies to download
+    pub pending_installs: Vec<PendingInstall>,
 }
 
 impl State {
@@ -82,6 +90,8 @@ impl State {
             packages: HashMap::new(),
             installed_apis: HashSet::new(),
             is_serving_public: false,
+            dependencies: HashMap::new(),
+            pending_installs: Vec::new(),
         };
         state.populate_packages_from_filesystem()?;
         state.populate_other_from_file()?;
@@ -163,6 +173,19 @@ impl State {
         let persisted_state: FilePersistedState = serde_json::from_slice(bytes).unwrap_or_default();
 
         self.is_serving_public = persisted_state.is_serving_public;
+        self.dependencies = persisted_state
+            .dependencies
+            .into_iter()
+            .filter_map(|(package_id, dependencies)| {
+                let package_id = package_id.parse::<PackageId>().ok()?;
+                let dependencies = dependencies
+                    .iter()
+                    .filter_map(|dependency| dependency.parse::<Dependency>().ok())
+                    .collect();
+                Some((package_id, dependencies))
+            })
+            .collect();
+        self.pending_installs = persisted_state.pending_installs;
 
         return Ok(());
     }
@@ -175,6 +198,17 @@ impl State {
 
         let state_to_persist = serde_json::to_vec(&FilePersistedState {
             is_serving_public: self.is_serving_public,
+            dependencies: self
+                .dependencies
+                .iter()
+                .map(|(package_id, dependencies)| {
+                    (
+                        package_id.to_string(),
+                        dependencies.iter().map(|d| d.to_string()).collect(),
+                    )
+                })
+                .collect(),
+            pending_installs: self.pending_installs.clone(),
         })
         .unwrap();
 
@@ -182,11 +216,28 @@ impl State {
 
         return Ok(());
     }
+
+    /// installed packages that depend on `package_id`
+    pub fn dependents(&self, package_id: &PackageId) -> Vec<PackageId> {
+        self.dependencies
+            .iter()
+            .filter(|(dependent, dependencies)| {
+                self.packages.contains_key(dependent)
+                    && dependencies.iter().any(|d| d.package_id == *package_id)
+            })
+            .map(|(dependent, _)| dependent.clone())
+            .collect()
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 struct FilePersistedState {
     is_serving_public: bool,
+    /// package id -> the dependencies it declared when we installed it
+    #[serde(default)]
+    dependencies: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pending_installs: Vec<PendingInstall>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
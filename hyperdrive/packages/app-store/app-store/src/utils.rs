@@ -1,5 +1,6 @@
 use {
     crate::{
+        dependencies,
         hyperware::process::{
             chain::{ChainRequest, ChainResponse, OnchainMetadata},
             downloads::{AddDownloadRequest, DownloadRequest, DownloadResponse},
@@ -11,6 +12,7 @@ use {
         get_blob, kernel_types as kt, println, vfs, Address, Capability, LazyLoadBlob, PackageId,
         ProcessId, Request,
     },
+    serde::{Deserialize, Serialize},
     std::collections::{HashMap, HashSet},
 };
 
@@ -50,9 +52,9 @@ pub fn sha_256_hash(bytes: &[u8]) -> String {
 
 /// note: this can only be called in the install process,
 /// manifest.json for an arbitrary download can be found with GetFiles
-pub fn fetch_package_manifest(
-    package_id: &PackageId,
-) -> anyhow::Result<Vec<kt::PackageManifestEntry>> {
+/// The raw manifest.json of an installed package, for the parts of it that
+/// `kt::PackageManifestEntry` leaves out.
+pub fn fetch_package_manifest_bytes(package_id: &PackageId) -> anyhow::Result<Vec<u8>> {
     vfs_request(
         format!("/{package_id}/pkg/manifest.json"),
         vfs::VfsAction::Read,
@@ -61,9 +63,7 @@ pub fn fetch_package_manifest(
     let Some(blob) = get_blob() else {
         return Err(anyhow::anyhow!("no blob"));
     };
-    Ok(serde_json::from_slice::<Vec<kt::PackageManifestEntry>>(
-        &blob.bytes,
-    )?)
+    Ok(blob.bytes)
 }
 
 pub fn fetch_package_metadata(
@@ -227,8 +227,8 @@ pub fn install(
     }
 
     // get the package manifest
-    let drive_path = format!("/{process_package_id}/pkg");
-    let manifest = fetch_package_manifest(&process_package_id)?;
+    let manifest_bytes = fetch_package_manifest_bytes(&process_package_id)?;
+    let manifest = serde_json::from_slice::<Vec<kt::PackageManifestEntry>>(&manifest_bytes)?;
     // get wit version from metadata if local or chain if remote.
     let metadata = if let Some(metadata) = metadata {
         metadata
@@ -243,16 +243,56 @@ pub fn install(
         manifest_hash: Some(manifest_hash),
     };
 
+    let declared_dependencies = dependencies::declared_dependencies(&metadata)?;
+
     state
         .packages
         .insert(process_package_id.clone(), package_state);
 
-    let wit_version = metadata.properties.wit_version;
+    if let Err(e) = start_processes(
+        package_id,
+        &manifest,
+        &manifest_bytes,
+        metadata.properties.wit_version,
+        our_node,
+    ) {
+        // don't leave the package half-running
+        for entry in &manifest {
+            let _ = kernel_request(kt::KernelCommand::KillProcess(ProcessId::new(
+                Some(&entry.process_name),
+                process_package_id.package(),
+                process_package_id.publisher(),
+            )))
+            .send();
+        }
+        state.packages.remove(&process_package_id);
+        return Err(e);
+    }
+
+    state
+        .dependencies
+        .insert(process_package_id, declared_dependencies);
+    state.persist_to_file()?;
+    Ok(())
+}
+
+/// Initialize the processes in a package's manifest, set up its supervision groups,
+/// grant the capabilities it asks for, and start the processes.
+fn start_processes(
+    package_id: &crate::hyperware::process::main::PackageId,
+    manifest: &[kt::PackageManifestEntry],
+    manifest_bytes: &[u8],
+    wit_version: Option<u32>,
+    our_node: &str,
+) -> anyhow::Result<()> {
+    let process_package_id = package_id.clone().to_process_lib();
+    let drive_path = format!("/{process_package_id}/pkg");
+    let mut wasi_policies = wasi_policies(manifest_bytes)?;
 
     // first, for each process in manifest, initialize it
     // then, once all have been initialized, grant them requested caps
     // and finally start them.
-    for entry in &manifest {
+    for entry in manifest {
         let wasm_path = if entry.process_wasm_path.starts_with("/") {
             entry.process_wasm_path.clone()
         } else {
@@ -278,17 +318,21 @@ pub fn install(
 
         // use inherited blob to initialize process in kernel
         let Ok(kt::KernelResponse::InitializedProcess) = serde_json::from_slice(
-            kernel_request(kt::KernelCommand::InitializeProcess {
-                id: process_id.clone(),
-                wasm_bytes_handle: wasm_path,
-                wit_version,
-                on_exit: entry.on_exit.clone(),
-                initial_capabilities: HashSet::new(),
-                public: entry.public,
-            })
-            .inherit(true)
-            .send_and_await_response(VFS_TIMEOUT)??
-            .body(),
+            Request::to(("our", "kernel", "distro", "sys"))
+                .body(initialize_process_body(
+                    kt::KernelCommand::InitializeProcess {
+                        id: process_id.clone(),
+                        wasm_bytes_handle: wasm_path,
+                        wit_version,
+                        on_exit: entry.on_exit.clone(),
+                        initial_capabilities: HashSet::new(),
+                        public: entry.public,
+                    },
+                    wasi_policies.remove(&entry.process_name),
+                )?)
+                .inherit(true)
+                .send_and_await_response(VFS_TIMEOUT)??
+                .body(),
         ) else {
             return Err(anyhow::anyhow!("failed to initialize process"));
         };
@@ -334,10 +378,25 @@ pub fn install(
             .unwrap();
     }
 
+    // set up supervision groups before any member runs, so that every exit is supervised
+    if let Ok(SupervisionResponse::SupervisionGroupError(e)) = serde_json::from_slice(
+        Request::to(("our", "kernel", "distro", "sys"))
+            .body(serde_json::to_vec(
+                &SupervisionCommand::SetPackageSupervision {
+                    package_id: process_package_id.clone(),
+                    manifest: serde_json::from_slice(manifest_bytes)?,
+                },
+            )?)
+            .send_and_await_response(VFS_TIMEOUT)??
+            .body(),
+    ) {
+        println!("couldn't set supervision groups of {process_package_id}: {e}");
+    }
+
     // THEN, *after* all processes have been initialized, grant caps in manifest
     // this is done after initialization so that processes within a package
     // can grant capabilities to one another in the manifest.
-    for entry in &manifest {
+    for entry in manifest {
         let process_id = ProcessId::new(
             Some(&entry.process_name),
             process_package_id.package(),
@@ -412,6 +471,7 @@ pub fn uninstall(our: &Address, state: &mut State, package_id: &PackageId) -> an
     if !state.packages.contains_key(package_id) {
         return Err(anyhow::anyhow!("package not found"));
     }
+    dependencies::check_uninstall(state, package_id)?;
 
     // the drive corresponding to the package we will be removing
     let drive_path = format!("/{package_id}/pkg");
@@ -429,6 +489,15 @@ pub fn uninstall(our: &Address, state: &mut State, package_id: &PackageId) -> an
     };
     let manifest = serde_json::from_slice::<Vec<kt::PackageManifestEntry>>(&blob.bytes)?;
 
+    Request::to(("our", "kernel", "distro", "sys"))
+        .body(serde_json::to_vec(
+            &SupervisionCommand::SetPackageSupervision {
+                package_id: package_id.clone(),
+                manifest: serde_json::json!([]),
+            },
+        )?)
+        .send()?;
+
     // reading from the package manifest, kill every process named
     // *and* remove it from the homepage!
     for entry in &manifest {
@@ -466,6 +535,9 @@ pub fn uninstall(our: &Address, state: &mut State, package_id: &PackageId) -> an
     // If this package had an API, remove it from installed_apis
     state.installed_apis.remove(package_id);
 
+    state.dependencies.remove(package_id);
+    state.persist_to_file()?;
+
     // set auto_update to false
     Request::to(("our", "chain", "app-store", "sys"))
         .body(&ChainRequest::StopAutoUpdate(
@@ -477,6 +549,57 @@ pub fn uninstall(our: &Address, state: &mut State, package_id: &PackageId) -> an
     Ok(())
 }
 
+/// The body of `command`, an `InitializeProcess`, with the WASI policy the runtime
+/// also takes but the process_lib's command lacks. The policy is passed through from
+/// the manifest as is.
+fn initialize_process_body(
+    command: kt::KernelCommand,
+    wasi: Option<serde_json::Value>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut body = serde_json::to_value(&command)?;
+    if let (Some(wasi), Some(fields)) = (
+        wasi,
+        body.get_mut("InitializeProcess")
+            .and_then(|fields| fields.as_object_mut()),
+    ) {
+        fields.insert("wasi".to_string(), wasi);
+    }
+    Ok(serde_json::to_vec(&body)?)
+}
+
+#[derive(Deserialize)]
+struct ManifestWasiEntry {
+    process_name: String,
+    #[serde(default)]
+    wasi: Option<serde_json::Value>,
+}
+
+/// The WASI policy each process of a package declares in its manifest, by process name.
+fn wasi_policies(manifest_bytes: &[u8]) -> anyhow::Result<HashMap<String, serde_json::Value>> {
+    Ok(
+        serde_json::from_slice::<Vec<ManifestWasiEntry>>(manifest_bytes)?
+            .into_iter()
+            .filter_map(|entry| Some((entry.process_name, entry.wasi?)))
+            .collect(),
+    )
+}
+
+/// Mirrors `KernelCommand::SetPackageSupervision` in the runtime, which reads the
+/// supervision groups from the manifest as is; keep the two in sync.
+#[derive(Serialize)]
+enum SupervisionCommand {
+    SetPackageSupervision {
+        package_id: PackageId,
+        manifest: serde_json::Value,
+    },
+}
+
+/// The one `KernelResponse` to `SetPackageSupervision` that is acted on.
+#[derive(Deserialize)]
+enum SupervisionResponse {
+    SupervisionGroupError(String),
+}
+
 pub fn _extract_caps_hashes(manifest_bytes: &[u8]) -> anyhow::Result<HashMap<String, String>> {
     let manifest = serde_json::from_slice::<Vec<kt::PackageManifestEntry>>(manifest_bytes)?;
     let mut caps_hashes = HashMap::new();
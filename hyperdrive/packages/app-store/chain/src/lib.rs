@@ -55,7 +55,7 @@ use std::str::FromStr;
 wit_bindgen::generate!({
     path: "../target/wit",
     generate_unused_types: true,
-    world: "app-store-sys-v2",
+    world: "app-store-sys-v3",
     additional_derives: [serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
 });
 
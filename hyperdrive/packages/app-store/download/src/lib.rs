@@ -20,7 +20,7 @@ use hyperware_process_lib::{
 wit_bindgen::generate!({
     path: "../target/wit",
     generate_unused_types: true,
-    world: "app-store-sys-v2",
+    world: "app-store-sys-v3",
     additional_derives: [PartialEq, serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
 });
 
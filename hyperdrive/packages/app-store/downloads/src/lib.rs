@@ -68,7 +68,7 @@ use std::{
 wit_bindgen::generate!({
     path: "../target/wit",
     generate_unused_types: true,
-    world: "app-store-sys-v2",
+    world: "app-store-sys-v3",
     additional_derives: [serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
 });
 
@@ -102,6 +102,9 @@ pub struct ManualDownloadStatus {
 
 type ManualDownloads = HashMap<(PackageId, String), ManualDownloadStatus>;
 
+/// local downloads in flight, with the ft-worker receiving each (none for http downloads)
+type InFlight = HashMap<(PackageId, String), Option<Address>>;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct State {
     // persisted metadata about which packages we are mirroring
@@ -144,6 +147,7 @@ fn init(our: Address) {
     let mut auto_updates: AutoUpdates = HashMap::new();
     // metadata for in-flight manual downloads (used for mirror retries)
     let mut manual_downloads: ManualDownloads = HashMap::new();
+    let mut in_flight: InFlight = HashMap::new();
 
     loop {
         match await_message() {
@@ -156,6 +160,7 @@ fn init(our: Address) {
                     // &mut tmp,
                     &mut auto_updates,
                     &mut manual_downloads,
+                    &mut in_flight,
                 ) {
                     print_to_terminal(1, &format!("error handling message: {e:?}"));
                 }
@@ -170,6 +175,7 @@ fn init(our: Address) {
                             download_request.package_id.to_process_lib(),
                             download_request.desired_version_hash.clone(),
                         );
+                        in_flight.remove(&key);
 
                         // Get the error first
                         let error = match send_error.kind {
@@ -211,6 +217,7 @@ fn handle_message(
     // _tmp: &mut Directory,
     auto_updates: &mut AutoUpdates,
     manual_downloads: &mut ManualDownloads,
+    in_flight: &mut InFlight,
 ) -> anyhow::Result<()> {
     if message.is_request() {
         match message.body().try_into()? {
@@ -242,6 +249,11 @@ fn handle_message(
                     desired_version_hash.clone(),
                 );
 
+                // the same download asked for again, e.g. by an install resumed after a restart
+                if in_flight.contains_key(&key) {
+                    return Ok(());
+                }
+
                 if !download_from.starts_with("http")
                     && !auto_updates.contains_key(&key)
                     && !manual_downloads.contains_key(&key)
@@ -266,6 +278,7 @@ fn handle_message(
                         .context(serde_json::to_vec(&download_request)?)
                         .expects_response(60)
                         .send()?;
+                    in_flight.insert(key, None);
                     return Ok(());
                 }
 
@@ -277,6 +290,7 @@ fn handle_message(
                     &desired_version_hash,
                     &download_from,
                 )?;
+                in_flight.insert(key, Some(our_worker.clone()));
 
                 Request::to((&download_from, "downloads", "app-store", "sys"))
                     .body(DownloadRequest::RemoteDownload(RemoteDownloadRequest {
@@ -338,6 +352,7 @@ fn handle_message(
                     req.package_id.clone().to_process_lib(),
                     req.version_hash.clone(),
                 );
+                in_flight.remove(&key);
 
                 if let Some(metadata) = auto_updates.remove(&key) {
                     if let Some(err) = req.err {
@@ -443,6 +458,25 @@ fn handle_message(
                     .body(Resp::Download(DownloadResponse::Success))
                     .send()?;
             }
+            DownloadRequest::CancelDownload(cancel_req) => {
+                if !message.is_local() {
+                    return Err(anyhow::anyhow!("not local"));
+                }
+                let key = (
+                    cancel_req.package_id.clone().to_process_lib(),
+                    cancel_req.version_hash.clone(),
+                );
+                // no other mirror is tried once the download stops
+                manual_downloads.remove(&key);
+                // an http download can't be stopped, but its response is dropped when it
+                // arrives; a worker stops and removes what it received
+                if let Some(Some(worker)) = in_flight.remove(&key) {
+                    Request::new()
+                        .target(worker)
+                        .body(DownloadRequest::CancelDownload(cancel_req))
+                        .send()?;
+                }
+            }
             DownloadRequest::StartMirroring(package_id) => {
                 let package_id = package_id.to_process_lib();
                 state.mirroring.insert(package_id);
@@ -591,6 +625,7 @@ fn handle_message(
                     );
                     match download_response {
                         DownloadResponse::Err(e) => {
+                            in_flight.remove(&key);
                             print_to_terminal(1, &format!("downloads: got error response: {e:?}"));
                             if let Some(metadata) = auto_updates.remove(&key) {
                                 try_next_mirror(metadata, key, auto_updates, e);
@@ -637,6 +672,10 @@ fn handle_message(
 
                 // Check if this is an auto-update request
                 let is_auto_update = auto_updates.contains_key(&key);
+                if in_flight.remove(&key).is_none() && !is_auto_update {
+                    // cancelled
+                    return Ok(());
+                }
                 let metadata = if is_auto_update {
                     auto_updates.remove(&key)
                 } else {
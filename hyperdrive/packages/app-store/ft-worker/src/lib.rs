@@ -57,7 +57,7 @@ pub mod ft_worker_lib;
 wit_bindgen::generate!({
     path: "../target/wit",
     generate_unused_types: true,
-    world: "app-store-sys-v2",
+    world: "app-store-sys-v3",
     additional_derives: [serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
 });
 
@@ -99,13 +99,14 @@ fn init(our: Address) {
                 &package_id.to_process_lib(),
                 &desired_version_hash,
             ) {
-                Ok(_) => print_to_terminal(
+                Ok(true) => print_to_terminal(
                     1,
                     &format!(
                         "ft_worker: received downloaded package in {}ms",
                         start.elapsed().as_millis()
                     ),
                 ),
+                Ok(false) => print_to_terminal(1, "ft_worker: download cancelled"),
                 Err(e) => {
                     print_to_terminal(1, &format!("ft_worker: receive error: {}", e));
                     // fallback bubble up to parent.
@@ -176,11 +177,12 @@ fn handle_sender(worker: &str, package_id: &PackageId, version_hash: &str) -> an
     Ok(())
 }
 
+/// Returns whether the package was received, rather than the download cancelled.
 fn handle_receiver(
     parent_process: &Address,
     package_id: &PackageId,
     version_hash: &str,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<bool> {
     let timer_address = Address::from_str("our@timer:distro:sys")?;
 
     let mut file: Option<File> = None;
@@ -209,7 +211,7 @@ fn handle_receiver(
                 }))
                 .target(parent_process.clone())
                 .send()?;
-            return Ok(());
+            return Ok(true);
         }
         if !message.is_request() {
             return Err(anyhow::anyhow!("ft_worker: got bad message"));
@@ -281,13 +283,19 @@ fn handle_receiver(
                             }))
                             .target(parent_process.clone())
                             .send()?;
-                        return Ok(());
+                        return Ok(true);
                     }
                 }
             }
             DownloadRequest::Size(update) => {
                 size = Some(update.size);
             }
+            DownloadRequest::CancelDownload(_) if message.source() == parent_process => {
+                if let Some(file) = file {
+                    let _ = vfs::remove_file(&file.path, None);
+                }
+                return Ok(false);
+            }
             _ => println!("ft_worker: got unexpected message"),
         }
     }
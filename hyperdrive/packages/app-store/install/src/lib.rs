@@ -18,7 +18,7 @@ use hyperware_process_lib::{
 wit_bindgen::generate!({
     path: "../target/wit",
     generate_unused_types: true,
-    world: "app-store-sys-v2",
+    world: "app-store-sys-v3",
     additional_derives: [PartialEq, serde::Deserialize, serde::Serialize, process_macros::SerdeJsonInto],
 });
 
@@ -77,6 +77,9 @@ fn init(our: Address) {
             println!("failed to install package {package_id}");
             println!("make sure that the package has been downloaded!")
         }
+        LocalResponse::InstallResponse(InstallResponse::DownloadingDependencies) => {
+            println!("downloading the dependencies of {package_id}; it will be installed once they arrive");
+        }
         _ => {
             println!("install: unexpected response from app-store..!");
             return;
@@ -0,0 +1,69 @@
+import React, { useEffect, useState } from 'react';
+import useAppsStore from '../store/appStoreStore';
+import { InstallPlan } from '../types/Apps';
+import { FaCircleNotch } from 'react-icons/fa';
+
+interface InstallPlanDisplayProps {
+    packageId: string;
+    versionHash: string;
+    // called with whether the app's dependencies could be resolved
+    onResolved: (resolved: boolean) => void;
+}
+
+const InstallPlanDisplay: React.FC<InstallPlanDisplayProps> = ({ packageId, versionHash, onResolved }) => {
+    const { getInstallPlan } = useAppsStore();
+    const [plan, setPlan] = useState<InstallPlan | null>(null);
+    const [error, setError] = useState<string | null>(null);
+
+    useEffect(() => {
+        let cancelled = false;
+        setPlan(null);
+        setError(null);
+        onResolved(false);
+        getInstallPlan(packageId, versionHash).then(result => {
+            if (cancelled) return;
+            if ('error' in result) {
+                setError(result.error);
+                onResolved(false);
+            } else {
+                setPlan(result);
+                onResolved(true);
+            }
+        });
+        return () => { cancelled = true; };
+    }, [packageId, versionHash]);
+
+    if (error) {
+        return <p className="text-red-500 bg-red-500/10 p-2 rounded-lg">Can't install: {error}</p>;
+    }
+    if (!plan) {
+        return <p className="flex items-center gap-2"><FaCircleNotch className="animate-spin" /> Resolving dependencies...</p>;
+    }
+    // the last step is the app itself
+    const dependencies = plan.steps.slice(0, -1);
+    if (dependencies.length === 0) {
+        return null;
+    }
+    return (
+        <div className="flex flex-col items-stretch gap-2">
+            <h4 className="text-sm font-bold prose">Also installs these dependencies:</h4>
+            <ul className="flex flex-col gap-1">
+                {dependencies.map(step => {
+                    const id = `${step.package_id.package_name}:${step.package_id.publisher_node}`;
+                    return (
+                        <li key={id} className="pl-2 flex flex-col">
+                            <span className="font-bold">{id}</span>
+                            <span className="text-xs opacity-50 break-all">
+                                {step.pinned ? 'pinned version' : 'current version'} {step.version_hash}
+                                {step.download && ', downloaded first'}
+                                {step.required_by.length > 0 && `, required by ${step.required_by.join(', ')}`}
+                            </span>
+                        </li>
+                    );
+                })}
+            </ul>
+        </div>
+    );
+};
+
+export default InstallPlanDisplay;
@@ -2,5 +2,6 @@ export { default as Header } from './Header';
 export { default as MirrorSelector } from './MirrorSelector';
 export { default as PackageSelector } from './PackageSelector';
 export { default as ManifestDisplay } from './ManifestDisplay';
+export { default as InstallPlanDisplay } from './InstallPlanDisplay';
 export { default as NotificationBay } from './NotificationBay';
 export { default as ResetButton } from './ResetButton';
\ No newline at end of file
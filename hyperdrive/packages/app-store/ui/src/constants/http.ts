@@ -13,6 +13,7 @@ export enum HTTP_STATUS {
   UNAUTHORIZED = 401,
   FORBIDDEN = 403,
   NOT_FOUND = 404,
+  CONFLICT = 409,
   PAYLOAD_TOO_LARGE = 413,
   UNSUPPORTED_MEDIA_TYPE = 415,
   TOO_MANY_REQUESTS = 429,
@@ -3,7 +3,7 @@ import { useParams, useLocation } from "react-router-dom";
 import useAppsStore from "../store/appStoreStore";
 import { AppListing, PackageState, ManifestResponse, HomepageApp } from "../types/Apps";
 import { compareVersions } from "../utils/compareVersions";
-import { MirrorSelector, ManifestDisplay } from '../components';
+import { MirrorSelector, ManifestDisplay, InstallPlanDisplay } from '../components';
 import { FaChevronDown, FaChevronRight, FaCheck, FaCircleNotch, FaPlay, FaXmark } from "react-icons/fa6";
 import { BsDownload } from "react-icons/bs";
 import { Modal } from "../components/Modal";
@@ -74,6 +74,7 @@ export default function AppPage() {
   const [isMirrorOnline, setIsMirrorOnline] = useState<boolean | null>(null);
   const [showCapApproval, setShowCapApproval] = useState(false);
   const [manifestResponse, setManifestResponse] = useState<ManifestResponse | null>(null);
+  const [planResolved, setPlanResolved] = useState(false);
   const [canLaunch, setCanLaunch] = useState(false);
   const [attemptedDownload, setAttemptedDownload] = useState(false);
   const [mirrorError, setMirrorError] = useState<string | null>(null);
@@ -340,9 +341,10 @@ export default function AppPage() {
     try {
       await uninstallApp(`${app.package_id.package_name}:${app.package_id.publisher_node}`);
       await loadData();
+      window.location.reload();
     } catch (error) {
       console.error('Uninstallation failed:', error);
-      const errorString = Object.keys(error).length > 0 ? `: ${JSON.stringify(error).slice(0, 100)}...` : '';
+      const errorString = error instanceof Error ? error.message : Object.keys(error).length > 0 ? `${JSON.stringify(error).slice(0, 100)}...` : '';
       addNotification({
         id: `uninstallation-failed-${id}`,
         timestamp: Date.now(),
@@ -351,7 +353,7 @@ export default function AppPage() {
       });
     } finally {
       setIsUninstalling(false);
-      window.location.reload();
+      setShowUninstallConfirmModal(false);
     }
   };
 
@@ -588,6 +590,10 @@ export default function AppPage() {
         <Modal onClose={() => setShowCapApproval(false)}>
           <h3 className="prose">Approve Capabilities</h3>
           <ManifestDisplay manifestResponse={manifestResponse} />
+          <InstallPlanDisplay
+            packageId={`${manifestResponse.package_id.package_name}:${manifestResponse.package_id.publisher_node}`}
+            versionHash={manifestResponse.version_hash}
+            onResolved={setPlanResolved} />
           <div className="flex flex-col items-stretch gap-2">
             <button
               className="clear"
@@ -597,7 +603,7 @@ export default function AppPage() {
                 setIsInstalling(false);
               }}>Cancel</button>
             <button
-              disabled={isInstalling}
+              disabled={isInstalling || !planResolved}
               onClick={confirmInstall}
             >
               {isInstalling ? <FaCircleNotch className="animate-spin" /> : "Approve and Install"}
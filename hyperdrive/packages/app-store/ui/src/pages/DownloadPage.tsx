@@ -2,7 +2,7 @@ import React, { useState, useEffect, useCallback, useMemo } from "react";
 import { useParams } from "react-router-dom";
 import { FaDownload, FaSpinner, FaChevronDown, FaChevronUp, FaRocket, FaTrash, FaPlay } from "react-icons/fa";
 import useAppsStore from "../store/appStoreStore";
-import { MirrorSelector, ManifestDisplay } from '../components';
+import { MirrorSelector, ManifestDisplay, InstallPlanDisplay } from '../components';
 import { ManifestResponse } from "../types/Apps";
 import { Modal } from "../components/Modal";
 import { FaChevronRight } from "react-icons/fa6";
@@ -32,6 +32,7 @@ export default function DownloadPage() {
     const [isMirrorOnline, setIsMirrorOnline] = useState<boolean | null>(null);
     const [showCapApproval, setShowCapApproval] = useState(false);
     const [manifestResponse, setManifestResponse] = useState<ManifestResponse | null>(null);
+    const [planResolved, setPlanResolved] = useState(false);
     const [isInstalling, setIsInstalling] = useState(false);
     const [isPolling, setIsPolling] = useState(false);
 
@@ -348,12 +349,16 @@ export default function DownloadPage() {
 
                 <h3 className="prose">Approve Capabilities</h3>
                 <ManifestDisplay manifestResponse={manifestResponse} />
+                <InstallPlanDisplay
+                    packageId={`${manifestResponse.package_id.package_name}:${manifestResponse.package_id.publisher_node}`}
+                    versionHash={manifestResponse.version_hash}
+                    onResolved={setPlanResolved} />
                 <div className="flex gap-2 flex-col md:flex-row">
                     <button
                         className="clear"
                         onClick={() => setShowCapApproval(false)}>Cancel</button>
                     <button
-
+                        disabled={!planResolved}
                         onClick={confirmInstall}>
                         Approve and Install
                     </button>
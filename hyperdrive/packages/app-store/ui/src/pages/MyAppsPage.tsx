@@ -2,7 +2,7 @@ import React, { useState, useEffect, useMemo } from "react";
 import { FaFolder, FaFile, FaChevronLeft, FaSync, FaRocket, FaSpinner, FaCheck, FaTrash, FaExclamationTriangle, FaTimesCircle, FaChevronDown, FaChevronRight } from "react-icons/fa";
 import { useNavigate } from "react-router-dom";
 import useAppsStore from "../store/appStoreStore";
-import { ResetButton } from "../components";
+import { ResetButton, InstallPlanDisplay } from "../components";
 import { DownloadItem, PackageManifestEntry, PackageState, Updates, DownloadError, UpdateInfo } from "../types/Apps";
 import { BsTrash, BsX } from "react-icons/bs";
 import classNames from "classnames";
@@ -52,6 +52,7 @@ export default function MyAppsPage() {
     const [showCapApproval, setShowCapApproval] = useState(false);
     const [manifest, setManifest] = useState<PackageManifestEntry | null>(null);
     const [selectedItem, setSelectedItem] = useState<DownloadItem | null>(null);
+    const [planResolved, setPlanResolved] = useState(false);
     const [showUninstallConfirm, setShowUninstallConfirm] = useState(false);
     const [appToUninstall, setAppToUninstall] = useState<any>(null);
     const [showAdvanced, setShowAdvanced] = useState(false);
@@ -244,6 +245,15 @@ export default function MyAppsPage() {
         }
     };
 
+    // the package and version hash of the selected download, named <package>:<publisher>:<hash>.zip
+    const installTarget = useMemo(() => {
+        if (!selectedItem?.File) return null;
+        const parts = selectedItem.File.name.split(':');
+        const versionHash = parts.pop()?.replace('.zip', '');
+        if (!versionHash) return null;
+        return { packageId: [...currentPath, ...parts].join(':'), versionHash };
+    }, [selectedItem, currentPath]);
+
     const confirmInstall = async () => {
         if (!selectedItem?.File) return;
         setIsInstalling(true);
@@ -543,9 +553,13 @@ export default function MyAppsPage() {
                             <pre className="json-display">
                                 {JSON.stringify(manifest[0]?.request_capabilities || [], null, 2)}
                             </pre>
+                            {installTarget && <InstallPlanDisplay
+                                packageId={installTarget.packageId}
+                                versionHash={installTarget.versionHash}
+                                onResolved={setPlanResolved} />}
                             <div className="approval-buttons">
                                 <button onClick={() => setShowCapApproval(false)}>Cancel</button>
-                                <button onClick={confirmInstall} disabled={isInstalling}>
+                                <button onClick={confirmInstall} disabled={isInstalling || !planResolved}>
                                     {isInstalling ? <FaSpinner className="fa-spin" /> : 'Approve and Install'}
                                 </button>
                             </div>
@@ -1,6 +1,6 @@
 import { create } from 'zustand'
 import { persist } from 'zustand/middleware'
-import { PackageState, AppListing, MirrorCheckFile, DownloadItem, HomepageApp, ManifestResponse, Notification, UpdateInfo } from '../types/Apps'
+import { PackageState, AppListing, MirrorCheckFile, DownloadItem, HomepageApp, ManifestResponse, Notification, UpdateInfo, InstallPlan } from '../types/Apps'
 import { HTTP_STATUS } from '../constants/http'
 import HyperwareClientApi from "@hyperware-ai/client-api"
 import { WEBSOCKET_URL } from '../utils/ws'
@@ -39,6 +39,7 @@ interface AppsStore {
   removeNotification: (id: string) => void;
   clearNotifications: () => void;
 
+  getInstallPlan: (id: string, version_hash: string) => Promise<InstallPlan | { error: string }>
   installApp: (id: string, version_hash: string) => Promise<void>
   uninstallApp: (id: string) => Promise<void>
   downloadApp: (id: string, version_hash: string, downloadFrom: string) => Promise<void>
@@ -239,6 +240,20 @@ const useAppsStore = create<AppsStore>()((set, get) => ({
     return null;
   },
 
+  getInstallPlan: async (id: string, version_hash: string) => {
+    try {
+      const res = await fetch(`${BASE_URL}/apps/${id}/install-plan?version_hash=${version_hash}`);
+      const data = await res.json();
+      if (res.status === HTTP_STATUS.OK) {
+        return data as InstallPlan;
+      }
+      return { error: data.error ?? `Failed to resolve dependencies (${res.status})` };
+    } catch (error) {
+      console.error("Error fetching install plan:", error);
+      return { error: "Failed to resolve dependencies" };
+    }
+  },
+
   installApp: async (id: string, version_hash: string) => {
     try {
       const res = await fetch(`${BASE_URL}/apps/${id}/install`, {
@@ -248,6 +263,8 @@ const useAppsStore = create<AppsStore>()((set, get) => ({
       if (res.status === HTTP_STATUS.CREATED) {
         await get().fetchInstalled();
         await get().fetchHomepageApps();
+      } else if (res.status === HTTP_STATUS.ACCEPTED) {
+        get().addToast(`Downloading dependencies of ${id}; it will be installed once they arrive`, 'info');
       }
     } catch (error) {
       console.error("Error installing app:", error);
@@ -255,14 +272,21 @@ const useAppsStore = create<AppsStore>()((set, get) => ({
   },
 
   uninstallApp: async (id: string) => {
+    let refusal: string | null = null;
     try {
       const res = await fetch(`${BASE_URL}/apps/${id}`, { method: 'DELETE' });
       if (res.status === HTTP_STATUS.NO_CONTENT) {
         await get().fetchInstalled();
+      } else if (res.status === HTTP_STATUS.CONFLICT) {
+        // other installed apps depend on this one
+        refusal = await res.text();
       }
     } catch (error) {
       console.error("Error uninstalling app:", error);
     }
+    if (refusal) {
+      throw new Error(refusal);
+    }
   },
 
   downloadApp: async (id: string, version_hash: string, downloadFrom: string) => {
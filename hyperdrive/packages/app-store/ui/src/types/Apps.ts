@@ -47,6 +47,7 @@ export interface Erc721Properties {
     license?: string;
     screenshots?: string[];
     wit_version?: number;
+    dependencies?: string[];
 }
 
 export interface OnchainPackageMetadata {
@@ -76,6 +77,19 @@ export interface PackageManifestEntry {
     public: boolean
 }
 
+export interface InstallPlanStep {
+    package_id: PackageId;
+    version_hash: string;
+    pinned: boolean;
+    download: boolean;
+    required_by: string[];
+}
+
+// packages to install, dependencies first; the app asked for is last
+export interface InstallPlan {
+    steps: InstallPlanStep[];
+}
+
 export interface ManifestResponse {
     package_id: PackageId;
     version_hash: string;
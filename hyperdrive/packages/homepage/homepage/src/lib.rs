@@ -49,6 +49,23 @@ enum NotificationsAction {
     GetSubscription {
         endpoint: String,
     },
+    GetPreferences,
+    SetAppPreference {
+        process: String,
+        preference: Option<serde_json::Value>,
+    },
+    SetDevicePreference {
+        endpoint: String,
+        preference: Option<serde_json::Value>,
+    },
+    GetInbox {
+        since: Option<u64>,
+        limit: Option<usize>,
+    },
+    MarkRead {
+        ids: Vec<u64>,
+    },
+    ClearInbox,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -60,7 +77,11 @@ enum NotificationsResponse {
     SubscriptionRemoved,
     SubscriptionsCleared,
     SubscriptionInfo(Option<PushSubscription>),
-    Err(String),
+    Preferences(serde_json::Value),
+    PreferencesUpdated,
+    Inbox(serde_json::Value),
+    InboxUpdated,
+    Err(serde_json::Value),
 }
 
 wit_bindgen::generate!({
@@ -291,6 +312,12 @@ fn init(our: Address) {
     http_server
         .bind_http_path("/api/notifications/unsubscribe-all", http_config.clone())
         .expect("failed to bind /api/notifications/unsubscribe-all");
+    http_server
+        .bind_http_path("/api/notifications/inbox", http_config.clone())
+        .expect("failed to bind /api/notifications/inbox");
+    http_server
+        .bind_http_path("/api/notifications/preferences", http_config.clone())
+        .expect("failed to bind /api/notifications/preferences");
     http_server
         .bind_http_path("/api/notifications/test-vapid", http_config)
         .expect("failed to bind /api/notifications/test-vapid");
@@ -909,6 +936,107 @@ fn init(our: Address) {
                                     ),
                                 }
                             }
+                            "/api/notifications/inbox" => {
+                                // GET lists the inbox, POST {"read": [ids]} marks entries read,
+                                // DELETE clears it
+                                let action = match incoming.method() {
+                                    Ok(http::Method::GET) => NotificationsAction::GetInbox {
+                                        since: None,
+                                        limit: None,
+                                    },
+                                    Ok(http::Method::POST) => {
+                                        let Some(ids) = get_blob()
+                                            .and_then(|body| {
+                                                serde_json::from_slice::<serde_json::Value>(
+                                                    &body.bytes,
+                                                )
+                                                .ok()
+                                            })
+                                            .and_then(|body| {
+                                                serde_json::from_value::<Vec<u64>>(
+                                                    body.get("read")?.clone(),
+                                                )
+                                                .ok()
+                                            })
+                                        else {
+                                            return (
+                                                server::HttpResponse::new(
+                                                    http::StatusCode::BAD_REQUEST,
+                                                ),
+                                                None,
+                                            );
+                                        };
+                                        NotificationsAction::MarkRead { ids }
+                                    }
+                                    Ok(http::Method::DELETE) => NotificationsAction::ClearInbox,
+                                    _ => {
+                                        return (
+                                            server::HttpResponse::new(
+                                                http::StatusCode::METHOD_NOT_ALLOWED,
+                                            ),
+                                            None,
+                                        );
+                                    }
+                                };
+                                notifications_http_response(&our, &action)
+                            }
+                            "/api/notifications/preferences" => {
+                                // GET returns all preferences; POST sets one, with a body of
+                                // {"app": process, "preference": ...} or
+                                // {"endpoint": endpoint, "preference": ...}; a null preference removes it
+                                let action = match incoming.method() {
+                                    Ok(http::Method::GET) => NotificationsAction::GetPreferences,
+                                    Ok(http::Method::POST) => {
+                                        let Some(body) = get_blob().and_then(|body| {
+                                            serde_json::from_slice::<serde_json::Value>(
+                                                &body.bytes,
+                                            )
+                                            .ok()
+                                        }) else {
+                                            return (
+                                                server::HttpResponse::new(
+                                                    http::StatusCode::BAD_REQUEST,
+                                                ),
+                                                None,
+                                            );
+                                        };
+                                        let preference = body
+                                            .get("preference")
+                                            .filter(|p| !p.is_null())
+                                            .cloned();
+                                        if let Some(app) = body.get("app").and_then(|a| a.as_str())
+                                        {
+                                            NotificationsAction::SetAppPreference {
+                                                process: app.to_string(),
+                                                preference,
+                                            }
+                                        } else if let Some(endpoint) =
+                                            body.get("endpoint").and_then(|e| e.as_str())
+                                        {
+                                            NotificationsAction::SetDevicePreference {
+                                                endpoint: endpoint.to_string(),
+                                                preference,
+                                            }
+                                        } else {
+                                            return (
+                                                server::HttpResponse::new(
+                                                    http::StatusCode::BAD_REQUEST,
+                                                ),
+                                                None,
+                                            );
+                                        }
+                                    }
+                                    _ => {
+                                        return (
+                                            server::HttpResponse::new(
+                                                http::StatusCode::METHOD_NOT_ALLOWED,
+                                            ),
+                                            None,
+                                        );
+                                    }
+                                };
+                                notifications_http_response(&our, &action)
+                            }
                             _ => (server::HttpResponse::new(http::StatusCode::NOT_FOUND), None),
                         }
                     },
@@ -1024,6 +1152,59 @@ fn init(our: Address) {
     }
 }
 
+/// Forward an inbox or preferences action to the notifications server and
+/// turn its response into JSON for the frontend.
+fn notifications_http_response(
+    our: &Address,
+    action: &NotificationsAction,
+) -> (server::HttpResponse, Option<LazyLoadBlob>) {
+    let notifications_address = Address::new(
+        &our.node,
+        ProcessId::new(Some("notifications"), "distro", "sys"),
+    );
+    let (status, json) = match Request::to(notifications_address)
+        .body(serde_json::to_vec(action).unwrap())
+        .send_and_await_response(5)
+    {
+        Ok(Ok(response)) => {
+            match serde_json::from_slice::<NotificationsResponse>(response.body()) {
+                Ok(NotificationsResponse::Inbox(inbox)) => {
+                    (http::StatusCode::OK, serde_json::json!({ "inbox": inbox }))
+                }
+                Ok(NotificationsResponse::Preferences(preferences)) => (
+                    http::StatusCode::OK,
+                    serde_json::json!({ "preferences": preferences }),
+                ),
+                Ok(
+                    NotificationsResponse::InboxUpdated | NotificationsResponse::PreferencesUpdated,
+                ) => (http::StatusCode::OK, serde_json::json!({ "success": true })),
+                Ok(NotificationsResponse::Err(e)) => {
+                    println!("homepage: notifications server error: {}", e);
+                    (
+                        http::StatusCode::INTERNAL_SERVER_ERROR,
+                        serde_json::json!({ "error": e }),
+                    )
+                }
+                _ => (
+                    http::StatusCode::INTERNAL_SERVER_ERROR,
+                    serde_json::json!({ "error": "Unexpected response from notifications service" }),
+                ),
+            }
+        }
+        _ => (
+            http::StatusCode::INTERNAL_SERVER_ERROR,
+            serde_json::json!({ "error": "Failed to contact notifications server" }),
+        ),
+    };
+    (
+        server::HttpResponse::new(status),
+        Some(LazyLoadBlob::new(
+            Some("application/json"),
+            serde_json::to_vec(&json).unwrap(),
+        )),
+    )
+}
+
 fn version_from_cargo_toml() -> String {
     let version = CARGO_TOML
         .lines()
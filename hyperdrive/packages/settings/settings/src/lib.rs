@@ -1,7 +1,8 @@
 use crate::hyperware::process::settings::{
-    Direct, EthConfigRequest as SettingsEthConfigAction, HiRequest, Identity as SettingsIdentity,
-    NodeOrRpcUrl as SettingsNodeOrRpcUrl, NodeRouting as SettingsNodeRouting,
-    Request as SettingsRequest, Response as SettingsResponse, SettingsData, SettingsError,
+    CapHolder, Direct, EthConfigRequest as SettingsEthConfigAction, HiRequest,
+    Identity as SettingsIdentity, NodeOrRpcUrl as SettingsNodeOrRpcUrl,
+    NodeRouting as SettingsNodeRouting, Request as SettingsRequest, Response as SettingsResponse,
+    SettingsData, SettingsError,
 };
 use hyperware_process_lib::{
     await_message, call_init,
@@ -16,6 +17,27 @@ use std::{collections::HashMap, str::FromStr, vec};
 
 const ICON: &str = include_str!("icon");
 
+/// Mirrors `KernelCommand::Debug(KernelPrint::CapHolders)` in the runtime; keep the two in sync.
+#[derive(Serialize)]
+enum CapHoldersCommand {
+    Debug(CapHoldersPrint),
+}
+
+#[derive(Serialize)]
+enum CapHoldersPrint {
+    CapHolders(ProcessId),
+}
+
+#[derive(Deserialize)]
+enum CapHoldersResponse {
+    Debug(CapHoldersPrintResponse),
+}
+
+#[derive(Deserialize)]
+enum CapHoldersPrintResponse {
+    CapHolders(HashMap<ProcessId, Vec<Capability>>),
+}
+
 wit_bindgen::generate!({
     path: "../target/wit",
     world: "settings-sys-v0",
@@ -533,6 +555,34 @@ fn handle_settings_request(
             state.stylesheet = Some(stylesheet);
             return SettingsResponse::Ok(None);
         }
+        SettingsRequest::CapHolders(pid_str) => {
+            let Ok(pid) = pid_str.parse::<ProcessId>() else {
+                return SettingsResponse::Err(SettingsError::MalformedRequest);
+            };
+            let Ok(Ok(msg)) = Request::to(("our", "kernel", "distro", "sys"))
+                .body(
+                    serde_json::to_vec(&CapHoldersCommand::Debug(CapHoldersPrint::CapHolders(pid)))
+                        .unwrap(),
+                )
+                .send_and_await_response(30)
+            else {
+                return SettingsResponse::Err(SettingsError::KernelNonresponsive);
+            };
+            let Ok(CapHoldersResponse::Debug(CapHoldersPrintResponse::CapHolders(holders))) =
+                serde_json::from_slice(msg.body())
+            else {
+                return SettingsResponse::Err(SettingsError::KernelNonresponsive);
+            };
+            let mut holders: Vec<CapHolder> = holders
+                .into_iter()
+                .map(|(holder, caps)| CapHolder {
+                    holder: holder.to_string(),
+                    caps: caps.into_iter().map(|cap| cap.params).collect(),
+                })
+                .collect();
+            holders.sort_by(|a, b| a.holder.cmp(&b.holder));
+            return SettingsResponse::Ok(Some(SettingsData::CapHolders(holders)));
+        }
     }
 
     state.fetch().map_err(|_| SettingsError::StateFetchFailed)?;
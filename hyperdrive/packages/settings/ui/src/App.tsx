@@ -22,6 +22,11 @@ interface EthRpcSettings {
   deny: string[];
 }
 
+interface CapHolder {
+  holder: string;
+  caps: string[];
+}
+
 interface ProcessInfo {
   public: boolean;
   on_exit: string;
@@ -63,6 +68,8 @@ function App() {
   const [showHyperwareCss, setShowHyperwareCss] = useState(false);
   const [showPing, setShowPing] = useState(false);
   const [selectedProcess, setSelectedProcess] = useState<string | null>(null);
+  const [capHolders, setCapHolders] = useState<CapHolder[] | null>(null);
+  const [capHoldersError, setCapHoldersError] = useState<string | null>(null);
 
 
   const { address } = useAccount();
@@ -100,6 +107,27 @@ function App() {
     setTimeout(() => window.location.reload(), 1000);
   };
 
+  const handleSelectProcess = async (id: string) => {
+    setSelectedProcess(id);
+    setCapHolders(null);
+    setCapHoldersError(null);
+    if (!id) return;
+    try {
+      const response = await apiCall({ "CapHolders": id });
+      if (!response.ok) {
+        throw new Error(`${response.status} ${response.statusText}`);
+      }
+      const data = await response.json();
+      if (!Array.isArray(data?.CapHolders)) {
+        throw new Error("unexpected response");
+      }
+      setCapHolders(data.CapHolders);
+    } catch (err) {
+      console.error(err);
+      setCapHoldersError(`couldn't load capability holders: ${err}`);
+    }
+  };
+
   const handleSaveStylesheet = () => {
     const stylesheet = (document.getElementById('stylesheet-editor') as HTMLTextAreaElement).value;
     apiCall({ "SetStylesheet": stylesheet });
@@ -382,7 +410,7 @@ function App() {
               <h2 className="text-lg font-bold prose" >Running processes</h2>
               <select
                 id="process-select"
-                onChange={(e) => setSelectedProcess(e.target.value)}
+                onChange={(e) => handleSelectProcess(e.target.value)}
                 className="p-2 outline-1 text-gray-500"
               >
                 <option key="none" value="">Select a process</option>
@@ -406,6 +434,18 @@ function App() {
                         <li key={i}>{cap.issuer}({JSON.stringify(JSON.parse(cap.params), null, 2)})</li>
                       ))}
                     </ul>
+                    <p>holders of capabilities issued by this process:</p>
+                    {capHoldersError
+                      ? <p>{capHoldersError}</p>
+                      : capHolders === null
+                        ? <p>loading...</p>
+                        : capHolders.length === 0
+                          ? <p>none</p>
+                          : <ul>
+                            {capHolders.map(({ holder, caps }) => (
+                              <li key={holder}>{holder}: {caps.join(', ')}</li>
+                            ))}
+                          </ul>}
                   </div>
                   : <p>Selected process {selectedProcess} not found!</p>
                 : <p>Select a process to view details</p>}
@@ -0,0 +1,118 @@
+use clap::{Arg, Command};
+use hyperware_process_lib::{script, Address, Message, ProcessId, Request};
+use serde::{Deserialize, Serialize};
+
+wit_bindgen::generate!({
+    path: "../target/wit",
+    world: "process-v1",
+});
+
+const USAGE: &str = "\x1b[1mUsage:\x1b[0m crashes [<process-id>] [-f <show-full>]";
+
+/// Mirrors `KernelCommand::Debug(KernelPrint::Crashes)` in the runtime; keep the two in sync.
+#[derive(Serialize)]
+enum KernelCommand {
+    Debug(KernelPrint),
+}
+
+#[derive(Serialize)]
+enum KernelPrint {
+    Crashes(Option<ProcessId>),
+}
+
+#[derive(Deserialize)]
+enum KernelResponse {
+    Debug(KernelPrintResponse),
+}
+
+#[derive(Deserialize)]
+enum KernelPrintResponse {
+    Crashes(Vec<CrashEvent>),
+}
+
+#[derive(Deserialize)]
+struct CrashEvent {
+    process: ProcessId,
+    report: CrashReport,
+}
+
+#[derive(Deserialize)]
+struct CrashReport {
+    timestamp: u64,
+    trap: String,
+    backtrace: Option<String>,
+    stderr: String,
+    restart_attempt: Option<u32>,
+}
+
+script!(init);
+fn init(_our: Address, args: String) -> String {
+    let body_string = format!("crashes {args}");
+
+    let Ok(parsed) = Command::new("crashes")
+        .disable_help_flag(true)
+        .arg(Arg::new("process").index(1))
+        .arg(
+            Arg::new("show-full")
+                .short('f')
+                .long("show-full")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .try_get_matches_from(body_string.split_whitespace())
+    else {
+        return format!("Failed to parse args.\n{USAGE}");
+    };
+
+    let process = match parsed.get_one::<String>("process") {
+        None => None,
+        Some(process) => match process.parse::<ProcessId>() {
+            Ok(process) => Some(process),
+            Err(e) => return format!("invalid process id: {e}\n{USAGE}"),
+        },
+    };
+    let show_full = parsed.get_flag("show-full");
+
+    let Ok(Ok(Message::Response { body, .. })) = Request::to(("our", "kernel", "distro", "sys"))
+        .body(serde_json::to_vec(&KernelCommand::Debug(KernelPrint::Crashes(process))).unwrap())
+        .send_and_await_response(60)
+    else {
+        return "Failed to get response from kernel".to_string();
+    };
+    let Ok(KernelResponse::Debug(KernelPrintResponse::Crashes(crashes))) =
+        serde_json::from_slice::<KernelResponse>(&body)
+    else {
+        return "Failed to parse kernel response".to_string();
+    };
+
+    if crashes.is_empty() {
+        return "crashes: no crash reports".to_string();
+    }
+    crashes
+        .iter()
+        .map(|crash| print_crash(crash, show_full))
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+fn print_crash(crash: &CrashEvent, show_full: bool) -> String {
+    let report = &crash.report;
+    let mut lines = vec![format!(
+        "\x1b[1m{}\x1b[0m at {} (unix time){}: {}",
+        crash.process,
+        report.timestamp,
+        match report.restart_attempt {
+            Some(attempt) => format!(", restart attempt {attempt}"),
+            None => String::new(),
+        },
+        report.trap,
+    )];
+    if show_full {
+        if let Some(backtrace) = &report.backtrace {
+            lines.push(format!("    backtrace:\r\n{backtrace}"));
+        }
+        if !report.stderr.is_empty() {
+            lines.push(format!("    stderr:\r\n{}", report.stderr));
+        }
+    }
+    lines.join("\r\n")
+}
@@ -5,23 +5,34 @@ wit_bindgen::generate!({
     world: "process-v1",
 });
 
-const HELP_MESSAGES: [[&str; 2]; 16] = [
+const HELP_MESSAGES: [[&str; 2]; 19] = [
     ["add-node-provider", "\n\x1b[1madd-node-provider\x1b[0m <chain-id> <node-name> <public-key> <ip-address> <ws-port> [--trusted <true|false>]: add a node provider to the providers configuration.\n    - Examples:\n      \x1b[1madd-node-provider 8453 other-node.hypr abc123pubkey 192.168.1.1 9000\x1b[0m (defaults to trusted=false)\n      \x1b[1madd-node-provider 1 other-node.hypr abc123pubkey 192.168.1.1 9000 --trusted true\x1b[0m"],
     ["add-rpcurl-provider", "\n\x1b[1madd-rpcurl-provider\x1b[0m <rpc-url> [--chain-id <id>] [--trusted <true|false>] [--auth-type <basic|bearer|raw> --auth-value <value>]: add an RPC URL provider to the providers configuration.\n    - Examples:\n      \x1b[1madd-rpcurl-provider wss://base-mainnet.infura.io/v3/your-key\x1b[0m (defaults to chain-id=8453, trusted=true)\n      \x1b[1madd-rpcurl-provider wss://mainnet.infura.io/v3/your-key --chain-id 1\x1b[0m\n      \x1b[1madd-rpcurl-provider wss://base-mainnet.infura.io/ws/v3/your-key --trusted false\x1b[0m\n      \x1b[1madd-rpcurl-provider wss://rpc.example.com --auth-type bearer --auth-value your-token\x1b[0m"],
     ["alias", "\n\x1b[1malias\x1b[0m <shorthand> <process-id>: create an alias for a script.\n    - Example: \x1b[1malias get-block get-block:hns-indexer:sys\x1b[0m\n    - note: all of these listed commands are just default aliases for terminal scripts."],
     ["cat", "\n\x1b[1mcat\x1b[0m <vfs-file-path>: print the contents of a file in the terminal.\n    - Example: \x1b[1mcat /terminal:sys/pkg/scripts.json\x1b[0m"],
     ["clear-state", "\n\x1b[1mclear-state\x1b[0m <process-id>: clear the state of the given process."],
+    ["crashes", "\n\x1b[1mcrashes\x1b[0m [<process-id>] [-f <show-full>]: print recent crash reports, kept across reboots, of all processes or of one. Add \x1b[1m-f\x1b[0m to also show each crash's backtrace, stderr and the message being handled.\n    - Example: \x1b[1mcrashes chess:chess:sys -f\x1b[0m"],
     ["echo", "\n\x1b[1mecho\x1b[0m <text>: print text to the terminal.\n    - Example: \x1b[1mecho foo\x1b[0m"],
     ["get-providers", "\n\x1b[1mget-providers\x1b[0m: display the providers configuration."],
     ["hi", "\n\x1b[1mhi\x1b[0m <name> <string>: send a text message to another node's command line.\n    - Example: \x1b[1mhi mothu.hypr hello world\x1b[0m"],
     ["kfetch", "\n\x1b[1mkfetch\x1b[0m: print system information a la neofetch. No arguments."],
     ["kill", "\n\x1b[1mkill\x1b[0m <process-id>: terminate a running process. This will bypass any restart behavior; use judiciously.\n    - Example: \x1b[1mkill chess:chess:sys\x1b[0m"],
+    ["log", "\n\x1b[1mlog\x1b[0m [<process-id>] [-v <max-verbosity>] [-s <since>] [-n <count>]: print recent terminal log entries, optionally only those from one process, at or below a verbosity, or from the last <since> (e.g. 90s, 15m, 2h, 1d). Shows the last 50 entries unless -n is given. Requires the node to be run with \x1b[1m--log-format json\x1b[0m.\n    - Example: \x1b[1mlog net:distro:sys -v 1 -s 15m\x1b[0m"],
     ["m", "\n\x1b[1mm\x1b[0m <address> '<json>': send an inter-process message. <address> is formatted as <node>@<process-id>. <process-id> is formatted as <process-name>:<package-name>:<publisher-node>. JSON containing spaces must be wrapped in single-quotes (\x1b[1m''\x1b[0m).\n    - Example: \x1b[1mm our@eth:distro:sys \"SetPublic\" -a 5\x1b[0m\n    - the '-a' flag is used to expect a response with a given timeout\n    - \x1b[1mour\x1b[0m will always be interpolated by the system as your node's name"],
     ["net-diagnostics", "\n\x1b[1mnet-diagnostics\x1b[0m: print some useful networking diagnostic data."],
     ["peer", "\n\x1b[1mpeer\x1b[0m <name>: print the peer's PKI info, if it exists."],
     ["peers", "\n\x1b[1mpeers\x1b[0m: print the peers the node currently hold connections with."],
     ["remove-provider", "\n\x1b[1mremove-provider\x1b[0m <chain-id> <nodename or rpc-url>: remove a provider from the providers configuration.\n    - Example: \x1b[1mremove-provider 8453 wss://base-mainnet.infura.io/ws/v3/your-key\x1b[0m"],
-    ["top", "\n\x1b[1mtop\x1b[0m <process-id>: display kernel debugging info about a process. Leave the process ID blank to display info about all processes and get the total number of running processes.\n    - Example: \x1b[1mtop net:distro:sys\x1b[0m\n    - Example: \x1b[1mtop\x1b[0m"],
+    ["top", "\n\x1b[1mtop\x1b[0m <process-id>: display kernel debugging info about a process. Leave the process ID blank to display info about all processes and get the total number of running processes. Runtime modules that hold files, sockets or databases also show their open and allocated file descriptors.\n    - Example: \x1b[1mtop net:distro:sys\x1b[0m\n    - Example: \x1b[1mtop\x1b[0m"],
+    ["trace", "\n\x1b[1mtrace\x1b[0m start [<process-id>] | stop [<process-id>] | export: record message traces. \x1b[1mstart\x1b[0m turns on recording and, given a process, starts a new trace at each request it sends; every message sent in handling of a traced message, on this node or on a recording peer, joins the trace. \x1b[1mstop\x1b[0m with a process stops starting traces there, and without one stops recording. \x1b[1mexport\x1b[0m writes the recorded spans to an OTLP JSON file in the node's .traces directory.\n    - Example: \x1b[1mtrace start chess:chess:sys\x1b[0m"],
+];
+
+const SHELL_MESSAGES: [&str; 5] = [
+    "\n\x1b[1ma | b\x1b[0m to run \x1b[1mb\x1b[0m with the output of \x1b[1ma\x1b[0m appended to its arguments (and attached as its blob)",
+    "\n\x1b[1ma ; b\x1b[0m to run \x1b[1ma\x1b[0m then \x1b[1mb\x1b[0m; \x1b[1ma && b\x1b[0m to run \x1b[1mb\x1b[0m only if \x1b[1ma\x1b[0m succeeded",
+    "\n    - a script fails by responding with metadata \x1b[1merror\x1b[0m; scripts that only return their output always succeed",
+    "\n\x1b[1ma > /pkg:publisher/drive/file\x1b[0m to write the output of \x1b[1ma\x1b[0m to a VFS file, or \x1b[1m>>\x1b[0m to append to it",
+    "\n    - operators inside single or double quotes are passed through to the script as-is",
 ];
 
 const CONTROL_MESSAGES: [&str; 10] = [
@@ -58,6 +69,11 @@ fn init(_our: Address, args: String) -> String {
             help_message.push_str("\n");
         }
 
+        for message in SHELL_MESSAGES.iter() {
+            help_message.push_str(message);
+            help_message.push_str("\n");
+        }
+
         for message in CONTROL_MESSAGES.iter() {
             help_message.push_str(message);
             help_message.push_str("\n");
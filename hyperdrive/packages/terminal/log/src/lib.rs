@@ -0,0 +1,135 @@
+use clap::{Arg, Command};
+use hyperware_process_lib::{script, Address, Message, ProcessId, Request};
+use serde::{Deserialize, Serialize};
+
+wit_bindgen::generate!({
+    path: "../target/wit",
+    world: "process-v1",
+});
+
+const USAGE: &str =
+    "\x1b[1mUsage:\x1b[0m log [<process-id>] [-v <max-verbosity>] [-s <since>] [-n <count>]
+    \n<since> is a duration such as 90s, 15m, 2h or 1d";
+
+const DEFAULT_COUNT: usize = 50;
+
+/// Mirrors `RuntimeTerminalRequest` in the runtime; keep the two in sync.
+#[derive(Serialize)]
+enum RuntimeTerminalRequest {
+    QueryLogs(LogQuery),
+}
+
+#[derive(Serialize)]
+struct LogQuery {
+    process: Option<ProcessId>,
+    max_verbosity: Option<u8>,
+    since_secs: Option<u64>,
+    limit: usize,
+}
+
+#[derive(Deserialize)]
+enum RuntimeTerminalResponse {
+    QueryLogs(Vec<LogEntry>),
+    Err(String),
+}
+
+#[derive(Deserialize)]
+struct LogEntry {
+    timestamp: String,
+    verbosity: u8,
+    source: ProcessId,
+    content: String,
+}
+
+script!(init);
+fn init(_our: Address, args: String) -> String {
+    let body_string = format!("log {args}");
+
+    let Ok(parsed) = Command::new("log")
+        .disable_help_flag(true)
+        .arg(Arg::new("process").index(1))
+        .arg(
+            Arg::new("verbosity")
+                .short('v')
+                .long("verbosity")
+                .value_parser(clap::value_parser!(u8)),
+        )
+        .arg(Arg::new("since").short('s').long("since"))
+        .arg(
+            Arg::new("count")
+                .short('n')
+                .long("count")
+                .value_parser(clap::value_parser!(usize)),
+        )
+        .try_get_matches_from(body_string.split_whitespace())
+    else {
+        return format!("Failed to parse args.\n{USAGE}");
+    };
+
+    let process = match parsed.get_one::<String>("process") {
+        None => None,
+        Some(process) => match process.parse::<ProcessId>() {
+            Ok(process) => Some(process),
+            Err(e) => return format!("invalid process id: {e}\n{USAGE}"),
+        },
+    };
+    let since_secs = match parsed.get_one::<String>("since") {
+        None => None,
+        Some(since) => match parse_duration(since) {
+            Some(secs) => Some(secs),
+            None => return format!("invalid duration: {since}\n{USAGE}"),
+        },
+    };
+
+    let query = RuntimeTerminalRequest::QueryLogs(LogQuery {
+        process,
+        max_verbosity: parsed.get_one::<u8>("verbosity").copied(),
+        since_secs,
+        limit: parsed
+            .get_one::<usize>("count")
+            .copied()
+            .unwrap_or(DEFAULT_COUNT),
+    });
+    let Ok(Ok(Message::Response { body, .. })) = Request::to(("our", "terminal", "distro", "sys"))
+        .body(serde_json::to_vec(&query).unwrap())
+        .send_and_await_response(60)
+    else {
+        return "Failed to get response from runtime terminal".to_string();
+    };
+    let Ok(response) = serde_json::from_slice::<RuntimeTerminalResponse>(&body) else {
+        return "Failed to parse runtime terminal response".to_string();
+    };
+
+    match response {
+        RuntimeTerminalResponse::Err(e) => format!("log: {e}"),
+        RuntimeTerminalResponse::QueryLogs(entries) if entries.is_empty() => {
+            "log: no matching entries".to_string()
+        }
+        RuntimeTerminalResponse::QueryLogs(entries) => entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{} [{}] {}: {}",
+                    entry.timestamp, entry.verbosity, entry.source, entry.content
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\r\n"),
+    }
+}
+
+/// parse `90`, `90s`, `15m`, `2h` or `1d` into seconds
+fn parse_duration(s: &str) -> Option<u64> {
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
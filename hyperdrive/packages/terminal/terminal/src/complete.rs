@@ -0,0 +1,182 @@
+//! Tab completion for the runtime terminal.
+//!
+//! The runtime sends the command line up to the cursor. We complete its last word:
+//! a script name or alias at the head of a command, a VFS path after a redirect or
+//! when the word starts with `/`, and a process ID anywhere else.
+
+use crate::hyperware::process::terminal::CompleteResponse;
+use crate::VersionedState;
+use hyperware_process_lib::{kernel_types as kt, vfs, Message, Request};
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+/// How long the scripts found in installed packages are reused before their
+/// scripts.json files are read again
+const SCRIPTS_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Scripts found in installed packages, so that each Tab doesn't read every
+/// package's scripts.json
+#[derive(Default)]
+pub struct Cache {
+    scripts: Option<(Instant, BTreeSet<String>)>,
+}
+
+impl Cache {
+    fn scripts(&mut self) -> &BTreeSet<String> {
+        if self
+            .scripts
+            .as_ref()
+            .is_none_or(|(read_at, _)| read_at.elapsed() >= SCRIPTS_CACHE_TTL)
+        {
+            self.scripts = Some((Instant::now(), scripts()));
+        }
+        &self.scripts.as_ref().unwrap().1
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Position {
+    /// the script to run: first word of a command
+    Head,
+    Argument,
+    /// target of `>` or `>>`
+    Redirect,
+}
+
+pub fn complete(state: &VersionedState, cache: &mut Cache, line: &str) -> CompleteResponse {
+    let (word, position) = last_word(line);
+    let candidates = if position == Position::Redirect || word.starts_with('/') {
+        paths(word)
+    } else if position == Position::Head {
+        let mut commands: BTreeSet<String> = state.aliases().keys().cloned().collect();
+        commands.extend(cache.scripts().iter().cloned());
+        commands
+    } else {
+        match word.split_once('@') {
+            // an address, as taken by `m`
+            Some((node, _)) => processes()
+                .into_iter()
+                .map(|process| format!("{node}@{process}"))
+                .collect(),
+            None => processes(),
+        }
+    };
+    CompleteResponse {
+        word: word.to_string(),
+        candidates: candidates
+            .into_iter()
+            .filter(|candidate| candidate.starts_with(word))
+            .collect(),
+    }
+}
+
+/// Find the word that ends the line, skipping over quoted text the same way the
+/// shell grammar does, and where in its command that word sits.
+fn last_word(line: &str) -> (&str, Position) {
+    let mut word_start = 0;
+    let mut in_word = false;
+    let mut position = Position::Head;
+    let mut quote: Option<char> = None;
+
+    for (i, c) in line.char_indices() {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '\'' | '"' => {
+                quote = Some(c);
+                in_word = true;
+            }
+            '|' | ';' | '&' | '>' => {
+                position = if c == '>' {
+                    Position::Redirect
+                } else {
+                    Position::Head
+                };
+                word_start = i + 1;
+                in_word = false;
+            }
+            c if c.is_whitespace() => {
+                if in_word && position == Position::Head {
+                    position = Position::Argument;
+                }
+                word_start = i + c.len_utf8();
+                in_word = false;
+            }
+            _ => in_word = true,
+        }
+    }
+    (&line[word_start..], position)
+}
+
+/// The full process ID of every script in every installed package.
+fn scripts() -> BTreeSet<String> {
+    let mut scripts = BTreeSet::new();
+    for package in read_dir("/") {
+        let Ok(manifest) = crate::scripts_manifest(&package.path) else {
+            continue;
+        };
+        for script in manifest.keys() {
+            if let Some(name) = script.strip_suffix(".wasm") {
+                scripts.insert(format!("{name}:{}", package.path));
+            }
+        }
+    }
+    scripts
+}
+
+fn processes() -> BTreeSet<String> {
+    let Ok(Ok(Message::Response { body, .. })) = Request::to(("our", "kernel", "distro", "sys"))
+        .body(serde_json::to_vec(&kt::KernelCommand::Debug(kt::KernelPrint::ProcessMap)).unwrap())
+        .send_and_await_response(5)
+    else {
+        return BTreeSet::new();
+    };
+    let Ok(kt::KernelResponse::Debug(kt::KernelPrintResponse::ProcessMap(map))) =
+        serde_json::from_slice::<kt::KernelResponse>(&body)
+    else {
+        return BTreeSet::new();
+    };
+    map.keys().map(|process| process.to_string()).collect()
+}
+
+/// Entries of the directory the word points into, with a trailing `/` on directories
+/// so that completion can continue into them.
+fn paths(word: &str) -> BTreeSet<String> {
+    let dir = match word.rfind('/') {
+        Some(i) => &word[..=i],
+        None => "/",
+    };
+    read_dir(dir)
+        .into_iter()
+        .map(|entry| {
+            let suffix = match entry.file_type {
+                vfs::FileType::Directory => "/",
+                _ => "",
+            };
+            format!("/{}{suffix}", entry.path)
+        })
+        .collect()
+}
+
+fn read_dir(path: &str) -> Vec<vfs::DirEntry> {
+    let Ok(Ok(Message::Response { body, .. })) = Request::to(("our", "vfs", "distro", "sys"))
+        .body(
+            serde_json::to_vec(&vfs::VfsRequest {
+                path: path.to_string(),
+                action: vfs::VfsAction::ReadDir,
+            })
+            .unwrap(),
+        )
+        .send_and_await_response(5)
+    else {
+        return vec![];
+    };
+    match serde_json::from_slice::<vfs::VfsResponse>(&body) {
+        Ok(vfs::VfsResponse::ReadDir(entries)) => entries,
+        _ => vec![],
+    }
+}
@@ -6,7 +6,13 @@ use hyperware_process_lib::{
     set_state, vfs, Address, Capability, Message, ProcessId, Request, Response,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+mod complete;
+mod shell;
+
+/// how long a script in a pipeline or sequence may take to return its output
+const SCRIPT_OUTPUT_TIMEOUT: u64 = 60;
 
 wit_bindgen::generate!({
     path: "../target/wit",
@@ -23,6 +29,9 @@ enum ScriptError {
     NoScriptInManifest,
     InvalidScriptsManifest,
     KernelUnresponsive,
+    InvalidSyntax(String),
+    NoOutput(String),
+    RedirectFailed(String),
 }
 
 impl std::fmt::Display for ScriptError {
@@ -36,6 +45,11 @@ impl std::fmt::Display for ScriptError {
             ScriptError::NoScriptInManifest => write!(f, "script not in scripts.json file"),
             ScriptError::InvalidScriptsManifest => write!(f, "could not parse scripts.json file"),
             ScriptError::KernelUnresponsive => write!(f, "kernel unresponsive"),
+            ScriptError::InvalidSyntax(e) => write!(f, "syntax error: {e}"),
+            ScriptError::NoOutput(name) => {
+                write!(f, "'{name}' did not return output in time")
+            }
+            ScriptError::RedirectFailed(e) => write!(f, "failed to redirect output: {e}"),
         }
     }
 }
@@ -80,6 +94,10 @@ impl VersionedState {
                     "clear-state".to_string(),
                     ProcessId::new(Some("clear-state"), "terminal", "sys"),
                 ),
+                (
+                    "crashes".to_string(),
+                    ProcessId::new(Some("crashes"), "terminal", "sys"),
+                ),
                 (
                     "echo".to_string(),
                     ProcessId::new(Some("echo"), "terminal", "sys"),
@@ -104,6 +122,10 @@ impl VersionedState {
                     "kfetch".to_string(),
                     ProcessId::new(Some("kfetch"), "terminal", "sys"),
                 ),
+                (
+                    "log".to_string(),
+                    ProcessId::new(Some("log"), "terminal", "sys"),
+                ),
                 (
                     "m".to_string(),
                     ProcessId::new(Some("m"), "terminal", "sys"),
@@ -128,6 +150,10 @@ impl VersionedState {
                     "top".to_string(),
                     ProcessId::new(Some("top"), "terminal", "sys"),
                 ),
+                (
+                    "trace".to_string(),
+                    ProcessId::new(Some("trace"), "terminal", "sys"),
+                ),
             ]),
         })
     }
@@ -161,6 +187,112 @@ impl VersionedState {
     }
 }
 
+/// A command line with operators. Its scripts run one at a time, each started
+/// once the output of the one before it arrives, so that the terminal keeps taking
+/// input meanwhile.
+struct Job {
+    /// pipelines not yet started
+    pipelines: VecDeque<(shell::Sequence, shell::Pipeline)>,
+    /// commands of the current pipeline not yet started
+    commands: VecDeque<String>,
+    redirect: Option<shell::Redirect>,
+    /// the script whose output we're waiting for
+    running: Option<ProcessId>,
+    /// whether the last pipeline to end succeeded
+    last_ok: bool,
+}
+
+impl Job {
+    fn new(pipelines: Vec<(shell::Sequence, shell::Pipeline)>) -> Self {
+        Self {
+            pipelines: pipelines.into(),
+            commands: VecDeque::new(),
+            redirect: None,
+            running: None,
+            last_ok: true,
+        }
+    }
+
+    /// Start the next script, given the output of the one that just ended, if any,
+    /// and whether it succeeded. Returns false once the job has ended.
+    fn advance(
+        &mut self,
+        state: &VersionedState,
+        id: u64,
+        mut output: Option<(String, bool)>,
+    ) -> bool {
+        loop {
+            if let Some(command) = self.commands.pop_front() {
+                match self.start(state, id, &command, output.take().map(|(o, _)| o)) {
+                    Ok(()) => return true,
+                    Err(e) => self.fail(e),
+                }
+                continue;
+            }
+            if let Some((output, ok)) = output.take() {
+                // the output of the last command of the pipeline, which decides
+                // whether the pipeline succeeded
+                self.last_ok = match self.redirect.take() {
+                    None => {
+                        println!("{output}");
+                        ok
+                    }
+                    Some(redirect) => match write_output(&redirect, output.into_bytes()) {
+                        Ok(()) => ok,
+                        Err(e) => {
+                            println!("error calling script: {e}");
+                            false
+                        }
+                    },
+                };
+            }
+            let Some((sequence, pipeline)) = self.pipelines.pop_front() else {
+                return false;
+            };
+            if sequence == shell::Sequence::IfOk && !self.last_ok {
+                continue;
+            }
+            self.commands = pipeline.commands.into();
+            self.redirect = pipeline.redirect;
+        }
+    }
+
+    /// Run a command, appending the previous command's output to its arguments and
+    /// attaching it as the blob.
+    fn start(
+        &mut self,
+        state: &VersionedState,
+        id: u64,
+        command: &str,
+        input: Option<String>,
+    ) -> Result<(), ScriptError> {
+        let (process, mut args) = resolve(state, command)?;
+        if let Some(input) = &input {
+            if !args.is_empty() {
+                args.push(' ');
+            }
+            args.push_str(input);
+        }
+        handle_run(
+            state.our(),
+            &process,
+            args,
+            Some((id, input.map(String::into_bytes))),
+        )?;
+        self.running = Some(process);
+        Ok(())
+    }
+
+    /// End the current pipeline with an error.
+    fn fail(&mut self, e: ScriptError) {
+        println!("error calling script: {e}");
+        self.commands.clear();
+        self.redirect = None;
+        self.running = None;
+        self.last_ok = false;
+    }
+}
+
 call_init!(init);
 fn init(our: Address) {
     let mut state: VersionedState =
@@ -177,9 +309,21 @@ fn init(our: Address) {
             None => VersionedState::new(our),
         };
 
+    let mut jobs: HashMap<u64, Job> = HashMap::new();
+    let mut completions = complete::Cache::default();
+
     loop {
         let message = match await_message() {
             Err(e) => {
+                // a script of a job didn't return its output in time
+                if let Some((id, mut job)) = job_of(&mut jobs, e.context.as_deref()) {
+                    let process = job.running.take().unwrap_or(e.target.process);
+                    job.fail(ScriptError::NoOutput(process.to_string()));
+                    if job.advance(&state, id, None) {
+                        jobs.insert(id, job);
+                    }
+                    continue;
+                }
                 println!("net error: {e:?}!");
                 continue;
             }
@@ -194,19 +338,33 @@ fn init(our: Address) {
             } => {
                 // this is a message from the runtime terminal, parse as a command
                 if *state.our() == source {
-                    if let Err(e) =
-                        parse_command(&mut state, String::from_utf8_lossy(&body).to_string())
-                    {
+                    if let Err(e) = parse_command(
+                        &mut state,
+                        &mut jobs,
+                        String::from_utf8_lossy(&body).to_string(),
+                    ) {
                         println!("error calling script: {e}");
                     }
                 // checks for a request from a terminal script (different process, same package)
+                // or from the runtime terminal asking for completions
                 } else if state.our().node == source.node
-                    && state.our().package() == source.package()
+                    && (state.our().package() == source.package()
+                        || source.process == ProcessId::new(Some("terminal"), "distro", "sys"))
                 {
                     let Ok(action) = serde_json::from_slice::<TerminalRequest>(&body) else {
                         println!("failed to parse TerminalRequest from {source}");
                         continue;
                     };
+                    // the runtime terminal may only ask for completions
+                    if state.our().package() != source.package()
+                        && !matches!(action, TerminalRequest::Complete(_))
+                    {
+                        hyperware_process_lib::print_to_terminal(
+                            2,
+                            &format!("ignoring non-completion request from {source}"),
+                        );
+                        continue;
+                    }
                     match action {
                         TerminalRequest::EditAlias(edit_alias_request) => {
                             let terminal_response = handle_alias_change(
@@ -221,6 +379,19 @@ fn init(our: Address) {
                                     .unwrap();
                             }
                         }
+                        TerminalRequest::Complete(complete_request) => {
+                            let terminal_response = TerminalResponse::Complete(complete::complete(
+                                &state,
+                                &mut completions,
+                                &complete_request.line,
+                            ));
+                            if expects_response.is_some() {
+                                Response::new()
+                                    .body(serde_json::to_vec(&terminal_response).unwrap())
+                                    .send()
+                                    .unwrap();
+                            }
+                        }
                     }
                 } else {
                     hyperware_process_lib::print_to_terminal(
@@ -229,7 +400,21 @@ fn init(our: Address) {
                     );
                 }
             }
-            Message::Response { body, .. } => {
+            Message::Response {
+                body,
+                metadata,
+                context,
+                ..
+            } => {
+                if let Some((id, mut job)) = job_of(&mut jobs, context.as_deref()) {
+                    job.running = None;
+                    let output = String::from_utf8_lossy(&body).to_string();
+                    let ok = shell::succeeded(metadata.as_deref());
+                    if job.advance(&state, id, Some((output, ok))) {
+                        jobs.insert(id, job);
+                    }
+                    continue;
+                }
                 if let Ok(txt) = std::str::from_utf8(&body) {
                     println!("{txt}");
                 } else {
@@ -240,22 +425,108 @@ fn init(our: Address) {
     }
 }
 
-fn parse_command(state: &mut VersionedState, line: String) -> Result<(), ScriptError> {
+fn parse_command(
+    state: &mut VersionedState,
+    jobs: &mut HashMap<u64, Job>,
+    line: String,
+) -> Result<(), ScriptError> {
     if line.is_empty() {
         return Ok(());
     }
-    let (head, args) = line.split_once(" ").unwrap_or((&line, ""));
-    match state.aliases().get(head) {
-        Some(process) => handle_run(state.our(), process, args.to_string()),
-        None => match head.parse::<ProcessId>() {
-            Ok(pid) => handle_run(state.our(), &pid, args.to_string()),
-            Err(_) => Err(ScriptError::UnknownName(head.to_string())),
-        },
+    let is_command = |word: &str| is_command(state, word);
+    if shell::is_plain(&line, is_command) {
+        // a single script prints its own output
+        let (process, args) = resolve(state, &line)?;
+        handle_run(state.our(), &process, args, None)?;
+        return Ok(());
+    }
+    let pipelines = shell::parse(&line, is_command).map_err(ScriptError::InvalidSyntax)?;
+    let id = rand::random();
+    let mut job = Job::new(pipelines);
+    if job.advance(state, id, None) {
+        jobs.insert(id, job);
+    }
+    Ok(())
+}
+
+/// Take the job a response or timeout is for, given the context of the request.
+fn job_of(jobs: &mut HashMap<u64, Job>, context: Option<&[u8]>) -> Option<(u64, Job)> {
+    let id = u64::from_le_bytes(context?.try_into().ok()?);
+    jobs.remove(&id).map(|job| (id, job))
+}
+
+/// Whether a word names a script, by alias or process ID
+fn is_command(state: &VersionedState, word: &str) -> bool {
+    state.aliases().contains_key(word) || word.parse::<ProcessId>().is_ok()
+}
+
+/// Split a command into the script it names, by alias or process ID, and its arguments
+fn resolve(state: &VersionedState, command: &str) -> Result<(ProcessId, String), ScriptError> {
+    let (head, args) = command.split_once(" ").unwrap_or((command, ""));
+    let process = match state.aliases().get(head) {
+        Some(process) => process.clone(),
+        None => head
+            .parse::<ProcessId>()
+            .map_err(|_| ScriptError::UnknownName(head.to_string()))?,
+    };
+    Ok((process, args.to_string()))
+}
+
+/// Write pipeline output to a VFS file: `>` replaces the file, `>>` appends to it
+fn write_output(redirect: &shell::Redirect, output: Vec<u8>) -> Result<(), ScriptError> {
+    if redirect.append {
+        // appending needs an existing file
+        vfs_request(
+            &redirect.path,
+            vfs::VfsAction::OpenFile { create: true },
+            None,
+        )?;
+        vfs_request(&redirect.path, vfs::VfsAction::Append, Some(output))
+    } else {
+        vfs_request(&redirect.path, vfs::VfsAction::Write, Some(output))
+    }
+}
+
+fn vfs_request(
+    path: &str,
+    action: vfs::VfsAction,
+    blob: Option<Vec<u8>>,
+) -> Result<(), ScriptError> {
+    let mut request = Request::to(("our", "vfs", "distro", "sys")).body(
+        serde_json::to_vec(&vfs::VfsRequest {
+            path: path.to_string(),
+            action,
+        })
+        .unwrap(),
+    );
+    if let Some(blob) = blob {
+        request = request.blob_bytes(blob);
+    }
+    let Ok(Message::Response { body, .. }) = request.send_and_await_response(5).unwrap() else {
+        return Err(ScriptError::RedirectFailed(format!(
+            "{path}: vfs unresponsive"
+        )));
+    };
+    match serde_json::from_slice::<vfs::VfsResponse>(&body) {
+        Ok(vfs::VfsResponse::Err(e)) => Err(ScriptError::RedirectFailed(format!("{path}: {e}"))),
+        Ok(_) => Ok(()),
+        Err(_) => Err(ScriptError::RedirectFailed(format!(
+            "{path}: malformed vfs response"
+        ))),
     }
 }
 
-/// Run a script by loading it from the VFS
-fn handle_run(our: &Address, process: &ProcessId, args: String) -> Result<(), ScriptError> {
+/// Run a script by loading it from the VFS.
+///
+/// Without a `job`, the script prints its own output. Otherwise the script's output
+/// comes back as a response with the job's ID as context, and `input`, if any, is
+/// attached as the blob of the arguments request.
+fn handle_run(
+    our: &Address,
+    process: &ProcessId,
+    args: String,
+    job: Option<(u64, Option<Vec<u8>>)>,
+) -> Result<(), ScriptError> {
     let entry = get_entry(process)?;
     let wasm_path = format!(
         "/{}:{}/pkg/{}.wasm",
@@ -424,11 +695,16 @@ fn handle_run(our: &Address, process: &ProcessId, args: String) -> Result<(), Sc
         .map_err(|_| ScriptError::KernelUnresponsive)?;
 
     // once process is running, send the arguments to it
-    Request::to(("our", process_id))
-        .body(args.into_bytes())
-        .send()
-        .unwrap();
-
+    let mut request = Request::to(("our", process_id)).body(args.into_bytes());
+    if let Some((id, input)) = job {
+        request = request
+            .expects_response(SCRIPT_OUTPUT_TIMEOUT)
+            .context(id.to_le_bytes());
+        if let Some(input) = input {
+            request = request.blob_bytes(input);
+        }
+    }
+    request.send().unwrap();
     Ok(())
 }
 
@@ -462,21 +738,19 @@ fn handle_alias_change(
 }
 
 fn get_entry(process: &ProcessId) -> Result<kt::DotScriptsEntry, ScriptError> {
-    let file = vfs::File::new(
-        format!(
-            "/{}:{}/pkg/scripts.json",
-            process.package(),
-            process.publisher()
-        ),
-        5,
-    )
-    .read()
-    .map_err(|_| ScriptError::NoScriptsManifest)?;
-
-    let dot_scripts = serde_json::from_slice::<HashMap<String, kt::DotScriptsEntry>>(&file)
-        .map_err(|_| ScriptError::InvalidScriptsManifest)?;
+    let dot_scripts = scripts_manifest(&format!("{}:{}", process.package(), process.publisher()))?;
     let Some(entry) = dot_scripts.get(&format!("{}.wasm", process.process())) else {
         return Err(ScriptError::NoScriptInManifest);
     };
     Ok(entry.to_owned())
 }
+
+/// Read the scripts.json of a package, given as `package:publisher`
+fn scripts_manifest(package: &str) -> Result<HashMap<String, kt::DotScriptsEntry>, ScriptError> {
+    let file = vfs::File::new(format!("/{package}/pkg/scripts.json"), 5)
+        .read()
+        .map_err(|_| ScriptError::NoScriptsManifest)?;
+
+    serde_json::from_slice::<HashMap<String, kt::DotScriptsEntry>>(&file)
+        .map_err(|_| ScriptError::InvalidScriptsManifest)
+}
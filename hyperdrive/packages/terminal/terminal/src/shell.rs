@@ -0,0 +1,294 @@
+//! A small shell grammar for the command line:
+//!
+//! ```text
+//! line     := pipeline ((";" | "&&") pipeline)*
+//! pipeline := command ("|" command)* ((">" | ">>") path)?
+//! ```
+//!
+//! Operators inside single or double quotes are left alone, as are those not
+//! followed by what they take: a command after `|`, `;` and `&&`, an absolute
+//! path after `>` and `>>`. So plain text, like a message sent with `hi`, keeps
+//! its meaning. Commands keep their original text, quotes included, since each
+//! script parses its own arguments.
+
+/// How a pipeline depends on the one before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sequence {
+    /// first pipeline, or after `;`: always run
+    Always,
+    /// after `&&`: run only if the previous pipeline succeeded
+    IfOk,
+}
+
+/// Response metadata by which a script reports that it failed, so that a
+/// pipeline after `&&` doesn't run. Scripts written with `script!` respond with
+/// their output only, so they always count as having succeeded.
+pub const FAILED_METADATA: &str = "error";
+
+/// Whether a script succeeded, given the metadata of its response.
+pub fn succeeded(metadata: Option<&str>) -> bool {
+    metadata != Some(FAILED_METADATA)
+}
+
+#[derive(Debug)]
+pub struct Redirect {
+    /// VFS path, e.g. `/pkg:publisher/drive/file`
+    pub path: String,
+    /// `>>` rather than `>`
+    pub append: bool,
+}
+
+#[derive(Debug)]
+pub struct Pipeline {
+    /// each command is `name args...`; every command after the first also
+    /// receives the previous command's output
+    pub commands: Vec<String>,
+    pub redirect: Option<Redirect>,
+}
+
+enum Token {
+    Word(String),
+    Pipe,
+    Semicolon,
+    And,
+    Redirect { append: bool },
+}
+
+/// Whether the line uses no operators. Plain lines keep running the old way:
+/// the script prints its own output, and we do not wait for it. A lone
+/// apostrophe, as in `hi friend.os what's up`, does not make a line non-plain.
+/// `is_command` tells whether a word names a script.
+pub fn is_plain(line: &str, is_command: impl Fn(&str) -> bool) -> bool {
+    !tokenize(line, is_command)
+        .is_ok_and(|tokens| tokens.iter().any(|t| !matches!(t, Token::Word(_))))
+}
+
+pub fn parse(
+    line: &str,
+    is_command: impl Fn(&str) -> bool,
+) -> Result<Vec<(Sequence, Pipeline)>, String> {
+    let mut pipelines = vec![];
+    let mut sequence = Sequence::Always;
+    let mut commands: Vec<String> = vec![];
+    let mut redirect: Option<Redirect> = None;
+    let mut after_pipe = false;
+    let mut tokens = tokenize(line, is_command)?.into_iter();
+
+    while let Some(token) = tokens.next() {
+        if after_pipe && !matches!(token, Token::Word(_)) {
+            return Err("`|` needs a command on both sides".to_string());
+        }
+        match token {
+            Token::Word(command) => {
+                commands.push(command);
+                after_pipe = false;
+            }
+            Token::Pipe => {
+                if commands.is_empty() || redirect.is_some() {
+                    return Err("`|` needs a command on both sides".to_string());
+                }
+                after_pipe = true;
+            }
+            Token::Redirect { append } => {
+                let Some(Token::Word(path)) = tokens.next() else {
+                    return Err("redirect needs a VFS path".to_string());
+                };
+                if commands.is_empty() || redirect.is_some() {
+                    return Err("redirect needs a command to take output from".to_string());
+                }
+                if !path.starts_with('/') || path.contains(char::is_whitespace) {
+                    return Err(format!(
+                        "bad redirect path `{path}`: must be an absolute VFS path"
+                    ));
+                }
+                redirect = Some(Redirect { path, append });
+            }
+            Token::Semicolon | Token::And => {
+                if commands.is_empty() {
+                    return Err("`;` and `&&` need a command before them".to_string());
+                }
+                pipelines.push((
+                    sequence,
+                    Pipeline {
+                        commands: std::mem::take(&mut commands),
+                        redirect: redirect.take(),
+                    },
+                ));
+                sequence = if matches!(token, Token::And) {
+                    Sequence::IfOk
+                } else {
+                    Sequence::Always
+                };
+            }
+        }
+    }
+    if after_pipe {
+        return Err("`|` needs a command on both sides".to_string());
+    }
+    if !commands.is_empty() {
+        pipelines.push((sequence, Pipeline { commands, redirect }));
+    } else if sequence == Sequence::IfOk {
+        return Err("`&&` needs a command after it".to_string());
+    }
+    Ok(pipelines)
+}
+
+/// Split the line on unquoted operators. Each run of text between operators
+/// becomes one trimmed [`Token::Word`], with its quotes kept.
+fn tokenize(line: &str, is_command: impl Fn(&str) -> bool) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut word = String::new();
+    let mut quote: Option<char> = None;
+    let mut chars = line.char_indices();
+
+    fn push_word(tokens: &mut Vec<Token>, word: &mut String) {
+        let trimmed = word.trim();
+        if !trimmed.is_empty() {
+            tokens.push(Token::Word(trimmed.to_string()));
+        }
+        word.clear();
+    }
+
+    while let Some((i, c)) = chars.next() {
+        if let Some(q) = quote {
+            if c == q {
+                quote = None;
+            }
+            word.push(c);
+            continue;
+        }
+        let (operator, len) = match c {
+            '\'' | '"' => {
+                quote = Some(c);
+                word.push(c);
+                continue;
+            }
+            '|' => (Token::Pipe, 1),
+            ';' => (Token::Semicolon, 1),
+            '&' if line[i + 1..].starts_with('&') => (Token::And, 2),
+            '>' if line[i + 1..].starts_with('>') => (Token::Redirect { append: true }, 2),
+            '>' => (Token::Redirect { append: false }, 1),
+            _ => {
+                word.push(c);
+                continue;
+            }
+        };
+        let rest = line[i + len..].trim_start();
+        let is_operator = match operator {
+            Token::Redirect { .. } => rest.starts_with('/'),
+            _ => rest
+                .split(|c: char| c.is_whitespace() || "|;&>".contains(c))
+                .next()
+                .is_some_and(&is_command),
+        };
+        if !is_operator {
+            word.push(c);
+            continue;
+        }
+        if len == 2 {
+            chars.next();
+        }
+        push_word(&mut tokens, &mut word);
+        tokens.push(operator);
+    }
+    if quote.is_some() {
+        return Err("unterminated quote".to_string());
+    }
+    push_word(&mut tokens, &mut word);
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_command(word: &str) -> bool {
+        ["echo", "hi", "m", "peers", "top"].contains(&word)
+    }
+
+    fn parse(line: &str) -> Result<Vec<(Sequence, Pipeline)>, String> {
+        super::parse(line, is_command)
+    }
+
+    fn commands(pipelines: &[(Sequence, Pipeline)]) -> Vec<(Sequence, Vec<&str>)> {
+        pipelines
+            .iter()
+            .map(|(sequence, pipeline)| {
+                (
+                    *sequence,
+                    pipeline.commands.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn plain_text_keeps_its_meaning() {
+        for line in [
+            "hi friend.os see you; bye && thanks",
+            "hi friend.os 3 > 2, and 2 >> 1",
+            "hi friend.os what's up | a & b",
+            "hi friend.os done;",
+            "hi friend.os done &&",
+            "m our@foo:bar:baz '{\"a\": \"b; top\"}'",
+            "m our@foo:bar:baz \"x | peers > /a:b/c\"",
+        ] {
+            assert!(is_plain(line, is_command), "{line}");
+        }
+        assert!(!is_plain("peers | echo", is_command));
+        assert!(!is_plain("peers > /a:b/c", is_command));
+    }
+
+    #[test]
+    fn pipes_and_sequences() {
+        let pipelines = parse("peers | echo found ; echo a&&top|echo").unwrap();
+        assert_eq!(
+            commands(&pipelines),
+            [
+                (Sequence::Always, vec!["peers", "echo found"]),
+                (Sequence::Always, vec!["echo a"]),
+                (Sequence::IfOk, vec!["top", "echo"]),
+            ]
+        );
+        assert!(pipelines.iter().all(|(_, p)| p.redirect.is_none()));
+
+        // text before an operator is kept whole
+        let pipelines = parse("hi friend.os see you; bye; top").unwrap();
+        assert_eq!(
+            commands(&pipelines),
+            [
+                (Sequence::Always, vec!["hi friend.os see you; bye"]),
+                (Sequence::Always, vec!["top"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn redirects() {
+        let pipelines = parse("peers | echo > /a:b/c && top >> /a:b/d").unwrap();
+        let redirects: Vec<_> = pipelines
+            .iter()
+            .map(|(_, p)| p.redirect.as_ref().map(|r| (r.path.as_str(), r.append)))
+            .collect();
+        assert_eq!(redirects, [Some(("/a:b/c", false)), Some(("/a:b/d", true))]);
+
+        assert!(parse("peers > /a:b/c d").is_err());
+        assert!(parse("peers > /a:b/c > /a:b/d").is_err());
+        assert!(parse("peers > /a:b/c | echo").is_err());
+    }
+
+    #[test]
+    fn only_a_script_that_says_so_fails() {
+        assert!(succeeded(None));
+        assert!(succeeded(Some("")));
+        assert!(!succeeded(Some(FAILED_METADATA)));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert!(parse("| peers").is_err());
+        assert!(parse("; peers").is_err());
+        assert!(parse("> /a:b/c").is_err());
+        assert!(parse("echo 'a ; top").is_err());
+    }
+}
@@ -3,6 +3,7 @@ use hyperware_process_lib::kernel_types::{
     KernelCommand, KernelPrint, KernelPrintResponse, KernelResponse, PersistedProcess,
 };
 use hyperware_process_lib::{script, Address, Message, ProcessId, Request};
+use std::collections::HashMap;
 
 wit_bindgen::generate!({
     path: "../target/wit",
@@ -58,16 +59,25 @@ fn init(_our: Address, args: String) -> String {
     else {
         return "Failed to parse kernel response".to_string();
     };
+    let fds_limits = get_fds_limits();
 
     match kernel_print_response {
         KernelPrintResponse::ProcessMap(process_map) => {
             let len = process_map.len();
             let printout = process_map
                 .iter()
-                .map(|(proc_id, process)| print_process(proc_id, process, show_caps))
+                .map(|(proc_id, process)| {
+                    print_process(proc_id, process, fds_limits.get(proc_id), show_caps)
+                })
                 .collect::<Vec<_>>()
                 .join("\r\n");
-            format!("\r\n{printout}\r\n\r\ntop: {len} running processes")
+            let (open, limit) = fds_limits.values().fold((0, 0), |(open, limit), fds| {
+                (
+                    open + fds_field(fds, "open").unwrap_or_default(),
+                    limit + fds_field(fds, "limit").unwrap_or_default(),
+                )
+            });
+            format!("\r\n{printout}\r\n\r\ntop: {len} running processes, {open} fds open of {limit} allocated")
         }
         KernelPrintResponse::Process(process) => match process {
             None => {
@@ -79,10 +89,8 @@ fn init(_our: Address, args: String) -> String {
                 )
             }
             Some(process) => {
-                format!(
-                    "{}",
-                    print_process(&target.unwrap().unwrap(), &process, show_caps)
-                )
+                let target = target.unwrap().unwrap();
+                print_process(&target, &process, fds_limits.get(&target), show_caps)
             }
         },
         KernelPrintResponse::HasCap(_) => {
@@ -91,9 +99,58 @@ fn init(_our: Address, args: String) -> String {
     }
 }
 
-fn print_process(id: &ProcessId, process: &PersistedProcess, show_caps: bool) -> String {
+/// fd limits and usage of the runtime modules that open files, sockets or databases,
+/// as the `FdsLimit`s fd-manager serializes, read by field so they needn't be mirrored
+fn get_fds_limits() -> HashMap<ProcessId, serde_json::Value> {
+    let Ok(Ok(Message::Response { body, .. })) =
+        Request::to(("our", "fd-manager", "distro", "sys"))
+            .body(serde_json::to_vec(&serde_json::json!("GetState")).unwrap())
+            .send_and_await_response(5)
+    else {
+        return HashMap::new();
+    };
+    let Ok(serde_json::Value::Object(mut response)) = serde_json::from_slice(&body) else {
+        return HashMap::new();
+    };
+    response
+        .remove("GetState")
+        .and_then(|fds_limits| serde_json::from_value(fds_limits).ok())
+        .unwrap_or_default()
+}
+
+fn fds_field(fds: &serde_json::Value, field: &str) -> Option<u64> {
+    fds.get(field).and_then(|value| value.as_u64())
+}
+
+fn print_fds(fds: &serde_json::Value) -> String {
+    let budget = fds.get("budget").cloned().unwrap_or_default();
+    let pin = match (fds_field(&budget, "min"), fds_field(&budget, "max")) {
+        (None, None) => String::new(),
+        (min, max) => format!(
+            ", pinned to {}..{}",
+            min.map(|min| min.to_string()).unwrap_or_default(),
+            max.map(|max| max.to_string()).unwrap_or_default()
+        ),
+    };
+    format!(
+        "\r\n    fds: {} open of {} allocated, limit hit {} times recently, weight {}{pin}",
+        fds_field(fds, "open").unwrap_or_default(),
+        fds_field(fds, "limit").unwrap_or_default(),
+        fds_field(fds, "hit_count")
+            .unwrap_or_default()
+            .saturating_sub(1),
+        fds_field(&budget, "weight").unwrap_or(1)
+    )
+}
+
+fn print_process(
+    id: &ProcessId,
+    process: &PersistedProcess,
+    fds: Option<&serde_json::Value>,
+    show_caps: bool,
+) -> String {
     format!(
-        "{}:\r\n    {}\r\n    wit: {}\r\n    on-exit: {:?}\r\n    public: {}\r\n    capabilities:\r\n        {}",
+        "{}:\r\n    {}\r\n    wit: {}\r\n    on-exit: {:?}\r\n    public: {}{}\r\n    capabilities:\r\n        {}",
         id,
         if process.wasm_bytes_handle.is_empty() {
             "(runtime)"
@@ -103,6 +160,7 @@ fn print_process(id: &ProcessId, process: &PersistedProcess, show_caps: bool) ->
         process.wit_version.unwrap_or_default(),
         process.on_exit,
         process.public,
+        fds.map(print_fds).unwrap_or_default(),
         if show_caps {
             process
                 .capabilities
@@ -0,0 +1,93 @@
+use clap::{Arg, Command};
+use hyperware_process_lib::{script, Address, Message, ProcessId, Request};
+use serde::{Deserialize, Serialize};
+
+wit_bindgen::generate!({
+    path: "../target/wit",
+    world: "process-v1",
+});
+
+const USAGE: &str = "\x1b[1mUsage:\x1b[0m
+    \ntrace start [<process-id>] <- record traced messages, starting a trace at each request from <process-id>
+    \ntrace stop [<process-id>] <- stop starting traces at <process-id>, or stop recording
+    \ntrace export <- write recorded spans to an OTLP JSON file";
+
+/// Mirrors the tracing variants of `KernelCommand` in the runtime; keep the two in sync.
+#[derive(Serialize)]
+enum KernelCommand {
+    StartTracing(Option<ProcessId>),
+    StopTracing(Option<ProcessId>),
+    ExportTraces,
+}
+
+#[derive(Deserialize)]
+enum KernelResponse {
+    Tracing {
+        recording: bool,
+        roots: Vec<ProcessId>,
+    },
+    ExportedTraces {
+        path: String,
+        spans: usize,
+    },
+    ExportTracesError(String),
+}
+
+script!(init);
+fn init(_our: Address, args: String) -> String {
+    let body_string = format!("trace {args}");
+
+    let Ok(parsed) = Command::new("trace")
+        .disable_help_flag(true)
+        .arg(Arg::new("action").index(1).required(true))
+        .arg(Arg::new("process").index(2))
+        .try_get_matches_from(body_string.split_whitespace())
+    else {
+        return format!("Failed to parse args.\n{USAGE}");
+    };
+
+    let process = match parsed.get_one::<String>("process") {
+        None => None,
+        Some(process) => match process.parse::<ProcessId>() {
+            Ok(process) => Some(process),
+            Err(e) => return format!("invalid process id: {e}\n{USAGE}"),
+        },
+    };
+    let command = match parsed.get_one::<String>("action").unwrap().as_str() {
+        "start" => KernelCommand::StartTracing(process),
+        "stop" => KernelCommand::StopTracing(process),
+        "export" if process.is_none() => KernelCommand::ExportTraces,
+        _ => return USAGE.to_string(),
+    };
+
+    let Ok(Ok(Message::Response { body, .. })) = Request::to(("our", "kernel", "distro", "sys"))
+        .body(serde_json::to_vec(&command).unwrap())
+        .send_and_await_response(60)
+    else {
+        return "Failed to get response from kernel".to_string();
+    };
+    let Ok(response) = serde_json::from_slice::<KernelResponse>(&body) else {
+        return "Failed to parse kernel response".to_string();
+    };
+
+    match response {
+        KernelResponse::Tracing {
+            recording: false, ..
+        } => "trace: not recording".to_string(),
+        KernelResponse::Tracing { roots, .. } if roots.is_empty() => {
+            "trace: recording traced messages; no processes start traces".to_string()
+        }
+        KernelResponse::Tracing { roots, .. } => format!(
+            "trace: recording; traces start at {}",
+            roots
+                .iter()
+                .map(|root| root.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        KernelResponse::ExportedTraces { path, spans } => {
+            format!("trace: wrote {spans} spans to {path}")
+        }
+        KernelResponse::ExportTracesError(e) => format!("trace: failed to export: {e}"),
+    }
+}
@@ -0,0 +1,418 @@
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use indexmap::IndexMap;
+use lib::types::eth::{CacheStats, EthResponse};
+use rocksdb::{DBCompactionStyle, FifoCompactOptions, Options, DB};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, Mutex};
+
+use super::DELAY_MS;
+
+const MAX_RECENT_CACHE_LEN: usize = 500;
+/// a block this many blocks behind the highest block we have seen on a chain
+/// is treated as final, and queries pinned to it are cached on disk
+const FINALITY_DEPTH: u64 = 64;
+/// responses larger than this are never written to disk
+const MAX_DURABLE_RESPONSE_BYTES: usize = 8 * 1024 * 1024;
+/// past this size on disk, the oldest responses are dropped
+const MAX_DURABLE_CACHE_BYTES: u64 = 1024 * 1024 * 1024;
+/// heads are kept alongside responses, whose keys are JSON and so never start with this
+const HEAD_KEY_PREFIX: &[u8] = b"head:";
+
+/// Response cache for eth:distro:sys.
+///
+/// Three layers, checked in order:
+/// 1. a short-lived in-memory cache of any successful response,
+/// 2. a durable on-disk cache of responses to immutable queries
+///    (see [`ResponseCache::is_immutable`]), which survives restarts and is
+///    bounded by [`MAX_DURABLE_CACHE_BYTES`], oldest entries dropped first,
+/// 3. coalescing of identical requests that are in flight at the same time.
+///
+/// Keys are the serialized [`lib::types::eth::EthAction::Request`].
+pub struct ResponseCache {
+    recent: Mutex<IndexMap<Vec<u8>, (EthResponse, Instant)>>,
+    /// None if the on-disk cache could not be opened; we then run memory-only
+    db: Option<DB>,
+    in_flight: DashMap<Vec<u8>, broadcast::Sender<EthResponse>>,
+    /// highest block number observed per chain id from our url providers, persisted
+    /// so that finality is known after a restart before any new head is seen
+    heads: DashMap<u64, u64>,
+    memory_hits: AtomicU64,
+    disk_hits: AtomicU64,
+    coalesced: AtomicU64,
+    misses: AtomicU64,
+}
+
+/// Held by the one task actually making a request; identical requests wait on it.
+/// If dropped without [`InFlightGuard::finish`] (e.g. on timeout), waiters are
+/// released and make the request themselves.
+pub struct InFlightGuard {
+    cache: Arc<ResponseCache>,
+    key: Vec<u8>,
+    finished: bool,
+}
+
+impl InFlightGuard {
+    pub fn finish(mut self, response: &EthResponse) {
+        self.finished = true;
+        if let Some((_, sender)) = self.cache.in_flight.remove(&self.key) {
+            let _ = sender.send(response.clone());
+        }
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.cache.in_flight.remove(&self.key);
+        }
+    }
+}
+
+impl ResponseCache {
+    pub fn new(home_directory_path: &Path) -> Self {
+        // use String to not upset rocksdb, see state.rs
+        let db_path = format!("{}/eth_cache", home_directory_path.display());
+        let mut fifo = FifoCompactOptions::default();
+        fifo.set_max_table_files_size(MAX_DURABLE_CACHE_BYTES);
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.set_compaction_style(DBCompactionStyle::Fifo);
+        opts.set_fifo_compaction_options(&fifo);
+        let db = DB::open(&opts, db_path).ok();
+        let heads = DashMap::new();
+        if let Some(db) = &db {
+            let prefix = rocksdb::IteratorMode::From(HEAD_KEY_PREFIX, rocksdb::Direction::Forward);
+            for (key, value) in db.iterator(prefix).filter_map(Result::ok) {
+                let Some(chain_id) = key.strip_prefix(HEAD_KEY_PREFIX) else {
+                    break;
+                };
+                if let (Ok(chain_id), Ok(head)) = (chain_id.try_into(), (*value).try_into()) {
+                    heads.insert(u64::from_be_bytes(chain_id), u64::from_be_bytes(head));
+                }
+            }
+        }
+        Self {
+            recent: Mutex::new(IndexMap::new()),
+            db,
+            in_flight: DashMap::new(),
+            heads,
+            memory_hits: AtomicU64::new(0),
+            disk_hits: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// check the in-memory cache, then the on-disk cache if the query is immutable
+    pub async fn get(
+        &self,
+        key: &[u8],
+        chain_id: u64,
+        method: &str,
+        params: &serde_json::Value,
+    ) -> Option<EthResponse> {
+        {
+            let mut recent = self.recent.lock().await;
+            if let Some((cache_hit, time_of_hit)) = recent.shift_remove(key) {
+                // refresh cache entry (it is most recently accessed) & return it
+                if time_of_hit.elapsed() < Duration::from_millis(DELAY_MS) {
+                    recent.insert(key.to_vec(), (cache_hit.clone(), time_of_hit));
+                    self.memory_hits.fetch_add(1, Ordering::Relaxed);
+                    return Some(cache_hit);
+                }
+            }
+        }
+        if !self.is_immutable(chain_id, method, params) {
+            return None;
+        }
+        let bytes = self.db.as_ref()?.get(key).ok()??;
+        let response = serde_json::from_slice::<EthResponse>(&bytes).ok()?;
+        self.disk_hits.fetch_add(1, Ordering::Relaxed);
+        Some(response)
+    }
+
+    /// Either become the task that makes this request, or get a receiver for
+    /// the response of the identical request already in flight.
+    pub fn join_in_flight(
+        self: &Arc<Self>,
+        key: &[u8],
+    ) -> Result<InFlightGuard, broadcast::Receiver<EthResponse>> {
+        match self.in_flight.entry(key.to_vec()) {
+            Entry::Occupied(entry) => {
+                self.coalesced.fetch_add(1, Ordering::Relaxed);
+                Err(entry.get().subscribe())
+            }
+            Entry::Vacant(entry) => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                let (sender, _) = broadcast::channel(1);
+                entry.insert(sender);
+                Ok(InFlightGuard {
+                    cache: self.clone(),
+                    key: key.to_vec(),
+                    finished: false,
+                })
+            }
+        }
+    }
+
+    /// store a successful response; errors and empty results are never cached
+    pub async fn insert(
+        &self,
+        key: Vec<u8>,
+        chain_id: u64,
+        method: &str,
+        params: &serde_json::Value,
+        response: &EthResponse,
+    ) {
+        let EthResponse::Response(ref value) = response else {
+            return;
+        };
+        if value.is_null() {
+            return;
+        }
+        if self.is_immutable(chain_id, method, params) {
+            if let Some(db) = &self.db {
+                if let Ok(bytes) = serde_json::to_vec(response) {
+                    if bytes.len() <= MAX_DURABLE_RESPONSE_BYTES {
+                        let _ = db.put(&key, bytes);
+                    }
+                }
+            }
+        }
+        let mut recent = self.recent.lock().await;
+        if recent.len() >= MAX_RECENT_CACHE_LEN {
+            // drop 10% oldest cache entries
+            recent.drain(0..MAX_RECENT_CACHE_LEN / 10);
+        }
+        recent.insert(key, (response.clone(), Instant::now()));
+    }
+
+    /// drop all cached responses and the known heads
+    pub async fn clear(&self) {
+        self.recent.lock().await.clear();
+        self.heads.clear();
+        if let Some(db) = &self.db {
+            let keys: Vec<Box<[u8]>> = db
+                .iterator(rocksdb::IteratorMode::Start)
+                .filter_map(|item| item.ok().map(|(key, _)| key))
+                .collect();
+            for key in keys {
+                let _ = db.delete(key);
+            }
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            memory_hits: self.memory_hits.load(Ordering::Relaxed),
+            disk_hits: self.disk_hits.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            disk_entries: self
+                .db
+                .as_ref()
+                .and_then(|db| db.property_int_value("rocksdb.estimate-num-keys").ok())
+                .flatten()
+                .unwrap_or(0),
+        }
+    }
+
+    /// Track the chain head from a response that reveals it. Only call this with
+    /// responses of our own url providers: a head claimed too far ahead would make
+    /// recent blocks look final, and their responses would be cached for good.
+    pub fn observe_head(
+        &self,
+        chain_id: u64,
+        method: &str,
+        params: &serde_json::Value,
+        value: &serde_json::Value,
+    ) {
+        let head = match method {
+            "eth_blockNumber" => explicit_block_number(value),
+            "eth_getBlockByNumber" if params.get(0).and_then(|p| p.as_str()) == Some("latest") => {
+                value.get("number").and_then(explicit_block_number)
+            }
+            _ => None,
+        };
+        let Some(head) = head else {
+            return;
+        };
+        let advanced = match self.heads.entry(chain_id) {
+            Entry::Occupied(mut entry) if *entry.get() < head => {
+                entry.insert(head);
+                true
+            }
+            Entry::Occupied(_) => false,
+            Entry::Vacant(entry) => {
+                entry.insert(head);
+                true
+            }
+        };
+        if advanced {
+            if let Some(db) = &self.db {
+                let key = [HEAD_KEY_PREFIX, &chain_id.to_be_bytes()].concat();
+                let _ = db.put(key, head.to_be_bytes());
+            }
+        }
+    }
+
+    fn is_final(&self, chain_id: u64, block: u64) -> bool {
+        self.heads
+            .get(&chain_id)
+            .map(|head| block.saturating_add(FINALITY_DEPTH) <= *head)
+            .unwrap_or(false)
+    }
+
+    /// Whether a query's result can never change: it is pinned to a block hash,
+    /// or to a block number deep enough to be final.
+    fn is_immutable(&self, chain_id: u64, method: &str, params: &serde_json::Value) -> bool {
+        match method {
+            "eth_chainId" | "eth_getBlockByHash" => true,
+            "eth_getBlockByNumber" => params
+                .get(0)
+                .and_then(explicit_block_number)
+                .map(|block| self.is_final(chain_id, block))
+                .unwrap_or(false),
+            "eth_call"
+            | "eth_getBalance"
+            | "eth_getCode"
+            | "eth_getStorageAt"
+            | "eth_getTransactionCount" => {
+                // block parameter is always last
+                let Some(block) = params.as_array().and_then(|p| p.last()) else {
+                    return false;
+                };
+                if block.get("blockHash").is_some() {
+                    return true;
+                }
+                block
+                    .get("blockNumber")
+                    .and_then(explicit_block_number)
+                    .or_else(|| explicit_block_number(block))
+                    .map(|block| self.is_final(chain_id, block))
+                    .unwrap_or(false)
+            }
+            "eth_getLogs" => {
+                let Some(filter) = params.get(0) else {
+                    return false;
+                };
+                if filter.get("blockHash").is_some() {
+                    return true;
+                }
+                filter
+                    .get("fromBlock")
+                    .and_then(explicit_block_number)
+                    .is_some()
+                    && filter
+                        .get("toBlock")
+                        .and_then(explicit_block_number)
+                        .map(|block| self.is_final(chain_id, block))
+                        .unwrap_or(false)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// a block number given as a hex string or integer, but not a tag like "latest"
+fn explicit_block_number(value: &serde_json::Value) -> Option<u64> {
+    match value {
+        serde_json::Value::String(s) => u64::from_str_radix(s.strip_prefix("0x")?, 16).ok(),
+        serde_json::Value::Number(n) => n.as_u64(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(block: u64) -> serde_json::Value {
+        serde_json::json!(format!("{block:#x}"))
+    }
+
+    fn block_number(head: u64) -> EthResponse {
+        EthResponse::Response(hex(head))
+    }
+
+    #[tokio::test]
+    async fn only_final_blocks_are_immutable() {
+        let home = tempfile::tempdir().unwrap();
+        let cache = ResponseCache::new(home.path());
+        let params = serde_json::json!(["0x10", false]);
+        assert!(!cache.is_immutable(1, "eth_getBlockByNumber", &params));
+        cache.observe_head(
+            1,
+            "eth_blockNumber",
+            &serde_json::json!([]),
+            &hex(0x10 + FINALITY_DEPTH),
+        );
+        assert!(cache.is_immutable(1, "eth_getBlockByNumber", &params));
+        assert!(!cache.is_immutable(2, "eth_getBlockByNumber", &params));
+        assert!(!cache.is_immutable(
+            1,
+            "eth_getBlockByNumber",
+            &serde_json::json!(["latest", false])
+        ));
+        assert!(cache.is_immutable(1, "eth_getBlockByHash", &serde_json::json!(["0xab", false])));
+    }
+
+    #[tokio::test]
+    async fn heads_survive_a_restart_but_not_a_clear() {
+        let home = tempfile::tempdir().unwrap();
+        let params = serde_json::json!([{ "fromBlock": "0x1", "toBlock": "0x2" }]);
+        {
+            let cache = ResponseCache::new(home.path());
+            cache.observe_head(1, "eth_blockNumber", &serde_json::json!([]), &hex(1_000));
+            // a lower head never replaces a higher one
+            cache.observe_head(1, "eth_blockNumber", &serde_json::json!([]), &hex(1));
+            // nor does a response that only passes through the cache
+            cache
+                .insert(
+                    vec![0],
+                    2,
+                    "eth_blockNumber",
+                    &serde_json::json!([]),
+                    &block_number(1_000),
+                )
+                .await;
+        }
+        let cache = ResponseCache::new(home.path());
+        assert_eq!(cache.heads.get(&1).map(|head| *head), Some(1_000));
+        assert_eq!(cache.heads.get(&2).map(|head| *head), None);
+        assert!(cache.is_immutable(1, "eth_getLogs", &params));
+        cache.clear().await;
+        drop(cache);
+        let cache = ResponseCache::new(home.path());
+        assert!(cache.heads.is_empty());
+        assert!(!cache.is_immutable(1, "eth_getLogs", &params));
+    }
+
+    #[tokio::test]
+    async fn immutable_responses_are_read_back_from_disk() {
+        let home = tempfile::tempdir().unwrap();
+        let params = serde_json::json!(["0xab", false]);
+        let response = EthResponse::Response(serde_json::json!({ "number": "0x1" }));
+        {
+            let cache = ResponseCache::new(home.path());
+            cache
+                .insert(b"key".to_vec(), 1, "eth_getBlockByHash", &params, &response)
+                .await;
+        }
+        let cache = ResponseCache::new(home.path());
+        assert!(matches!(
+            cache.get(b"key", 1, "eth_getBlockByHash", &params).await,
+            Some(EthResponse::Response(value)) if value["number"] == "0x1"
+        ));
+        assert_eq!(cache.stats().disk_hits, 1);
+        cache.clear().await;
+        assert!(cache
+            .get(b"key", 1, "eth_getBlockByHash", &params)
+            .await
+            .is_none());
+    }
+}
@@ -4,7 +4,6 @@ use alloy::rpc::json_rpc::RpcError;
 use anyhow::Result;
 use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
-use indexmap::IndexMap;
 use lib::types::core::*;
 use lib::types::eth::*;
 use serde::{Deserialize, Serialize};
@@ -12,15 +11,15 @@ use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
 use tokio::task::JoinHandle;
 use utils::*;
 
+mod cache;
+mod quotas;
 mod subscription;
 mod utils;
 
 const DELAY_MS: u64 = 1_000;
-const MAX_REQUEST_CACHE_LEN: usize = 500;
 const REMOTE_QUEUE_CAPACITY: usize = 100;
 const DELAYED_QUEUE_CAPACITY: usize = 1_000;
 const REMOTE_QUEUE_IDLE_TIMEOUT_SECS: u64 = 5;
@@ -47,7 +46,7 @@ type ActiveSubscriptions = Arc<DashMap<Address, HashMap<u64, ActiveSub>>>;
 type ResponseChannels = Arc<DashMap<u64, ProcessMessageSender>>;
 type LocalToRemoteSubs = Arc<DashMap<Address, HashMap<u64, u64>>>;
 type PerNodeQueues = Arc<DashMap<String, tokio::sync::mpsc::Sender<KernelMessage>>>;
-type RequestCache = Arc<Mutex<IndexMap<Vec<u8>, (EthResponse, Instant)>>>;
+type RequestCache = Arc<cache::ResponseCache>;
 
 #[derive(Debug)]
 struct ActiveProviders {
@@ -97,6 +96,10 @@ struct ModuleState {
     home_directory_path: PathBuf,
     /// the access settings for this provider
     access_settings: AccessSettings,
+    /// RPC quotas for local processes and remote nodes
+    quotas: RpcQuotas,
+    /// per-address request and subscription counts
+    usage: quotas::UsageTracker,
     /// the set of providers we have available for all chains
     providers: Providers,
     /// the set of active subscriptions we are currently maintaining
@@ -111,7 +114,7 @@ struct ModuleState {
     send_to_loop: MessageSender,
     /// our sender for terminal prints
     print_tx: PrintSender,
-    /// cache of ETH requests
+    /// cache of ETH request responses, in memory and on disk
     request_cache: RequestCache,
 }
 
@@ -143,8 +146,11 @@ fn spawn_node_worker(
     tokio::spawn(async move {
         let mut next_allowed = Instant::now();
         loop {
-            match tokio::time::timeout(Duration::from_secs(REMOTE_QUEUE_IDLE_TIMEOUT_SECS), rx.recv())
-                .await
+            match tokio::time::timeout(
+                Duration::from_secs(REMOTE_QUEUE_IDLE_TIMEOUT_SECS),
+                rx.recv(),
+            )
+            .await
             {
                 Ok(Some(km)) => {
                     let now = Instant::now();
@@ -464,14 +470,22 @@ pub async fn provider(
         &format!("eth: access settings loaded: {access_settings:?}"),
     )
     .await;
+    let quotas: RpcQuotas =
+        match tokio::fs::read_to_string(home_directory_path.join(".eth_quotas")).await {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+            Err(_) => RpcQuotas::default(),
+        };
 
     // initialize module state
     // fill out providers based on saved configs (possibly persisted, given to us)
     // this can be a mix of node providers and rpc providers
+    let request_cache = Arc::new(cache::ResponseCache::new(&home_directory_path));
     let mut state = ModuleState {
+        usage: quotas::UsageTracker::new(&our),
         our: Arc::new(our),
         home_directory_path,
         access_settings,
+        quotas,
         providers: Arc::new(DashMap::new()),
         active_subscriptions: Arc::new(DashMap::new()),
         local_to_remote_subs: Arc::new(DashMap::new()),
@@ -479,7 +493,7 @@ pub async fn provider(
         response_channels: Arc::new(DashMap::new()),
         send_to_loop,
         print_tx,
-        request_cache: Arc::new(Mutex::new(IndexMap::new())),
+        request_cache,
     };
 
     // convert saved configs into data structure that we will use to route queries
@@ -496,9 +510,20 @@ pub async fn provider(
 
     verbose_print(&state.print_tx, "eth: provider initialized").await;
 
+    let mut prune_usage_interval = tokio::time::interval(quotas::QUOTA_WINDOW);
+
     // main loop: handle incoming network errors and incoming kernel messages
     loop {
         tokio::select! {
+            _ = prune_usage_interval.tick() => {
+                let active_subscriptions = &state.active_subscriptions;
+                state.usage.prune(|address| {
+                    active_subscriptions
+                        .get(address)
+                        .map(|subs| subs.len() as u64)
+                        .unwrap_or(0)
+                });
+            }
             Some(wrapped_error) = net_error_recv.recv() => {
                 handle_network_error(
                     wrapped_error,
@@ -827,6 +852,29 @@ async fn handle_eth_action(
         }
     }
 
+    // count the action against the source's quota, if it has one
+    let is_subscription = match &eth_action {
+        EthAction::SubscribeLogs { .. } => Some(true),
+        EthAction::Request { .. } => Some(false),
+        EthAction::UnsubscribeLogs(_) => None,
+    };
+    if let Some(is_subscription) = is_subscription {
+        let open_subscriptions = open_subscriptions(state, &km.source);
+        if !state.usage.charge(
+            &km.source,
+            &state.quotas,
+            is_subscription,
+            open_subscriptions,
+        ) {
+            verbose_print(
+                &state.print_tx,
+                &format!("eth: {} exceeded its RPC quota", km.source),
+            )
+            .await;
+            return Err(EthError::RateLimited);
+        }
+    }
+
     verbose_print(
         &state.print_tx,
         &format!(
@@ -1000,7 +1048,7 @@ async fn handle_eth_action(
             let providers = state.providers.clone();
             let response_channels = state.response_channels.clone();
             let print_tx = state.print_tx.clone();
-            let mut request_cache = Arc::clone(&state.request_cache);
+            let request_cache = Arc::clone(&state.request_cache);
             tokio::spawn(async move {
                 match tokio::time::timeout(
                     std::time::Duration::from_secs(timeout),
@@ -1013,7 +1061,7 @@ async fn handle_eth_action(
                         &mut receiver,
                         &response_channels,
                         &print_tx,
-                        &mut request_cache,
+                        &request_cache,
                     ),
                 )
                 .await
@@ -1033,7 +1081,7 @@ async fn handle_eth_action(
                                     &mut receiver,
                                     &response_channels,
                                     &print_tx,
-                                    &mut request_cache,
+                                    &request_cache,
                                 ),
                             )
                             .await
@@ -1090,6 +1138,27 @@ async fn handle_eth_action(
     Ok(())
 }
 
+/// Number of subscriptions held against the quota of `source`: its own for a
+/// local process, or those of every address on its node for a remote node.
+fn open_subscriptions(state: &ModuleState, source: &Address) -> u64 {
+    if source.node == *state.our {
+        return state
+            .active_subscriptions
+            .get(source)
+            .map(|subs| subs.len() as u64)
+            .unwrap_or(0);
+    }
+    state
+        .active_subscriptions
+        .iter()
+        .filter(|entry| entry.key().node == source.node)
+        .map(|entry| entry.value().len() as u64)
+        .sum()
+}
+
+/// Serve a request from the response cache if possible; otherwise, make it
+/// (once, even if identical requests arrive while it is in flight) and cache
+/// the response.
 async fn fulfill_request(
     our: &str,
     km_id: u64,
@@ -1099,7 +1168,7 @@ async fn fulfill_request(
     remote_request_receiver: &mut ProcessMessageReceiver,
     response_channels: &ResponseChannels,
     print_tx: &PrintSender,
-    request_cache: &mut RequestCache,
+    request_cache: &RequestCache,
 ) -> EthResponse {
     let serialized_action = serde_json::to_vec(eth_action).unwrap();
     let EthAction::Request {
@@ -1110,16 +1179,62 @@ async fn fulfill_request(
     else {
         return EthResponse::Err(EthError::PermissionDenied); // will never hit
     };
+    if let Some(cache_hit) = request_cache
+        .get(&serialized_action, *chain_id, method, params)
+        .await
     {
-        let mut request_cache = request_cache.lock().await;
-        if let Some((cache_hit, time_of_hit)) = request_cache.shift_remove(&serialized_action) {
-            // refresh cache entry (it is most recently accessed) & return it
-            if time_of_hit.elapsed() < Duration::from_millis(DELAY_MS) {
-                request_cache.insert(serialized_action, (cache_hit.clone(), time_of_hit));
-                return cache_hit;
+        return cache_hit;
+    }
+    let in_flight = match request_cache.join_in_flight(&serialized_action) {
+        Ok(guard) => Some(guard),
+        Err(mut receiver) => {
+            if let Ok(response) = receiver.recv().await {
+                return response;
             }
+            // the identical request we waited on was abandoned: make it ourselves
+            None
         }
+    };
+    let response = fulfill_request_from_providers(
+        our,
+        km_id,
+        send_to_loop,
+        eth_action,
+        providers,
+        remote_request_receiver,
+        response_channels,
+        print_tx,
+        request_cache,
+    )
+    .await;
+    request_cache
+        .insert(serialized_action, *chain_id, method, params, &response)
+        .await;
+    if let Some(guard) = in_flight {
+        guard.finish(&response);
     }
+    response
+}
+
+async fn fulfill_request_from_providers(
+    our: &str,
+    km_id: u64,
+    send_to_loop: &MessageSender,
+    eth_action: &EthAction,
+    providers: &Providers,
+    remote_request_receiver: &mut ProcessMessageReceiver,
+    response_channels: &ResponseChannels,
+    print_tx: &PrintSender,
+    request_cache: &RequestCache,
+) -> EthResponse {
+    let EthAction::Request {
+        ref chain_id,
+        ref method,
+        ref params,
+    } = eth_action
+    else {
+        return EthResponse::Err(EthError::PermissionDenied); // will never hit
+    };
     let Some(method) = valid_method(&method) else {
         return EthResponse::Err(EthError::InvalidMethod(method.to_string()));
     };
@@ -1202,14 +1317,9 @@ async fn fulfill_request(
                     }
                 });
 
-                let response = EthResponse::Response(value);
-                let mut request_cache = request_cache.lock().await;
-                if request_cache.len() >= MAX_REQUEST_CACHE_LEN {
-                    // drop 10% oldest cache entries
-                    request_cache.drain(0..MAX_REQUEST_CACHE_LEN / 10);
-                }
-                request_cache.insert(serialized_action, (response.clone(), Instant::now()));
-                return response;
+                // node providers aren't trusted to tell us the chain head
+                request_cache.observe_head(*chain_id, method, params, &value);
+                return EthResponse::Response(value);
             }
             Err(rpc_error) => {
                 verbose_print(
@@ -1581,6 +1691,7 @@ async fn handle_eth_config_action(
 
     let mut save_settings = false;
     let mut save_providers = false;
+    let mut save_quotas = false;
     let mut provider_not_found = false;
 
     // modify our providers and access settings based on config action
@@ -1675,8 +1786,43 @@ async fn handle_eth_config_action(
                     })
                     .collect(),
                 outstanding_requests: state.response_channels.iter().map(|e| *e.key()).collect(),
+                cache_stats: state.request_cache.stats(),
+                usage: state.usage.snapshot(|address| {
+                    state
+                        .active_subscriptions
+                        .get(address)
+                        .map(|subs| subs.len() as u64)
+                        .unwrap_or(0)
+                }),
             };
         }
+        EthConfigAction::ClearCache => {
+            state.request_cache.clear().await;
+            verbose_print(&state.print_tx, "eth: cleared response cache").await;
+        }
+        EthConfigAction::SetProcessQuota((process, quota)) => {
+            state.quotas.processes.insert(process, quota);
+            save_quotas = true;
+        }
+        EthConfigAction::RemoveProcessQuota(process) => {
+            state.quotas.processes.remove(&process);
+            save_quotas = true;
+        }
+        EthConfigAction::SetNodeQuota((node, quota)) => {
+            state.quotas.nodes.insert(node, quota);
+            save_quotas = true;
+        }
+        EthConfigAction::RemoveNodeQuota(node) => {
+            state.quotas.nodes.remove(&node);
+            save_quotas = true;
+        }
+        EthConfigAction::SetDefaultNodeQuota(quota) => {
+            state.quotas.default_node = quota;
+            save_quotas = true;
+        }
+        EthConfigAction::GetQuotas => {
+            return EthConfigResponse::Quotas(state.quotas.clone());
+        }
     }
     // save providers and/or access settings, depending on necessity, to disk
     if save_settings {
@@ -1689,6 +1835,16 @@ async fn handle_eth_config_action(
             verbose_print(&state.print_tx, "eth: saved new access settings").await;
         };
     }
+    if save_quotas {
+        if let Ok(()) = tokio::fs::write(
+            state.home_directory_path.join(".eth_quotas"),
+            serde_json::to_string(&state.quotas).unwrap(),
+        )
+        .await
+        {
+            verbose_print(&state.print_tx, "eth: saved new quotas").await;
+        };
+    }
     if save_providers {
         let saved_configs = providers_to_saved_configs(&state.providers);
 
@@ -0,0 +1,210 @@
+use lib::types::core::Address;
+use lib::types::eth::{RpcQuota, RpcQuotas, RpcUsage};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+pub const QUOTA_WINDOW: Duration = Duration::from_secs(60);
+
+/// a fixed one-minute request counter
+#[derive(Default)]
+struct Window {
+    start: Option<Instant>,
+    count: u64,
+}
+
+impl Window {
+    fn expired(&self, now: Instant) -> bool {
+        self.start
+            .is_none_or(|start| now.duration_since(start) >= QUOTA_WINDOW)
+    }
+
+    /// the count for the window containing `now`, starting a new window if needed
+    fn current(&mut self, now: Instant) -> &mut u64 {
+        if self.expired(now) {
+            self.start = Some(now);
+            self.count = 0;
+        }
+        &mut self.count
+    }
+}
+
+/// Counts requests and subscriptions per [`Address`] and enforces [`RpcQuotas`].
+/// Local processes are limited individually; remote nodes are limited across
+/// all of their processes.
+pub struct UsageTracker {
+    our: String,
+    usage: HashMap<Address, (RpcUsage, Window)>,
+    /// request windows of remote nodes, summed over all their addresses
+    node_windows: HashMap<String, Window>,
+}
+
+impl UsageTracker {
+    pub fn new(our: &str) -> Self {
+        Self {
+            our: our.to_string(),
+            usage: HashMap::new(),
+            node_windows: HashMap::new(),
+        }
+    }
+
+    fn quota_for<'a>(&self, source: &Address, quotas: &'a RpcQuotas) -> Option<&'a RpcQuota> {
+        if source.node == self.our {
+            quotas.processes.get(&source.process)
+        } else {
+            quotas
+                .nodes
+                .get(&source.node)
+                .or(quotas.default_node.as_ref())
+        }
+    }
+
+    /// Record a request (or a new subscription, if `is_subscription`) from `source`.
+    /// Returns false, and records nothing but the refusal, if it would exceed a quota.
+    /// `open_subscriptions` is the number of subscriptions the quota holder already has.
+    pub fn charge(
+        &mut self,
+        source: &Address,
+        quotas: &RpcQuotas,
+        is_subscription: bool,
+        open_subscriptions: u64,
+    ) -> bool {
+        let quota = self.quota_for(source, quotas);
+        let remote = source.node != self.our;
+        let now = Instant::now();
+        let (usage, window) = self.usage.entry(source.clone()).or_default();
+
+        let allowed = match quota {
+            None => true,
+            Some(quota) if is_subscription => quota
+                .max_subscriptions
+                .is_none_or(|max| open_subscriptions < max),
+            Some(quota) => {
+                let used = if remote {
+                    *self
+                        .node_windows
+                        .entry(source.node.clone())
+                        .or_default()
+                        .current(now)
+                } else {
+                    *window.current(now)
+                };
+                quota.requests_per_minute.is_none_or(|max| used < max)
+            }
+        };
+        if !allowed {
+            usage.rate_limited += 1;
+            return false;
+        }
+
+        if is_subscription {
+            usage.subscriptions += 1;
+        } else {
+            usage.requests += 1;
+            *window.current(now) += 1;
+            if remote {
+                *self
+                    .node_windows
+                    .entry(source.node.clone())
+                    .or_default()
+                    .current(now) += 1;
+            }
+        }
+        true
+    }
+
+    /// Forget the addresses, such as those of exited processes, that have made no
+    /// request in the current window and hold no subscriptions, and the windows of
+    /// remote nodes that have expired.
+    pub fn prune(&mut self, open_subscriptions: impl Fn(&Address) -> u64) {
+        let now = Instant::now();
+        self.usage
+            .retain(|address, (_, window)| !window.expired(now) || open_subscriptions(address) > 0);
+        self.node_windows.retain(|_, window| !window.expired(now));
+    }
+
+    /// usage of every address seen since it was last pruned, for [`lib::types::eth::EthConfigResponse::State`]
+    pub fn snapshot(
+        &mut self,
+        open_subscriptions: impl Fn(&Address) -> u64,
+    ) -> HashMap<Address, RpcUsage> {
+        let now = Instant::now();
+        self.usage
+            .iter_mut()
+            .map(|(address, (usage, window))| {
+                let mut usage = usage.clone();
+                usage.requests_this_minute = *window.current(now);
+                usage.active_subscriptions = open_subscriptions(address);
+                (address.clone(), usage)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib::types::core::ProcessId;
+
+    fn address(node: &str, name: &str) -> Address {
+        Address::new(node, ProcessId::new(Some(name), "pkg", "sys"))
+    }
+
+    /// move every window back past its end, as if a minute had passed
+    fn expire_windows(tracker: &mut UsageTracker) {
+        let past = Instant::now().checked_sub(QUOTA_WINDOW).unwrap();
+        for (_, window) in tracker.usage.values_mut() {
+            window.start = Some(past);
+        }
+        for window in tracker.node_windows.values_mut() {
+            window.start = Some(past);
+        }
+    }
+
+    #[test]
+    fn requests_are_limited_per_window() {
+        let mut tracker = UsageTracker::new("our");
+        let quota = RpcQuota {
+            requests_per_minute: Some(2),
+            max_subscriptions: Some(1),
+        };
+        let quotas = RpcQuotas {
+            default_node: Some(quota),
+            ..Default::default()
+        };
+        // remote nodes are limited across their processes
+        assert!(tracker.charge(&address("them", "a"), &quotas, false, 0));
+        assert!(tracker.charge(&address("them", "b"), &quotas, false, 0));
+        assert!(!tracker.charge(&address("them", "a"), &quotas, false, 0));
+        assert!(tracker.charge(&address("them", "a"), &quotas, true, 0));
+        assert!(!tracker.charge(&address("them", "a"), &quotas, true, 1));
+        // local processes without a quota aren't limited
+        for _ in 0..10 {
+            assert!(tracker.charge(&address("our", "a"), &quotas, false, 0));
+        }
+
+        expire_windows(&mut tracker);
+        assert!(tracker.charge(&address("them", "a"), &quotas, false, 0));
+    }
+
+    #[test]
+    fn idle_addresses_are_pruned() {
+        let mut tracker = UsageTracker::new("our");
+        let quotas = RpcQuotas::default();
+        for name in ["a", "b"] {
+            assert!(tracker.charge(&address("our", name), &quotas, false, 0));
+        }
+        assert!(tracker.charge(&address("them", "a"), &quotas, false, 0));
+
+        // addresses active in the current window are kept
+        tracker.prune(|_| 0);
+        assert_eq!(tracker.usage.len(), 3);
+
+        // after it, only those holding subscriptions are
+        expire_windows(&mut tracker);
+        let subscribed = address("our", "b");
+        tracker.prune(|address| (*address == subscribed) as u64);
+        assert_eq!(tracker.usage.len(), 1);
+        assert!(tracker.usage.contains_key(&subscribed));
+        assert!(tracker.node_windows.is_empty());
+    }
+}
@@ -1,6 +1,18 @@
 use crate::eth::*;
 use alloy::pubsub::RawSubscription;
 use alloy::rpc::types::eth::pubsub::SubscriptionResult;
+use std::collections::BTreeMap;
+
+/// number of blocks requested per `eth_getLogs` call while backfilling
+const BACKFILL_BATCH_BLOCKS: u64 = 5_000;
+/// how many live logs may arrive during a backfill before the subscription
+/// fails, to be resumed with a later `from_block`
+const MAX_HELD_BACK_LOGS: usize = 10_000;
+/// how often to poll the chain head for a subscription with confirmations
+const HEAD_POLL_INTERVAL_SECS: u64 = 4;
+/// how many blocks past their confirmation depth delivered logs are remembered,
+/// so that deeper-than-expected reorgs can still be rolled back
+const DELIVERED_HISTORY_BLOCKS: u64 = 128;
 
 /// cleans itself up when the subscription is closed or fails.
 pub async fn create_new_subscription(
@@ -18,6 +30,15 @@ pub async fn create_new_subscription(
     let providers = state.providers.clone();
     let response_channels = state.response_channels.clone();
     let print_tx = state.print_tx.clone();
+    let (confirmations, from_block, filter) = match &eth_action {
+        EthAction::SubscribeLogs {
+            confirmations,
+            from_block,
+            params,
+            ..
+        } => (*confirmations, *from_block, params.clone()),
+        _ => (None, None, serde_json::Value::Null),
+    };
 
     tokio::spawn(async move {
         match tokio::time::timeout(
@@ -75,7 +96,7 @@ pub async fn create_new_subscription(
                 let local_to_remote_subs = local_to_remote_subs.clone();
                 let (close_sender, close_receiver) = tokio::sync::mpsc::channel(1);
                 match maybe_raw_sub {
-                    Ok((rx, _chain_id)) => {
+                    Ok((rx, pubsub)) => {
                         subs.insert(
                             sub_id,
                             // this is a local sub, as in, we connect to the rpc endpoint
@@ -83,17 +104,35 @@ pub async fn create_new_subscription(
                                 close_sender,
                                 tokio::spawn(async move {
                                     // await the subscription error and kill it if so
-                                    let r = maintain_local_subscription(
-                                        &our,
-                                        sub_id,
-                                        rx,
-                                        &target,
-                                        &rsvp,
-                                        &send_to_loop,
-                                        &active_subscriptions,
-                                        close_receiver,
-                                    )
-                                    .await;
+                                    let r = if confirmations.is_some() || from_block.is_some() {
+                                        maintain_confirmed_subscription(
+                                            &our,
+                                            sub_id,
+                                            rx,
+                                            pubsub,
+                                            filter,
+                                            confirmations.unwrap_or(0),
+                                            from_block,
+                                            &target,
+                                            &rsvp,
+                                            &send_to_loop,
+                                            &active_subscriptions,
+                                            close_receiver,
+                                        )
+                                        .await
+                                    } else {
+                                        maintain_local_subscription(
+                                            &our,
+                                            sub_id,
+                                            rx,
+                                            &target,
+                                            &rsvp,
+                                            &send_to_loop,
+                                            &active_subscriptions,
+                                            close_receiver,
+                                        )
+                                        .await
+                                    };
                                     let Err(e) = r else {
                                         return;
                                     };
@@ -187,17 +226,28 @@ async fn build_subscription(
     providers: &Providers,
     response_channels: &ResponseChannels,
     print_tx: &PrintSender,
-) -> Result<Result<(RawSubscription, u64), (String, u64)>, EthError> {
+) -> Result<Result<(RawSubscription, RootProvider<PubSubFrontend>), (String, u64)>, EthError> {
     let EthAction::SubscribeLogs {
         chain_id,
         kind,
         params,
+        confirmations,
+        from_block,
         ..
     } = eth_action
     else {
         return Err(EthError::PermissionDenied); // will never hit
     };
 
+    // confirmations and backfill only make sense for logs,
+    // and backfill needs a filter object to set a block range on
+    if (confirmations.is_some() || from_block.is_some()) && *kind != SubscriptionKind::Logs {
+        return Err(EthError::InvalidParams);
+    }
+    if from_block.is_some() && !params.is_object() {
+        return Err(EthError::InvalidParams);
+    }
+
     if *kind == SubscriptionKind::NewHeads {
         Printout::new(
             0,
@@ -248,6 +298,7 @@ async fn build_subscription(
         {
             Ok(sub) => {
                 let rx = sub.into_raw();
+                let pubsub = pubsub.clone();
                 if newly_activated {
                     let mut provider_found = false;
                     providers.entry(*chain_id).and_modify(|aps| {
@@ -266,7 +317,7 @@ async fn build_subscription(
                         .await;
                     }
                 }
-                return Ok(Ok((rx, *chain_id)));
+                return Ok(Ok((rx, pubsub)));
             }
             Err(rpc_error) => {
                 verbose_print(
@@ -331,6 +382,8 @@ async fn build_subscription(
                 chain_id: chain_id.clone(),
                 kind: kind.clone(),
                 params: params.clone(),
+                confirmations: *confirmations,
+                from_block: *from_block,
             },
             &send_to_loop,
             &mut response_receiver,
@@ -427,7 +480,11 @@ async fn maintain_local_subscription(
                     rsvp.clone(),
                     true,
                     None,
-                    EthSubResult::Ok(EthSub { id: sub_id, result }),
+                    EthSubResult::Ok(EthSub {
+                        id: sub_id,
+                        result,
+                        event: None,
+                    }),
                     &send_to_loop,
                 )
                 .await;
@@ -446,6 +503,261 @@ async fn maintain_local_subscription(
     })
 }
 
+/// Like [`maintain_local_subscription`], but for a logs subscription with a
+/// confirmation depth and/or a block to backfill from. Logs are buffered in a
+/// [`ConfirmationBuffer`] and delivered with an [`EthSubEvent`].
+async fn maintain_confirmed_subscription(
+    our: &str,
+    sub_id: u64,
+    mut rx: RawSubscription,
+    pubsub: RootProvider<PubSubFrontend>,
+    filter: serde_json::Value,
+    confirmations: u64,
+    from_block: Option<u64>,
+    target: &Address,
+    rsvp: &Option<Address>,
+    send_to_loop: &MessageSender,
+    active_subscriptions: &ActiveSubscriptions,
+    mut close_receiver: tokio::sync::mpsc::Receiver<bool>,
+) -> Result<(), EthSubError> {
+    let mut buffer = ConfirmationBuffer::new(confirmations);
+    let e = 'sub: {
+        let head = match pubsub.get_block_number().await {
+            Ok(head) => head,
+            Err(e) => break 'sub e.to_string(),
+        };
+        buffer.set_head(head);
+
+        if let Some(from_block) = from_block {
+            // live logs that arrive during backfill are held back so that
+            // everything is delivered in block order
+            let mut held_back = vec![];
+            let mut start = from_block;
+            while start <= head {
+                let end = start.saturating_add(BACKFILL_BATCH_BLOCKS - 1).min(head);
+                let mut batch_filter = filter.clone();
+                batch_filter["fromBlock"] = format!("0x{start:x}").into();
+                batch_filter["toBlock"] = format!("0x{end:x}").into();
+                let logs = match pubsub
+                    .raw_request::<_, Vec<serde_json::Value>>("eth_getLogs".into(), [batch_filter])
+                    .await
+                {
+                    Ok(logs) => logs,
+                    Err(e) => break 'sub e.to_string(),
+                };
+                for log in logs {
+                    let events = buffer.push_log(log);
+                    send_sub_events(our, sub_id, target, rsvp, events, send_to_loop).await;
+                }
+                while let Ok(value) = rx.try_recv() {
+                    if let Ok(log) = serde_json::from_str::<serde_json::Value>(value.get()) {
+                        held_back.push(log);
+                    }
+                }
+                if held_back.len() > MAX_HELD_BACK_LOGS {
+                    break 'sub format!(
+                        "more than {MAX_HELD_BACK_LOGS} live logs arrived during backfill"
+                    );
+                }
+                start = end + 1;
+            }
+            send_sub_events(
+                our,
+                sub_id,
+                target,
+                rsvp,
+                vec![(EthSubEvent::Live, head.into())],
+                send_to_loop,
+            )
+            .await;
+            for log in held_back {
+                let events = buffer.push_log(log);
+                send_sub_events(our, sub_id, target, rsvp, events, send_to_loop).await;
+            }
+        }
+
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(HEAD_POLL_INTERVAL_SECS));
+        loop {
+            tokio::select! {
+                is_error = close_receiver.recv() => {
+                    match is_error {
+                        Some(true) => {
+                            // Network error - return an error so client gets EthSubResult::Err
+                            return Err(EthSubError {
+                                id: sub_id,
+                                error: "subscription closed due to network error".to_string(),
+                            });
+                        },
+                        Some(false) | None => {
+                            // Manual close by user - clean up and return Ok
+                            active_subscriptions
+                                .entry(target.clone())
+                                .and_modify(|sub_map| {
+                                    sub_map.remove(&sub_id);
+                                });
+                            return Ok(());
+                        }
+                    }
+                },
+                value = rx.recv() => {
+                    let value = match value {
+                        Ok(v) => serde_json::from_str::<serde_json::Value>(v.get()),
+                        Err(e) => break 'sub e.to_string(),
+                    };
+                    let log = match value {
+                        Ok(v) => v,
+                        Err(e) => break 'sub e.to_string(),
+                    };
+                    let events = buffer.push_log(log);
+                    send_sub_events(our, sub_id, target, rsvp, events, send_to_loop).await;
+                },
+                _ = interval.tick() => {
+                    // a failed poll is not fatal: the next tick will try again
+                    if let Ok(head) = pubsub.get_block_number().await {
+                        let events = buffer.set_head(head);
+                        send_sub_events(our, sub_id, target, rsvp, events, send_to_loop).await;
+                    }
+                },
+            }
+        }
+    };
+    active_subscriptions
+        .entry(target.clone())
+        .and_modify(|sub_map| {
+            sub_map.remove(&sub_id);
+        });
+
+    Err(EthSubError {
+        id: sub_id,
+        error: format!("subscription ({target}) closed unexpectedly: {e}"),
+    })
+}
+
+async fn send_sub_events(
+    our: &str,
+    sub_id: u64,
+    target: &Address,
+    rsvp: &Option<Address>,
+    events: Vec<(EthSubEvent, serde_json::Value)>,
+    send_to_loop: &MessageSender,
+) {
+    for (event, result) in events {
+        kernel_message(
+            our,
+            rand::random(),
+            target.clone(),
+            rsvp.clone(),
+            true,
+            None,
+            EthSubResult::Ok(EthSub {
+                id: sub_id,
+                result,
+                event: Some(event),
+            }),
+            send_to_loop,
+        )
+        .await;
+    }
+}
+
+/// Holds logs until they are `confirmations` blocks deep, and remembers
+/// recently delivered logs so that a reorg removing them can be reported.
+/// Logs are identified by (block number, log index) and compared by block hash.
+struct ConfirmationBuffer {
+    confirmations: u64,
+    /// highest block number seen
+    head: u64,
+    pending: BTreeMap<(u64, u64), serde_json::Value>,
+    delivered: BTreeMap<(u64, u64), serde_json::Value>,
+}
+
+impl ConfirmationBuffer {
+    fn new(confirmations: u64) -> Self {
+        Self {
+            confirmations,
+            head: 0,
+            pending: BTreeMap::new(),
+            delivered: BTreeMap::new(),
+        }
+    }
+
+    /// add a log from backfill or from the live subscription, returning
+    /// any events that should now be delivered
+    fn push_log(&mut self, log: serde_json::Value) -> Vec<(EthSubEvent, serde_json::Value)> {
+        let (Some(block), Some(index)) = (
+            log.get("blockNumber").and_then(hex_u64),
+            log.get("logIndex").and_then(hex_u64),
+        ) else {
+            // a log not yet in a block can never be confirmed
+            return vec![];
+        };
+        let key = (block, index);
+        let mut events = vec![];
+
+        if log.get("removed").and_then(|r| r.as_bool()) == Some(true) {
+            if self.pending.get(&key).is_some_and(|p| same_block(p, &log)) {
+                self.pending.remove(&key);
+            } else if self
+                .delivered
+                .get(&key)
+                .is_some_and(|d| same_block(d, &log))
+            {
+                self.delivered.remove(&key);
+                events.push((EthSubEvent::Rollback, log));
+            }
+            return events;
+        }
+
+        if let Some(delivered) = self.delivered.get(&key) {
+            if same_block(delivered, &log) {
+                // already delivered, e.g. seen in both backfill and live updates
+                return events;
+            }
+            // a reorg replaced this log without a removal notice
+            let mut old = self.delivered.remove(&key).unwrap();
+            old["removed"] = true.into();
+            events.push((EthSubEvent::Rollback, old));
+        }
+        self.pending.insert(key, log);
+        self.head = self.head.max(block);
+        events.extend(self.release());
+        events
+    }
+
+    /// record a new chain head, returning any logs that are now deep enough
+    fn set_head(&mut self, head: u64) -> Vec<(EthSubEvent, serde_json::Value)> {
+        self.head = self.head.max(head);
+        self.release()
+    }
+
+    fn release(&mut self) -> Vec<(EthSubEvent, serde_json::Value)> {
+        let mut events = vec![];
+        while let Some(entry) = self.pending.first_entry() {
+            if entry.key().0.saturating_add(self.confirmations) > self.head {
+                break;
+            }
+            let (key, log) = entry.remove_entry();
+            self.delivered.insert(key, log.clone());
+            events.push((EthSubEvent::Confirmed, log));
+        }
+        // forget delivered logs that are too deep to be reorged
+        let horizon = self
+            .head
+            .saturating_sub(self.confirmations + DELIVERED_HISTORY_BLOCKS);
+        self.delivered = self.delivered.split_off(&(horizon, 0));
+        events
+    }
+}
+
+fn hex_u64(value: &serde_json::Value) -> Option<u64> {
+    u64::from_str_radix(value.as_str()?.strip_prefix("0x")?, 16).ok()
+}
+
+fn same_block(a: &serde_json::Value, b: &serde_json::Value) -> bool {
+    a.get("blockHash") == b.get("blockHash")
+}
+
 /// handle the subscription updates from a remote provider,
 /// and also perform keepalive checks on that provider.
 /// current keepalive is 30s, this can be adjusted as desired
@@ -487,6 +799,7 @@ async fn maintain_remote_subscription(
                             EthSubResult::Ok(EthSub {
                                 id: sub_id,
                                 result: upd.result,
+                                event: upd.event,
                             }),
                             &send_to_loop,
                         )
@@ -566,3 +879,112 @@ async fn maintain_remote_subscription(
     response_channels.remove(&keepalive_km_id);
     e
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(block: u64, index: u64, hash: &str) -> serde_json::Value {
+        serde_json::json!({
+            "blockNumber": format!("{block:#x}"),
+            "logIndex": format!("{index:#x}"),
+            "blockHash": hash,
+        })
+    }
+
+    fn removed(block: u64, index: u64, hash: &str) -> serde_json::Value {
+        let mut log = log(block, index, hash);
+        log["removed"] = true.into();
+        log
+    }
+
+    /// (event, block number) of each event
+    fn summary(events: Vec<(EthSubEvent, serde_json::Value)>) -> Vec<(EthSubEvent, u64)> {
+        events
+            .into_iter()
+            .map(|(event, log)| (event, hex_u64(&log["blockNumber"]).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn logs_wait_for_confirmation_depth() {
+        let mut buffer = ConfirmationBuffer::new(3);
+        assert!(buffer.set_head(10).is_empty());
+        assert!(buffer.push_log(log(10, 0, "a")).is_empty());
+        assert!(buffer.push_log(log(11, 0, "b")).is_empty());
+        assert!(buffer.set_head(12).is_empty());
+        assert_eq!(summary(buffer.set_head(13)), [(EthSubEvent::Confirmed, 10)]);
+        // a log already deep enough is released at once, in block order
+        assert_eq!(summary(buffer.set_head(20)), [(EthSubEvent::Confirmed, 11)]);
+        assert_eq!(
+            summary(buffer.push_log(log(15, 0, "c"))),
+            [(EthSubEvent::Confirmed, 15)]
+        );
+        // a log without a block can never be confirmed
+        assert!(buffer
+            .push_log(serde_json::json!({ "logIndex": "0x0" }))
+            .is_empty());
+    }
+
+    #[test]
+    fn zero_confirmations_deliver_at_once_and_ignore_duplicates() {
+        let mut buffer = ConfirmationBuffer::new(0);
+        assert_eq!(
+            summary(buffer.push_log(log(5, 0, "a"))),
+            [(EthSubEvent::Confirmed, 5)]
+        );
+        // seen again, e.g. in both backfill and live updates
+        assert!(buffer.push_log(log(5, 0, "a")).is_empty());
+    }
+
+    #[test]
+    fn removed_pending_log_is_dropped_silently() {
+        let mut buffer = ConfirmationBuffer::new(3);
+        buffer.set_head(10);
+        buffer.push_log(log(10, 0, "a"));
+        assert!(buffer.push_log(removed(10, 0, "a")).is_empty());
+        assert!(buffer.set_head(20).is_empty());
+    }
+
+    #[test]
+    fn removed_delivered_log_is_rolled_back() {
+        let mut buffer = ConfirmationBuffer::new(1);
+        buffer.set_head(10);
+        buffer.push_log(log(10, 0, "a"));
+        assert_eq!(summary(buffer.set_head(11)), [(EthSubEvent::Confirmed, 10)]);
+        // a removal for a log on another fork is not ours
+        assert!(buffer.push_log(removed(10, 0, "other")).is_empty());
+        let events = buffer.push_log(removed(10, 0, "a"));
+        assert_eq!(summary(events), [(EthSubEvent::Rollback, 10)]);
+        // only rolled back once
+        assert!(buffer.push_log(removed(10, 0, "a")).is_empty());
+    }
+
+    #[test]
+    fn replaced_delivered_log_is_rolled_back_then_reconfirmed() {
+        let mut buffer = ConfirmationBuffer::new(1);
+        buffer.set_head(11);
+        buffer.push_log(log(10, 0, "a"));
+        // the reorg replaces the log without a removal notice
+        let events = buffer.push_log(log(10, 0, "b"));
+        assert_eq!(
+            events
+                .iter()
+                .map(|(event, log)| (*event, log["blockHash"].as_str().unwrap()))
+                .collect::<Vec<_>>(),
+            [(EthSubEvent::Rollback, "a"), (EthSubEvent::Confirmed, "b")]
+        );
+        assert_eq!(events[0].1["removed"], true);
+    }
+
+    #[test]
+    fn deep_delivered_logs_are_forgotten() {
+        let mut buffer = ConfirmationBuffer::new(1);
+        buffer.set_head(10);
+        buffer.push_log(log(10, 0, "a"));
+        buffer.set_head(11);
+        buffer.set_head(11 + 1 + DELIVERED_HISTORY_BLOCKS);
+        // too deep to be reorged: a removal for it is ignored
+        assert!(buffer.push_log(removed(10, 0, "a")).is_empty());
+    }
+}
@@ -147,6 +147,7 @@ pub async fn kernel_message<T: Serialize>(
             ))
         },
         lazy_load_blob: None,
+        trace: None,
     }) else {
         // not Err -> send successful; done here
         return;
@@ -138,6 +138,66 @@ pub async fn mint_local(
         )
     };
 
+    send_and_mine(&provider, &wallet, wallet_address, to, input_bytes).await
+}
+
+/// Publish a new `~net-key` note for a fakechain node, as its owner would
+/// to finish a networking key rotation.
+pub async fn set_net_key_local(
+    name: &str,
+    pubkey: &[u8],
+    fakechain_port: u16,
+) -> Result<(), anyhow::Error> {
+    let privkey_signer = PrivateKeySigner::from_str(
+        "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80",
+    )?;
+    let wallet_address = privkey_signer.address();
+    let wallet: EthereumWallet = privkey_signer.into();
+
+    let hypermap = Address::from_str(HYPERMAP_ADDRESS)?;
+
+    let endpoint = format!("ws://localhost:{}", fakechain_port);
+    let ws = WsConnect::new(endpoint);
+    let provider: RootProvider<PubSubFrontend> = ProviderBuilder::default().on_ws(ws).await?;
+
+    let get_tx = TransactionRequest::default()
+        .to(hypermap)
+        .input(TransactionInput::new(
+            getCall {
+                namehash: keygen::namehash(name).into(),
+            }
+            .abi_encode()
+            .into(),
+        ));
+    let tba = getCall::abi_decode_returns(&provider.call(&get_tx).await?, false)?.tba;
+    if tba == Address::default() {
+        return Err(anyhow::anyhow!("{name} is not registered on the fakechain"));
+    }
+
+    let execute_call = executeCall {
+        to: hypermap,
+        value: U256::from(0),
+        data: noteCall {
+            note: "~net-key".into(),
+            data: Bytes::copy_from_slice(pubkey),
+        }
+        .abi_encode()
+        .into(),
+        operation: 0,
+    }
+    .abi_encode();
+
+    send_and_mine(&provider, &wallet, wallet_address, tba, execute_call).await
+}
+
+/// Send a transaction from the fakechain's funded wallet and wait for its receipt.
+async fn send_and_mine(
+    provider: &RootProvider<PubSubFrontend>,
+    wallet: &EthereumWallet,
+    wallet_address: Address,
+    to: Address,
+    input_bytes: Vec<u8>,
+) -> Result<(), anyhow::Error> {
     let nonce = provider.get_transaction_count(wallet_address).await?;
 
     let tx = TransactionRequest::default()
@@ -150,7 +210,7 @@ pub async fn mint_local(
         .with_max_fee_per_gas(300_000_000_000);
 
     // Build the transaction using the `EthereumSigner` with the provided signer.
-    let tx_envelope = tx.build(&wallet).await?;
+    let tx_envelope = tx.build(wallet).await?;
 
     // Encode the transaction using EIP-2718 encoding.
     let tx_encoded = tx_envelope.encoded_2718();
@@ -176,7 +236,7 @@ pub async fn mint_local(
         .with_max_priority_fee_per_gas(200_000_000_000)
         .with_max_fee_per_gas(300_000_000_000);
 
-    let small_tx_envelope = small_tx.build(&wallet).await?;
+    let small_tx_envelope = small_tx.build(wallet).await?;
     let small_tx_encoded = small_tx_envelope.encoded_2718();
 
     let small_tx_hash = provider.send_raw_transaction(&small_tx_encoded).await?;
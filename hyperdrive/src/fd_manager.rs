@@ -1,7 +1,7 @@
 use lib::types::core::{
-    Address, FdManagerError, FdManagerRequest, FdManagerResponse, FdsLimit, KernelMessage, Message,
-    MessageReceiver, MessageSender, PrintSender, Printout, ProcessId, Request,
-    FD_MANAGER_PROCESS_ID,
+    Address, CapMessage, CapMessageSender, Capability, FdManagerError, FdManagerRequest,
+    FdManagerResponse, FdsBudget, FdsLimit, KernelMessage, Message, MessageReceiver, MessageSender,
+    PrintSender, Printout, ProcessId, Request, FD_MANAGER_PROCESS_ID,
 };
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, sync::Arc};
@@ -16,11 +16,16 @@ const SYS_RESERVED_FDS: u64 = 30;
 
 const DEFAULT_FDS_AS_FRACTION_OF_ULIMIT_PERCENTAGE: u64 = 90;
 const DEFAULT_UPDATE_ULIMIT_SECS: u64 = 3600;
+const DEFAULT_POLL_FDS_USAGE_SECS: u64 = 60;
 const _DEFAULT_CULL_FRACTION_DENOMINATOR: u64 = 2;
+/// hits counted towards a process' demand; halved at each poll of fds usage
+const MAX_HIT_COUNT: u64 = 16;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct State {
     fds_limits: HashMap<ProcessId, FdsLimit>,
+    /// budgets set by root, including for processes that have not requested a limit yet
+    budgets: HashMap<ProcessId, FdsBudget>,
     mode: Mode,
     max_fds: u64,
 }
@@ -44,6 +49,7 @@ impl State {
     fn default(static_max_fds: Option<u64>) -> Self {
         Self {
             fds_limits: HashMap::new(),
+            budgets: HashMap::new(),
             mode: Mode::default(static_max_fds),
             max_fds: match static_max_fds {
                 Some(max) => max,
@@ -72,21 +78,76 @@ impl State {
             ulimit_max_fds * max_fds_as_fraction_of_ulimit_percentage / 100 - SYS_RESERVED_FDS;
     }
 
+    /// Half of max_fds is split between processes by weight alone, the other half by
+    /// weight times observed demand: the fds a process has open, scaled by how often
+    /// it has recently hit its limit. Pinned bounds are applied last, so pinned
+    /// minimums can add up to more than max_fds. Only processes whose limit changed
+    /// are told.
     async fn update_all_fds_limits(&mut self, our_node: &str, send_to_loop: &MessageSender) {
+        let changed = self.allocate();
+        send_fds_limits(our_node, send_to_loop, changed).await;
+    }
+
+    /// Set the limit of each process, returning those that changed.
+    fn allocate(&mut self) -> Vec<(ProcessId, u64)> {
+        let demand = |limit: &FdsLimit| limit.budget.weight * (limit.open + 1) * limit.hit_count;
         let weights = self
             .fds_limits
             .values()
-            .map(|limit| limit.hit_count)
+            .map(|limit| limit.budget.weight)
             .sum::<u64>();
+        let demands = self.fds_limits.values().map(demand).sum::<u64>();
         let statically_allocated = self.max_fds as f64 / 2.0;
-        let per_process_unweighted =
-            statically_allocated / std::cmp::max(self.fds_limits.len() as u64, 1) as f64;
-        let per_process_weighted = statically_allocated / std::cmp::max(weights, 1) as f64;
-        for limit in self.fds_limits.values_mut() {
-            limit.limit = (per_process_unweighted + per_process_weighted * limit.hit_count as f64)
+        let per_weight = statically_allocated / std::cmp::max(weights, 1) as f64;
+        let per_demand = statically_allocated / std::cmp::max(demands, 1) as f64;
+        let mut changed = vec![];
+        for (process, limit) in self.fds_limits.iter_mut() {
+            let mut new_limit = (per_weight * limit.budget.weight as f64
+                + per_demand * demand(limit) as f64)
                 .floor() as u64;
+            if let Some(min) = limit.budget.min {
+                new_limit = new_limit.max(min);
+            }
+            if let Some(max) = limit.budget.max {
+                new_limit = new_limit.min(max);
+            }
+            if new_limit != limit.limit {
+                limit.limit = new_limit;
+                changed.push((process.clone(), new_limit));
+            }
+        }
+        changed
+    }
+
+    /// A process can't keep more fds open than its limit, so a larger report could
+    /// only inflate its demand: count at most the limit.
+    fn record_usage(&mut self, process: &ProcessId, open: u64) {
+        if let Some(limit) = self.fds_limits.get_mut(process) {
+            limit.open = open.min(limit.limit);
+        }
+    }
+
+    fn record_hit(&mut self, process: &ProcessId) {
+        if let Some(limit) = self.fds_limits.get_mut(process) {
+            limit.hit_count = (limit.hit_count + 1).min(MAX_HIT_COUNT);
+        }
+    }
+
+    /// Halve the hit counts, so that a process whose demand has passed gives its
+    /// extra fds back rather than keeping them for good.
+    fn decay_hit_counts(&mut self) {
+        for limit in self.fds_limits.values_mut() {
+            limit.hit_count = (limit.hit_count / 2).max(1);
+        }
+    }
+
+    /// record a budget set by root, and apply it to the process if it has a limit
+    fn set_budget(&mut self, process: ProcessId, set: impl FnOnce(&mut FdsBudget)) {
+        let budget = self.budgets.entry(process.clone()).or_default();
+        set(budget);
+        if let Some(limit) = self.fds_limits.get_mut(&process) {
+            limit.budget = *budget;
         }
-        send_all_fds_limits(our_node, send_to_loop, self).await;
     }
 }
 
@@ -109,6 +170,7 @@ pub async fn fd_manager(
     send_to_loop: MessageSender,
     send_to_terminal: PrintSender,
     mut recv_from_loop: MessageReceiver,
+    caps_oracle: CapMessageSender,
     static_max_fds: Option<u64>,
 ) -> anyhow::Result<()> {
     // Windows does not allow querying of max fds allowed.
@@ -134,6 +196,9 @@ pub async fn fd_manager(
         };
         tokio::time::interval(tokio::time::Duration::from_secs(*update_ulimit_secs))
     };
+    let mut poll_usage_interval = tokio::time::interval(tokio::time::Duration::from_secs(
+        DEFAULT_POLL_FDS_USAGE_SECS,
+    ));
     loop {
         #[cfg(unix)]
         tokio::select! {
@@ -143,6 +208,7 @@ pub async fn fd_manager(
                     message,
                     &mut state,
                     &send_to_loop,
+                    &caps_oracle,
                 ).await {
                     Ok(Some(to_print)) => {
                         Printout::new(2, FD_MANAGER_PROCESS_ID.clone(), to_print).send(&send_to_terminal).await;
@@ -168,25 +234,39 @@ pub async fn fd_manager(
                         .await,
                 }
             }
+            _ = poll_usage_interval.tick() => {
+                // rebalance on the reports from the last poll, and ask for fresh ones
+                state.update_all_fds_limits(our_node.as_str(), &send_to_loop).await;
+                state.decay_hit_counts();
+                send_all_get_fds_usage(our_node.as_str(), &send_to_loop, &state).await;
+            }
         }
         #[cfg(target_os = "windows")]
-        if let Some(message) = recv_from_loop.recv().await {
-            match handle_message(&our_node, message, &mut state, &send_to_loop).await {
-                Ok(Some(to_print)) => {
-                    Printout::new(2, FD_MANAGER_PROCESS_ID.clone(), to_print)
+        tokio::select! {
+            Some(message) = recv_from_loop.recv() => {
+                match handle_message(&our_node, message, &mut state, &send_to_loop, &caps_oracle).await {
+                    Ok(Some(to_print)) => {
+                        Printout::new(2, FD_MANAGER_PROCESS_ID.clone(), to_print)
+                            .send(&send_to_terminal)
+                            .await;
+                    }
+                    Err(e) => {
+                        Printout::new(
+                            1,
+                            FD_MANAGER_PROCESS_ID.clone(),
+                            &format!("handle_message error: {e:?}"),
+                        )
                         .send(&send_to_terminal)
                         .await;
+                    }
+                    _ => {}
                 }
-                Err(e) => {
-                    Printout::new(
-                        1,
-                        FD_MANAGER_PROCESS_ID.clone(),
-                        &format!("handle_message error: {e:?}"),
-                    )
-                    .send(&send_to_terminal)
-                    .await;
-                }
-                _ => {}
+            }
+            _ = poll_usage_interval.tick() => {
+                // rebalance on the reports from the last poll, and ask for fresh ones
+                state.update_all_fds_limits(our_node.as_str(), &send_to_loop).await;
+                state.decay_hit_counts();
+                send_all_get_fds_usage(our_node.as_str(), &send_to_loop, &state).await;
             }
         }
     }
@@ -197,6 +277,7 @@ async fn handle_message(
     km: KernelMessage,
     state: &mut State,
     send_to_loop: &MessageSender,
+    caps_oracle: &CapMessageSender,
 ) -> anyhow::Result<Option<String>> {
     let Message::Request(Request {
         body,
@@ -210,33 +291,75 @@ async fn handle_message(
         serde_json::from_slice(&body).map_err(|_e| FdManagerError::BadRequest)?;
     let return_value = match request {
         FdManagerRequest::RequestFdsLimit => {
-            // divide max_fds by number of processes requesting fds limits,
+            // divide max_fds between processes requesting fds limits,
             // then send each process its new limit
-            // TODO can weight different processes differently
+            let budget = state
+                .budgets
+                .get(&km.source.process)
+                .copied()
+                .unwrap_or_default();
             state.fds_limits.insert(
                 km.source.process,
                 FdsLimit {
                     limit: 0,
                     hit_count: 1, // starts with 1 to give initial weight
+                    open: 0,
+                    budget,
                 },
             );
-            state.update_all_fds_limits(our_node, &send_to_loop).await;
+            state.update_all_fds_limits(our_node, send_to_loop).await;
             None
         }
         FdManagerRequest::FdsLimitHit => {
             // sender process hit its fd limit
             // react to this by incrementing hit count and
             // re-weighting all processes' limits
-            state.fds_limits.get_mut(&km.source.process).map(|limit| {
-                limit.hit_count += 1;
-            });
-            state.update_all_fds_limits(our_node, &send_to_loop).await;
+            state.record_hit(&km.source.process);
+            state.update_all_fds_limits(our_node, send_to_loop).await;
             Some(format!("{} hit its fd limit", km.source.process))
         }
         FdManagerRequest::FdsLimit(_) => {
             // should only send this, never receive it
             return Err(FdManagerError::FdManagerWasSentLimit.into());
         }
+        FdManagerRequest::GetFdsUsage => {
+            // should only send this, never receive it
+            return Err(FdManagerError::BadRequest.into());
+        }
+        FdManagerRequest::FdsUsage(open) => {
+            // acted on at the next poll, so that limits don't churn with every report
+            state.record_usage(&km.source.process, open);
+            None
+        }
+        FdManagerRequest::SetFdsWeight { process, weight } => {
+            check_for_root_cap(our_node, &km.source, caps_oracle).await?;
+            if weight == 0 {
+                return Err(FdManagerError::BadRequest.into());
+            }
+            state.set_budget(process.clone(), |budget| budget.weight = weight);
+            state.update_all_fds_limits(our_node, send_to_loop).await;
+            Some(format!(
+                "{process} fd weight set to {weight} by {}",
+                km.source
+            ))
+        }
+        FdManagerRequest::PinFdsLimit { process, min, max } => {
+            check_for_root_cap(our_node, &km.source, caps_oracle).await?;
+            if let (Some(min), Some(max)) = (min, max) {
+                if min > max {
+                    return Err(FdManagerError::BadRequest.into());
+                }
+            }
+            state.set_budget(process.clone(), |budget| {
+                budget.min = min;
+                budget.max = max;
+            });
+            state.update_all_fds_limits(our_node, send_to_loop).await;
+            Some(format!(
+                "{process} fd limit pinned to {min:?}..{max:?} by {}",
+                km.source
+            ))
+        }
         FdManagerRequest::UpdateMaxFdsAsFractionOfUlimitPercentage(new) => {
             match state.mode {
                 Mode::DynamicMax {
@@ -342,24 +465,78 @@ fn get_max_fd_limit() -> anyhow::Result<u64> {
     }
 }
 
-async fn send_all_fds_limits(our_node: &str, send_to_loop: &MessageSender, state: &State) {
-    for (process_id, limit) in &state.fds_limits {
-        KernelMessage::builder()
-            .id(rand::random())
-            .source((our_node, FD_MANAGER_PROCESS_ID.clone()))
-            .target((our_node, process_id))
-            .message(Message::Request(Request {
-                inherit: false,
-                expects_response: None,
-                body: serde_json::to_vec(&FdManagerRequest::FdsLimit(limit.limit)).unwrap(),
-                metadata: None,
-                capabilities: vec![],
-            }))
-            .build()
-            .unwrap()
-            .send(send_to_loop)
-            .await;
+async fn send_fds_limits(
+    our_node: &str,
+    send_to_loop: &MessageSender,
+    limits: Vec<(ProcessId, u64)>,
+) {
+    for (process_id, limit) in limits {
+        send_to_process(
+            our_node,
+            &process_id,
+            FdManagerRequest::FdsLimit(limit),
+            send_to_loop,
+        )
+        .await;
+    }
+}
+
+async fn send_all_get_fds_usage(our_node: &str, send_to_loop: &MessageSender, state: &State) {
+    for process_id in state.fds_limits.keys() {
+        send_to_process(
+            our_node,
+            process_id,
+            FdManagerRequest::GetFdsUsage,
+            send_to_loop,
+        )
+        .await;
+    }
+}
+
+async fn send_to_process(
+    our_node: &str,
+    process_id: &ProcessId,
+    request: FdManagerRequest,
+    send_to_loop: &MessageSender,
+) {
+    KernelMessage::builder()
+        .id(rand::random())
+        .source((our_node, FD_MANAGER_PROCESS_ID.clone()))
+        .target((our_node, process_id))
+        .message(Message::Request(Request {
+            inherit: false,
+            expects_response: None,
+            body: serde_json::to_vec(&request).unwrap(),
+            metadata: None,
+            capabilities: vec![],
+        }))
+        .build()
+        .unwrap()
+        .send(send_to_loop)
+        .await;
+}
+
+/// Budgets can only be set by local processes holding the root capability for fd-manager.
+async fn check_for_root_cap(
+    our_node: &str,
+    source: &Address,
+    caps_oracle: &CapMessageSender,
+) -> anyhow::Result<()> {
+    if source.node != our_node {
+        return Err(FdManagerError::NoRootCap(source.process.clone()).into());
+    }
+    let (send_cap_bool, recv_cap_bool) = tokio::sync::oneshot::channel();
+    caps_oracle
+        .send(CapMessage::Has {
+            on: source.process.clone(),
+            cap: Capability::new((our_node, FD_MANAGER_PROCESS_ID.clone()), "{\"root\":true}"),
+            responder: send_cap_bool,
+        })
+        .await?;
+    if !recv_cap_bool.await.unwrap_or(false) {
+        return Err(FdManagerError::NoRootCap(source.process.clone()).into());
     }
+    Ok(())
 }
 
 pub async fn send_fd_manager_request_fds_limit(our: &Address, send_to_loop: &MessageSender) {
@@ -373,6 +550,17 @@ pub async fn send_fd_manager_request_fds_limit(our: &Address, send_to_loop: &Mes
     send_to_fd_manager(our, message, send_to_loop).await
 }
 
+pub async fn send_fd_manager_fds_usage(our: &Address, open: u64, send_to_loop: &MessageSender) {
+    let message = Message::Request(Request {
+        inherit: false,
+        expects_response: None,
+        body: serde_json::to_vec(&FdManagerRequest::FdsUsage(open)).unwrap(),
+        metadata: None,
+        capabilities: vec![],
+    });
+    send_to_fd_manager(our, message, send_to_loop).await
+}
+
 pub async fn send_fd_manager_hit_fds_limit(our: &Address, send_to_loop: &MessageSender) {
     let message = Message::Request(Request {
         inherit: false,
@@ -395,3 +583,107 @@ async fn send_to_fd_manager(our: &Address, message: Message, send_to_loop: &Mess
         .send(send_to_loop)
         .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(name: &str) -> ProcessId {
+        ProcessId::new(Some(name), "distro", "sys")
+    }
+
+    fn state_with(processes: &[&str]) -> State {
+        let mut state = State::new(Some(100));
+        for name in processes {
+            state.fds_limits.insert(
+                process(name),
+                FdsLimit {
+                    limit: 0,
+                    hit_count: 1,
+                    open: 0,
+                    budget: FdsBudget::default(),
+                },
+            );
+        }
+        state.allocate();
+        state
+    }
+
+    fn limit(state: &State, name: &str) -> u64 {
+        state.fds_limits[&process(name)].limit
+    }
+
+    #[test]
+    fn equal_processes_split_max_fds() {
+        let state = state_with(&["a", "b"]);
+        assert_eq!(limit(&state, "a"), 50);
+        assert_eq!(limit(&state, "b"), 50);
+    }
+
+    #[test]
+    fn demand_and_weight_shift_fds() {
+        let mut state = state_with(&["a", "b"]);
+        state.set_budget(process("a"), |budget| budget.weight = 3);
+        let changed = state.allocate();
+        assert_eq!(changed.len(), 2);
+        // a: 50 * 3/4 + 50 * 3/4
+        assert_eq!(limit(&state, "a"), 75);
+        assert_eq!(limit(&state, "b"), 25);
+
+        state.record_usage(&process("b"), 24);
+        state.allocate();
+        // demands: a 3 * 1 * 1 = 3, b 1 * 25 * 1 = 25
+        // a: 50 * 3/4 + 50 * 3/28, b: 50 * 1/4 + 50 * 25/28
+        assert_eq!(limit(&state, "a"), 42);
+        assert_eq!(limit(&state, "b"), 57);
+        assert!(limit(&state, "a") + limit(&state, "b") <= 100);
+    }
+
+    #[test]
+    fn pins_bound_limits() {
+        let mut state = state_with(&["a", "b"]);
+        state.set_budget(process("a"), |budget| budget.max = Some(10));
+        state.set_budget(process("b"), |budget| budget.min = Some(80));
+        state.allocate();
+        assert_eq!(limit(&state, "a"), 10);
+        assert_eq!(limit(&state, "b"), 80);
+    }
+
+    #[test]
+    fn reported_usage_is_capped_at_limit() {
+        let mut state = state_with(&["a", "b"]);
+        state.record_usage(&process("a"), 1_000_000);
+        assert_eq!(state.fds_limits[&process("a")].open, 50);
+        state.allocate();
+        // demands: a 51, b 1
+        assert_eq!(limit(&state, "a"), 25 + 49);
+        assert_eq!(limit(&state, "b"), 25);
+
+        // the process's share can't grow from reporting alone
+        for _ in 0..10 {
+            state.record_usage(&process("a"), 1_000_000);
+            state.allocate();
+        }
+        assert!(limit(&state, "a") < 100);
+        assert!(limit(&state, "b") > 0);
+    }
+
+    #[test]
+    fn hits_are_capped_and_decay() {
+        let mut state = state_with(&["a", "b"]);
+        for _ in 0..100 {
+            state.record_hit(&process("a"));
+        }
+        assert_eq!(state.fds_limits[&process("a")].hit_count, MAX_HIT_COUNT);
+        state.allocate();
+        assert!(limit(&state, "a") > limit(&state, "b"));
+
+        for _ in 0..5 {
+            state.decay_hit_counts();
+        }
+        assert_eq!(state.fds_limits[&process("a")].hit_count, 1);
+        state.allocate();
+        assert_eq!(limit(&state, "a"), 50);
+        assert_eq!(limit(&state, "b"), 50);
+    }
+}
@@ -6,12 +6,19 @@ use futures::StreamExt;
 use http::header::{HeaderMap, HeaderName, HeaderValue};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Message as TungsteniteMessage};
 use tokio_tungstenite::{connect_async, tungstenite};
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 
 use lib::types::{core::*, http_client::*, http_server::*};
 
+/// Bytes of a download collected before each write to the vfs.
+/// A progress update, if asked for, follows each write.
+const DOWNLOAD_WRITE_SIZE: usize = 1024 * 1024;
+/// Seconds to wait for the vfs to acknowledge a download write
+const VFS_TIMEOUT: u64 = 30;
+
 // Test http-client with these commands in the terminal
 // m our@http-client:distro:sys '{"method": "GET", "url": "https://jsonplaceholder.typicode.com/posts", "headers": {}}'
 // m our@http-client:distro:sys '{"method": "POST", "url": "https://jsonplaceholder.typicode.com/posts", "headers": {"Content-Type": "application/json"}}'
@@ -27,6 +34,8 @@ type WebSocketMap = DashMap<
 /// The WebSocket streams are split into sink and stream
 /// so that both incoming and outgoing pushes can be routed appropriately
 type WebSocketStreams = Arc<WebSocketMap>;
+/// Senders for the vfs responses that download writes wait on, by request ID
+type VfsResponseSenders = Arc<DashMap<u64, tokio::sync::oneshot::Sender<Vec<u8>>>>;
 
 pub async fn http_client(
     our_name: String,
@@ -38,6 +47,7 @@ pub async fn http_client(
     let our_name = Arc::new(our_name);
 
     let ws_streams: WebSocketStreams = Arc::new(DashMap::new());
+    let vfs_responses: VfsResponseSenders = Arc::new(DashMap::new());
 
     while let Some(KernelMessage {
         id,
@@ -48,13 +58,19 @@ pub async fn http_client(
         ..
     }) = recv_in_client.recv().await
     {
-        let Message::Request(Request {
-            body,
-            expects_response,
-            ..
-        }) = message
-        else {
-            continue;
+        let (body, expects_response) = match message {
+            Message::Request(Request {
+                body,
+                expects_response,
+                ..
+            }) => (body, expects_response),
+            // the vfs acknowledging a download write
+            Message::Response((Response { body, .. }, _)) => {
+                if let Some((_, sender)) = vfs_responses.remove(&id) {
+                    let _ = sender.send(body);
+                }
+                continue;
+            }
         };
         // Check that the incoming request body is a HttpClientAction
         let Ok(request) = serde_json::from_slice::<HttpClientAction>(&body) else {
@@ -89,6 +105,7 @@ pub async fn http_client(
                     client.clone(),
                     send_to_loop.clone(),
                     print_tx.clone(),
+                    vfs_responses.clone(),
                 ));
                 (
                     false,
@@ -167,6 +184,7 @@ pub async fn http_client(
                         None,
                     )),
                     lazy_load_blob: None,
+                    trace: None,
                 })
                 .await;
         }
@@ -374,32 +392,79 @@ async fn handle_http_request(
     client: reqwest::Client,
     send_to_loop: MessageSender,
     print_tx: PrintSender,
+    vfs_responses: VfsResponseSenders,
 ) {
+    match execute_http_request(
+        &our,
+        id,
+        &target,
+        req,
+        body,
+        client,
+        &send_to_loop,
+        &print_tx,
+        &vfs_responses,
+    )
+    .await
+    {
+        Ok((response, blob)) => {
+            // Forward the response to the target process
+            let Ok(body) =
+                serde_json::to_vec::<Result<HttpClientResponse, HttpClientError>>(&Ok(response))
+            else {
+                return;
+            };
+            let _ = send_to_loop
+                .send(KernelMessage {
+                    id,
+                    source: Address {
+                        node: our.to_string(),
+                        process: ProcessId::new(Some("http-client"), "distro", "sys"),
+                    },
+                    target,
+                    rsvp: None,
+                    message: Message::Response((
+                        Response {
+                            inherit: false,
+                            body,
+                            metadata: None,
+                            capabilities: vec![],
+                        },
+                        None,
+                    )),
+                    lazy_load_blob: blob,
+                    trace: None,
+                })
+                .await;
+        }
+        Err(e) => {
+            // Forward the error to the target process
+            http_error_message(our, id, target, expects_response, e, send_to_loop).await;
+        }
+    }
+}
+
+/// Build, send, and read the response of an outgoing HTTP request.
+/// Returns the response to send to the requester, and the body as a blob
+/// if it was not streamed to a vfs file.
+async fn execute_http_request(
+    our: &Arc<String>,
+    id: u64,
+    target: &Address,
+    req: OutgoingHttpRequest,
+    body: Option<LazyLoadBlob>,
+    client: reqwest::Client,
+    send_to_loop: &MessageSender,
+    print_tx: &PrintSender,
+    vfs_responses: &VfsResponseSenders,
+) -> Result<(HttpClientResponse, Option<LazyLoadBlob>), HttpClientError> {
     // Parse the HTTP Method
     let Ok(req_method) = http::Method::from_bytes(req.method.as_bytes()) else {
-        http_error_message(
-            our,
-            id,
-            target,
-            expects_response,
-            HttpClientError::BadMethod { method: req.method },
-            send_to_loop,
-        )
-        .await;
-        return;
+        return Err(HttpClientError::BadMethod { method: req.method });
     };
 
     let Ok(url) = url::Url::parse(&req.url) else {
-        http_error_message(
-            our,
-            id,
-            target,
-            expects_response,
-            HttpClientError::BadUrl { url: req.url },
-            send_to_loop,
-        )
-        .await;
-        return;
+        return Err(HttpClientError::BadUrl { url: req.url });
     };
 
     let _ = print_tx
@@ -410,6 +475,30 @@ async fn handle_http_request(
         ))
         .await;
 
+    // Redirect and proxy settings live on the client, so requests that
+    // customize them get a client of their own
+    let client = if req.max_redirects.is_some() || req.proxy.is_some() {
+        let mut client_builder = reqwest::Client::builder();
+        if let Some(max_redirects) = req.max_redirects {
+            client_builder = client_builder.redirect(if max_redirects == 0 {
+                reqwest::redirect::Policy::none()
+            } else {
+                reqwest::redirect::Policy::limited(max_redirects)
+            });
+        }
+        if let Some(proxy) = req.proxy {
+            let Ok(proxy) = reqwest::Proxy::all(&proxy) else {
+                return Err(HttpClientError::BadProxy { proxy });
+            };
+            client_builder = client_builder.proxy(proxy);
+        }
+        client_builder
+            .build()
+            .map_err(|e| HttpClientError::BuildRequestFailed(e.to_string()))?
+    } else {
+        client
+    };
+
     // Build the request
     let mut request_builder = client.request(req_method, url);
 
@@ -420,18 +509,7 @@ async fn handle_http_request(
             "HTTP/1.1" => request_builder.version(http::Version::HTTP_11),
             "HTTP/2.0" => request_builder.version(http::Version::HTTP_2),
             "HTTP/3.0" => request_builder.version(http::Version::HTTP_3),
-            _ => {
-                http_error_message(
-                    our,
-                    id,
-                    target,
-                    expects_response,
-                    HttpClientError::BadVersion { version },
-                    send_to_loop,
-                )
-                .await;
-                return;
-            }
+            _ => return Err(HttpClientError::BadVersion { version }),
         }
     }
 
@@ -441,59 +519,55 @@ async fn handle_http_request(
     }
 
     // Add the headers
-    let build = request_builder
+    let request = request_builder
         .headers(deserialize_headers(req.headers))
-        .build();
-    if let Err(e) = build {
-        http_error_message(
-            our,
-            id,
-            target,
-            expects_response,
-            HttpClientError::BuildRequestFailed(e.to_string()),
-            send_to_loop,
-        )
-        .await;
-        return;
-    };
+        .build()
+        .map_err(|e| HttpClientError::BuildRequestFailed(e.to_string()))?;
+
+    // Create the download destination before sending anything,
+    // which checks that the requester may write to it
+    let download = req.download_to.as_ref().map(|download_to| Download {
+        our,
+        requester: target,
+        send_to_loop,
+        vfs_responses,
+        target: download_to,
+    });
+    if let Some(download) = &download {
+        download.write(VfsAction::Write, vec![]).await?;
+    }
 
+    let result = send_and_read(
+        id,
+        request,
+        req.retry.as_ref(),
+        req.max_body_size,
+        download.as_ref(),
+        &client,
+        print_tx,
+    )
+    .await;
+    if let (Err(_), Some(download)) = (&result, &download) {
+        // don't leave an empty or partial file behind
+        let _ = download.write(VfsAction::RemoveFile, vec![]).await;
+    }
+    result
+}
+
+/// Send a built request and read its response, streaming the body to the
+/// download file if there is one.
+async fn send_and_read(
+    id: u64,
+    request: reqwest::Request,
+    retry: Option<&RetryPolicy>,
+    max_body_size: Option<u64>,
+    download: Option<&Download<'_>>,
+    client: &reqwest::Client,
+    print_tx: &PrintSender,
+) -> Result<(HttpClientResponse, Option<LazyLoadBlob>), HttpClientError> {
     // Send the HTTP request
-    match client.execute(build.unwrap()).await {
-        Ok(response) => {
-            // Handle the response and forward to the target process
-            let Ok(body) = serde_json::to_vec::<Result<HttpClientResponse, HttpClientError>>(&Ok(
-                HttpClientResponse::Http(HttpResponse {
-                    status: response.status().as_u16(),
-                    headers: serialize_headers(response.headers()),
-                }),
-            )) else {
-                return;
-            };
-            let _ = send_to_loop
-                .send(KernelMessage {
-                    id,
-                    source: Address {
-                        node: our.to_string(),
-                        process: ProcessId::new(Some("http-client"), "distro", "sys"),
-                    },
-                    target,
-                    rsvp: None,
-                    message: Message::Response((
-                        Response {
-                            inherit: false,
-                            body,
-                            metadata: None,
-                            capabilities: vec![],
-                        },
-                        None,
-                    )),
-                    lazy_load_blob: Some(LazyLoadBlob {
-                        mime: None,
-                        bytes: response.bytes().await.unwrap_or_default().to_vec(),
-                    }),
-                })
-                .await;
-        }
+    let response = match execute_with_retry(client, request, retry, print_tx).await {
+        Ok(response) => response,
         Err(e) => {
             let _ = print_tx
                 .send(Printout::new(
@@ -502,16 +576,249 @@ async fn handle_http_request(
                     "http-client: executed request but got error".to_string(),
                 ))
                 .await;
-            // Forward the error to the target process
-            http_error_message(
-                our,
+            return Err(HttpClientError::ExecuteRequestFailed(e.to_string()));
+        }
+    };
+
+    if let (Some(max_body_size), Some(content_length)) = (max_body_size, response.content_length())
+    {
+        if content_length > max_body_size {
+            return Err(HttpClientError::BodyTooLarge { max_body_size });
+        }
+    }
+
+    let http_response = HttpResponse {
+        status: response.status().as_u16(),
+        headers: serialize_headers(response.headers()),
+    };
+
+    match download {
+        Some(download) => {
+            let bytes_written = download.body(id, response, max_body_size).await?;
+            Ok((
+                HttpClientResponse::Download {
+                    response: http_response,
+                    path: download.target.path.clone(),
+                    bytes_written,
+                },
+                None,
+            ))
+        }
+        None => {
+            let bytes = read_body(response, max_body_size).await?;
+            Ok((
+                HttpClientResponse::Http(http_response),
+                Some(LazyLoadBlob { mime: None, bytes }),
+            ))
+        }
+    }
+}
+
+/// Execute a request, retrying according to the policy if one is given.
+/// If retries are exhausted on a retryable status, the last response is returned.
+async fn execute_with_retry(
+    client: &reqwest::Client,
+    request: reqwest::Request,
+    retry: Option<&RetryPolicy>,
+    print_tx: &PrintSender,
+) -> reqwest::Result<reqwest::Response> {
+    let Some(retry) = retry else {
+        return client.execute(request).await;
+    };
+    let mut backoff = Duration::from_millis(retry.initial_backoff_ms);
+    let max_backoff = Duration::from_millis(retry.max_backoff_ms);
+    let mut attempt = 1;
+    loop {
+        // bodies are always in-memory bytes, so cloning only fails
+        // for streaming bodies, which we never build
+        let Some(attempt_request) = request.try_clone() else {
+            return client.execute(request).await;
+        };
+        let result = client.execute(attempt_request).await;
+        let should_retry = match &result {
+            Ok(response) => is_retryable_status(response.status(), &retry.retry_on_status),
+            Err(e) => e.is_connect() || e.is_timeout() || e.is_request(),
+        };
+        if !should_retry || attempt >= retry.max_attempts {
+            return result;
+        }
+        let _ = print_tx
+            .send(Printout::new(
+                2,
+                HTTP_CLIENT_PROCESS_ID.clone(),
+                format!(
+                    "http-client: attempt {attempt}/{} to {} failed, retrying in {}ms",
+                    retry.max_attempts,
+                    request.url(),
+                    backoff.as_millis(),
+                ),
+            ))
+            .await;
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(max_backoff);
+        attempt += 1;
+    }
+}
+
+fn is_retryable_status(status: reqwest::StatusCode, retry_on_status: &[u16]) -> bool {
+    if retry_on_status.is_empty() {
+        status == reqwest::StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+    } else {
+        retry_on_status.contains(&status.as_u16())
+    }
+}
+
+/// Read a response body into memory, enforcing the max body size
+async fn read_body(
+    mut response: reqwest::Response,
+    max_body_size: Option<u64>,
+) -> Result<Vec<u8>, HttpClientError> {
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| HttpClientError::ExecuteRequestFailed(e.to_string()))?
+    {
+        if let Some(max_body_size) = max_body_size {
+            if (bytes.len() + chunk.len()) as u64 > max_body_size {
+                return Err(HttpClientError::BodyTooLarge { max_body_size });
+            }
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// A response body being written to a vfs file. Writes are sent to the vfs on
+/// behalf of the requester, so the vfs checks the requester's capabilities.
+struct Download<'a> {
+    our: &'a Arc<String>,
+    requester: &'a Address,
+    send_to_loop: &'a MessageSender,
+    vfs_responses: &'a VfsResponseSenders,
+    target: &'a DownloadTarget,
+}
+
+impl Download<'_> {
+    /// Stream the response body into the file, enforcing the max body size and
+    /// sending progress updates to the requester if asked to.
+    /// Returns the number of bytes written.
+    async fn body(
+        &self,
+        id: u64,
+        mut response: reqwest::Response,
+        max_body_size: Option<u64>,
+    ) -> Result<u64, HttpClientError> {
+        let total = response.content_length();
+        let mut downloaded: u64 = 0;
+        let mut buffer = Vec::with_capacity(DOWNLOAD_WRITE_SIZE);
+        loop {
+            let chunk = response.chunk().await.map_err(|e| self.error(&e))?;
+            if let Some(chunk) = &chunk {
+                downloaded += chunk.len() as u64;
+                if let Some(max_body_size) = max_body_size {
+                    if downloaded > max_body_size {
+                        return Err(HttpClientError::BodyTooLarge { max_body_size });
+                    }
+                }
+                buffer.extend_from_slice(chunk);
+            }
+            if !buffer.is_empty() && (buffer.len() >= DOWNLOAD_WRITE_SIZE || chunk.is_none()) {
+                self.write(VfsAction::Append, std::mem::take(&mut buffer))
+                    .await?;
+                if self.target.progress {
+                    self.progress(id, downloaded, total).await;
+                }
+            }
+            if chunk.is_none() {
+                return Ok(downloaded);
+            }
+        }
+    }
+
+    /// Send a request for the file to the vfs and wait for it to succeed.
+    async fn write(&self, action: VfsAction, bytes: Vec<u8>) -> Result<(), HttpClientError> {
+        // the vfs must not mistake a remote requester for a local process
+        if self.requester.node != **self.our {
+            return Err(HttpClientError::DownloadNoWriteCap {
+                path: self.target.path.clone(),
+            });
+        }
+        let id: u64 = rand::random();
+        let (send_response, recv_response) = tokio::sync::oneshot::channel();
+        self.vfs_responses.insert(id, send_response);
+        let _ = self
+            .send_to_loop
+            .send(KernelMessage {
                 id,
-                target,
-                expects_response,
-                HttpClientError::ExecuteRequestFailed(e.to_string()),
-                send_to_loop,
-            )
+                source: self.requester.clone(),
+                target: Address::new(self.our.as_str(), VFS_PROCESS_ID.clone()),
+                rsvp: Some(Address::new(
+                    self.our.as_str(),
+                    HTTP_CLIENT_PROCESS_ID.clone(),
+                )),
+                message: Message::Request(Request {
+                    inherit: false,
+                    body: serde_json::to_vec(&VfsRequest {
+                        path: self.target.path.clone(),
+                        action,
+                    })
+                    .unwrap(),
+                    expects_response: Some(VFS_TIMEOUT),
+                    metadata: None,
+                    capabilities: vec![],
+                }),
+                lazy_load_blob: Some(LazyLoadBlob { mime: None, bytes }),
+                trace: None,
+            })
+            .await;
+        let response = tokio::time::timeout(Duration::from_secs(VFS_TIMEOUT), recv_response).await;
+        self.vfs_responses.remove(&id);
+        let Ok(Ok(body)) = response else {
+            return Err(self.error(&"vfs did not respond"));
+        };
+        match serde_json::from_slice::<VfsResponse>(&body) {
+            Ok(VfsResponse::Ok) => Ok(()),
+            Ok(VfsResponse::Err(VfsError::NoWriteCap)) => {
+                Err(HttpClientError::DownloadNoWriteCap {
+                    path: self.target.path.clone(),
+                })
+            }
+            Ok(VfsResponse::Err(e)) => Err(self.error(&e)),
+            _ => Err(self.error(&"unexpected vfs response")),
+        }
+    }
+
+    async fn progress(&self, id: u64, downloaded: u64, total: Option<u64>) {
+        let _ = self
+            .send_to_loop
+            .send(KernelMessage {
+                id,
+                source: Address::new(self.our.as_str(), HTTP_CLIENT_PROCESS_ID.clone()),
+                target: self.requester.clone(),
+                rsvp: None,
+                message: Message::Request(Request {
+                    inherit: false,
+                    body: serde_json::to_vec(&DownloadProgress {
+                        path: self.target.path.clone(),
+                        downloaded,
+                        total,
+                    })
+                    .unwrap(),
+                    expects_response: None,
+                    metadata: None,
+                    capabilities: vec![],
+                }),
+                lazy_load_blob: None,
+                trace: None,
+            })
             .await;
+    }
+
+    fn error(&self, e: &dyn std::fmt::Display) -> HttpClientError {
+        HttpClientError::DownloadFailed {
+            path: self.target.path.clone(),
+            error: e.to_string(),
         }
     }
 }
@@ -592,6 +899,7 @@ async fn http_error_message(
                     None,
                 )),
                 lazy_load_blob: None,
+                trace: None,
             })
             .await;
     }
@@ -682,6 +990,223 @@ async fn handle_ws_message(
                 capabilities: vec![],
             }),
             lazy_load_blob: blob,
+            trace: None,
         })
         .await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: Vec<u8>) -> reqwest::Response {
+        reqwest::Response::from(http::Response::new(body))
+    }
+
+    fn download_target(progress: bool) -> DownloadTarget {
+        DownloadTarget {
+            path: "/pkg:publisher.os/downloads/file".to_string(),
+            progress,
+        }
+    }
+
+    /// vfs requests received, and all other messages sent
+    type FakeVfsLog = (Vec<(VfsRequest, KernelMessage)>, Vec<KernelMessage>);
+
+    /// Stand in for the vfs and the main loop: answer each vfs request with
+    /// `answer`, returning the requests and any other messages sent.
+    fn fake_vfs(
+        mut recv_from_client: MessageReceiver,
+        vfs_responses: VfsResponseSenders,
+        answer: VfsResponse,
+    ) -> tokio::task::JoinHandle<FakeVfsLog> {
+        tokio::spawn(async move {
+            let (mut vfs_requests, mut others) = (vec![], vec![]);
+            while let Some(km) = recv_from_client.recv().await {
+                if km.target.process != *VFS_PROCESS_ID {
+                    others.push(km);
+                    continue;
+                }
+                let Message::Request(request) = &km.message else {
+                    panic!("expected a request");
+                };
+                let vfs_request: VfsRequest = serde_json::from_slice(&request.body).unwrap();
+                let (_, sender) = vfs_responses.remove(&km.id).unwrap();
+                sender.send(serde_json::to_vec(&answer).unwrap()).unwrap();
+                vfs_requests.push((vfs_request, km));
+            }
+            (vfs_requests, others)
+        })
+    }
+
+    #[tokio::test]
+    async fn download_is_written_through_vfs_as_requester() {
+        let our = Arc::new("our.os".to_string());
+        let requester = Address::new("our.os", ProcessId::new(Some("a"), "pkg", "publisher.os"));
+        let (send_to_loop, recv_from_client) = tokio::sync::mpsc::channel(100);
+        let vfs_responses: VfsResponseSenders = Arc::new(DashMap::new());
+        let vfs = fake_vfs(recv_from_client, vfs_responses.clone(), VfsResponse::Ok);
+
+        let target = download_target(true);
+        let download = Download {
+            our: &our,
+            requester: &requester,
+            send_to_loop: &send_to_loop,
+            vfs_responses: &vfs_responses,
+            target: &target,
+        };
+        let body: Vec<u8> = (0..DOWNLOAD_WRITE_SIZE * 2 + 10).map(|i| i as u8).collect();
+        download.write(VfsAction::Write, vec![]).await.unwrap();
+        let written = download
+            .body(1, response(body.clone()), None)
+            .await
+            .unwrap();
+        assert_eq!(written, body.len() as u64);
+        drop(send_to_loop);
+
+        let (vfs_requests, others) = vfs.await.unwrap();
+        assert!(matches!(vfs_requests[0].0.action, VfsAction::Write));
+        let mut appended = vec![];
+        for (request, km) in &vfs_requests {
+            assert_eq!(request.path, target.path);
+            assert_eq!(km.source, requester);
+            assert_eq!(km.rsvp.as_ref().unwrap().process, *HTTP_CLIENT_PROCESS_ID);
+            if matches!(request.action, VfsAction::Append) {
+                appended.extend_from_slice(&km.lazy_load_blob.as_ref().unwrap().bytes);
+            }
+        }
+        assert_eq!(appended, body);
+
+        // one progress update per write, ending with the total
+        let progress: Vec<DownloadProgress> = others
+            .iter()
+            .map(|km| {
+                assert_eq!(km.target, requester);
+                let Message::Request(request) = &km.message else {
+                    panic!("expected a request");
+                };
+                serde_json::from_slice(&request.body).unwrap()
+            })
+            .collect();
+        assert_eq!(progress.len(), vfs_requests.len() - 1);
+        assert_eq!(progress.last().unwrap().downloaded, body.len() as u64);
+        assert!(vfs_responses.is_empty());
+    }
+
+    #[tokio::test]
+    async fn download_without_write_cap_is_refused() {
+        let our = Arc::new("our.os".to_string());
+        let requester = Address::new("our.os", ProcessId::new(Some("a"), "pkg", "publisher.os"));
+        let (send_to_loop, recv_from_client) = tokio::sync::mpsc::channel(100);
+        let vfs_responses: VfsResponseSenders = Arc::new(DashMap::new());
+        let _vfs = fake_vfs(
+            recv_from_client,
+            vfs_responses.clone(),
+            VfsResponse::Err(VfsError::NoWriteCap),
+        );
+
+        let target = download_target(false);
+        let download = Download {
+            our: &our,
+            requester: &requester,
+            send_to_loop: &send_to_loop,
+            vfs_responses: &vfs_responses,
+            target: &target,
+        };
+        assert!(matches!(
+            download.write(VfsAction::Write, vec![]).await,
+            Err(HttpClientError::DownloadNoWriteCap { .. })
+        ));
+
+        // a remote requester never reaches the vfs
+        let remote = Address::new("other.os", ProcessId::new(Some("a"), "pkg", "publisher.os"));
+        let download = Download {
+            requester: &remote,
+            ..download
+        };
+        assert!(matches!(
+            download.write(VfsAction::Write, vec![]).await,
+            Err(HttpClientError::DownloadNoWriteCap { .. })
+        ));
+        assert!(vfs_responses.is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_download_removes_its_file() {
+        let our = Arc::new("our.os".to_string());
+        let requester = Address::new("our.os", ProcessId::new(Some("a"), "pkg", "publisher.os"));
+        let (send_to_loop, recv_from_client) = tokio::sync::mpsc::channel(100);
+        let (print_tx, _print_rx) = tokio::sync::mpsc::channel(100);
+        let vfs_responses: VfsResponseSenders = Arc::new(DashMap::new());
+        let vfs = fake_vfs(recv_from_client, vfs_responses.clone(), VfsResponse::Ok);
+
+        // nothing listens on port 1, so the request fails after the file is created
+        let mut request: OutgoingHttpRequest = serde_json::from_str(
+            r#"{"method": "GET", "version": null, "url": "http://127.0.0.1:1", "headers": {}}"#,
+        )
+        .unwrap();
+        request.download_to = Some(download_target(false));
+        let result = execute_http_request(
+            &our,
+            1,
+            &requester,
+            request,
+            None,
+            reqwest::Client::new(),
+            &send_to_loop,
+            &print_tx,
+            &vfs_responses,
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(HttpClientError::ExecuteRequestFailed(_))
+        ));
+        drop(send_to_loop);
+
+        let (vfs_requests, _) = vfs.await.unwrap();
+        let actions: Vec<_> = vfs_requests.iter().map(|(r, _)| &r.action).collect();
+        assert!(matches!(
+            actions[..],
+            [VfsAction::Write, VfsAction::RemoveFile]
+        ));
+    }
+
+    #[tokio::test]
+    async fn body_size_is_limited() {
+        assert_eq!(
+            read_body(response(vec![0; 10]), Some(10))
+                .await
+                .unwrap()
+                .len(),
+            10
+        );
+        assert!(matches!(
+            read_body(response(vec![0; 11]), Some(10)).await,
+            Err(HttpClientError::BodyTooLarge { max_body_size: 10 })
+        ));
+    }
+
+    #[test]
+    fn retryable_statuses() {
+        let status = |code| reqwest::StatusCode::from_u16(code).unwrap();
+        assert!(is_retryable_status(status(429), &[]));
+        assert!(is_retryable_status(status(503), &[]));
+        assert!(!is_retryable_status(status(404), &[]));
+        assert!(is_retryable_status(status(404), &[404]));
+        assert!(!is_retryable_status(status(503), &[404]));
+    }
+
+    #[test]
+    fn requests_without_new_fields_still_parse() {
+        let request: OutgoingHttpRequest = serde_json::from_str(
+            r#"{"method": "GET", "version": null, "url": "https://example.com", "headers": {}}"#,
+        )
+        .unwrap();
+        assert!(request.download_to.is_none());
+        assert!(request.retry.is_none());
+        assert!(request.max_redirects.is_none());
+        assert!(request.max_body_size.is_none());
+        assert!(request.proxy.is_none());
+    }
+}
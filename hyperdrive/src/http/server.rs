@@ -10,15 +10,16 @@ use dashmap::DashMap;
 use futures::{SinkExt, StreamExt};
 use http::uri::Authority;
 use lib::types::core::{
-    check_process_id_hypermap_safe, Address, KernelCommand, KernelMessage, LazyLoadBlob, LoginInfo,
-    Message, MessageReceiver, MessageSender, PrintSender, Printout, ProcessId, Request, Response,
-    HTTP_SERVER_PROCESS_ID,
+    check_process_id_hypermap_safe, Address, ChangePasswordInfo, KernelCommand, KernelMessage,
+    LazyLoadBlob, LoginInfo, Message, MessageReceiver, MessageSender, PrintSender, Printout,
+    ProcessId, Request, Response, HTTP_SERVER_PROCESS_ID,
 };
 use route_recognizer::Router;
 use sha2::{Digest, Sha256};
 use std::{
     collections::{HashMap, HashSet},
     net::SocketAddr,
+    path::PathBuf,
     sync::Arc,
 };
 use tokio::sync::RwLock;
@@ -43,6 +44,9 @@ const LOGIN_HTML: &str = include_str!("login.html");
 type PathBindings = Arc<RwLock<Router<BoundPath>>>;
 type WsPathBindings = Arc<RwLock<Router<BoundWsPath>>>;
 type SecureSubdomains = Arc<RwLock<HashSet<String>>>;
+/// keyfile and JWT secret, replaced when the password is changed;
+/// each request works with a snapshot
+type SharedSecret = Arc<std::sync::RwLock<Arc<Vec<u8>>>>;
 
 struct BoundPath {
     pub app: Option<ProcessId>, // if None, path has been unbound
@@ -184,6 +188,7 @@ pub async fn http_server(
     send_to_loop: MessageSender,
     print_tx: PrintSender,
     expose_local: bool,
+    home_directory_path: PathBuf,
 ) -> anyhow::Result<()> {
     let http_response_senders: HttpResponseSenders = Arc::new(DashMap::new());
     let ws_senders: WebSocketSenders = Arc::new(DashMap::new());
@@ -215,11 +220,12 @@ pub async fn http_server(
         ws_path_bindings.clone(),
         ws_senders.clone(),
         secure_subdomains.clone(),
-        Arc::new(encoded_keyfile),
-        Arc::new(jwt_secret_bytes),
+        Arc::new(std::sync::RwLock::new(Arc::new(encoded_keyfile))),
+        Arc::new(std::sync::RwLock::new(Arc::new(jwt_secret_bytes))),
         send_to_loop.clone(),
         print_tx.clone(),
         expose_local,
+        Arc::new(home_directory_path),
     ));
 
     while let Some(km) = recv_in_server.recv().await {
@@ -248,13 +254,15 @@ async fn serve(
     ws_path_bindings: WsPathBindings,
     ws_senders: WebSocketSenders,
     secure_subdomains: SecureSubdomains,
-    encoded_keyfile: Arc<Vec<u8>>,
-    jwt_secret_bytes: Arc<Vec<u8>>,
+    encoded_keyfile: SharedSecret,
+    jwt_secret_bytes: SharedSecret,
     send_to_loop: MessageSender,
     print_tx: PrintSender,
     expose_local: bool,
+    home_directory_path: Arc<PathBuf>,
 ) {
     // filter to receive websockets
+    let cloned_ws_senders = ws_senders.clone();
     let cloned_our = our.clone();
     let cloned_jwt_secret_bytes = jwt_secret_bytes.clone();
     let cloned_msg_tx = send_to_loop.clone();
@@ -265,7 +273,7 @@ async fn serve(
         .and(warp::filters::host::optional())
         .and(warp::filters::header::headers_cloned())
         .and(warp::any().map(move || cloned_our.clone()))
-        .and(warp::any().map(move || cloned_jwt_secret_bytes.clone()))
+        .and(warp::any().map(move || cloned_jwt_secret_bytes.read().unwrap().clone()))
         .and(warp::any().map(move || ws_senders.clone()))
         .and(warp::any().map(move || ws_path_bindings.clone()))
         .and(warp::any().map(move || cloned_msg_tx.clone()))
@@ -285,6 +293,7 @@ async fn serve(
             .replace("${fake}", fake_node),
     );
     let cloned_our = our.clone();
+    let cloned_encoded_keyfile = encoded_keyfile.clone();
     let cloned_login_html: &'static str = login_html.to_string().leak();
     let login = warp::path("login").and(warp::path::end()).and(
         warp::get()
@@ -297,10 +306,28 @@ async fn serve(
                 .and(warp::body::content_length_limit(1024 * 16))
                 .and(warp::body::bytes())
                 .and(warp::any().map(move || cloned_our.clone()))
-                .and(warp::any().map(move || encoded_keyfile.clone()))
+                .and(warp::any().map(move || cloned_encoded_keyfile.read().unwrap().clone()))
                 .and_then(login_handler)),
     );
 
+    // filter to receive and handle password changes
+    let cloned_our = our.clone();
+    let cloned_jwt_secret_bytes = jwt_secret_bytes.clone();
+    let cloned_print_tx = print_tx.clone();
+    let change_password = warp::path("change-password")
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::filters::header::headers_cloned())
+        .and(warp::body::content_length_limit(1024 * 16))
+        .and(warp::body::bytes())
+        .and(warp::any().map(move || cloned_our.clone()))
+        .and(warp::any().map(move || encoded_keyfile.clone()))
+        .and(warp::any().map(move || cloned_jwt_secret_bytes.clone()))
+        .and(warp::any().map(move || cloned_ws_senders.clone()))
+        .and(warp::any().map(move || home_directory_path.clone()))
+        .and(warp::any().map(move || cloned_print_tx.clone()))
+        .and_then(change_password_handler);
+
     // filter to receive all other HTTP requests
     let filter = warp::filters::method::method()
         .and(warp::addr::remote())
@@ -313,14 +340,14 @@ async fn serve(
         .and(warp::any().map(move || http_response_senders.clone()))
         .and(warp::any().map(move || path_bindings.clone()))
         .and(warp::any().map(move || secure_subdomains.clone()))
-        .and(warp::any().map(move || jwt_secret_bytes.clone()))
+        .and(warp::any().map(move || jwt_secret_bytes.read().unwrap().clone()))
         .and(warp::any().map(move || send_to_loop.clone()))
         .and(warp::any().map(move || print_tx.clone()))
         .and(warp::any().map(move || login_html.clone()))
         .and(warp::any().map(move || expose_local.clone()))
         .and_then(http_handler);
 
-    let filter_with_ws = ws_route.or(login).or(filter);
+    let filter_with_ws = ws_route.or(login).or(change_password).or(filter);
     warp::serve(filter_with_ws)
         .run(([0, 0, 0, 0], our_port))
         .await;
@@ -456,6 +483,105 @@ async fn login_handler(
     }
 }
 
+/// handle POST on /change-password. requires a valid auth cookie for our node
+/// and the current password; re-encrypts the keyfile on disk under the new
+/// password, and if asked, replaces the JWT secret so every session must log in again.
+async fn change_password_handler(
+    headers: warp::http::HeaderMap,
+    body: warp::hyper::body::Bytes,
+    our: Arc<String>,
+    encoded_keyfile: SharedSecret,
+    jwt_secret_bytes: SharedSecret,
+    ws_senders: WebSocketSenders,
+    home_directory_path: Arc<PathBuf>,
+    print_tx: PrintSender,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let current_jwt_secret = jwt_secret_bytes.read().unwrap().clone();
+    let cookie = headers
+        .get("cookie")
+        .and_then(|cookie| cookie.to_str().ok())
+        .unwrap_or_default();
+    if !utils::auth_token_valid(&our, None, cookie, &current_jwt_secret) {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"Not logged in"),
+            StatusCode::UNAUTHORIZED,
+        )
+        .into_response());
+    }
+
+    let Ok(info) = serde_json::from_slice::<ChangePasswordInfo>(&body) else {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&"Failed to parse password change info"),
+            StatusCode::BAD_REQUEST,
+        )
+        .into_response());
+    };
+
+    let current_keyfile = encoded_keyfile.read().unwrap().clone();
+    let new_jwt_secret = info.rotate_jwt_secret.then(keygen::generate_jwt_secret);
+    let new_jwt_secret_clone = new_jwt_secret.clone();
+    // key derivation is deliberately slow: keep it off the async workers
+    let reencrypted = tokio::task::spawn_blocking(move || {
+        keygen::reencrypt_keyfile(
+            &current_keyfile,
+            &info.old_password_hash,
+            info.new_password_hash,
+            new_jwt_secret_clone.as_deref(),
+        )
+    })
+    .await;
+    let new_keyfile = match reencrypted {
+        Ok(Ok(new_keyfile)) => new_keyfile,
+        Ok(Err(e)) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&format!("Failed to change password: {e}")),
+                StatusCode::FORBIDDEN,
+            )
+            .into_response());
+        }
+        Err(e) => {
+            return Ok(warp::reply::with_status(
+                warp::reply::json(&format!("Failed to change password: {e}")),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+            .into_response());
+        }
+    };
+
+    if let Err(e) = keygen::write_keyfile_atomically(&home_directory_path, &new_keyfile).await {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&format!("Failed to write keyfile: {e}")),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        )
+        .into_response());
+    }
+    *encoded_keyfile.write().unwrap() = Arc::new(new_keyfile);
+    let rotated = new_jwt_secret.is_some();
+    if let Some(new_jwt_secret) = new_jwt_secret {
+        *jwt_secret_bytes.write().unwrap() = Arc::new(new_jwt_secret);
+        // websocket clients logged in with the old secret and would otherwise
+        // keep their connections
+        close_authenticated_websockets(&ws_senders).await;
+    }
+
+    Printout::new(
+        0,
+        HTTP_SERVER_PROCESS_ID.clone(),
+        if rotated {
+            "http-server: password changed; all sessions have been logged out"
+        } else {
+            "http-server: password changed"
+        },
+    )
+    .send(&print_tx)
+    .await;
+
+    Ok(
+        warp::reply::with_status(warp::reply::json(&"Password changed"), StatusCode::OK)
+            .into_response(),
+    )
+}
+
 async fn ws_handler(
     ws_connection: Ws,
     socket_addr: Option<SocketAddr>,
@@ -558,6 +684,7 @@ async fn ws_handler(
     }
 
     let extension = bound_path.extension;
+    let authenticated = cfg!(not(feature = "public-mode")) && bound_path.authenticated;
 
     drop(ws_path_bindings);
 
@@ -586,6 +713,7 @@ async fn ws_handler(
             send_to_loop.clone(),
             print_tx.clone(),
             extension,
+            authenticated,
         )
         .await;
     }))
@@ -833,6 +961,7 @@ async fn http_handler(
                     mime: None,
                     bytes: body.to_vec(),
                 }),
+                trace: None,
             },
             false,
         )
@@ -949,6 +1078,7 @@ async fn handle_rpc_message(
                 capabilities: vec![],
             }),
             lazy_load_blob: blob,
+            trace: None,
         },
         rpc_message.expects_response.is_none(),
     ))
@@ -1014,6 +1144,7 @@ fn make_websocket_message(
             mime: None,
             bytes: msg,
         }),
+        trace: None,
     })
 }
 
@@ -1096,6 +1227,7 @@ fn make_ext_websocket_message(
         rsvp: None,
         message,
         lazy_load_blob: blob,
+        trace: None,
     })
 }
 
@@ -1110,12 +1242,13 @@ async fn maintain_websocket(
     send_to_loop: MessageSender,
     print_tx: PrintSender,
     extension: bool,
+    authenticated: bool,
 ) {
     let (mut write_stream, mut read_stream) = ws.split();
 
     let channel_id: u32 = rand::random();
     let (ws_sender, mut ws_receiver) = tokio::sync::mpsc::channel(100);
-    ws_senders.insert(channel_id, (app.clone(), ws_sender));
+    ws_senders.insert(channel_id, (app.clone(), ws_sender, authenticated));
 
     Printout::new(
         2,
@@ -1208,6 +1341,22 @@ async fn maintain_websocket(
     let _ = stream.close().await;
 }
 
+/// Close every WebSocket connection that was authenticated with a login cookie.
+/// Each connection tells its process it closed once it winds down.
+async fn close_authenticated_websockets(ws_senders: &WebSocketSenders) {
+    let channel_ids: Vec<u32> = ws_senders
+        .iter()
+        .filter(|entry| entry.value().2)
+        .map(|entry| *entry.key())
+        .collect();
+    for channel_id in channel_ids {
+        // take the sender out first: avoid holding a map reference across the await
+        if let Some((_, (_, ws_sender, _))) = ws_senders.remove(&channel_id) {
+            let _ = ws_sender.send(warp::ws::Message::close()).await;
+        }
+    }
+}
+
 async fn websocket_close(
     channel_id: u32,
     process: ProcessId,
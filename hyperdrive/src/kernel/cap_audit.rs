@@ -0,0 +1,206 @@
+//! Audit log of capability changes made by the capabilities oracle, appended as
+//! JSON lines to `.cap_audit.jsonl` in the home directory. Once the log passes
+//! [`MAX_AUDIT_LOG_BYTES`] it's moved to `.cap_audit.jsonl.1`, replacing the
+//! previous one, and a new log is started.
+
+use lib::types::core::{self as t, KERNEL_PROCESS_ID};
+use std::path::{Path, PathBuf};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+
+const AUDIT_LOG_FILE: &str = ".cap_audit.jsonl";
+const MAX_AUDIT_LOG_BYTES: u64 = 16 * 1024 * 1024;
+
+pub struct CapAudit {
+    /// lines for the writer task
+    lines: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+impl CapAudit {
+    /// Start writing the entries recorded to the audit log in `home_directory_path`.
+    pub fn new(home_directory_path: &Path, send_to_terminal: t::PrintSender) -> Self {
+        let mut writer = Writer::new(home_directory_path);
+        let (send_lines, mut recv_lines) = mpsc::unbounded_channel::<Vec<u8>>();
+        tokio::spawn(async move {
+            while let Some(lines) = recv_lines.recv().await {
+                if let Err(e) = writer.write(&lines).await {
+                    t::Printout::new(
+                        0,
+                        KERNEL_PROCESS_ID.clone(),
+                        format!("kernel: couldn't append to capability audit log: {e}"),
+                    )
+                    .send(&send_to_terminal)
+                    .await;
+                }
+            }
+        });
+        Self { lines: send_lines }
+    }
+
+    /// Append an entry for each of `caps` changed on `target`.
+    pub fn record<'a>(
+        &self,
+        action: t::CapAuditAction,
+        actor: &t::ProcessId,
+        target: &t::ProcessId,
+        caps: impl IntoIterator<Item = &'a t::Capability>,
+        reason: &str,
+    ) {
+        let entries = caps.into_iter().map(|cap| t::CapAuditEntry {
+            timestamp: super::unix_time_secs(),
+            action,
+            actor: actor.clone(),
+            target: target.clone(),
+            cap: cap.clone(),
+            reason: reason.to_string(),
+        });
+        let lines = lines(entries);
+        if !lines.is_empty() {
+            self.lines.send(lines).ok();
+        }
+    }
+}
+
+/// An entry for `cap` granted to `target` while bootstrapping.
+pub fn bootstrap_entry(
+    actor: &t::ProcessId,
+    target: &t::ProcessId,
+    cap: &t::Capability,
+) -> t::CapAuditEntry {
+    t::CapAuditEntry {
+        timestamp: super::unix_time_secs(),
+        action: t::CapAuditAction::Add,
+        actor: actor.clone(),
+        target: target.clone(),
+        cap: cap.clone(),
+        reason: "bootstrap".to_string(),
+    }
+}
+
+/// Append the capabilities granted while bootstrapping, before the kernel starts.
+pub async fn record_bootstrap(
+    home_directory_path: &Path,
+    entries: Vec<t::CapAuditEntry>,
+) -> std::io::Result<()> {
+    let lines = lines(entries);
+    if lines.is_empty() {
+        return Ok(());
+    }
+    Writer::new(home_directory_path).write(&lines).await
+}
+
+fn lines(entries: impl IntoIterator<Item = t::CapAuditEntry>) -> Vec<u8> {
+    let mut lines = Vec::new();
+    for entry in entries {
+        serde_json::to_writer(&mut lines, &entry).unwrap();
+        lines.push(b'\n');
+    }
+    lines
+}
+
+struct Writer {
+    path: PathBuf,
+    /// bytes in the log; `None` until it's been opened
+    len: Option<u64>,
+}
+
+impl Writer {
+    fn new(home_directory_path: &Path) -> Self {
+        Self {
+            path: home_directory_path.join(AUDIT_LOG_FILE),
+            len: None,
+        }
+    }
+
+    async fn write(&mut self, lines: &[u8]) -> std::io::Result<()> {
+        let len = match self.len {
+            Some(len) => len,
+            None => match tokio::fs::metadata(&self.path).await {
+                Ok(metadata) => metadata.len(),
+                Err(_) => 0,
+            },
+        };
+        let len = if len > 0 && len + lines.len() as u64 > MAX_AUDIT_LOG_BYTES {
+            tokio::fs::rename(&self.path, self.path.with_extension("jsonl.1")).await?;
+            0
+        } else {
+            len
+        };
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(lines).await?;
+        file.flush().await?;
+        self.len = Some(len + lines.len() as u64);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(reason: String) -> t::CapAuditEntry {
+        let process = t::ProcessId::new(Some("a"), "pkg", "sys");
+        t::CapAuditEntry {
+            timestamp: 0,
+            action: t::CapAuditAction::Add,
+            actor: KERNEL_PROCESS_ID.clone(),
+            target: process.clone(),
+            cap: t::Capability::messaging(t::Address::new("our", process)),
+            reason,
+        }
+    }
+
+    #[tokio::test]
+    async fn rotates_log_past_limit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let home = tmp.path();
+
+        // each entry is over half the limit, so each write rotates the log
+        let big = "x".repeat(MAX_AUDIT_LOG_BYTES as usize / 2 + 1);
+        record_bootstrap(home, vec![entry(format!("first{big}"))])
+            .await
+            .unwrap();
+        record_bootstrap(home, vec![entry(format!("second{big}"))])
+            .await
+            .unwrap();
+        record_bootstrap(home, vec![entry(format!("third{big}"))])
+            .await
+            .unwrap();
+
+        let current = tokio::fs::read_to_string(home.join(AUDIT_LOG_FILE))
+            .await
+            .unwrap();
+        let rotated = tokio::fs::read_to_string(home.join(".cap_audit.jsonl.1"))
+            .await
+            .unwrap();
+        assert!(current.contains("\"third"));
+        assert!(!current.contains("\"second"));
+        assert!(rotated.contains("\"second"));
+        assert!(!rotated.contains("\"first"));
+    }
+
+    #[tokio::test]
+    async fn appends_below_limit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let home = tmp.path();
+
+        let mut writer = Writer::new(home);
+        writer
+            .write(&lines(vec![entry("one".into())]))
+            .await
+            .unwrap();
+        writer
+            .write(&lines(vec![entry("two".into())]))
+            .await
+            .unwrap();
+
+        let current = tokio::fs::read_to_string(home.join(AUDIT_LOG_FILE))
+            .await
+            .unwrap();
+        assert_eq!(current.lines().count(), 2);
+        assert!(!home.join(".cap_audit.jsonl.1").exists());
+    }
+}
@@ -0,0 +1,216 @@
+//! On-disk cache of compiled Wasm components, so that processes skip compilation
+//! when they start at boot or restart.
+//!
+//! Entries are keyed by a hash of the Wasm bytes, so a package update produces a
+//! new entry rather than reusing a stale one; entries for Wasm no longer installed
+//! are removed at the next boot. They live in a directory named after the engine's
+//! compatibility hash: a wasmtime upgrade or config change makes a new directory,
+//! and the old ones are removed at boot.
+//!
+//! The cache is only an optimization: if it can't be used, components are compiled
+//! every time.
+
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    hash::{DefaultHasher, Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::io::AsyncWriteExt;
+use wasmtime::{component::Component, Engine};
+
+const ENTRY_EXTENSION: &str = "cwasm";
+
+#[derive(Clone)]
+pub struct ComponentCache {
+    /// `None` if the cache couldn't be set up
+    dir: Option<Arc<PathBuf>>,
+}
+
+impl ComponentCache {
+    /// Create the cache directory for this engine under `<home>/.component_cache`,
+    /// removing the directories of other engines, which only costs disk if it fails.
+    pub async fn new(home_directory_path: &Path, engine: &Engine) -> anyhow::Result<Self> {
+        let root = home_directory_path.join(".component_cache");
+        let mut hasher = DefaultHasher::new();
+        engine.precompile_compatibility_hash().hash(&mut hasher);
+        let engine_dir = format!("{:016x}", hasher.finish());
+
+        tokio::fs::create_dir_all(&root).await?;
+        let mut entries = tokio::fs::read_dir(&root).await?;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.file_name() != engine_dir.as_str() {
+                let path = entry.path();
+                let _ = match tokio::fs::metadata(&path).await {
+                    Ok(metadata) if metadata.is_dir() => tokio::fs::remove_dir_all(&path).await,
+                    _ => tokio::fs::remove_file(&path).await,
+                };
+            }
+        }
+        let dir = root.join(engine_dir);
+        tokio::fs::create_dir_all(&dir).await?;
+        Ok(Self {
+            dir: Some(Arc::new(dir)),
+        })
+    }
+
+    /// A cache that caches nothing, for when `new` fails.
+    pub fn disabled() -> Self {
+        Self { dir: None }
+    }
+
+    /// The name of the cache entry for these Wasm bytes.
+    pub fn entry_name(wasm_bytes: &[u8]) -> String {
+        format!(
+            "{}.{ENTRY_EXTENSION}",
+            hex::encode(Sha256::digest(wasm_bytes))
+        )
+    }
+
+    /// Load the compiled component for these Wasm bytes, compiling and caching it on
+    /// a miss. A cache entry that can't be loaded is replaced.
+    pub async fn get(&self, engine: &Engine, wasm_bytes: &[u8]) -> anyhow::Result<Component> {
+        let Some(dir) = &self.dir else {
+            return Component::new(engine, wasm_bytes);
+        };
+        let path = dir.join(Self::entry_name(wasm_bytes));
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            // SAFETY: files in the cache directory are only written by `write_entry`
+            // below, from `Component::serialize` output of an engine with this config,
+            // and are synced to disk before they take an entry's name.
+            if let Ok(component) = unsafe { Component::deserialize_file(engine, &path) } {
+                return Ok(component);
+            }
+        }
+        let component = Component::new(engine, wasm_bytes)?;
+        // a failure to cache only costs a compile next time
+        if let Ok(serialized) = component.serialize() {
+            let _ = write_entry(&path, &serialized).await;
+        }
+        Ok(component)
+    }
+
+    /// Remove the entries not named in `keep`, and any partly written entries left
+    /// by a crash. Returns how many files were removed.
+    pub async fn retain(&self, keep: &HashSet<String>) -> anyhow::Result<usize> {
+        self.remove_where(|name| {
+            !(name.ends_with(&format!(".{ENTRY_EXTENSION}")) && keep.contains(name))
+        })
+        .await
+    }
+
+    /// Remove every cached component. Returns how many were removed.
+    pub async fn purge(&self) -> anyhow::Result<usize> {
+        self.remove_where(|_| true).await
+    }
+
+    async fn remove_where(&self, remove: impl Fn(&str) -> bool) -> anyhow::Result<usize> {
+        let Some(dir) = &self.dir else {
+            return Ok(0);
+        };
+        let mut removed = 0;
+        let mut entries = tokio::fs::read_dir(dir.as_ref()).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if remove(&entry.file_name().to_string_lossy()) {
+                tokio::fs::remove_file(entry.path()).await?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Write to a temporary file, sync it, then rename it into place, so that neither a
+/// concurrent start nor one after a crash ever loads a partial file.
+async fn write_entry(path: &Path, serialized: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension(format!("{}.tmp", rand::random::<u64>()));
+    let result = async {
+        let mut file = tokio::fs::File::create(&tmp).await?;
+        file.write_all(serialized).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&tmp, path).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = tokio::fs::remove_file(&tmp).await;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn names(cache: &ComponentCache) -> HashSet<String> {
+        let mut names = HashSet::new();
+        let mut entries = tokio::fs::read_dir(cache.dir.as_ref().unwrap().as_ref())
+            .await
+            .unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            names.insert(entry.file_name().to_string_lossy().to_string());
+        }
+        names
+    }
+
+    #[tokio::test]
+    async fn compiled_components_are_cached_and_loaded() {
+        let home = tempfile::tempdir().unwrap();
+        let engine = Engine::default();
+        let cache = ComponentCache::new(home.path(), &engine).await.unwrap();
+        let wasm = b"(component)";
+        cache.get(&engine, wasm).await.unwrap();
+        assert_eq!(
+            names(&cache).await,
+            HashSet::from([ComponentCache::entry_name(wasm)])
+        );
+        cache.get(&engine, wasm).await.unwrap();
+        assert_eq!(names(&cache).await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn retain_removes_stale_entries_and_leftovers() {
+        let home = tempfile::tempdir().unwrap();
+        let engine = Engine::default();
+        let cache = ComponentCache::new(home.path(), &engine).await.unwrap();
+        let dir = cache.dir.clone().unwrap();
+        for name in ["kept.cwasm", "stale.cwasm", "kept.1234.tmp"] {
+            tokio::fs::write(dir.join(name), b"").await.unwrap();
+        }
+        let keep = HashSet::from(["kept.cwasm".to_string(), "kept.1234.tmp".to_string()]);
+        assert_eq!(cache.retain(&keep).await.unwrap(), 2);
+        assert_eq!(
+            names(&cache).await,
+            HashSet::from(["kept.cwasm".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn stray_files_from_other_engines_are_removed() {
+        let home = tempfile::tempdir().unwrap();
+        let root = home.path().join(".component_cache");
+        tokio::fs::create_dir_all(root.join("old-engine"))
+            .await
+            .unwrap();
+        tokio::fs::write(root.join("stray"), b"").await.unwrap();
+        let cache = ComponentCache::new(home.path(), &Engine::default())
+            .await
+            .unwrap();
+        let mut entries = tokio::fs::read_dir(&root).await.unwrap();
+        let mut count = 0;
+        while entries.next_entry().await.unwrap().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 1);
+        assert!(cache.dir.is_some());
+    }
+
+    #[tokio::test]
+    async fn disabled_cache_still_compiles() {
+        let engine = Engine::default();
+        let cache = ComponentCache::disabled();
+        let wasm = b"(component)";
+        assert!(cache.get(&engine, wasm).await.is_ok());
+        assert_eq!(cache.purge().await.unwrap(), 0);
+    }
+}
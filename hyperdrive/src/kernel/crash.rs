@@ -0,0 +1,277 @@
+//! Recent crash reports of each process, kept in `.crash_reports` in the home
+//! directory so they survive a reboot, and sent on to subscribed processes, which
+//! are kept in `.crash_subscribers`.
+
+use super::persisted;
+use lib::types::core::{self as t, KERNEL_PROCESS_ID};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+};
+
+/// oldest reports of a process are dropped past this many
+const MAX_REPORTS_PER_PROCESS: usize = 10;
+
+pub struct CrashReports {
+    path: PathBuf,
+    subscribers_path: PathBuf,
+    reports: HashMap<t::ProcessId, VecDeque<t::CrashReport>>,
+    subscribers: HashSet<t::Address>,
+}
+
+impl CrashReports {
+    pub async fn load(home_directory_path: &Path, send_to_terminal: &t::PrintSender) -> Self {
+        let path = home_directory_path.join(".crash_reports");
+        let subscribers_path = home_directory_path.join(".crash_subscribers");
+        let reports = persisted::load_or_report(&path, send_to_terminal).await;
+        let subscribers = persisted::load_or_report(&subscribers_path, send_to_terminal).await;
+        Self {
+            path,
+            subscribers_path,
+            reports,
+            subscribers,
+        }
+    }
+
+    pub async fn subscribe(&mut self, subscriber: t::Address, send_to_terminal: &t::PrintSender) {
+        if self.subscribers.insert(subscriber) {
+            self.persist_subscribers(send_to_terminal).await;
+        }
+    }
+
+    pub async fn unsubscribe(
+        &mut self,
+        subscriber: &t::Address,
+        send_to_terminal: &t::PrintSender,
+    ) {
+        if self.subscribers.remove(subscriber) {
+            self.persist_subscribers(send_to_terminal).await;
+        }
+    }
+
+    /// Drop the reports and subscriptions of processes that aren't `installed`.
+    pub async fn retain(
+        &mut self,
+        installed: impl Fn(&t::ProcessId) -> bool,
+        send_to_terminal: &t::PrintSender,
+    ) {
+        let reports = self.reports.len();
+        self.reports.retain(|process, _| installed(process));
+        if self.reports.len() != reports {
+            self.persist_reports(send_to_terminal).await;
+        }
+        let subscribers = self.subscribers.len();
+        self.subscribers
+            .retain(|subscriber| installed(&subscriber.process));
+        if self.subscribers.len() != subscribers {
+            self.persist_subscribers(send_to_terminal).await;
+        }
+    }
+
+    /// Drop the reports and subscription of an uninstalled process.
+    pub async fn forget(&mut self, process: &t::ProcessId, send_to_terminal: &t::PrintSender) {
+        self.retain(|installed| installed != process, send_to_terminal)
+            .await;
+    }
+
+    /// Recent reports of one process, or of all processes, oldest first.
+    pub fn get(&self, process: Option<&t::ProcessId>) -> Vec<t::CrashEvent> {
+        let mut events: Vec<t::CrashEvent> = self
+            .reports
+            .iter()
+            .filter(|(id, _)| process.is_none_or(|process| *id == process))
+            .flat_map(|(id, reports)| {
+                reports.iter().map(|report| t::CrashEvent {
+                    process: id.clone(),
+                    report: report.clone(),
+                })
+            })
+            .collect();
+        events.sort_by_key(|event| event.report.timestamp);
+        events
+    }
+
+    /// Store a report, persist the reports, and send the report to subscribers.
+    /// The message the process was handling may hold another process's data, so
+    /// it is only sent to the process itself, and is neither stored nor persisted.
+    pub async fn record(
+        &mut self,
+        our_name: &str,
+        process: t::ProcessId,
+        report: t::CrashReport,
+        send_to_loop: &t::MessageSender,
+        send_to_terminal: &t::PrintSender,
+    ) {
+        let stripped = t::CrashReport {
+            last_message: None,
+            ..report.clone()
+        };
+        let reports = self.reports.entry(process.clone()).or_default();
+        reports.push_back(stripped.clone());
+        if reports.len() > MAX_REPORTS_PER_PROCESS {
+            reports.pop_front();
+        }
+        self.persist_reports(send_to_terminal).await;
+
+        let own_body = serde_json::to_vec(&t::CrashEvent {
+            process: process.clone(),
+            report,
+        })
+        .unwrap();
+        let body = serde_json::to_vec(&t::CrashEvent {
+            process: process.clone(),
+            report: stripped,
+        })
+        .unwrap();
+        for subscriber in &self.subscribers {
+            let body = if subscriber.node == our_name && subscriber.process == process {
+                own_body.clone()
+            } else {
+                body.clone()
+            };
+            t::KernelMessage::builder()
+                .id(rand::random())
+                .source((our_name, KERNEL_PROCESS_ID.clone()))
+                .target(subscriber.clone())
+                .message(t::Message::Request(t::Request {
+                    inherit: false,
+                    expects_response: None,
+                    body,
+                    metadata: None,
+                    capabilities: vec![],
+                }))
+                .build()
+                .unwrap()
+                .send(send_to_loop)
+                .await;
+        }
+    }
+
+    async fn persist_reports(&self, send_to_terminal: &t::PrintSender) {
+        persist(&self.path, &self.reports, "crash reports", send_to_terminal).await;
+    }
+
+    async fn persist_subscribers(&self, send_to_terminal: &t::PrintSender) {
+        persist(
+            &self.subscribers_path,
+            &self.subscribers,
+            "crash subscribers",
+            send_to_terminal,
+        )
+        .await;
+    }
+}
+
+async fn persist(
+    path: &Path,
+    value: &impl serde::Serialize,
+    what: &str,
+    send_to_terminal: &t::PrintSender,
+) {
+    if let Err(e) = persisted::save(path, value).await {
+        t::Printout::new(
+            0,
+            KERNEL_PROCESS_ID.clone(),
+            format!("kernel: couldn't persist {what}: {e}"),
+        )
+        .send(send_to_terminal)
+        .await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(name: &str) -> t::ProcessId {
+        t::ProcessId::new(Some(name), "pkg", "sys")
+    }
+
+    fn report(timestamp: u64) -> t::CrashReport {
+        t::CrashReport {
+            timestamp,
+            trap: "trap".into(),
+            backtrace: None,
+            stderr: String::new(),
+            last_message: Some("secret".into()),
+            restart_attempt: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn only_the_crashed_process_gets_its_last_message() {
+        let home = tempfile::tempdir().unwrap();
+        let (send_to_loop, mut recv_from_loop) = tokio::sync::mpsc::channel(10);
+        let (send_to_terminal, _recv_prints) = tokio::sync::mpsc::channel(10);
+        let mut crash_reports = CrashReports::load(home.path(), &send_to_terminal).await;
+        crash_reports
+            .subscribe(t::Address::new("our", process("a")), &send_to_terminal)
+            .await;
+        crash_reports
+            .subscribe(t::Address::new("our", process("b")), &send_to_terminal)
+            .await;
+
+        crash_reports
+            .record(
+                "our",
+                process("a"),
+                report(1),
+                &send_to_loop,
+                &send_to_terminal,
+            )
+            .await;
+        for _ in 0..2 {
+            let km = recv_from_loop.recv().await.unwrap();
+            let t::Message::Request(request) = km.message else {
+                panic!("expected a request");
+            };
+            let event: t::CrashEvent = serde_json::from_slice(&request.body).unwrap();
+            if km.target.process == process("a") {
+                assert_eq!(event.report.last_message.as_deref(), Some("secret"));
+            } else {
+                assert_eq!(event.report.last_message, None);
+            }
+        }
+        // nor is it kept in the stored reports
+        assert_eq!(crash_reports.get(None)[0].report.last_message, None);
+    }
+
+    #[tokio::test]
+    async fn subscribers_persist_and_uninstalled_processes_are_forgotten() {
+        let home = tempfile::tempdir().unwrap();
+        let (send_to_loop, _recv_from_loop) = tokio::sync::mpsc::channel(10);
+        let (send_to_terminal, _recv_prints) = tokio::sync::mpsc::channel(10);
+        let mut crash_reports = CrashReports::load(home.path(), &send_to_terminal).await;
+        for name in ["a", "b"] {
+            crash_reports
+                .subscribe(t::Address::new("our", process(name)), &send_to_terminal)
+                .await;
+            crash_reports
+                .record(
+                    "our",
+                    process(name),
+                    report(1),
+                    &send_to_loop,
+                    &send_to_terminal,
+                )
+                .await;
+        }
+
+        let mut crash_reports = CrashReports::load(home.path(), &send_to_terminal).await;
+        assert_eq!(crash_reports.subscribers.len(), 2);
+        assert_eq!(crash_reports.get(None).len(), 2);
+
+        crash_reports.forget(&process("a"), &send_to_terminal).await;
+        assert!(crash_reports.get(Some(&process("a"))).is_empty());
+
+        // at boot, only installed processes are kept
+        let mut crash_reports = CrashReports::load(home.path(), &send_to_terminal).await;
+        assert_eq!(crash_reports.subscribers.len(), 1);
+        crash_reports
+            .retain(|process_id| *process_id == process("c"), &send_to_terminal)
+            .await;
+        let crash_reports = CrashReports::load(home.path(), &send_to_terminal).await;
+        assert!(crash_reports.subscribers.is_empty());
+        assert!(crash_reports.get(None).is_empty());
+    }
+}
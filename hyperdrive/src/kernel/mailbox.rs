@@ -0,0 +1,207 @@
+//! Process mailboxes. Each has two lanes: a control lane for Responses, send errors,
+//! and Requests from the kernel, and a bulk lane for all other Requests. A process
+//! always takes from the control lane first, so a flood of Requests can't hold up
+//! the Responses and kernel commands behind it. The kernel delivers with `try_send`,
+//! so a full lane never stalls the event loop: a Request for a full bulk lane is
+//! reported to its sender, and anything for a full control lane waits its turn in
+//! an overflow queue, which is moved into the lane in order as room frees up.
+//!
+//! Each process's capacity can be set apart from the default, and is kept in
+//! `.mailbox_capacities` in the home directory.
+
+use super::persisted;
+use lib::types::core::{self as t, KERNEL_PROCESS_ID};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::mpsc::{
+    self,
+    error::{SendError, TrySendError},
+};
+
+/// Messages each lane of a mailbox holds unless configured otherwise.
+pub const DEFAULT_MAILBOX_CAPACITY: usize = 100;
+
+const MAILBOX_CAPACITIES_FILE: &str = ".mailbox_capacities";
+
+type ProcessMessage = Result<t::KernelMessage, t::WrappedSendError>;
+
+#[derive(Clone)]
+pub struct MailboxSender {
+    control: mpsc::Sender<ProcessMessage>,
+    bulk: mpsc::Sender<ProcessMessage>,
+    /// control messages waiting for room in the control lane, oldest first
+    overflow: Arc<Mutex<VecDeque<ProcessMessage>>>,
+}
+
+pub struct Mailbox {
+    control: mpsc::Receiver<ProcessMessage>,
+    bulk: mpsc::Receiver<ProcessMessage>,
+}
+
+/// Create a mailbox whose lanes hold `capacity` messages each.
+pub fn mailbox(capacity: usize) -> (MailboxSender, Mailbox) {
+    let (control_sender, control) = mpsc::channel(capacity);
+    let (bulk_sender, bulk) = mpsc::channel(capacity);
+    (
+        MailboxSender {
+            control: control_sender,
+            bulk: bulk_sender,
+            overflow: Arc::new(Mutex::new(VecDeque::new())),
+        },
+        Mailbox { control, bulk },
+    )
+}
+
+impl MailboxSender {
+    /// Deliver a message, waiting for room if it goes in the bulk lane.
+    pub async fn send(&self, message: ProcessMessage) -> Result<(), SendError<ProcessMessage>> {
+        match self.try_send(message) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(message)) => self.bulk.send(message).await,
+            Err(TrySendError::Closed(message)) => Err(SendError(message)),
+        }
+    }
+
+    /// Deliver a message without waiting. Only fails with [`TrySendError::Full`]
+    /// for the bulk lane: a message for the control lane is queued behind any that
+    /// are already waiting for room, and moved into the lane once there is some.
+    pub fn try_send(&self, message: ProcessMessage) -> Result<(), TrySendError<ProcessMessage>> {
+        if matches!(&message, Ok(km) if is_bulk(km)) {
+            return self.bulk.try_send(message);
+        }
+        let mut overflow = self.overflow.lock().unwrap();
+        if !overflow.is_empty() {
+            overflow.push_back(message);
+            return Ok(());
+        }
+        match self.control.try_send(message) {
+            Err(TrySendError::Full(message)) => {
+                overflow.push_back(message);
+                tokio::spawn(drain_overflow(self.control.clone(), self.overflow.clone()));
+                Ok(())
+            }
+            result => result,
+        }
+    }
+}
+
+/// Move queued control messages into the control lane as room frees up, in order.
+/// A message leaves the queue only together with the room it takes, so that a
+/// message sent meanwhile can't overtake it.
+async fn drain_overflow(
+    control: mpsc::Sender<ProcessMessage>,
+    overflow: Arc<Mutex<VecDeque<ProcessMessage>>>,
+) {
+    loop {
+        let Ok(permit) = control.reserve().await else {
+            // the process is gone
+            overflow.lock().unwrap().clear();
+            return;
+        };
+        let mut overflow = overflow.lock().unwrap();
+        let Some(message) = overflow.pop_front() else {
+            return;
+        };
+        permit.send(message);
+    }
+}
+
+/// Whether a message goes in the bulk lane: a Request from anyone but the kernel.
+fn is_bulk(km: &t::KernelMessage) -> bool {
+    matches!(km.message, t::Message::Request(_)) && km.source.process != *KERNEL_PROCESS_ID
+}
+
+impl Mailbox {
+    /// Take the next message, from the control lane if it has any. `None` once every
+    /// sender is gone.
+    pub async fn recv(&mut self) -> Option<ProcessMessage> {
+        tokio::select! {
+            biased;
+            Some(message) = self.control.recv() => Some(message),
+            Some(message) = self.bulk.recv() => Some(message),
+            else => None,
+        }
+    }
+}
+
+/// The capacity of each process's mailbox lanes.
+pub struct MailboxCapacities {
+    path: PathBuf,
+    default: usize,
+    /// processes whose capacity differs from the default
+    set: HashMap<t::ProcessId, usize>,
+}
+
+impl MailboxCapacities {
+    pub async fn load(
+        home_directory_path: &Path,
+        default: usize,
+        send_to_terminal: &t::PrintSender,
+    ) -> Self {
+        let path = home_directory_path.join(MAILBOX_CAPACITIES_FILE);
+        let set = persisted::load_or_report(&path, send_to_terminal).await;
+        Self { path, default, set }
+    }
+
+    /// The capacity of each lane of `process`'s mailbox.
+    pub fn of(&self, process: &t::ProcessId) -> usize {
+        self.set.get(process).copied().unwrap_or(self.default)
+    }
+
+    /// Set the capacity of `process`'s mailbox, or with `None` return it to the
+    /// default. Takes effect the next time the process starts.
+    pub async fn set(
+        &mut self,
+        process: t::ProcessId,
+        capacity: Option<usize>,
+    ) -> Result<(), String> {
+        match capacity {
+            Some(0) => return Err("mailbox capacity must be at least 1".to_string()),
+            Some(capacity) => self.set.insert(process, capacity),
+            None => self.set.remove(&process),
+        };
+        persisted::save(&self.path, &self.set)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(id: u64) -> ProcessMessage {
+        Ok(t::KernelMessage::builder()
+            .id(id)
+            .source(("our", "a", "a", "sys"))
+            .target(("our", "b", "b", "sys"))
+            .message(t::Message::Response((
+                t::Response {
+                    inherit: false,
+                    body: vec![],
+                    metadata: None,
+                    capabilities: vec![],
+                },
+                None,
+            )))
+            .build()
+            .unwrap())
+    }
+
+    #[tokio::test]
+    async fn a_full_control_lane_queues_messages_in_order() {
+        let (sender, mut mailbox) = mailbox(2);
+        for id in 0..10 {
+            sender.try_send(response(id)).unwrap();
+        }
+        for id in 0..10 {
+            let Some(Ok(km)) = mailbox.recv().await else {
+                panic!("expected a message");
+            };
+            assert_eq!(km.id, id);
+        }
+    }
+}
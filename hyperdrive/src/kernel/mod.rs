@@ -2,23 +2,44 @@ use lib::types::core::{self as t, KERNEL_PROCESS_ID, STATE_PROCESS_ID, VFS_PROCE
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{HashMap, HashSet},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
     time::Duration,
 };
 use tokio::{
-    sync::{mpsc, Mutex},
+    sync::{mpsc::error::TrySendError, Mutex},
     task::JoinHandle,
 };
-use wasmtime::{Config, Engine, WasmBacktraceDetails};
+use wasmtime::{component::Component, Config, Engine, WasmBacktraceDetails};
 
+/// Log capability changes made by the capabilities oracle.
+pub mod cap_audit;
+/// Cache compiled Wasm components on disk.
+mod component_cache;
+/// Keep crash reports of processes.
+mod crash;
+/// Give each process a mailbox with priority lanes.
+mod mailbox;
+/// Save kernel state to files that a crash midway can't tear.
+mod persisted;
 /// Manipulate a single process.
 pub mod process;
+/// Record the messages a process takes in and sends during one run.
+mod record;
+/// Replay a process recording offline against the process's Wasm.
+pub mod replay;
 /// Implement the functions served to processes by `wit-v1.0.0/hyperware.wit`.
 mod standard_host_v1;
+/// Link the lifecycles of processes in supervision groups.
+pub mod supervision;
+/// Record spans for traced messages and export them.
+mod trace;
+/// Build the WASI a process gets from its policy and capabilities.
+mod wasi;
 
 pub const LATEST_WIT_VERSION: u32 = 1;
-const PROCESS_CHANNEL_CAPACITY: usize = 100;
+/// how often expired capabilities are removed from the process map
+const PRUNE_EXPIRED_CAPS_SECS: u64 = 60;
 
 #[derive(Serialize, Deserialize)]
 struct StartProcessMetadata {
@@ -38,7 +59,48 @@ enum ProcessSender {
         sender: t::MessageSender,
         net_errors: Option<t::NetworkErrorSender>,
     },
-    Userspace(t::ProcessMessageSender),
+    Userspace(mailbox::MailboxSender),
+}
+
+/// State of the process upgrades in progress.
+#[derive(Default)]
+struct Upgrades {
+    /// by the ID of the upgrade request sent to the old code
+    pending: HashMap<u64, PendingUpgrade>,
+    /// requests for processes being upgraded, in the order they arrived. Responses
+    /// and send errors still go to the old code, which made the requests they answer.
+    held: HashMap<t::ProcessId, Vec<t::KernelMessage>>,
+}
+
+impl Upgrades {
+    /// Hold `km` for delivery to the new code if it's a request for a process being
+    /// upgraded, or give it back.
+    fn hold(&mut self, km: t::KernelMessage) -> Option<t::KernelMessage> {
+        match self.held.get_mut(&km.target.process) {
+            Some(held) if matches!(km.message, t::Message::Request(_)) => {
+                held.push(km);
+                None
+            }
+            _ => Some(km),
+        }
+    }
+
+    /// Whether `km` is a process's response to the upgrade request sent to it.
+    fn is_handoff(&self, km: &t::KernelMessage) -> bool {
+        matches!(km.message, t::Message::Response(_))
+            && self
+                .pending
+                .get(&km.id)
+                .is_some_and(|upgrade| upgrade.process_id == km.source.process)
+    }
+}
+
+struct PendingUpgrade {
+    process_id: t::ProcessId,
+    wasm_bytes_handle: String,
+    wasm_bytes: Vec<u8>,
+    /// ID and source of the `UpgradeProcess` command
+    command: (u64, t::Address),
 }
 
 pub type ProcessRestartBackoffs = HashMap<t::ProcessId, Arc<Mutex<Option<RestartBackoff>>>>;
@@ -92,12 +154,50 @@ async fn handle_kernel_request(
     senders: &mut Senders,
     process_handles: &mut ProcessHandles,
     process_map: &mut t::ProcessMap,
+    reverse_cap_index: &t::ReverseCapIndex,
     caps_oracle: &t::CapMessageSender,
     engine: &Engine,
+    component_cache: &component_cache::ComponentCache,
     process_restart_backoffs: &mut ProcessRestartBackoffs,
+    mailbox_capacities: &mut mailbox::MailboxCapacities,
+    tracer: &mut trace::Tracer,
+    home_directory_path: &Path,
+    upgrades: &mut Upgrades,
+    crash_reports: &mut crash::CrashReports,
+    supervisor: &mut supervision::Supervisor,
+    recordings: &mut HashMap<t::ProcessId, Arc<record::Recorder>>,
 ) -> Option<()> {
-    let t::Message::Request(request) = km.message else {
-        return None;
+    let request = match km.message {
+        t::Message::Request(request) => request,
+        // the old code of a process being upgraded handing off, or the handoff timing out
+        t::Message::Response(_) => {
+            let handoff = match upgrades.pending.get(&km.id) {
+                Some(upgrade) if km.source.process == upgrade.process_id => km.lazy_load_blob,
+                Some(_) if km.source.process == *KERNEL_PROCESS_ID => None,
+                _ => return None,
+            };
+            let upgrade = upgrades.pending.remove(&km.id).unwrap();
+            finish_upgrade(
+                our_name,
+                keypair,
+                send_to_loop,
+                send_to_terminal,
+                senders,
+                process_handles,
+                process_map,
+                engine,
+                component_cache,
+                caps_oracle,
+                process_restart_backoffs,
+                mailbox_capacities,
+                &home_directory_path.join("vfs"),
+                upgrades,
+                upgrade,
+                handoff,
+            )
+            .await;
+            return None;
+        }
     };
     let command: t::KernelCommand = match serde_json::from_slice(&request.body) {
         Err(e) => {
@@ -157,6 +257,7 @@ async fn handle_kernel_request(
             on_exit,
             initial_capabilities,
             public,
+            wasi,
         } => {
             let Some(blob) = km.lazy_load_blob else {
                 t::Printout::new(
@@ -256,6 +357,8 @@ async fn handle_kernel_request(
                 .send(t::CapMessage::Add {
                     on: km.source.process.clone(),
                     caps: vec![msg_cap],
+                    actor: km.source.process.clone(),
+                    reason: "InitializeProcess: initializer may message new process",
                     responder: None,
                 })
                 .await
@@ -274,9 +377,32 @@ async fn handle_kernel_request(
                     on_exit,
                     capabilities: valid_capabilities,
                     public,
+                    wasi,
                 },
                 reboot: false,
             };
+            // the restart of a member of a supervision group that gave up: keep the
+            // process installed, but don't start it
+            if km.source.process == *KERNEL_PROCESS_ID
+                && supervisor.is_stopped(&start_process_metadata.process_id)
+            {
+                t::Printout::new(
+                    0,
+                    KERNEL_PROCESS_ID.clone(),
+                    format!(
+                        "kernel: not restarting {}: its supervision group gave up",
+                        start_process_metadata.process_id
+                    ),
+                )
+                .send(send_to_terminal)
+                .await;
+                process_map.insert(
+                    start_process_metadata.process_id,
+                    start_process_metadata.persisted,
+                );
+                persist_state(send_to_loop, process_map).await;
+                return None;
+            }
             let response = match start_process(
                 our_name,
                 keypair.clone(),
@@ -286,9 +412,13 @@ async fn handle_kernel_request(
                 senders,
                 process_handles,
                 engine,
+                component_cache,
                 caps_oracle,
                 &start_process_metadata,
                 process_restart_backoffs,
+                mailbox_capacities,
+                &home_directory_path.join("vfs"),
+                None,
             )
             .await
             {
@@ -334,14 +464,309 @@ async fn handle_kernel_request(
                 .await;
             None
         }
+        t::KernelCommand::ReportCrash { process, report } => {
+            if km.source.process == *KERNEL_PROCESS_ID {
+                crash_reports
+                    .record(our_name, process, report, send_to_loop, send_to_terminal)
+                    .await;
+            }
+            None
+        }
+        t::KernelCommand::SubscribeCrashes => {
+            crash_reports.subscribe(km.source, send_to_terminal).await;
+            None
+        }
+        t::KernelCommand::UnsubscribeCrashes => {
+            crash_reports
+                .unsubscribe(&km.source, send_to_terminal)
+                .await;
+            None
+        }
+        t::KernelCommand::SetSupervisionGroup(group) => {
+            let name = group.name.clone();
+            let response = match supervisor.set(group).await {
+                Ok(stopped) => {
+                    // a group that gave up starts over
+                    for process_id in stopped {
+                        if !senders.contains_key(&process_id) {
+                            restart_process(
+                                our_name,
+                                keypair,
+                                send_to_loop,
+                                send_to_terminal,
+                                senders,
+                                process_handles,
+                                process_map,
+                                engine,
+                                component_cache,
+                                caps_oracle,
+                                process_restart_backoffs,
+                                mailbox_capacities,
+                                home_directory_path,
+                                &process_id,
+                                None,
+                            )
+                            .await;
+                        }
+                    }
+                    t::KernelResponse::SupervisionGroupSet(name)
+                }
+                Err(e) => t::KernelResponse::SupervisionGroupError(e),
+            };
+            if request.expects_response.is_some() {
+                respond(send_to_loop, km.id, km.rsvp.unwrap_or(km.source), response).await;
+            }
+            None
+        }
+        t::KernelCommand::RemoveSupervisionGroup(name) => {
+            let response = match supervisor.remove(&name).await {
+                Ok(true) => t::KernelResponse::SupervisionGroupRemoved(name),
+                Ok(false) => t::KernelResponse::SupervisionGroupError(format!("no group {name}")),
+                Err(e) => t::KernelResponse::SupervisionGroupError(e),
+            };
+            if request.expects_response.is_some() {
+                respond(send_to_loop, km.id, km.rsvp.unwrap_or(km.source), response).await;
+            }
+            None
+        }
+        t::KernelCommand::SetPackageSupervision {
+            package_id,
+            manifest,
+        } => {
+            let response = match t::SupervisionGroup::from_manifest(&package_id, &manifest) {
+                Ok(groups) => match supervisor.set_package(&package_id, groups).await {
+                    Ok(names) => t::KernelResponse::PackageSupervisionSet(names),
+                    Err(e) => t::KernelResponse::SupervisionGroupError(e),
+                },
+                Err(e) => t::KernelResponse::SupervisionGroupError(e),
+            };
+            if request.expects_response.is_some() {
+                respond(send_to_loop, km.id, km.rsvp.unwrap_or(km.source), response).await;
+            }
+            None
+        }
+        t::KernelCommand::SetMailboxCapacity { target, capacity } => {
+            let response = match mailbox_capacities.set(target.clone(), capacity).await {
+                Ok(()) => t::KernelResponse::MailboxCapacitySet(target),
+                Err(e) => t::KernelResponse::MailboxCapacityError(e),
+            };
+            if request.expects_response.is_some() {
+                respond(send_to_loop, km.id, km.rsvp.unwrap_or(km.source), response).await;
+            }
+            None
+        }
+        t::KernelCommand::ProcessExited(process_id) => {
+            if km.source.process != *KERNEL_PROCESS_ID {
+                return None;
+            }
+            // an exiting process stops its recording; a restart is recorded only if
+            // asked for again
+            if recordings
+                .get(&process_id)
+                .is_some_and(|recorder| recorder.is_stopped())
+            {
+                recordings.remove(&process_id);
+            }
+            let restarts_itself = process_map
+                .get(&process_id)
+                .is_some_and(|persisted| persisted.on_exit.is_restart());
+            let exit = supervisor.exited(&process_id, restarts_itself)?;
+            if exit.gave_up {
+                t::Printout::new(
+                    0,
+                    KERNEL_PROCESS_ID.clone(),
+                    format!(
+                        "kernel: supervision group {} restarted too often; stopping its processes until the next boot",
+                        exit.group
+                    ),
+                )
+                .send(send_to_terminal)
+                .await;
+                // the exited member carries out its own on-exit behavior
+                for member in supervisor.members(&exit.group) {
+                    if member != process_id {
+                        if let Some(handle) = process_handles.remove(&member) {
+                            handle.abort();
+                        }
+                        senders.remove(&member);
+                    }
+                }
+                return None;
+            }
+            for sibling in exit.siblings {
+                let Some(persisted) = process_map.get(&sibling) else {
+                    continue;
+                };
+                if !senders.contains_key(&sibling) {
+                    // already exiting, or waiting out a restart backoff
+                    continue;
+                }
+                match &persisted.on_exit {
+                    t::OnExit::Restart => {
+                        t::Printout::new(
+                            2,
+                            KERNEL_PROCESS_ID.clone(),
+                            format!(
+                                "kernel: restarting {sibling} after {process_id} exited (supervision group {})",
+                                exit.group
+                            ),
+                        )
+                        .send(send_to_terminal)
+                        .await;
+                        if let Some(handle) = process_handles.remove(&sibling) {
+                            handle.abort();
+                        }
+                        senders.remove(&sibling);
+                        restart_process(
+                            our_name,
+                            keypair,
+                            send_to_loop,
+                            send_to_terminal,
+                            senders,
+                            process_handles,
+                            process_map,
+                            engine,
+                            component_cache,
+                            caps_oracle,
+                            process_restart_backoffs,
+                            mailbox_capacities,
+                            home_directory_path,
+                            &sibling,
+                            None,
+                        )
+                        .await;
+                    }
+                    on_exit => {
+                        // even in death, a process can only message processes it has capabilities for
+                        if let t::OnExit::Requests(requests) = on_exit {
+                            for (address, request, blob) in requests {
+                                let mut request = request.to_owned();
+                                request.expects_response = None;
+                                if holds_cap(persisted, &t::Capability::messaging(address.clone()))
+                                {
+                                    t::KernelMessage::builder()
+                                        .id(rand::random())
+                                        .source((our_name, &sibling))
+                                        .target(address.clone())
+                                        .message(t::Message::Request(request))
+                                        .lazy_load_blob(blob.clone())
+                                        .build()
+                                        .unwrap()
+                                        .send(send_to_loop)
+                                        .await;
+                                }
+                            }
+                        }
+                        t::KernelMessage::builder()
+                            .id(rand::random())
+                            .source((our_name, KERNEL_PROCESS_ID.clone()))
+                            .target((our_name, KERNEL_PROCESS_ID.clone()))
+                            .message(t::Message::Request(t::Request {
+                                inherit: false,
+                                expects_response: None,
+                                body: serde_json::to_vec(&t::KernelCommand::KillProcess(sibling))
+                                    .unwrap(),
+                                metadata: None,
+                                capabilities: vec![],
+                            }))
+                            .build()
+                            .unwrap()
+                            .send(send_to_loop)
+                            .await;
+                    }
+                }
+            }
+            None
+        }
+        t::KernelCommand::RecordProcess(process_id) => {
+            let response = match process_map.get(&process_id) {
+                Some(persisted) if senders.contains_key(&process_id) => {
+                    let header = t::RecordingHeader {
+                        process: t::Address::new(our_name, process_id.clone()),
+                        wasm_bytes_handle: persisted.wasm_bytes_handle.clone(),
+                        wit_version: persisted.wit_version,
+                        on_exit: persisted.on_exit.clone(),
+                        public: persisted.public,
+                        started: unix_time_secs(),
+                        wasi: persisted.wasi.clone(),
+                    };
+                    match record::Recorder::create(home_directory_path, &header).await {
+                        Ok(recorder) => {
+                            let recorder = Arc::new(recorder);
+                            if let Some(previous) =
+                                recordings.insert(process_id.clone(), recorder.clone())
+                            {
+                                previous.stop();
+                            }
+                            // record from init, so that a replay starts where the process did
+                            if let Some(handle) = process_handles.remove(&process_id) {
+                                handle.abort();
+                            }
+                            senders.remove(&process_id);
+                            restart_process(
+                                our_name,
+                                keypair,
+                                send_to_loop,
+                                send_to_terminal,
+                                senders,
+                                process_handles,
+                                process_map,
+                                engine,
+                                component_cache,
+                                caps_oracle,
+                                process_restart_backoffs,
+                                mailbox_capacities,
+                                home_directory_path,
+                                &process_id,
+                                Some(recorder.clone()),
+                            )
+                            .await;
+                            t::KernelResponse::RecordingProcess(
+                                recorder.path().display().to_string(),
+                            )
+                        }
+                        Err(e) => t::KernelResponse::RecordingError(format!(
+                            "couldn't create recording: {e}"
+                        )),
+                    }
+                }
+                _ => t::KernelResponse::RecordingError(format!("{process_id} is not running")),
+            };
+            if request.expects_response.is_some() {
+                respond(send_to_loop, km.id, km.rsvp.unwrap_or(km.source), response).await;
+            }
+            None
+        }
+        t::KernelCommand::StopRecording(process_id) => {
+            let response = match recordings.remove(&process_id) {
+                Some(recorder) => {
+                    recorder.stop();
+                    t::KernelResponse::StoppedRecording(recorder.path().display().to_string())
+                }
+                None => t::KernelResponse::RecordingError(format!("{process_id} is not recording")),
+            };
+            if request.expects_response.is_some() {
+                respond(send_to_loop, km.id, km.rsvp.unwrap_or(km.source), response).await;
+            }
+            None
+        }
         t::KernelCommand::GrantCapabilities {
             target,
             capabilities,
+            constraints,
         } => {
             caps_oracle
                 .send(t::CapMessage::Add {
                     on: target,
-                    caps: capabilities,
+                    caps: match constraints {
+                        Some(constraints) => capabilities
+                            .iter()
+                            .map(|cap| cap.constrained(constraints.clone()))
+                            .collect(),
+                        None => capabilities,
+                    },
+                    actor: km.source.process,
+                    reason: "GrantCapabilities",
                     responder: None,
                 })
                 .await
@@ -356,6 +781,8 @@ async fn handle_kernel_request(
                 .send(t::CapMessage::Drop {
                     on: target,
                     caps: capabilities,
+                    actor: km.source.process,
+                    reason: "DropCapabilities",
                     responder: None,
                 })
                 .await
@@ -396,6 +823,8 @@ async fn handle_kernel_request(
                     } else {
                         t::KernelResponse::RunProcessError
                     }
+                } else if supervisor.is_stopped(&process_id) {
+                    t::KernelResponse::RunProcessError
                 } else {
                     t::Printout::new(
                         0,
@@ -436,6 +865,13 @@ async fn handle_kernel_request(
             let process_handle = match process_handles.remove(&process_id) {
                 Some(ph) => ph,
                 None => {
+                    // stopped members of a supervision group stay installed until killed
+                    if supervisor.is_stopped(&process_id)
+                        && process_map.remove(&process_id).is_some()
+                    {
+                        persist_state(send_to_loop, process_map).await;
+                        crash_reports.forget(&process_id, send_to_terminal).await;
+                    }
                     t::Printout::new(
                         2,
                         KERNEL_PROCESS_ID.clone(),
@@ -449,10 +885,18 @@ async fn handle_kernel_request(
             senders.remove(&process_id);
             process_handle.abort();
             process_map.remove(&process_id);
+            // an upgrade in progress will find the process gone
+            upgrades.held.remove(&process_id);
+            if let Some(recorder) = recordings.remove(&process_id) {
+                recorder.stop();
+            }
             if request.metadata != Some("no-revoke".to_string()) {
+                crash_reports.forget(&process_id, send_to_terminal).await;
                 caps_oracle
                     .send(t::CapMessage::RevokeAll {
                         on: process_id.clone(),
+                        actor: km.source.process.clone(),
+                        reason: "KillProcess",
                         responder: None,
                     })
                     .await
@@ -495,6 +939,105 @@ async fn handle_kernel_request(
                 .await;
             None
         }
+        //
+        // replace the code of a running process, handing off its state if it
+        // implements the upgrade hook. finished in `finish_upgrade`.
+        //
+        t::KernelCommand::UpgradeProcess {
+            id,
+            wasm_bytes_handle,
+        } => {
+            let command = (km.id, km.rsvp.unwrap_or(km.source));
+            let error = match (&km.lazy_load_blob, senders.get(&id)) {
+                _ if upgrades.held.contains_key(&id) => Some("process is already being upgraded"),
+                (None, _) => Some("process upgrade requires bytes"),
+                (Some(_), Some(ProcessSender::Userspace(_))) => None,
+                (Some(_), _) => Some("no such process to upgrade"),
+            };
+            if let Some(error) = error {
+                t::Printout::new(
+                    0,
+                    KERNEL_PROCESS_ID.clone(),
+                    format!("kernel: {error}: {id}"),
+                )
+                .send(send_to_terminal)
+                .await;
+                respond(
+                    send_to_loop,
+                    command.0,
+                    command.1,
+                    t::KernelResponse::UpgradeProcessError(error.to_string()),
+                )
+                .await;
+                return None;
+            }
+            // hold requests for the process from here on
+            upgrades.held.insert(id.clone(), vec![]);
+            let upgrade_request_id: u64 = rand::random();
+            let upgrade = PendingUpgrade {
+                process_id: id.clone(),
+                wasm_bytes_handle,
+                wasm_bytes: km.lazy_load_blob.unwrap().bytes,
+                command,
+            };
+            let vfs_path = home_directory_path.join("vfs");
+            if !implements_handoff(engine, component_cache, &vfs_path, process_map, &id).await {
+                // nothing to wait for: replace the code right away
+                finish_upgrade(
+                    our_name,
+                    keypair,
+                    send_to_loop,
+                    send_to_terminal,
+                    senders,
+                    process_handles,
+                    process_map,
+                    engine,
+                    component_cache,
+                    caps_oracle,
+                    process_restart_backoffs,
+                    mailbox_capacities,
+                    &vfs_path,
+                    upgrades,
+                    upgrade,
+                    None,
+                )
+                .await;
+                return None;
+            }
+            let Some(ProcessSender::Userspace(process_sender)) = senders.get(&id) else {
+                unreachable!();
+            };
+            // if this fails, the process has already exited: the timeout below finishes
+            let _ = process_sender
+                .send(Ok(t::KernelMessage::builder()
+                    .id(upgrade_request_id)
+                    .source((our_name, KERNEL_PROCESS_ID.clone()))
+                    .target((our_name, &id))
+                    .message(t::Message::Request(t::Request {
+                        inherit: false,
+                        expects_response: Some(t::UPGRADE_HANDOFF_TIMEOUT_SECS),
+                        body: t::UPGRADE_REQUEST_BODY.to_vec(),
+                        metadata: None,
+                        capabilities: vec![],
+                    }))
+                    .build()
+                    .unwrap()))
+                .await;
+            upgrades.pending.insert(upgrade_request_id, upgrade);
+            // without a handoff in time, the kernel answers the upgrade request itself
+            let send_to_loop = send_to_loop.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_secs(t::UPGRADE_HANDOFF_TIMEOUT_SECS)).await;
+                respond(
+                    &send_to_loop,
+                    upgrade_request_id,
+                    t::Address::new("our", KERNEL_PROCESS_ID.clone()),
+                    t::KernelResponse::UpgradeProcessError("handoff timed out".to_string()),
+                )
+                .await;
+            });
+            None
+        }
         t::KernelCommand::Debug(kind) => {
             let response = match kind {
                 t::KernelPrint::ProcessMap => t::KernelPrintResponse::ProcessMap(
@@ -510,8 +1053,31 @@ async fn handle_kernel_request(
                 t::KernelPrint::HasCap { on, cap } => t::KernelPrintResponse::HasCap(
                     process_map
                         .get(&on)
-                        .map(|p| p.capabilities.contains_key(&cap)),
+                        .map(|p| holds_cap(p, &cap)),
                 ),
+                t::KernelPrint::Crashes(process) => {
+                    t::KernelPrintResponse::Crashes(crash_reports.get(process.as_ref()))
+                }
+                t::KernelPrint::CapHolders(issuer) => {
+                    // the index isn't pruned on drop, so report only caps still held
+                    let now = unix_time_secs();
+                    let mut holders = HashMap::new();
+                    for (grantee, caps) in reverse_cap_index.get(&issuer).into_iter().flatten() {
+                        let Some(persisted) = process_map.get(grantee) else {
+                            continue;
+                        };
+                        let held: HashSet<t::Capability> = caps
+                            .iter()
+                            .filter_map(|cap| persisted.capabilities.get_key_value(cap))
+                            .filter(|(held, _)| !held.is_expired(now))
+                            .map(|(held, _)| held.clone())
+                            .collect();
+                        if !held.is_empty() {
+                            holders.insert(grantee.clone(), held.into_iter().collect());
+                        }
+                    }
+                    t::KernelPrintResponse::CapHolders(holders)
+                }
             };
             t::KernelMessage::builder()
                 .id(km.id)
@@ -532,9 +1098,377 @@ async fn handle_kernel_request(
                 .await;
             None
         }
+        t::KernelCommand::StartTracing(root) => {
+            tracer.start(root);
+            respond(
+                send_to_loop,
+                km.id,
+                km.rsvp.unwrap_or(km.source),
+                tracer.status(),
+            )
+            .await;
+            None
+        }
+        t::KernelCommand::StopTracing(root) => {
+            tracer.stop(root);
+            respond(
+                send_to_loop,
+                km.id,
+                km.rsvp.unwrap_or(km.source),
+                tracer.status(),
+            )
+            .await;
+            None
+        }
+        t::KernelCommand::ExportTraces => {
+            let response = match tracer.export(our_name, home_directory_path).await {
+                Ok((path, spans)) => t::KernelResponse::ExportedTraces { path, spans },
+                Err(e) => t::KernelResponse::ExportTracesError(e.to_string()),
+            };
+            respond(send_to_loop, km.id, km.rsvp.unwrap_or(km.source), response).await;
+            None
+        }
+        t::KernelCommand::PurgeComponentCache => {
+            let response = match component_cache.purge().await {
+                Ok(removed) => t::KernelResponse::PurgedComponentCache(removed),
+                Err(e) => t::KernelResponse::PurgeComponentCacheError(e.to_string()),
+            };
+            respond(send_to_loop, km.id, km.rsvp.unwrap_or(km.source), response).await;
+            None
+        }
     }
 }
 
+async fn respond(
+    send_to_loop: &t::MessageSender,
+    id: u64,
+    target: t::Address,
+    response: t::KernelResponse,
+) {
+    t::KernelMessage::builder()
+        .id(id)
+        .source(("our", KERNEL_PROCESS_ID.clone()))
+        .target(target)
+        .message(t::Message::Response((
+            t::Response {
+                inherit: false,
+                body: serde_json::to_vec(&response).unwrap(),
+                metadata: None,
+                capabilities: vec![],
+            },
+            None,
+        )))
+        .build()
+        .unwrap()
+        .send(send_to_loop)
+        .await;
+}
+
+/// Whether the running code of a process declares the upgrade hook by exporting
+/// [`t::HANDOFF_EXPORT`]. Code that can't be read is taken not to.
+async fn implements_handoff(
+    engine: &Engine,
+    component_cache: &component_cache::ComponentCache,
+    vfs_path: &Path,
+    process_map: &t::ProcessMap,
+    id: &t::ProcessId,
+) -> bool {
+    let Some(persisted) = process_map.get(id) else {
+        return false;
+    };
+    let Ok(wasm_bytes) =
+        tokio::fs::read(wasm_bytes_path(vfs_path, &persisted.wasm_bytes_handle)).await
+    else {
+        return false;
+    };
+    component_cache
+        .get(engine, &wasm_bytes)
+        .await
+        .is_ok_and(|component| exports_handoff(&component))
+}
+
+fn exports_handoff(component: &Component) -> bool {
+    component
+        .get_export_index(None, t::HANDOFF_EXPORT)
+        .is_some()
+}
+
+/// Replace the old code of a process being upgraded with the new, pass on its
+/// handoff blob if it gave one, deliver the messages held for it, and answer the
+/// `UpgradeProcess` command.
+async fn finish_upgrade(
+    our_name: &str,
+    keypair: &Arc<ring::signature::Ed25519KeyPair>,
+    send_to_loop: &t::MessageSender,
+    send_to_terminal: &t::PrintSender,
+    senders: &mut Senders,
+    process_handles: &mut ProcessHandles,
+    process_map: &mut t::ProcessMap,
+    engine: &Engine,
+    component_cache: &component_cache::ComponentCache,
+    caps_oracle: &t::CapMessageSender,
+    process_restart_backoffs: &mut ProcessRestartBackoffs,
+    mailbox_capacities: &mailbox::MailboxCapacities,
+    vfs_path: &Path,
+    upgrades: &mut Upgrades,
+    upgrade: PendingUpgrade,
+    handoff: Option<t::LazyLoadBlob>,
+) {
+    let id = upgrade.process_id;
+    let (command_id, command_source) = upgrade.command;
+    let held = upgrades.held.remove(&id);
+    let response = match (held, process_map.get_mut(&id)) {
+        (Some(held), Some(persisted)) => {
+            if let Some(handle) = process_handles.remove(&id) {
+                handle.abort();
+            }
+            senders.remove(&id);
+            persisted.wasm_bytes_handle = upgrade.wasm_bytes_handle;
+            let persisted = persisted.clone();
+            let start_process_metadata = StartProcessMetadata {
+                source: t::Address::new(our_name, KERNEL_PROCESS_ID.clone()),
+                process_id: id.clone(),
+                persisted,
+                reboot: false,
+            };
+            match start_process(
+                our_name,
+                keypair.clone(),
+                upgrade.wasm_bytes,
+                send_to_loop,
+                send_to_terminal,
+                senders,
+                process_handles,
+                engine,
+                component_cache,
+                caps_oracle,
+                &start_process_metadata,
+                process_restart_backoffs,
+                mailbox_capacities,
+                vfs_path,
+                None,
+            )
+            .await
+            {
+                Ok(()) => {
+                    if !start_process_metadata.persisted.on_exit.is_none() {
+                        persist_state(send_to_loop, process_map).await;
+                    }
+                    let Some(ProcessSender::Userspace(process_sender)) = senders.get(&id) else {
+                        unreachable!();
+                    };
+                    let kernel_request = |body: &[u8], blob: Option<t::LazyLoadBlob>| {
+                        t::KernelMessage::builder()
+                            .id(rand::random())
+                            .source((our_name, KERNEL_PROCESS_ID.clone()))
+                            .target((our_name, &id))
+                            .message(t::Message::Request(t::Request {
+                                inherit: false,
+                                expects_response: None,
+                                body: body.to_vec(),
+                                metadata: None,
+                                capabilities: vec![],
+                            }))
+                            .lazy_load_blob(blob)
+                            .build()
+                            .unwrap()
+                    };
+                    let handed_off = handoff.is_some();
+                    let mut messages = vec![Ok(kernel_request(b"run", None))];
+                    if handed_off {
+                        messages.push(Ok(kernel_request(t::HANDOFF_REQUEST_BODY, handoff)));
+                    }
+                    messages.extend(held.into_iter().map(Ok));
+                    // the held messages may not all fit in the new mailbox: as in the
+                    // event loop, Requests that don't fit are reported to their senders
+                    for message in messages {
+                        if let Err(TrySendError::Full(Ok(km))) = process_sender.try_send(message) {
+                            throw_queue_full(our_name, senders, km).await;
+                        }
+                    }
+                    t::KernelResponse::UpgradedProcess {
+                        id: id.clone(),
+                        handoff: handed_off,
+                    }
+                }
+                Err(e) => t::KernelResponse::UpgradeProcessError(format!(
+                    "couldn't start new code: {e:?}"
+                )),
+            }
+        }
+        _ => t::KernelResponse::UpgradeProcessError("process exited during upgrade".to_string()),
+    };
+    let message = match &response {
+        t::KernelResponse::UpgradedProcess { handoff: true, .. } => {
+            format!("kernel: upgraded process {id}, handing off its state")
+        }
+        t::KernelResponse::UpgradedProcess { .. } => format!("kernel: upgraded process {id}"),
+        t::KernelResponse::UpgradeProcessError(e) => {
+            format!("kernel: failed to upgrade process {id}: {e}")
+        }
+        _ => unreachable!(),
+    };
+    t::Printout::new(0, KERNEL_PROCESS_ID.clone(), message)
+        .send(send_to_terminal)
+        .await;
+    respond(send_to_loop, command_id, command_source, response).await;
+}
+
+/// start an installed process that isn't running from its persisted state, reading
+/// its Wasm bytes from the vfs
+async fn restart_process(
+    our_name: &str,
+    keypair: &Arc<ring::signature::Ed25519KeyPair>,
+    send_to_loop: &t::MessageSender,
+    send_to_terminal: &t::PrintSender,
+    senders: &mut Senders,
+    process_handles: &mut ProcessHandles,
+    process_map: &t::ProcessMap,
+    engine: &Engine,
+    component_cache: &component_cache::ComponentCache,
+    caps_oracle: &t::CapMessageSender,
+    process_restart_backoffs: &mut ProcessRestartBackoffs,
+    mailbox_capacities: &mailbox::MailboxCapacities,
+    home_directory_path: &Path,
+    process_id: &t::ProcessId,
+    recorder: Option<Arc<record::Recorder>>,
+) {
+    let Some(persisted) = process_map.get(process_id) else {
+        return;
+    };
+    let result = match tokio::fs::read(wasm_bytes_path(
+        &home_directory_path.join("vfs"),
+        &persisted.wasm_bytes_handle,
+    ))
+    .await
+    {
+        Ok(wasm_bytes) => {
+            let start_process_metadata = StartProcessMetadata {
+                source: t::Address::new(our_name, KERNEL_PROCESS_ID.clone()),
+                process_id: process_id.clone(),
+                persisted: persisted.clone(),
+                reboot: false,
+            };
+            start_process(
+                our_name,
+                keypair.clone(),
+                wasm_bytes,
+                send_to_loop,
+                send_to_terminal,
+                senders,
+                process_handles,
+                engine,
+                component_cache,
+                caps_oracle,
+                &start_process_metadata,
+                process_restart_backoffs,
+                mailbox_capacities,
+                &home_directory_path.join("vfs"),
+                recorder,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    match result {
+        Ok(()) => {
+            let Some(ProcessSender::Userspace(process_sender)) = senders.get(process_id) else {
+                unreachable!();
+            };
+            process_sender
+                .send(Ok(t::KernelMessage::builder()
+                    .id(rand::random())
+                    .source((our_name, KERNEL_PROCESS_ID.clone()))
+                    .target((our_name, process_id))
+                    .message(t::Message::Request(t::Request {
+                        inherit: false,
+                        expects_response: None,
+                        body: b"run".to_vec(),
+                        metadata: None,
+                        capabilities: vec![],
+                    }))
+                    .build()
+                    .unwrap()))
+                .await
+                .ok();
+        }
+        Err(e) => {
+            t::Printout::new(
+                0,
+                KERNEL_PROCESS_ID.clone(),
+                format!("kernel: couldn't restart process {process_id}: {e}"),
+            )
+            .send(send_to_terminal)
+            .await;
+        }
+    }
+}
+
+/// restart running processes whose preopened drives may have outlived the vfs
+/// capabilities they were derived from, so that they get only the drives they can
+/// still access
+async fn restart_for_preopens(
+    our_name: &str,
+    keypair: &Arc<ring::signature::Ed25519KeyPair>,
+    send_to_loop: &t::MessageSender,
+    send_to_terminal: &t::PrintSender,
+    senders: &mut Senders,
+    process_handles: &mut ProcessHandles,
+    process_map: &t::ProcessMap,
+    engine: &Engine,
+    component_cache: &component_cache::ComponentCache,
+    caps_oracle: &t::CapMessageSender,
+    process_restart_backoffs: &mut ProcessRestartBackoffs,
+    mailbox_capacities: &mailbox::MailboxCapacities,
+    home_directory_path: &Path,
+    recordings: &HashMap<t::ProcessId, Arc<record::Recorder>>,
+    process_ids: Vec<t::ProcessId>,
+) {
+    for process_id in process_ids {
+        let Some(handle) = process_handles.remove(&process_id) else {
+            continue;
+        };
+        handle.abort();
+        senders.remove(&process_id);
+        t::Printout::new(
+            1,
+            KERNEL_PROCESS_ID.clone(),
+            format!("kernel: restarting {process_id}: it lost access to a preopened drive"),
+        )
+        .send(send_to_terminal)
+        .await;
+        restart_process(
+            our_name,
+            keypair,
+            send_to_loop,
+            send_to_terminal,
+            senders,
+            process_handles,
+            process_map,
+            engine,
+            component_cache,
+            caps_oracle,
+            process_restart_backoffs,
+            mailbox_capacities,
+            home_directory_path,
+            &process_id,
+            recordings.get(&process_id).cloned(),
+        )
+        .await;
+    }
+}
+
+/// where the Wasm bytes of a process are kept, given its `wasm_bytes_handle`
+fn wasm_bytes_path(vfs_path: &Path, wasm_bytes_handle: &str) -> PathBuf {
+    let wasm_bytes_handle = wasm_bytes_handle
+        .strip_prefix("/")
+        .unwrap_or(wasm_bytes_handle);
+    #[cfg(unix)]
+    return vfs_path.join(wasm_bytes_handle);
+    #[cfg(target_os = "windows")]
+    return vfs_path.join(wasm_bytes_handle.replace(":", "_"));
+}
+
 /// spawn a process loop and insert the process in the relevant kernel state maps
 async fn start_process(
     our_name: &str,
@@ -545,12 +1479,16 @@ async fn start_process(
     senders: &mut Senders,
     process_handles: &mut ProcessHandles,
     engine: &Engine,
+    component_cache: &component_cache::ComponentCache,
     caps_oracle: &t::CapMessageSender,
     process_metadata: &StartProcessMetadata,
     process_restart_backoffs: &mut ProcessRestartBackoffs,
+    mailbox_capacities: &mailbox::MailboxCapacities,
+    vfs_path: &Path,
+    recorder: Option<Arc<record::Recorder>>,
 ) -> anyhow::Result<()> {
     let (send_to_process, recv_in_process) =
-        mpsc::channel::<Result<t::KernelMessage, t::WrappedSendError>>(PROCESS_CHANNEL_CAPACITY);
+        mailbox::mailbox(mailbox_capacities.of(&process_metadata.process_id));
     let id = &process_metadata.process_id;
     if senders.contains_key(id) {
         return Err(anyhow::anyhow!("process with ID {id} already exists"));
@@ -568,6 +1506,7 @@ async fn start_process(
         wit_version: process_metadata.persisted.wit_version,
         on_exit: process_metadata.persisted.on_exit.clone(),
         public: process_metadata.persisted.public,
+        wasi: process_metadata.persisted.wasi.clone(),
     };
     let maybe_restart_backoff = if let t::OnExit::Restart = process_metadata.persisted.on_exit {
         let restart_backoff = process_restart_backoffs
@@ -590,12 +1529,24 @@ async fn start_process(
             km_blob_bytes,
             caps_oracle.clone(),
             engine.clone(),
+            component_cache.clone(),
             maybe_restart_backoff,
+            recorder,
+            vfs_path.to_path_buf(),
         )),
     );
     Ok(())
 }
 
+/// the Wasm engine that runs processes
+pub fn make_engine() -> Engine {
+    let mut config = Config::new();
+    config.wasm_backtrace_details(WasmBacktraceDetails::Enable);
+    config.wasm_component_model(true);
+    config.async_support(true);
+    Engine::new(&config).unwrap()
+}
+
 /// the OS kernel. contains event loop which handles all message-passing between
 /// all processes (Wasm apps) and also runtime tasks.
 pub async fn kernel(
@@ -619,12 +1570,23 @@ pub async fn kernel(
         bool,
     )>,
     default_pki_entries: Vec<t::HnsUpdate>,
+    mailbox_capacity: usize,
 ) -> anyhow::Result<()> {
-    let mut config = Config::new();
-    config.wasm_backtrace_details(WasmBacktraceDetails::Enable);
-    config.wasm_component_model(true);
-    config.async_support(true);
-    let engine = Engine::new(&config).unwrap();
+    let engine = make_engine();
+    let component_cache = component_cache::ComponentCache::new(&home_directory_path, &engine).await;
+    let component_cache = match component_cache {
+        Ok(component_cache) => component_cache,
+        Err(e) => {
+            t::Printout::new(
+                0,
+                KERNEL_PROCESS_ID.clone(),
+                format!("kernel: couldn't set up component cache, compiling without it: {e}"),
+            )
+            .send(&send_to_terminal)
+            .await;
+            component_cache::ComponentCache::disabled()
+        }
+    };
 
     let vfs_path = home_directory_path.join("vfs");
     tokio::fs::create_dir_all(&vfs_path)
@@ -658,6 +1620,20 @@ pub async fn kernel(
     let mut print_full_event_loop: bool = true;
 
     let mut print_full_event_loop_for_process: HashSet<t::ProcessId> = HashSet::new();
+    let mut tracer = trace::Tracer::default();
+    let mut upgrades = Upgrades::default();
+    let mut crash_reports =
+        crash::CrashReports::load(&home_directory_path, &send_to_terminal).await;
+    let cap_audit = cap_audit::CapAudit::new(&home_directory_path, send_to_terminal.clone());
+    let mut supervisor =
+        supervision::Supervisor::load(&home_directory_path, &send_to_terminal).await;
+    let mut mailbox_capacities = mailbox::MailboxCapacities::load(
+        &home_directory_path,
+        mailbox_capacity,
+        &send_to_terminal,
+    )
+    .await;
+    let mut recordings: HashMap<t::ProcessId, Arc<record::Recorder>> = HashMap::new();
 
     // create a list of processes which are successfully rebooted,
     // keeping only them in the updated post-boot process map
@@ -665,20 +1641,16 @@ pub async fn kernel(
 
     let mut process_restart_backoffs: ProcessRestartBackoffs = HashMap::new();
 
+    // the component cache entries of installed processes; the rest are stale
+    let mut installed_components: HashSet<String> = HashSet::new();
+
     for (process_id, persisted) in &process_map {
         // runtime extensions will have a bytes_handle of "", because they have no
         // Wasm code saved in filesystem.
         if persisted.wasm_bytes_handle.is_empty() {
             continue;
         }
-        let wasm_bytes_handle = persisted
-            .wasm_bytes_handle
-            .strip_prefix("/")
-            .unwrap_or_else(|| &persisted.wasm_bytes_handle);
-        #[cfg(unix)]
-        let path = vfs_path.join(wasm_bytes_handle);
-        #[cfg(target_os = "windows")]
-        let path = vfs_path.join(wasm_bytes_handle.replace(":", "_"));
+        let path = wasm_bytes_path(&vfs_path, &persisted.wasm_bytes_handle);
 
         // read wasm bytes directly from vfs
         let wasm_bytes = match tokio::fs::read(&path).await {
@@ -695,6 +1667,7 @@ pub async fn kernel(
                 continue;
             }
         };
+        installed_components.insert(component_cache::ComponentCache::entry_name(&wasm_bytes));
         if let t::OnExit::Requests(requests) = &persisted.on_exit {
             // if a persisted process had on-death-requests, we should perform them now
             // even in death, a process can only message processes it has capabilities for
@@ -703,10 +1676,7 @@ pub async fn kernel(
                 let mut request = request.to_owned();
                 request.expects_response = None;
                 // TODO not sure if we need to verify the signature
-                if persisted
-                    .capabilities
-                    .contains_key(&t::Capability::messaging(address.clone()))
-                {
+                if holds_cap(persisted, &t::Capability::messaging(address.clone())) {
                     t::KernelMessage::builder()
                         .id(rand::random())
                         .source((&our.name, process_id))
@@ -740,9 +1710,13 @@ pub async fn kernel(
             &mut senders,
             &mut process_handles,
             &engine,
+            &component_cache,
             &caps_oracle_sender,
             &start_process_metadata,
             &mut process_restart_backoffs,
+            &mailbox_capacities,
+            &vfs_path,
+            None,
         )
         .await
         {
@@ -761,6 +1735,23 @@ pub async fn kernel(
     }
 
     process_map.retain(|process_id, _| !non_rebooted_processes.contains(process_id));
+    crash_reports
+        .retain(|process_id| process_map.contains_key(process_id), &send_to_terminal)
+        .await;
+
+    let stale_components = component_cache.clone();
+    let stale_components_print = send_to_terminal.clone();
+    tokio::spawn(async move {
+        if let Err(e) = stale_components.retain(&installed_components).await {
+            t::Printout::new(
+                1,
+                KERNEL_PROCESS_ID.clone(),
+                format!("kernel: couldn't remove stale component cache entries: {e}"),
+            )
+            .send(&stale_components_print)
+            .await;
+        }
+    });
 
     // persist new state
     persist_state(&send_to_loop, &process_map).await;
@@ -800,9 +1791,68 @@ pub async fn kernel(
         .send(&send_to_loop)
         .await;
 
+    let mut prune_expired_caps =
+        tokio::time::interval(Duration::from_secs(PRUNE_EXPIRED_CAPS_SECS));
+
     // main event loop
     loop {
         tokio::select! {
+            // remove expired capabilities: they're treated as not held already, but
+            // would otherwise pile up in the process map
+            _ = prune_expired_caps.tick() => {
+                let now = unix_time_secs();
+                let mut pruned = false;
+                let mut lost_drives = vec![];
+                for (process_id, persisted) in process_map.iter_mut() {
+                    let expired: Vec<t::Capability> = persisted
+                        .capabilities
+                        .keys()
+                        .filter(|cap| cap.is_expired(now))
+                        .cloned()
+                        .collect();
+                    if !expired.is_empty() {
+                        pruned = true;
+                        if wasi::preopens_depend_on(&our.name, persisted, &expired) {
+                            lost_drives.push(process_id.clone());
+                        }
+                        for cap in &expired {
+                            persisted.capabilities.remove(cap);
+                        }
+                        cap_audit.record(
+                            t::CapAuditAction::Expire,
+                            &KERNEL_PROCESS_ID,
+                            process_id,
+                            &expired,
+                            "expired",
+                        );
+                        for issued in reverse_cap_index.values_mut() {
+                            if let Some(caps) = issued.get_mut(process_id) {
+                                caps.retain(|cap| !cap.is_expired(now));
+                            }
+                        }
+                    }
+                }
+                if pruned {
+                    persist_state(&send_to_loop, &process_map).await;
+                }
+                restart_for_preopens(
+                    &our.name,
+                    &keypair,
+                    &send_to_loop,
+                    &send_to_terminal,
+                    &mut senders,
+                    &mut process_handles,
+                    &process_map,
+                    &engine,
+                    &component_cache,
+                    &caps_oracle_sender,
+                    &mut process_restart_backoffs,
+                    &mailbox_capacities,
+                    &home_directory_path,
+                    &recordings,
+                    lost_drives,
+                ).await;
+            },
             // debug mode toggle: when on, this loop becomes a manual step-through
             Some(debug_command) = recv_debug_in_loop.recv() => {
                 match debug_command {
@@ -839,7 +1889,7 @@ pub async fn kernel(
                 // forward the error to the relevant process
                 match senders.get(&wrapped_network_error.source.process) {
                     Some(ProcessSender::Userspace(sender)) => {
-                        sender.send(Err(wrapped_network_error)).await.ok();
+                        sender.try_send(Err(wrapped_network_error)).ok();
                     }
                     Some(ProcessSender::Runtime { net_errors, .. }) => {
                         if let Some(net_errors) = net_errors {
@@ -856,6 +1906,7 @@ pub async fn kernel(
                                 match wrapped_network_error.error.kind {
                                     t::SendErrorKind::Timeout => "due to timeout",
                                     t::SendErrorKind::Offline => "because the receiver is offline",
+                                    t::SendErrorKind::QueueFull => "because the receiver's mailbox is full",
                                 },
                             )
                         ).send(&send_to_terminal).await;
@@ -872,6 +1923,7 @@ pub async fn kernel(
                 if kernel_message.target.node == "our" {
                     kernel_message.target.node = our.name.clone();
                 }
+                let span = tracer.enqueue(&our.name, &mut kernel_message);
                 //
                 // here are the special kernel-level capabilities checks!
                 //
@@ -882,7 +1934,8 @@ pub async fn kernel(
                     let Some(proc) = process_map.get(&kernel_message.source.process) else {
                         continue;
                     };
-                    if !proc.capabilities.contains_key(
+                    if !holds_cap(
+                        proc,
                         &t::Capability::new((&our.name, KERNEL_PROCESS_ID.clone()), "\"network\"")
                     ) {
                         // capabilities are not correct! skip this message.
@@ -926,7 +1979,8 @@ pub async fn kernel(
                         ).send(&send_to_terminal).await;
                         continue;
                     };
-                    if !persisted.capabilities.contains_key(
+                    if !holds_cap(
+                        persisted,
                         &t::Capability::new((&our.name, KERNEL_PROCESS_ID.clone()), "\"network\"")
                     ) {
                         // capabilities are not correct! skip this message.
@@ -943,9 +1997,12 @@ pub async fn kernel(
                 } else {
                     // enforce that local process has capability to message a target process of this name
                     // kernel and filesystem can ALWAYS message any local process
+                    // and a process being upgraded can always hand off to the kernel
                     if kernel_message.source.process != *KERNEL_PROCESS_ID
                         && kernel_message.source.process != *STATE_PROCESS_ID
                         && kernel_message.source.process != *VFS_PROCESS_ID
+                        && !(kernel_message.target.process == *KERNEL_PROCESS_ID
+                            && upgrades.is_handoff(&kernel_message))
                     {
                         let Some(persisted_source) = process_map.get(&kernel_message.source.process) else {
                             throw_timeout(&our.name, &senders, kernel_message).await;
@@ -964,7 +2021,8 @@ pub async fn kernel(
                             continue;
                         };
                         if !persisted_target.public
-                        && !persisted_source.capabilities.contains_key(
+                        && !holds_cap(
+                            persisted_source,
                             &t::Capability::messaging((&our.name, &kernel_message.target.process))
                         ) {
                             // capabilities are not correct! skip this message.
@@ -1011,8 +2069,10 @@ pub async fn kernel(
                 if our.name != kernel_message.target.node {
                     // handle messages sent over network
                     send_to_net.send(kernel_message).await.expect("fatal: net module died");
+                    tracer.deliver(span);
                 } else if kernel_message.target.process.process() == "kernel" && kernel_message.source.node == our.name {
                     // handle messages sent to local kernel
+                    tracer.deliver(span);
                     if let Some(()) = handle_kernel_request(
                         &our.name,
                         &keypair,
@@ -1022,9 +2082,18 @@ pub async fn kernel(
                         &mut senders,
                         &mut process_handles,
                         &mut process_map,
+                        &reverse_cap_index,
                         &caps_oracle_sender,
                         &engine,
+                        &component_cache,
                         &mut process_restart_backoffs,
+                        &mut mailbox_capacities,
+                        &mut tracer,
+                        &home_directory_path,
+                        &mut upgrades,
+                        &mut crash_reports,
+                        &mut supervisor,
+                        &mut recordings,
                     ).await {
                         // drain process map of processes with OnExit::None
                         process_map.retain(|_, persisted| !persisted.on_exit.is_none());
@@ -1037,10 +2106,28 @@ pub async fn kernel(
                     // pass message to appropriate runtime module or process
                     match senders.get(&kernel_message.target.process) {
                         Some(ProcessSender::Userspace(sender)) => {
-                            sender.send(Ok(kernel_message)).await.ok();
+                            if let Some(kernel_message) = upgrades.hold(kernel_message) {
+                                match sender.try_send(Ok(kernel_message)) {
+                                    Ok(()) => tracer.deliver(span),
+                                    Err(TrySendError::Full(Ok(kernel_message))) => {
+                                        t::Printout::new(
+                                            2,
+                                            KERNEL_PROCESS_ID.clone(),
+                                            format!(
+                                                "event loop: mailbox of {} is full, dropping message from {}",
+                                                kernel_message.target.process,
+                                                kernel_message.source,
+                                            )
+                                        ).send(&send_to_terminal).await;
+                                        throw_queue_full(&our.name, &senders, kernel_message).await;
+                                    }
+                                    Err(_) => {}
+                                }
+                            }
                         }
                         Some(ProcessSender::Runtime { sender, .. }) => {
                             sender.send(kernel_message).await.expect("event loop: fatal: runtime module died");
+                            tracer.deliver(span);
                         }
                         None => {
                             t::Printout::new(
@@ -1080,7 +2167,7 @@ pub async fn kernel(
                     }
                 }
                 match cap_message {
-                    t::CapMessage::Add { on, caps, responder } => {
+                    t::CapMessage::Add { on, caps, actor, reason, responder } => {
                         // insert cap in process map
                         let Some(entry) = process_map.get_mut(&on) else {
                             if let Some(responder) = responder {
@@ -1088,12 +2175,23 @@ pub async fn kernel(
                             }
                             continue;
                         };
+                        // replace held versions, so that new constraints take effect,
+                        // but only the issuer may loosen them
                         let signed_caps: Vec<(t::Capability, Vec<u8>)> =
                             caps.into_iter().map(|cap| {
+                                let cap = match entry.capabilities.get_key_value(&cap) {
+                                    Some((held, _)) if cap.issuer.process != actor => {
+                                        cap.no_looser_than(held)
+                                    }
+                                    _ => cap,
+                                };
                                 let sig = keypair.sign(&rmp_serde::to_vec(&cap).unwrap());
                                 (cap, sig.as_ref().to_vec())
                             }).collect();
-                        entry.capabilities.extend(signed_caps.clone());
+                        for (cap, sig) in &signed_caps {
+                            entry.capabilities.remove(cap);
+                            entry.capabilities.insert(cap.clone(), sig.clone());
+                        }
                         // now we have to insert all caps into the reverse cap index
                         for (cap, _) in &signed_caps {
                             reverse_cap_index
@@ -1103,6 +2201,13 @@ pub async fn kernel(
                                 .or_insert_with(Vec::new)
                                 .push(cap.clone());
                         }
+                        cap_audit.record(
+                            t::CapAuditAction::Add,
+                            &actor,
+                            &on,
+                            signed_caps.iter().map(|(cap, _)| cap),
+                            reason,
+                        );
                         if !entry.on_exit.is_none() {
                             persist_state(&send_to_loop, &process_map).await;
                         }
@@ -1110,7 +2215,7 @@ pub async fn kernel(
                             responder.send(true).ok();
                         }
                     },
-                    t::CapMessage::Drop { on, caps, responder } => {
+                    t::CapMessage::Drop { on, caps, actor, reason, responder } => {
                         // remove cap from process map
                         let Some(entry) = process_map.get_mut(&on) else {
                             if let Some(responder) = responder {
@@ -1118,22 +2223,49 @@ pub async fn kernel(
                             }
                             continue;
                         };
-                        for cap in &caps {
-                            entry.capabilities.remove(&cap);
-                        }
+                        let dropped: Vec<t::Capability> = caps
+                            .into_iter()
+                            .filter(|cap| entry.capabilities.remove(cap).is_some())
+                            .collect();
+                        let lost_drives = wasi::preopens_depend_on(&our.name, entry, &dropped)
+                            .then(|| on.clone());
+                        cap_audit.record(
+                            t::CapAuditAction::Drop,
+                            &actor,
+                            &on,
+                            &dropped,
+                            reason,
+                        );
                         if !entry.on_exit.is_none() {
                             persist_state(&send_to_loop, &process_map).await;
                         }
                         if let Some(responder) = responder {
                             responder.send(true).ok();
                         }
+                        restart_for_preopens(
+                            &our.name,
+                            &keypair,
+                            &send_to_loop,
+                            &send_to_terminal,
+                            &mut senders,
+                            &mut process_handles,
+                            &process_map,
+                            &engine,
+                            &component_cache,
+                            &caps_oracle_sender,
+                            &mut process_restart_backoffs,
+                            &mailbox_capacities,
+                            &home_directory_path,
+                            &recordings,
+                            lost_drives.into_iter().collect(),
+                        ).await;
                     },
                     t::CapMessage::Has { on, cap, responder } => {
                         // return boolean on responder
                         responder.send(
                             match process_map.get(&on) {
                                 None => false,
-                                Some(p) => p.capabilities.contains_key(&cap),
+                                Some(p) => holds_cap(p, &cap),
                             }
                         ).ok();
                     },
@@ -1146,24 +2278,53 @@ pub async fn kernel(
                             }
                         ).ok();
                     },
-                    t::CapMessage::RevokeAll { on, responder } => {
+                    t::CapMessage::RevokeAll { on, actor, reason, responder } => {
                         let Some(granter) = reverse_cap_index.get(&on) else {
                             if let Some(responder) = responder {
                                 responder.send(true).ok();
                             }
                             continue;
                         };
+                        let mut lost_drives = vec![];
                         for (grantee, caps) in granter {
                             if let Some(entry) = process_map.get_mut(&grantee) {
-                                for cap in caps {
-                                    entry.capabilities.remove(&cap);
+                                let revoked: Vec<&t::Capability> = caps
+                                    .iter()
+                                    .filter(|cap| entry.capabilities.remove(*cap).is_some())
+                                    .collect();
+                                if wasi::preopens_depend_on(&our.name, entry, revoked.iter().copied()) {
+                                    lost_drives.push(grantee.clone());
                                 }
+                                cap_audit.record(
+                                    t::CapAuditAction::Revoke,
+                                    &actor,
+                                    grantee,
+                                    revoked,
+                                    reason,
+                                );
                             };
                         }
                         persist_state(&send_to_loop, &process_map).await;
                         if let Some(responder) = responder {
                             responder.send(true).ok();
                         }
+                        restart_for_preopens(
+                            &our.name,
+                            &keypair,
+                            &send_to_loop,
+                            &send_to_terminal,
+                            &mut senders,
+                            &mut process_handles,
+                            &process_map,
+                            &engine,
+                            &component_cache,
+                            &caps_oracle_sender,
+                            &mut process_restart_backoffs,
+                            &mailbox_capacities,
+                            &home_directory_path,
+                            &recordings,
+                            lost_drives,
+                        ).await;
                     }
                     t::CapMessage::FilterCaps { on, caps, responder } => {
                         responder.send(
@@ -1175,10 +2336,16 @@ pub async fn kernel(
                                         if cap.issuer.process == on {
                                             let sig = keypair.sign(&rmp_serde::to_vec(&cap).unwrap());
                                             Some((cap, sig.as_ref().to_vec()))
-                                        // otherwise, only attach previously saved caps
+                                        // otherwise, only attach previously saved caps,
+                                        // as held, if unexpired and delegable
                                         // NOTE we don't need to verify the sigs!
                                         } else {
-                                            p.capabilities.get(&cap).map(|sig| (cap, sig.clone()))
+                                            p.capabilities
+                                                .get_key_value(&cap)
+                                                .filter(|(held, _)| {
+                                                    !held.is_expired(unix_time_secs()) && held.is_delegable()
+                                                })
+                                                .map(|(held, sig)| (held.clone(), sig.clone()))
                                         }
                                     }).collect()
                                 },
@@ -1191,6 +2358,60 @@ pub async fn kernel(
     }
 }
 
+/// whether a process holds a capability that hasn't expired
+fn holds_cap(process: &t::PersistedProcess, cap: &t::Capability) -> bool {
+    process
+        .capabilities
+        .get_key_value(cap)
+        .is_some_and(|(held, _)| !held.is_expired(unix_time_secs()))
+}
+
+/// seconds since the unix epoch, as capability expiries are given
+fn unix_time_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// tell the sender of a Request that expects a Response that its target's mailbox
+/// was full. runtime modules hear of it as they do of network errors; remote senders
+/// are left to time out.
+async fn throw_queue_full(
+    our_name: &str,
+    senders: &HashMap<t::ProcessId, ProcessSender>,
+    km: t::KernelMessage,
+) {
+    let t::Message::Request(req) = &km.message else {
+        return;
+    };
+    if req.expects_response.is_none() || km.source.node != our_name {
+        return;
+    }
+    let error = t::WrappedSendError {
+        id: km.id,
+        source: t::Address::new(our_name, KERNEL_PROCESS_ID.clone()),
+        error: t::SendError {
+            kind: t::SendErrorKind::QueueFull,
+            target: km.target,
+            lazy_load_blob: km.lazy_load_blob,
+            message: km.message,
+        },
+    };
+    match senders.get(&km.source.process) {
+        Some(ProcessSender::Userspace(sender)) => {
+            sender.try_send(Err(error)).ok();
+        }
+        Some(ProcessSender::Runtime {
+            net_errors: Some(net_errors),
+            ..
+        }) => {
+            net_errors.try_send(error).ok();
+        }
+        _ => {}
+    }
+}
+
 async fn throw_timeout(
     our_name: &str,
     senders: &HashMap<t::ProcessId, ProcessSender>,
@@ -1200,7 +2421,7 @@ async fn throw_timeout(
         if req.expects_response.is_some() {
             if let Some(ProcessSender::Userspace(sender)) = senders.get(&km.source.process) {
                 sender
-                    .send(Err(t::WrappedSendError {
+                    .try_send(Err(t::WrappedSendError {
                         id: km.id,
                         source: t::Address {
                             node: our_name.to_string(),
@@ -1213,9 +2434,123 @@ async fn throw_timeout(
                             message: km.message,
                         },
                     }))
-                    .await
                     .ok();
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: u64, from: &str, to: &str, request: bool) -> t::KernelMessage {
+        let process = |name: &str| t::ProcessId::new(Some(name), "test", "sys");
+        t::KernelMessage::builder()
+            .id(id)
+            .source(("our", process(from)))
+            .target(("our", process(to)))
+            .message(if request {
+                t::Message::Request(t::Request {
+                    inherit: false,
+                    expects_response: None,
+                    body: vec![],
+                    metadata: None,
+                    capabilities: vec![],
+                })
+            } else {
+                t::Message::Response((
+                    t::Response {
+                        inherit: false,
+                        body: vec![],
+                        metadata: None,
+                        capabilities: vec![],
+                    },
+                    None,
+                ))
+            })
+            .build()
+            .unwrap()
+    }
+
+    fn upgrading(name: &str, request_id: u64) -> Upgrades {
+        let process_id = t::ProcessId::new(Some(name), "test", "sys");
+        let mut upgrades = Upgrades::default();
+        upgrades.held.insert(process_id.clone(), vec![]);
+        upgrades.pending.insert(
+            request_id,
+            PendingUpgrade {
+                process_id,
+                wasm_bytes_handle: String::new(),
+                wasm_bytes: vec![],
+                command: (0, t::Address::new("our", KERNEL_PROCESS_ID.clone())),
+            },
+        );
+        upgrades
+    }
+
+    #[test]
+    fn upgrade_holds_only_requests() {
+        let mut upgrades = upgrading("app", 7);
+        assert!(upgrades.hold(message(1, "other", "app", true)).is_none());
+        // the old code is waiting on this one
+        assert!(upgrades.hold(message(2, "other", "app", false)).is_some());
+        assert!(upgrades.hold(message(3, "other", "elsewhere", true)).is_some());
+        let held = &upgrades.held[&t::ProcessId::new(Some("app"), "test", "sys")];
+        assert_eq!(held.iter().map(|km| km.id).collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn handoff_comes_only_from_the_upgrading_process() {
+        let upgrades = upgrading("app", 7);
+        assert!(upgrades.is_handoff(&message(7, "app", "kernel", false)));
+        assert!(!upgrades.is_handoff(&message(7, "other", "kernel", false)));
+        assert!(!upgrades.is_handoff(&message(8, "app", "kernel", false)));
+        assert!(!upgrades.is_handoff(&message(7, "app", "kernel", true)));
+    }
+
+    #[test]
+    fn handoff_export_is_optional() {
+        let engine = make_engine();
+        let with = Component::new(
+            &engine,
+            r#"(component
+                (core module $m (func (export "f")))
+                (core instance $i (instantiate $m))
+                (func (export "handoff") (canon lift (core func $i "f")))
+            )"#,
+        )
+        .unwrap();
+        assert!(exports_handoff(&with));
+        let without = Component::new(&engine, "(component)").unwrap();
+        assert!(!exports_handoff(&without));
+    }
+
+    #[test]
+    fn only_losing_a_vfs_cap_takes_away_preopens() {
+        let mut persisted = t::PersistedProcess {
+            wasm_bytes_handle: String::new(),
+            wit_version: None,
+            on_exit: t::OnExit::None,
+            capabilities: HashMap::new(),
+            public: false,
+            wasi: t::WasiPolicy {
+                preopen_drives: true,
+                ..Default::default()
+            },
+        };
+        let vfs_cap = t::Capability::new(
+            ("our", VFS_PROCESS_ID.clone()),
+            r#"{"kind":"write","drive":"/test:sys/data"}"#,
+        );
+        let other_cap = t::Capability::new(("our", STATE_PROCESS_ID.clone()), "\"messaging\"");
+        let remote_cap = t::Capability::new(("their", VFS_PROCESS_ID.clone()), "{}");
+
+        assert!(wasi::preopens_depend_on("our", &persisted, [&vfs_cap]));
+        assert!(!wasi::preopens_depend_on("our", &persisted, [&other_cap]));
+        assert!(!wasi::preopens_depend_on("our", &persisted, [&remote_cap]));
+
+        persisted.wasi.preopen_drives = false;
+        assert!(!wasi::preopens_depend_on("our", &persisted, [&vfs_cap]));
+    }
+}
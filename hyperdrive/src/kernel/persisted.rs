@@ -0,0 +1,94 @@
+//! State the kernel keeps as JSON files in the home directory, such as crash
+//! reports, supervision groups and mailbox capacities.
+
+use lib::types::core::{self as t, KERNEL_PROCESS_ID};
+use serde::{de::DeserializeOwned, Serialize};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+
+/// Read state saved with [`save`]; a missing file is the default state. A file
+/// that can't be parsed is moved to `<file>.corrupt`, so that saving the default
+/// state doesn't overwrite it and it can be recovered by hand.
+pub async fn load<T: DeserializeOwned + Default>(path: &Path) -> Result<T, String> {
+    let bytes = match tokio::fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => return Err(format!("couldn't read {}: {e}", path.display())),
+    };
+    let e = match serde_json::from_slice(&bytes) {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+    let corrupt = with_suffix(path, "corrupt");
+    let kept = match tokio::fs::rename(path, &corrupt).await {
+        Ok(()) => format!("kept as {}", corrupt.display()),
+        Err(e) => format!("couldn't keep it: {e}"),
+    };
+    Err(format!("couldn't parse {}, {kept}: {e}", path.display()))
+}
+
+/// [`load`] state at boot, reporting an error to the terminal and starting over
+/// from the default state.
+pub async fn load_or_report<T: DeserializeOwned + Default>(
+    path: &Path,
+    send_to_terminal: &t::PrintSender,
+) -> T {
+    match load(path).await {
+        Ok(value) => value,
+        Err(e) => {
+            t::Printout::new(0, KERNEL_PROCESS_ID.clone(), format!("kernel: {e}"))
+                .send(send_to_terminal)
+                .await;
+            T::default()
+        }
+    }
+}
+
+/// Save state so that a crash midway leaves either the old state or the new one:
+/// write it to a temporary file, sync that, and rename it over the old file.
+pub async fn save(path: &Path, value: &impl Serialize) -> std::io::Result<()> {
+    let tmp_path = with_suffix(path, "tmp");
+    let mut file = tokio::fs::File::create(&tmp_path).await?;
+    file.write_all(&serde_json::to_vec(value).unwrap()).await?;
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(&tmp_path, path).await
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".");
+    path.push(suffix);
+    path.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn round_trips_and_keeps_corrupt_files() {
+        let home = tempfile::tempdir().unwrap();
+        let path = home.path().join(".state");
+
+        let missing: HashMap<String, u64> = load(&path).await.unwrap();
+        assert!(missing.is_empty());
+
+        let state = HashMap::from([("a".to_string(), 1u64)]);
+        save(&path, &state).await.unwrap();
+        assert_eq!(load::<HashMap<String, u64>>(&path).await.unwrap(), state);
+        assert!(!home.path().join(".state.tmp").exists());
+
+        // a torn write
+        tokio::fs::write(&path, b"{\"a\": ").await.unwrap();
+        assert!(load::<HashMap<String, u64>>(&path).await.is_err());
+        assert!(!path.exists());
+        assert_eq!(
+            tokio::fs::read(home.path().join(".state.corrupt"))
+                .await
+                .unwrap(),
+            b"{\"a\": "
+        );
+    }
+}
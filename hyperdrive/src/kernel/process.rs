@@ -2,7 +2,8 @@ use crate::KERNEL_PROCESS_ID;
 use bytes::{BufMut, Bytes, BytesMut};
 use lib::{types::core as t, v1::ProcessV1};
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
+    path::PathBuf,
     sync::{
         atomic::{AtomicUsize, Ordering},
         Arc, Mutex as StdMutex,
@@ -11,15 +12,27 @@ use std::{
 };
 use tokio::{sync::Mutex, task::JoinHandle};
 use wasmtime::{
-    component::{Component, Linker, ResourceTable as Table},
+    component::{Linker, ResourceTable as Table},
     Engine, Store,
 };
 use wasmtime_wasi::p2::{
     pipe::MemoryOutputPipe, IoView, StdoutStream, StreamResult, WasiCtx, WasiCtxBuilder, WasiView,
 };
+use wasmtime_wasi_http::{
+    bindings::http::types::ErrorCode,
+    body::HyperOutgoingBody,
+    types::{default_send_request, HostFutureIncomingResponse, OutgoingRequestConfig},
+    HttpResult, WasiHttpCtx, WasiHttpView,
+};
 use wasmtime_wasi_io::{async_trait, poll::Pollable, streams::OutputStream};
 
-use super::RestartBackoff;
+use super::{
+    component_cache::ComponentCache,
+    mailbox::{Mailbox, MailboxSender},
+    record::{HostReads, Recorder},
+    wasi::WasiAccess,
+    RestartBackoff,
+};
 
 const STACK_TRACE_SIZE: usize = 5000;
 const BASE_BACKOFF_SECS: u64 = 1;
@@ -40,9 +53,9 @@ pub struct ProcessState {
     /// information about ourself
     pub metadata: t::ProcessMetadata,
     /// pipe from which we get messages from the main event loop
-    pub recv_in_process: t::ProcessMessageReceiver,
+    pub recv_in_process: Mailbox,
     /// pipe to send messages to ourself (received in `recv_in_process`)
-    pub self_sender: t::ProcessMessageSender,
+    pub self_sender: MailboxSender,
     /// pipe for sending messages to the main event loop
     pub send_to_loop: t::MessageSender,
     /// pipe for sending [`t::Printout`]s to the terminal
@@ -63,12 +76,19 @@ pub struct ProcessState {
     pub message_queue: VecDeque<Result<t::KernelMessage, t::WrappedSendError>>,
     /// pipe for getting info about capabilities
     pub caps_oracle: t::CapMessageSender,
+    /// the latest version of each capability attached to a message we received,
+    /// so that saving one keeps the constraints it was sent with
+    pub received_caps: HashSet<t::Capability>,
+    /// records the messages taken in and sent during this run, if asked to
+    pub recorder: Option<Arc<Recorder>>,
 }
 
 pub struct ProcessWasiV1 {
     pub process: ProcessState,
     table: Table,
     wasi: WasiCtx,
+    http: WasiHttpCtx,
+    wasi_access: WasiAccess,
 }
 
 impl IoView for ProcessWasiV1 {
@@ -83,24 +103,52 @@ impl WasiView for ProcessWasiV1 {
     }
 }
 
-async fn make_table_and_wasi() -> (Table, WasiCtx, RotatingOutputPipe) {
+/// only linked for processes whose WASI policy allows some HTTP hosts
+impl WasiHttpView for ProcessWasiV1 {
+    fn ctx(&mut self) -> &mut WasiHttpCtx {
+        &mut self.http
+    }
+
+    fn send_request(
+        &mut self,
+        request: http::Request<HyperOutgoingBody>,
+        config: OutgoingRequestConfig,
+    ) -> HttpResult<HostFutureIncomingResponse> {
+        match request.uri().host() {
+            Some(host) if self.wasi_access.allows_http_host(host) => {
+                Ok(default_send_request(request, config))
+            }
+            _ => Err(ErrorCode::HttpRequestDenied.into()),
+        }
+    }
+}
+
+async fn make_table_and_wasi(
+    wasi_access: &WasiAccess,
+    host_reads: &HostReads,
+) -> anyhow::Result<(Table, WasiCtx, RotatingOutputPipe)> {
     let table = Table::new();
     let wasi_stderr = RotatingOutputPipe::new(STACK_TRACE_SIZE);
 
     let mut wasi = WasiCtxBuilder::new();
+    wasi_access.apply(&mut wasi)?;
+    host_reads.apply(&mut wasi, wasi_access.allows_wall_clock());
 
-    (table, wasi.stderr(wasi_stderr.clone()).build(), wasi_stderr)
+    Ok((table, wasi.stderr(wasi_stderr.clone()).build(), wasi_stderr))
 }
 
-async fn make_component_v1(
+pub async fn make_component_v1(
     engine: Engine,
+    component_cache: &ComponentCache,
     wasm_bytes: &[u8],
     process_state: ProcessState,
+    wasi_access: WasiAccess,
+    host_reads: HostReads,
 ) -> anyhow::Result<(ProcessV1, Store<ProcessWasiV1>, RotatingOutputPipe)> {
     let our_process_id = process_state.metadata.our.process.clone();
     let send_to_terminal = process_state.send_to_terminal.clone();
 
-    let component = match Component::new(&engine, wasm_bytes.to_vec()) {
+    let component = match component_cache.get(&engine, wasm_bytes).await {
         Ok(c) => c,
         Err(e) => {
             t::Printout::new(
@@ -116,14 +164,37 @@ async fn make_component_v1(
 
     let mut linker = Linker::new(&engine);
     ProcessV1::add_to_linker(&mut linker, |state: &mut ProcessWasiV1| state).unwrap();
-    let (table, wasi, wasi_stderr) = make_table_and_wasi().await;
-    wasmtime_wasi::p2::add_to_linker_async(&mut linker).unwrap();
+    let (table, wasi, wasi_stderr) = match make_table_and_wasi(&wasi_access, &host_reads).await {
+        Ok(t) => t,
+        Err(e) => {
+            t::Printout::new(
+                0,
+                t::KERNEL_PROCESS_ID.clone(),
+                format!("kernel: process {our_process_id} couldn't get its WASI: {e:?}"),
+            )
+            .send(&send_to_terminal)
+            .await;
+            return Err(e);
+        }
+    };
+    if let Err(e) = wasi_access.link(&mut linker) {
+        t::Printout::new(
+            0,
+            t::KERNEL_PROCESS_ID.clone(),
+            format!("kernel: process {our_process_id} couldn't link its WASI: {e:?}"),
+        )
+        .send(&send_to_terminal)
+        .await;
+        return Err(e);
+    }
     let mut store = Store::new(
         &engine,
         ProcessWasiV1 {
             process: process_state,
             table,
             wasi,
+            http: WasiHttpCtx::new(),
+            wasi_access,
         },
     );
 
@@ -150,12 +221,15 @@ pub async fn make_process_loop(
     metadata: t::ProcessMetadata,
     send_to_loop: t::MessageSender,
     send_to_terminal: t::PrintSender,
-    mut recv_in_process: t::ProcessMessageReceiver,
-    send_to_process: t::ProcessMessageSender,
+    mut recv_in_process: Mailbox,
+    send_to_process: MailboxSender,
     wasm_bytes: Vec<u8>,
     caps_oracle: t::CapMessageSender,
     engine: Engine,
+    component_cache: ComponentCache,
     maybe_restart_backoff: Option<Arc<Mutex<Option<RestartBackoff>>>>,
+    recorder: Option<Arc<Recorder>>,
+    vfs_path: PathBuf,
 ) -> anyhow::Result<()> {
     // before process can be instantiated, need to await 'run' message from kernel
     let mut pre_boot_queue = Vec::<Result<t::KernelMessage, t::WrappedSendError>>::new();
@@ -211,6 +285,7 @@ pub async fn make_process_loop(
 
     let our = metadata.our.clone();
     let wit_version = metadata.wit_version.clone();
+    let wasi_access = WasiAccess::new(&metadata, &vfs_path, &caps_oracle).await;
 
     let process_state = ProcessState {
         keypair,
@@ -225,13 +300,25 @@ pub async fn make_process_loop(
         contexts: HashMap::new(),
         message_queue: VecDeque::new(),
         caps_oracle: caps_oracle.clone(),
+        received_caps: HashSet::new(),
+        recorder: recorder.clone(),
     };
 
     let metadata = match wit_version {
         // assume missing version is oldest wit version
         None | Some(1) | _ => {
-            let (bindings, mut store, wasi_stderr) =
-                make_component_v1(engine, &wasm_bytes, process_state).await?;
+            let (bindings, mut store, wasi_stderr) = make_component_v1(
+                engine,
+                &component_cache,
+                &wasm_bytes,
+                process_state,
+                wasi_access,
+                match &recorder {
+                    Some(recorder) => HostReads::Recorded(recorder.clone()),
+                    None => HostReads::Live,
+                },
+            )
+            .await?;
 
             // the process will run until it returns from init() or crashes
             match bindings.call_init(&mut store, &our.to_string()).await {
@@ -248,7 +335,7 @@ pub async fn make_process_loop(
                     let stderr = wasi_stderr.contents().into();
                     let stderr = String::from_utf8(stderr)?;
                     let output = if stderr != String::new() {
-                        stderr
+                        stderr.clone()
                     } else {
                         format!("{}", e.root_cause())
                     };
@@ -259,9 +346,59 @@ pub async fn make_process_loop(
                     )
                     .send(&send_to_terminal)
                     .await;
+
+                    let restart_attempt = match &maybe_restart_backoff {
+                        Some(restart_backoff) => restart_backoff
+                            .lock()
+                            .await
+                            .as_ref()
+                            .map(|rb| rb.consecutive_attempts),
+                        None => None,
+                    };
+                    let report = t::CrashReport {
+                        timestamp: std::time::SystemTime::now()
+                            .duration_since(std::time::UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs(),
+                        trap: e.root_cause().to_string(),
+                        backtrace: e
+                            .downcast_ref::<wasmtime::WasmBacktrace>()
+                            .map(|backtrace| backtrace.to_string()),
+                        stderr,
+                        last_message: store
+                            .data()
+                            .process
+                            .prompting_message
+                            .as_ref()
+                            .map(|km| km.to_string()),
+                        restart_attempt,
+                    };
+                    t::KernelMessage::builder()
+                        .id(rand::random())
+                        .source((&our.node, KERNEL_PROCESS_ID.clone()))
+                        .target((&our.node, KERNEL_PROCESS_ID.clone()))
+                        .message(t::Message::Request(t::Request {
+                            inherit: false,
+                            expects_response: None,
+                            body: serde_json::to_vec(&t::KernelCommand::ReportCrash {
+                                process: our.process.clone(),
+                                report,
+                            })
+                            .unwrap(),
+                            metadata: None,
+                            capabilities: vec![],
+                        }))
+                        .build()
+                        .unwrap()
+                        .send(&send_to_loop)
+                        .await;
                 }
             };
 
+            if let Some(recorder) = &recorder {
+                recorder.stop();
+            }
+
             // update metadata to what was mutated by process in store
             store.data().process.metadata.to_owned()
         }
@@ -282,6 +419,27 @@ pub async fn make_process_loop(
     .send(&send_to_terminal)
     .await;
 
+    // let the kernel act on the process's supervision group, if any, before the
+    // process is killed or restarted below
+    t::KernelMessage::builder()
+        .id(rand::random())
+        .source((&our.node, KERNEL_PROCESS_ID.clone()))
+        .target((&our.node, KERNEL_PROCESS_ID.clone()))
+        .message(t::Message::Request(t::Request {
+            inherit: false,
+            expects_response: None,
+            body: serde_json::to_vec(&t::KernelCommand::ProcessExited(
+                metadata.our.process.clone(),
+            ))
+            .unwrap(),
+            metadata: None,
+            capabilities: vec![],
+        }))
+        .build()
+        .unwrap()
+        .send(&send_to_loop)
+        .await;
+
     // fulfill the designated OnExit behavior
     match metadata.on_exit {
         t::OnExit::None => {
@@ -414,6 +572,7 @@ pub async fn make_process_loop(
                             on_exit: metadata.on_exit,
                             initial_capabilities,
                             public: metadata.public,
+                            wasi: metadata.wasi,
                         })
                         .unwrap(),
                         metadata: None,
@@ -0,0 +1,317 @@
+//! Recording of one run of a process: every message it takes in and sends, and
+//! everything else it reads from the host that could differ on a second run (its
+//! capabilities, clocks, and insecure randomness), as JSON lines in `.recordings` in
+//! the home directory. See `replay` for the other half.
+//!
+//! A recording holds whatever the process was sent, so it is readable by our user
+//! only. Secure randomness is never recorded, since the process may have made keys
+//! of it: a replay draws fresh bytes instead.
+
+use lib::types::core::{self as t};
+use rand::RngCore;
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::{io::AsyncWriteExt, sync::mpsc};
+use wasmtime_wasi::{p2::WasiCtxBuilder, HostMonotonicClock, HostWallClock};
+
+use super::replay::ReplayedReads;
+
+pub struct Recorder {
+    path: PathBuf,
+    /// lines for the writer task; `None` once the recording has stopped
+    lines: Mutex<Option<mpsc::UnboundedSender<Vec<u8>>>>,
+}
+
+impl Recorder {
+    /// Create the recording file, write its header, and start writing events to it
+    /// as they're recorded.
+    pub async fn create(
+        home_directory_path: &Path,
+        header: &t::RecordingHeader,
+    ) -> std::io::Result<Self> {
+        let dir = home_directory_path.join(".recordings");
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = dir.join(format!(
+            "{}-{}.jsonl",
+            header.process.process.to_string().replace(':', "_"),
+            header.started,
+        ));
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&path).await?;
+        file.write_all(&line(header)?).await?;
+
+        let (send_lines, mut recv_lines) = mpsc::unbounded_channel::<Vec<u8>>();
+        tokio::spawn(async move {
+            // each line is written whole, so that a crash of the runtime loses at most
+            // the events not yet written
+            while let Some(line) = recv_lines.recv().await {
+                if file.write_all(&line).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            path,
+            lines: Mutex::new(Some(send_lines)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append an event. A recording that can't be written to stops. Never blocks:
+    /// the writing happens on a task of its own.
+    pub fn record(&self, event: &t::RecordedEvent) {
+        let mut lines = self.lines.lock().unwrap();
+        let written = match (lines.as_ref(), line(event)) {
+            (Some(send_lines), Ok(line)) => send_lines.send(line).is_ok(),
+            _ => false,
+        };
+        if !written {
+            *lines = None;
+        }
+    }
+
+    /// Stop recording. Events already recorded are still written.
+    pub fn stop(&self) {
+        self.lines.lock().unwrap().take();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.lines.lock().unwrap().is_none()
+    }
+}
+
+fn line<T: serde::Serialize>(value: &T) -> serde_json::Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// Where a process's clocks and randomness come from.
+pub enum HostReads {
+    /// the host, as WASI gives them by default
+    Live,
+    /// the host, recording each read
+    Recorded(Arc<Recorder>),
+    /// a recording, read back in the order they were recorded
+    Replayed(Arc<Mutex<ReplayedReads>>),
+}
+
+impl HostReads {
+    /// Set the clocks and randomness of a process's WASI. The wall clock is left alone
+    /// unless the process may read it.
+    pub fn apply(&self, builder: &mut WasiCtxBuilder, wall_clock: bool) {
+        match self {
+            HostReads::Live => {}
+            HostReads::Recorded(recorder) => {
+                let seed = rand::random();
+                recorder.record(&t::RecordedEvent::InsecureRandomSeed(seed));
+                builder.insecure_random_seed(seed);
+                builder.insecure_random(RecordedRng {
+                    recorder: recorder.clone(),
+                    rng: wasmtime_wasi::thread_rng(),
+                });
+                builder.monotonic_clock(RecordedMonotonicClock {
+                    recorder: recorder.clone(),
+                    start: Instant::now(),
+                });
+                if wall_clock {
+                    builder.wall_clock(RecordedWallClock {
+                        recorder: recorder.clone(),
+                    });
+                }
+            }
+            HostReads::Replayed(reads) => {
+                builder.insecure_random_seed(reads.lock().unwrap().insecure_random_seed);
+                builder.insecure_random(ReplayedRng {
+                    reads: reads.clone(),
+                });
+                builder.monotonic_clock(ReplayedMonotonicClock {
+                    reads: reads.clone(),
+                });
+                if wall_clock {
+                    builder.wall_clock(ReplayedWallClock {
+                        reads: reads.clone(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+struct RecordedWallClock {
+    recorder: Arc<Recorder>,
+}
+
+impl HostWallClock for RecordedWallClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self) -> Duration {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.recorder.record(&t::RecordedEvent::WallClock(now));
+        now
+    }
+}
+
+struct RecordedMonotonicClock {
+    recorder: Arc<Recorder>,
+    start: Instant,
+}
+
+impl HostMonotonicClock for RecordedMonotonicClock {
+    fn resolution(&self) -> u64 {
+        1
+    }
+
+    fn now(&self) -> u64 {
+        let now = self.start.elapsed().as_nanos() as u64;
+        self.recorder.record(&t::RecordedEvent::MonotonicClock(now));
+        now
+    }
+}
+
+struct RecordedRng {
+    recorder: Arc<Recorder>,
+    rng: Box<dyn RngCore + Send>,
+}
+
+impl RngCore for RecordedRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest);
+        self.recorder
+            .record(&t::RecordedEvent::InsecureRandom(dest.to_vec()));
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+struct ReplayedWallClock {
+    reads: Arc<Mutex<ReplayedReads>>,
+}
+
+impl HostWallClock for ReplayedWallClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(1)
+    }
+
+    fn now(&self) -> Duration {
+        self.reads.lock().unwrap().wall_clock()
+    }
+}
+
+struct ReplayedMonotonicClock {
+    reads: Arc<Mutex<ReplayedReads>>,
+}
+
+impl HostMonotonicClock for ReplayedMonotonicClock {
+    fn resolution(&self) -> u64 {
+        1
+    }
+
+    fn now(&self) -> u64 {
+        self.reads.lock().unwrap().monotonic_clock()
+    }
+}
+
+struct ReplayedRng {
+    reads: Arc<Mutex<ReplayedReads>>,
+}
+
+impl RngCore for ReplayedRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.reads.lock().unwrap().random(dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn events_are_written_after_the_header_until_stopped() {
+        let home = tempfile::tempdir().unwrap();
+        let header = t::RecordingHeader {
+            process: t::Address::new("our", t::ProcessId::new(Some("a"), "test", "sys")),
+            wasm_bytes_handle: "/test:sys/pkg/a.wasm".to_string(),
+            wit_version: Some(1),
+            on_exit: t::OnExit::None,
+            public: false,
+            started: 1,
+            wasi: t::WasiPolicy::default(),
+        };
+        let recorder = Recorder::create(home.path(), &header).await.unwrap();
+        recorder.record(&t::RecordedEvent::MonotonicClock(1));
+        recorder.record(&t::RecordedEvent::InsecureRandom(vec![2]));
+        recorder.stop();
+        assert!(recorder.is_stopped());
+        recorder.record(&t::RecordedEvent::MonotonicClock(3));
+
+        // events recorded before the stop are still written
+        let mut lines = vec![];
+        for _ in 0..100 {
+            let contents = tokio::fs::read_to_string(recorder.path()).await.unwrap();
+            lines = contents.lines().map(str::to_string).collect::<Vec<_>>();
+            if lines.len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(lines.len(), 3);
+        let header: t::RecordingHeader = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(header.started, 1);
+        assert!(matches!(
+            serde_json::from_str(&lines[2]).unwrap(),
+            t::RecordedEvent::InsecureRandom(bytes) if bytes == vec![2]
+        ));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let metadata = tokio::fs::metadata(recorder.path()).await.unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+    }
+}
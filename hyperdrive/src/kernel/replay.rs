@@ -0,0 +1,397 @@
+//! Offline replay of a process recording made by `record`. The process's Wasm is run
+//! with no kernel or other processes: each message it took in is fed to it again from
+//! the recording, and each message it sends is checked against what it sent before.
+//! Its capabilities, clocks, and insecure randomness are read back from the recording
+//! too; secure randomness was never recorded, so the process gets fresh bytes.
+//! The runtime's request IDs are random, so IDs of the process's own requests are
+//! mapped from the recording to the replay as the requests are sent.
+
+use lib::types::core::{self as t};
+use ring::signature::Ed25519KeyPair;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    sync::mpsc,
+    time::{timeout, Duration},
+};
+
+use super::{
+    component_cache::ComponentCache,
+    mailbox::{self, DEFAULT_MAILBOX_CAPACITY},
+    process,
+    record::HostReads,
+    wasi::WasiAccess,
+};
+
+/// how long to wait for the process to send a message it sent in the recording
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+/// how long to wait, after the last recorded event, for sends the recording lacks
+const TRAILING_SENDS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Replay a recording and print each divergence from it. Returns the number of
+/// divergences. `wasm` overrides the Wasm the recording was made with, which is
+/// otherwise read from the node's vfs.
+pub async fn replay(
+    home_directory_path: &Path,
+    recording: &Path,
+    wasm: Option<&Path>,
+) -> anyhow::Result<usize> {
+    let contents = tokio::fs::read_to_string(recording).await?;
+    let mut lines = contents.lines().filter(|line| !line.is_empty());
+    let header: t::RecordingHeader = serde_json::from_str(
+        lines
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("recording is empty"))?,
+    )?;
+    let events = lines
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<t::RecordedEvent>, _>>()?;
+    let (events, reads) = ReplayedReads::split(events);
+    let reads = Arc::new(Mutex::new(reads));
+
+    let wasm_path = match wasm {
+        Some(wasm) => wasm.to_path_buf(),
+        None => super::wasm_bytes_path(&home_directory_path.join("vfs"), &header.wasm_bytes_handle),
+    };
+    let wasm_bytes = tokio::fs::read(&wasm_path)
+        .await
+        .map_err(|e| anyhow::anyhow!("couldn't read {}: {e}", wasm_path.display()))?;
+
+    println!(
+        "replaying {} events of {} from {}",
+        events.len(),
+        header.process,
+        recording.display(),
+    );
+
+    let engine = super::make_engine();
+    // a replay doesn't need the cache: it only saves a compile
+    let component_cache = ComponentCache::new(home_directory_path, &engine)
+        .await
+        .unwrap_or_else(|_| ComponentCache::disabled());
+    let keypair = Arc::new(
+        Ed25519KeyPair::from_pkcs8(
+            Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new())
+                .unwrap()
+                .as_ref(),
+        )
+        .map_err(|e| anyhow::anyhow!("couldn't make a keypair: {e}"))?,
+    );
+
+    let (send_to_loop, mut recv_sends) =
+        mpsc::channel::<t::KernelMessage>(DEFAULT_MAILBOX_CAPACITY);
+    let (send_to_process, recv_in_process) = mailbox::mailbox(DEFAULT_MAILBOX_CAPACITY);
+    let (send_to_terminal, mut recv_printouts) = mpsc::channel::<t::Printout>(32);
+    let (caps_oracle, mut recv_caps) = mpsc::channel::<t::CapMessage>(32);
+
+    tokio::spawn(async move {
+        while let Some(printout) = recv_printouts.recv().await {
+            println!("{}", printout.content);
+        }
+    });
+    // the process holds every capability it asks about: the recording already shows
+    // what the real oracle allowed. asked for all of them, it gets what it got then
+    let oracle_reads = reads.clone();
+    tokio::spawn(async move {
+        while let Some(message) = recv_caps.recv().await {
+            match message {
+                t::CapMessage::Add { responder, .. }
+                | t::CapMessage::Drop { responder, .. }
+                | t::CapMessage::RevokeAll { responder, .. } => {
+                    if let Some(responder) = responder {
+                        responder.send(true).ok();
+                    }
+                }
+                t::CapMessage::Has { responder, .. } => {
+                    responder.send(true).ok();
+                }
+                t::CapMessage::GetAll { responder, .. } => {
+                    responder
+                        .send(oracle_reads.lock().unwrap().capabilities())
+                        .ok();
+                }
+                t::CapMessage::FilterCaps {
+                    caps, responder, ..
+                } => {
+                    responder
+                        .send(caps.into_iter().map(|cap| (cap, vec![])).collect())
+                        .ok();
+                }
+            }
+        }
+    });
+
+    let our = header.process.clone();
+    let process_state = process::ProcessState {
+        keypair,
+        metadata: t::ProcessMetadata {
+            our: header.process,
+            wasm_bytes_handle: header.wasm_bytes_handle,
+            wit_version: header.wit_version,
+            on_exit: header.on_exit,
+            public: header.public,
+            wasi: header.wasi.clone(),
+        },
+        recv_in_process,
+        self_sender: send_to_process.clone(),
+        send_to_loop,
+        send_to_terminal,
+        prompting_message: None,
+        last_message_blobbed: false,
+        last_blob: None,
+        contexts: HashMap::new(),
+        message_queue: VecDeque::new(),
+        caps_oracle,
+        received_caps: HashSet::new(),
+        recorder: None,
+    };
+    let (bindings, mut store, wasi_stderr) = process::make_component_v1(
+        engine,
+        &component_cache,
+        &wasm_bytes,
+        process_state,
+        WasiAccess::without_preopens(&header.wasi),
+        HostReads::Replayed(reads.clone()),
+    )
+    .await?;
+    let init = tokio::spawn(async move { bindings.call_init(&mut store, &our.to_string()).await });
+
+    let mut divergences = 0;
+    // recorded ID -> replayed ID, for requests the process sent
+    let mut ids: HashMap<u64, u64> = HashMap::new();
+    for (i, event) in events {
+        match event {
+            t::RecordedEvent::Received(mut message) => {
+                match &mut message {
+                    Ok(km) => km.id = *ids.get(&km.id).unwrap_or(&km.id),
+                    Err(e) => e.id = *ids.get(&e.id).unwrap_or(&e.id),
+                }
+                if send_to_process.send(message).await.is_err() {
+                    break;
+                }
+            }
+            t::RecordedEvent::Sent(expected) => {
+                match timeout(SEND_TIMEOUT, recv_sends.recv()).await {
+                    Ok(Some(actual)) => {
+                        if actual.id != expected.id {
+                            ids.insert(expected.id, actual.id);
+                        }
+                        if let Some(difference) = difference(&expected, &actual) {
+                            divergences += 1;
+                            println!("event {i}: sent {difference}\n  recorded: {expected}\n  replayed: {actual}");
+                        }
+                    }
+                    Ok(None) => {
+                        divergences += 1;
+                        println!("event {i}: process exited instead of sending {expected}");
+                        break;
+                    }
+                    Err(_) => {
+                        divergences += 1;
+                        println!("event {i}: process didn't send {expected}");
+                    }
+                }
+            }
+            // split off into `reads` above
+            _ => {}
+        }
+    }
+    while let Ok(Some(extra)) = timeout(TRAILING_SENDS_TIMEOUT, recv_sends.recv()).await {
+        divergences += 1;
+        println!("sent a message the recording lacks: {extra}");
+    }
+    let overruns = reads.lock().unwrap().overruns;
+    if overruns > 0 {
+        divergences += 1;
+        println!(
+            "read its capabilities, clocks, or randomness {overruns} more times than the recording has"
+        );
+    }
+
+    if init.is_finished() {
+        match init.await? {
+            Ok(()) => println!("process returned from init"),
+            Err(e) => {
+                let stderr = String::from_utf8_lossy(&wasi_stderr.contents()).to_string();
+                if stderr.is_empty() {
+                    println!("process ended with error: {}", e.root_cause());
+                } else {
+                    println!("process ended with error:\n{stderr}");
+                }
+            }
+        }
+    } else {
+        init.abort();
+    }
+    println!("replay done: {divergences} divergences");
+    Ok(divergences)
+}
+
+/// how a replayed send differs from the recorded one, ignoring its ID and the
+/// signatures on its capabilities, which the replay can't reproduce
+fn difference(expected: &t::KernelMessage, actual: &t::KernelMessage) -> Option<&'static str> {
+    if expected.source != actual.source {
+        Some("from a different source")
+    } else if expected.target != actual.target {
+        Some("to a different target")
+    } else if expected.rsvp != actual.rsvp {
+        Some("with a different rsvp")
+    } else if without_signatures(&expected.message) != without_signatures(&actual.message) {
+        Some("a different message")
+    } else if expected
+        .lazy_load_blob
+        .as_ref()
+        .map(|b| (&b.mime, &b.bytes))
+        != actual.lazy_load_blob.as_ref().map(|b| (&b.mime, &b.bytes))
+    {
+        Some("a different blob")
+    } else {
+        None
+    }
+}
+
+fn without_signatures(message: &t::Message) -> t::Message {
+    let mut message = message.clone();
+    let capabilities = match &mut message {
+        t::Message::Request(request) => &mut request.capabilities,
+        t::Message::Response((response, _)) => &mut response.capabilities,
+    };
+    for (_, signature) in capabilities.iter_mut() {
+        signature.clear();
+    }
+    message
+}
+
+/// What the process read from the host in the recording, besides messages, read back
+/// in the same order. Reads past the end of the recorded ones repeat the last clock
+/// reading, get no capabilities, or get zeroes for random bytes, and are counted.
+#[derive(Default)]
+pub struct ReplayedReads {
+    capabilities: VecDeque<Vec<(t::Capability, Vec<u8>)>>,
+    wall_clock: VecDeque<Duration>,
+    monotonic_clock: VecDeque<u64>,
+    insecure_random: VecDeque<u8>,
+    pub insecure_random_seed: u128,
+    last_wall_clock: Duration,
+    last_monotonic_clock: u64,
+    /// reads past the end of the recorded ones
+    pub overruns: usize,
+}
+
+impl ReplayedReads {
+    /// Separate the reads from a recording's events, leaving the messages, each with
+    /// its index among all the events.
+    fn split(events: Vec<t::RecordedEvent>) -> (Vec<(usize, t::RecordedEvent)>, Self) {
+        let mut reads = Self::default();
+        let mut messages = vec![];
+        for (i, event) in events.into_iter().enumerate() {
+            match event {
+                t::RecordedEvent::Received(_) | t::RecordedEvent::Sent(_) => {
+                    messages.push((i, event))
+                }
+                t::RecordedEvent::Capabilities(caps) => reads.capabilities.push_back(caps),
+                t::RecordedEvent::WallClock(now) => reads.wall_clock.push_back(now),
+                t::RecordedEvent::MonotonicClock(now) => reads.monotonic_clock.push_back(now),
+                t::RecordedEvent::InsecureRandom(bytes) => reads.insecure_random.extend(bytes),
+                t::RecordedEvent::InsecureRandomSeed(seed) => reads.insecure_random_seed = seed,
+            }
+        }
+        (messages, reads)
+    }
+
+    fn capabilities(&mut self) -> Vec<(t::Capability, Vec<u8>)> {
+        self.capabilities.pop_front().unwrap_or_else(|| {
+            self.overruns += 1;
+            vec![]
+        })
+    }
+
+    pub fn wall_clock(&mut self) -> Duration {
+        match self.wall_clock.pop_front() {
+            Some(now) => self.last_wall_clock = now,
+            None => self.overruns += 1,
+        }
+        self.last_wall_clock
+    }
+
+    pub fn monotonic_clock(&mut self) -> u64 {
+        match self.monotonic_clock.pop_front() {
+            Some(now) => self.last_monotonic_clock = now,
+            None => self.overruns += 1,
+        }
+        self.last_monotonic_clock
+    }
+
+    pub fn random(&mut self, dest: &mut [u8]) {
+        if self.insecure_random.len() < dest.len() {
+            self.overruns += 1;
+        }
+        for byte in dest.iter_mut() {
+            *byte = self.insecure_random.pop_front().unwrap_or(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_are_split_from_messages_and_read_back_in_order() {
+        let sent = t::KernelMessage::builder()
+            .id(1)
+            .source(("our", t::ProcessId::new(Some("a"), "test", "sys")))
+            .target(("our", t::ProcessId::new(Some("b"), "test", "sys")))
+            .message(t::Message::Request(t::Request {
+                inherit: false,
+                expects_response: None,
+                body: vec![],
+                metadata: None,
+                capabilities: vec![],
+            }))
+            .build()
+            .unwrap();
+        let (messages, mut reads) = ReplayedReads::split(vec![
+            t::RecordedEvent::InsecureRandomSeed(7),
+            t::RecordedEvent::WallClock(Duration::from_secs(10)),
+            t::RecordedEvent::Sent(sent),
+            t::RecordedEvent::InsecureRandom(vec![1, 2, 3]),
+            t::RecordedEvent::WallClock(Duration::from_secs(11)),
+            t::RecordedEvent::InsecureRandom(vec![4]),
+            t::RecordedEvent::MonotonicClock(5),
+        ]);
+        assert_eq!(
+            messages.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+            vec![2]
+        );
+        assert_eq!(reads.insecure_random_seed, 7);
+        assert_eq!(reads.wall_clock(), Duration::from_secs(10));
+        assert_eq!(reads.wall_clock(), Duration::from_secs(11));
+        assert_eq!(reads.monotonic_clock(), 5);
+        // random bytes come back however the process asks for them
+        let mut bytes = [0; 2];
+        reads.random(&mut bytes);
+        assert_eq!(bytes, [1, 2]);
+        reads.random(&mut bytes);
+        assert_eq!(bytes, [3, 4]);
+        assert_eq!(reads.overruns, 0);
+    }
+
+    #[test]
+    fn reads_past_the_recording_are_counted() {
+        let (_, mut reads) = ReplayedReads::split(vec![
+            t::RecordedEvent::WallClock(Duration::from_secs(10)),
+            t::RecordedEvent::InsecureRandom(vec![9]),
+        ]);
+        assert_eq!(reads.wall_clock(), Duration::from_secs(10));
+        assert_eq!(reads.wall_clock(), Duration::from_secs(10));
+        let mut bytes = [0; 2];
+        reads.random(&mut bytes);
+        assert_eq!(bytes, [9, 0]);
+        assert!(reads.capabilities().is_empty());
+        assert_eq!(reads.overruns, 3);
+    }
+}
@@ -74,24 +74,32 @@ impl process::ProcessState {
                 .await
                 .expect("fatal: process couldn't receive next message");
 
-            match &message {
+            let ingested = match &message {
                 Ok(km) => match &km.message {
                     t::Message::Response(_) => {
                         if let Some((_context, timeout_handle)) = self.contexts.get_mut(&km.id) {
                             timeout_handle.abort();
-                            return message;
+                            true
+                        } else {
+                            false
                         }
                     }
-                    _ => {
-                        return message;
-                    }
+                    _ => true,
                 },
                 Err(e) => {
                     if let Some((_context, timeout_handle)) = self.contexts.get_mut(&e.id) {
                         timeout_handle.abort();
-                        return message;
+                        true
+                    } else {
+                        false
                     }
                 }
+            };
+            if ingested {
+                if let Some(recorder) = &self.recorder {
+                    recorder.record(&t::RecordedEvent::Received(message.clone()));
+                }
+                return message;
             }
         }
     }
@@ -200,6 +208,18 @@ impl process::ProcessState {
             }
         };
 
+        let caps = match &km.message {
+            t::Message::Request(request) => &request.capabilities,
+            t::Message::Response((response, _)) => &response.capabilities,
+        };
+        if !caps.is_empty() {
+            let now = crate::kernel::unix_time_secs();
+            self.received_caps.retain(|cap| !cap.is_expired(now));
+            for (cap, _) in caps {
+                self.received_caps.replace(cap.clone());
+            }
+        }
+
         Ok((
             km.source.en_wit(),
             match km.message {
@@ -339,8 +359,13 @@ impl process::ProcessState {
             },
             message: t::Message::Request(request),
             lazy_load_blob: blob,
+            // requests sent while handling a traced message join its trace
+            trace: self.prompting_message.as_ref().and_then(|m| m.trace),
         };
 
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&t::RecordedEvent::Sent(kernel_message.clone()));
+        }
         self.send_to_loop
             .send(kernel_message)
             .await
@@ -400,19 +425,24 @@ impl process::ProcessState {
             };
         }
 
+        let kernel_message = t::KernelMessage {
+            id,
+            source: self.metadata.our.clone(),
+            target,
+            rsvp: None,
+            message: t::Message::Response((
+                response,
+                // the context will be set by the process receiving this Response.
+                None,
+            )),
+            lazy_load_blob: blob,
+            trace: prompting_message.trace,
+        };
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&t::RecordedEvent::Sent(kernel_message.clone()));
+        }
         self.send_to_loop
-            .send(t::KernelMessage {
-                id,
-                source: self.metadata.our.clone(),
-                target,
-                rsvp: None,
-                message: t::Message::Response((
-                    response,
-                    // the context will be set by the process receiving this Response.
-                    None,
-                )),
-                lazy_load_blob: blob,
-            })
+            .send(kernel_message)
             .await
             .expect("fatal: kernel couldn't send response");
     }
@@ -770,6 +800,8 @@ impl StandardHost for process::ProcessWasiV1 {
                         .map(|(cap, _sig)| cap)
                         .collect(),
                     public,
+                    // a child gets no more of WASI than its parent
+                    wasi: self.process.metadata.wasi.clone(),
                 })
                 .unwrap(),
                 metadata: None,
@@ -797,6 +829,8 @@ impl StandardHost for process::ProcessWasiV1 {
                         (self.process.metadata.our.node.clone(), &new_process_id),
                         params,
                     )],
+                    actor: self.process.metadata.our.process.clone(),
+                    reason: "spawn: granted by parent",
                     responder: Some(tx),
                 })
                 .await
@@ -845,6 +879,8 @@ impl StandardHost for process::ProcessWasiV1 {
             .send(t::CapMessage::Add {
                 on: new_process_id.clone(),
                 caps: vec![t::Capability::messaging(self.process.metadata.our.clone())],
+                actor: self.process.metadata.our.process.clone(),
+                reason: "spawn: child may message parent",
                 responder: Some(tx),
             })
             .await
@@ -861,6 +897,8 @@ impl StandardHost for process::ProcessWasiV1 {
                     self.process.metadata.our.node.clone(),
                     &new_process_id,
                 ))],
+                actor: self.process.metadata.our.process.clone(),
+                reason: "spawn: parent may message child",
                 responder: Some(tx),
             })
             .await
@@ -881,10 +919,24 @@ impl StandardHost for process::ProcessWasiV1 {
             .caps_oracle
             .send(t::CapMessage::Add {
                 on: self.process.metadata.our.process.clone(),
+                // the kernel can't see the signatures here, so keep the constraints
+                // of caps we were sent: another issuer's cap can't be saved looser
                 caps: caps
                     .iter()
-                    .map(|cap| t::de_wit_capability(cap.clone()).0)
+                    .map(|cap| {
+                        let cap = t::de_wit_capability(cap.clone()).0;
+                        match self.process.received_caps.get(&cap) {
+                            Some(received)
+                                if cap.issuer.process != self.process.metadata.our.process =>
+                            {
+                                cap.no_looser_than(received)
+                            }
+                            _ => cap,
+                        }
+                    })
                     .collect(),
+                actor: self.process.metadata.our.process.clone(),
+                reason: "save_capabilities",
                 responder: Some(tx),
             })
             .await?;
@@ -903,6 +955,8 @@ impl StandardHost for process::ProcessWasiV1 {
                     .iter()
                     .map(|cap| t::de_wit_capability(cap.clone()).0)
                     .collect(),
+                actor: self.process.metadata.our.process.clone(),
+                reason: "drop_capabilities",
                 responder: Some(tx),
             })
             .await?;
@@ -921,6 +975,9 @@ impl StandardHost for process::ProcessWasiV1 {
             })
             .await?;
         let caps = rx.await?;
+        if let Some(recorder) = &self.process.recorder {
+            recorder.record(&t::RecordedEvent::Capabilities(caps.clone()));
+        }
         Ok(caps
             .into_iter()
             .map(|cap| t::en_wit_capability(cap))
@@ -0,0 +1,351 @@
+//! Supervision groups, kept in `.supervision_groups` in the home directory so they
+//! survive a reboot. The kernel hears of each member's exit before the member
+//! carries out its own on-exit behavior, and the group decides what happens to the
+//! other members.
+
+use super::persisted;
+use lib::types::core::{self as t};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+};
+use tokio::time::{Duration, Instant};
+
+const SUPERVISION_GROUPS_FILE: &str = ".supervision_groups";
+
+pub struct Supervisor {
+    path: PathBuf,
+    groups: HashMap<String, t::SupervisionGroup>,
+    /// restarts of each group within its window, oldest first
+    restarts: HashMap<String, VecDeque<Instant>>,
+    /// groups that restarted too often: their members are not started again
+    stopped: HashSet<String>,
+}
+
+/// What a member's exit means for its group.
+pub struct Exit {
+    pub group: String,
+    /// other members to restart or kill, in member order
+    pub siblings: Vec<t::ProcessId>,
+    /// the group restarted too often: every member should be stopped instead
+    pub gave_up: bool,
+}
+
+impl Supervisor {
+    pub async fn load(home_directory_path: &Path, send_to_terminal: &t::PrintSender) -> Self {
+        let path = home_directory_path.join(SUPERVISION_GROUPS_FILE);
+        let groups = persisted::load_or_report(&path, send_to_terminal).await;
+        Self {
+            path,
+            groups,
+            restarts: HashMap::new(),
+            stopped: HashSet::new(),
+        }
+    }
+
+    /// Create or replace a group. Returns the members of the group's previous
+    /// version if that version had given up, so they can be started again.
+    pub async fn set(&mut self, group: t::SupervisionGroup) -> Result<Vec<t::ProcessId>, String> {
+        self.check(&group, |_| false)?;
+        self.restarts.remove(&group.name);
+        let stopped = if self.stopped.remove(&group.name) {
+            self.groups
+                .get(&group.name)
+                .map(|previous| previous.members.clone())
+                .unwrap_or_default()
+        } else {
+            vec![]
+        };
+        self.groups.insert(group.name.clone(), group);
+        self.persist().await.map_err(|e| e.to_string())?;
+        Ok(stopped)
+    }
+
+    /// Replace a package's groups, those named `<group>:<package-id>`, with `groups`.
+    /// Nothing changes if any of them can't be set. Members of a replaced group that
+    /// gave up are not started here: the package's installer starts its processes.
+    pub async fn set_package(
+        &mut self,
+        package_id: &t::PackageId,
+        groups: Vec<t::SupervisionGroup>,
+    ) -> Result<Vec<String>, String> {
+        let suffix = format!(":{package_id}");
+        for group in &groups {
+            self.check(group, |other| other.ends_with(&suffix))?;
+        }
+        let previous: Vec<String> = self
+            .groups
+            .keys()
+            .filter(|name| name.ends_with(&suffix))
+            .cloned()
+            .collect();
+        for name in previous {
+            self.restarts.remove(&name);
+            self.stopped.remove(&name);
+            self.groups.remove(&name);
+        }
+        let names = groups.iter().map(|group| group.name.clone()).collect();
+        for group in groups {
+            self.groups.insert(group.name.clone(), group);
+        }
+        self.persist().await.map_err(|e| e.to_string())?;
+        Ok(names)
+    }
+
+    /// Whether `group` can be set, given the groups it doesn't replace.
+    fn check(
+        &self,
+        group: &t::SupervisionGroup,
+        replaced: impl Fn(&str) -> bool,
+    ) -> Result<(), String> {
+        if group.name.is_empty() {
+            return Err("group must have a name".to_string());
+        }
+        if group.members.is_empty() {
+            return Err("group must have members".to_string());
+        }
+        if let Some(other) = self.groups.values().find(|other| {
+            other.name != group.name
+                && !replaced(&other.name)
+                && other
+                    .members
+                    .iter()
+                    .any(|member| group.members.contains(member))
+        }) {
+            return Err(format!("a member is already in group {}", other.name));
+        }
+        Ok(())
+    }
+
+    pub async fn remove(&mut self, name: &str) -> Result<bool, String> {
+        self.restarts.remove(name);
+        self.stopped.remove(name);
+        if self.groups.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.persist().await.map_err(|e| e.to_string())?;
+        Ok(true)
+    }
+
+    /// Whether a process is in a group that gave up, and so must not be restarted.
+    pub fn is_stopped(&self, process: &t::ProcessId) -> bool {
+        self.group_of(process)
+            .is_some_and(|group| self.stopped.contains(&group.name))
+    }
+
+    /// Count a member's exit against its group's restart limit, and return what the
+    /// exit means for the other members. `None` if the process is in no group, or
+    /// in one that has given up already.
+    pub fn exited(&mut self, process: &t::ProcessId, restarts_itself: bool) -> Option<Exit> {
+        let group = self.group_of(process)?;
+        if self.stopped.contains(&group.name) {
+            return None;
+        }
+        let siblings: Vec<t::ProcessId> = match group.strategy {
+            t::SupervisionStrategy::OneForOne => vec![],
+            t::SupervisionStrategy::OneForAll => group
+                .members
+                .iter()
+                .filter(|member| *member != process)
+                .cloned()
+                .collect(),
+            t::SupervisionStrategy::RestForOne => group
+                .members
+                .iter()
+                .skip_while(|member| *member != process)
+                .skip(1)
+                .cloned()
+                .collect(),
+        };
+        let name = group.name.clone();
+        let max_restarts = group.max_restarts as usize;
+        let window = Duration::from_secs(group.within_secs);
+
+        let mut gave_up = false;
+        if restarts_itself || !siblings.is_empty() {
+            let now = Instant::now();
+            let restarts = self.restarts.entry(name.clone()).or_default();
+            restarts.push_back(now);
+            while restarts
+                .front()
+                .is_some_and(|restart| now.duration_since(*restart) > window)
+            {
+                restarts.pop_front();
+            }
+            if restarts.len() > max_restarts {
+                self.restarts.remove(&name);
+                self.stopped.insert(name.clone());
+                gave_up = true;
+            }
+        }
+        Some(Exit {
+            group: name,
+            siblings,
+            gave_up,
+        })
+    }
+
+    pub fn members(&self, name: &str) -> Vec<t::ProcessId> {
+        self.groups
+            .get(name)
+            .map(|group| group.members.clone())
+            .unwrap_or_default()
+    }
+
+    fn group_of(&self, process: &t::ProcessId) -> Option<&t::SupervisionGroup> {
+        self.groups
+            .values()
+            .find(|group| group.members.contains(process))
+    }
+
+    async fn persist(&self) -> std::io::Result<()> {
+        persisted::save(&self.path, &self.groups).await
+    }
+}
+
+/// Add the groups declared in the manifests of bootstrapped packages to those
+/// persisted, replacing any of the same name.
+pub async fn save_bootstrapped_groups(
+    home_directory_path: &Path,
+    groups: Vec<t::SupervisionGroup>,
+) -> std::io::Result<()> {
+    if groups.is_empty() {
+        return Ok(());
+    }
+    let path = home_directory_path.join(SUPERVISION_GROUPS_FILE);
+    // the terminal isn't up yet: a corrupt file is reported when the kernel loads it
+    let mut saved: HashMap<String, t::SupervisionGroup> = match persisted::load(&path).await {
+        Ok(saved) => saved,
+        Err(e) => {
+            println!("bootstrap: {e}\r");
+            HashMap::new()
+        }
+    };
+    for group in groups {
+        saved.insert(group.name.clone(), group);
+    }
+    persisted::save(&path, &saved).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(name: &str) -> t::ProcessId {
+        t::ProcessId::new(Some(name), "pkg", "sys")
+    }
+
+    fn group(
+        name: &str,
+        strategy: t::SupervisionStrategy,
+        max_restarts: u32,
+    ) -> t::SupervisionGroup {
+        t::SupervisionGroup {
+            name: format!("{name}:pkg:sys"),
+            strategy,
+            members: vec![member("a"), member("b"), member("c")],
+            max_restarts,
+            within_secs: 60,
+        }
+    }
+
+    /// A supervisor with the given groups, saving them in a directory that is
+    /// removed once the returned guard drops.
+    async fn supervisor(groups: Vec<t::SupervisionGroup>) -> (tempfile::TempDir, Supervisor) {
+        let home = tempfile::tempdir().unwrap();
+        let (send_to_terminal, _) = tokio::sync::mpsc::channel(1);
+        let mut supervisor = Supervisor::load(home.path(), &send_to_terminal).await;
+        for group in groups {
+            supervisor.set(group).await.unwrap();
+        }
+        (home, supervisor)
+    }
+
+    #[tokio::test]
+    async fn strategies_pick_the_siblings() {
+        let (_home, mut supervisor) =
+            supervisor(vec![group("g", t::SupervisionStrategy::OneForOne, 5)]).await;
+        assert!(supervisor
+            .exited(&member("b"), true)
+            .unwrap()
+            .siblings
+            .is_empty());
+
+        supervisor
+            .set(group("g", t::SupervisionStrategy::OneForAll, 5))
+            .await
+            .unwrap();
+        let exit = supervisor.exited(&member("b"), true).unwrap();
+        assert_eq!(exit.siblings, [member("a"), member("c")]);
+
+        supervisor
+            .set(group("g", t::SupervisionStrategy::RestForOne, 5))
+            .await
+            .unwrap();
+        let exit = supervisor.exited(&member("b"), true).unwrap();
+        assert_eq!(exit.siblings, [member("c")]);
+
+        assert!(supervisor.exited(&member("d"), true).is_none());
+    }
+
+    #[tokio::test]
+    async fn a_group_gives_up_after_too_many_restarts() {
+        let (_home, mut supervisor) =
+            supervisor(vec![group("g", t::SupervisionStrategy::OneForOne, 2)]).await;
+        // an exit that restarts nothing doesn't count
+        for _ in 0..5 {
+            assert!(!supervisor.exited(&member("a"), false).unwrap().gave_up);
+        }
+        assert!(!supervisor.exited(&member("a"), true).unwrap().gave_up);
+        assert!(!supervisor.exited(&member("b"), true).unwrap().gave_up);
+        assert!(supervisor.exited(&member("c"), true).unwrap().gave_up);
+        assert!(supervisor.is_stopped(&member("a")));
+        assert!(supervisor.exited(&member("a"), true).is_none());
+
+        // setting the group again starts it over
+        let stopped = supervisor
+            .set(group("g", t::SupervisionStrategy::OneForOne, 2))
+            .await
+            .unwrap();
+        assert_eq!(stopped.len(), 3);
+        assert!(!supervisor.is_stopped(&member("a")));
+    }
+
+    #[tokio::test]
+    async fn a_process_is_in_at_most_one_group() {
+        let (_home, mut supervisor) =
+            supervisor(vec![group("g", t::SupervisionStrategy::OneForOne, 5)]).await;
+        assert!(supervisor
+            .set(group("h", t::SupervisionStrategy::OneForOne, 5))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn a_package_replaces_its_own_groups() {
+        let (_home, mut supervisor) =
+            supervisor(vec![group("g", t::SupervisionStrategy::OneForOne, 5)]).await;
+        let package_id = t::PackageId::new("pkg", "sys");
+        let names = supervisor
+            .set_package(
+                &package_id,
+                vec![group("h", t::SupervisionStrategy::OneForAll, 5)],
+            )
+            .await
+            .unwrap();
+        assert_eq!(names, ["h:pkg:sys"]);
+        assert!(supervisor.members("g:pkg:sys").is_empty());
+        assert_eq!(supervisor.members("h:pkg:sys").len(), 3);
+
+        // reloading sees what was persisted
+        let (send_to_terminal, _) = tokio::sync::mpsc::channel(1);
+        let reloaded = Supervisor::load(supervisor.path.parent().unwrap(), &send_to_terminal).await;
+        assert_eq!(reloaded.members("h:pkg:sys").len(), 3);
+
+        supervisor.set_package(&package_id, vec![]).await.unwrap();
+        assert!(supervisor.members("h:pkg:sys").is_empty());
+        tokio::fs::remove_dir_all(supervisor.path.parent().unwrap())
+            .await
+            .unwrap();
+    }
+}
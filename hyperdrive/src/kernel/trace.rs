@@ -0,0 +1,371 @@
+//! Message tracing. A trace starts at a request sent by a root process and follows
+//! every message sent in handling of it, locally and, for nodes that are also
+//! recording, over the network. Each request is a span: it opens when the kernel
+//! takes it in, and closes when its response comes back, or on delivery if no
+//! response is expected.
+
+use lib::types::core::{self as t};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// oldest spans are dropped past this many
+const MAX_SPANS: usize = 10_000;
+/// oldest requests stop being matched to their responses past this many
+const MAX_AWAITING_RESPONSE: usize = 10_000;
+
+struct Span {
+    trace_id: u128,
+    span_id: u64,
+    parent_span_id: Option<u64>,
+    message_id: u64,
+    source: t::Address,
+    target: t::Address,
+    enqueued: u64,
+    delivered: Option<u64>,
+    responded: Option<u64>,
+    expects_response: bool,
+}
+
+#[derive(Default)]
+pub struct Tracer {
+    recording: bool,
+    /// local processes whose untraced requests start a new trace
+    roots: HashSet<t::ProcessId>,
+    spans: HashMap<u64, Span>,
+    span_order: VecDeque<u64>,
+    /// span of each request awaiting a response, by request ID and by where the
+    /// response will be sent
+    awaiting_response: HashMap<(u64, t::Address), u64>,
+    awaiting_order: VecDeque<(u64, t::Address)>,
+}
+
+impl Tracer {
+    pub fn start(&mut self, root: Option<t::ProcessId>) {
+        self.recording = true;
+        if let Some(root) = root {
+            self.roots.insert(root);
+        }
+    }
+
+    pub fn stop(&mut self, root: Option<t::ProcessId>) {
+        match root {
+            Some(root) => {
+                self.roots.remove(&root);
+            }
+            None => {
+                self.recording = false;
+                self.roots.clear();
+            }
+        }
+    }
+
+    pub fn status(&self) -> t::KernelResponse {
+        t::KernelResponse::Tracing {
+            recording: self.recording,
+            roots: self.roots.iter().cloned().collect(),
+        }
+    }
+
+    /// Record a message as the kernel takes it in, giving traced requests a span of
+    /// their own. Returns the span to mark delivered once the message is handed off.
+    pub fn enqueue(&mut self, our: &str, km: &mut t::KernelMessage) -> Option<u64> {
+        if !self.recording {
+            return None;
+        }
+        match &km.message {
+            t::Message::Response(_) => {
+                // responses carry no span of their own: they close their request's
+                let key = (km.id, km.target.clone());
+                let span_id = self.awaiting_response.remove(&key)?;
+                let span = self.spans.get_mut(&span_id)?;
+                span.responded = Some(now());
+                km.trace.get_or_insert(t::TraceContext {
+                    trace_id: span.trace_id,
+                    span_id,
+                });
+                None
+            }
+            t::Message::Request(request) => {
+                let (trace_id, parent_span_id) = match km.trace {
+                    Some(trace) => (trace.trace_id, Some(trace.span_id)),
+                    None if km.source.node == our && self.roots.contains(&km.source.process) => {
+                        (rand::random(), None)
+                    }
+                    None => return None,
+                };
+                let span_id = rand::random();
+                km.trace = Some(t::TraceContext { trace_id, span_id });
+                if request.expects_response.is_some() {
+                    let key = (km.id, km.rsvp.clone().unwrap_or(km.source.clone()));
+                    self.awaiting_response.insert(key.clone(), span_id);
+                    self.awaiting_order.push_back(key);
+                    if self.awaiting_order.len() > MAX_AWAITING_RESPONSE {
+                        let oldest = self.awaiting_order.pop_front().unwrap();
+                        self.awaiting_response.remove(&oldest);
+                    }
+                }
+                self.spans.insert(
+                    span_id,
+                    Span {
+                        trace_id,
+                        span_id,
+                        parent_span_id,
+                        message_id: km.id,
+                        source: km.source.clone(),
+                        target: km.target.clone(),
+                        enqueued: now(),
+                        delivered: None,
+                        responded: None,
+                        expects_response: request.expects_response.is_some(),
+                    },
+                );
+                self.span_order.push_back(span_id);
+                if self.span_order.len() > MAX_SPANS {
+                    let oldest = self.span_order.pop_front().unwrap();
+                    self.spans.remove(&oldest);
+                }
+                Some(span_id)
+            }
+        }
+    }
+
+    pub fn deliver(&mut self, span_id: Option<u64>) {
+        if let Some(span) = span_id.and_then(|id| self.spans.get_mut(&id)) {
+            span.delivered = Some(now());
+        }
+    }
+
+    /// Write the spans recorded so far to `<home>/.traces/traces-<unix time>.json`
+    /// in OTLP JSON, and forget them. Returns the path written and the span count.
+    pub async fn export(&mut self, our: &str, home: &Path) -> anyhow::Result<(String, usize)> {
+        let dir = home.join(".traces");
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = dir.join(format!("traces-{}.json", now() / 1_000_000_000));
+
+        let spans: Vec<serde_json::Value> = self
+            .span_order
+            .iter()
+            .filter_map(|id| self.spans.get(id))
+            .map(Span::to_otlp)
+            .collect();
+        let count = spans.len();
+        let otlp = serde_json::json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [string_attribute("service.name", our)],
+                },
+                "scopeSpans": [{
+                    "scope": { "name": "hyperdrive.kernel" },
+                    "spans": spans,
+                }],
+            }],
+        });
+        tokio::fs::write(&path, serde_json::to_vec_pretty(&otlp)?).await?;
+
+        self.spans.clear();
+        self.span_order.clear();
+        Ok((path.display().to_string(), count))
+    }
+}
+
+impl Span {
+    fn to_otlp(&self) -> serde_json::Value {
+        let mut events = vec![event("enqueue", self.enqueued)];
+        if let Some(delivered) = self.delivered {
+            events.push(event("deliver", delivered));
+        }
+        if let Some(responded) = self.responded {
+            events.push(event("response", responded));
+        }
+        let end = match (self.expects_response, self.delivered, self.responded) {
+            (true, _, Some(responded)) => responded,
+            (false, Some(delivered), _) => delivered,
+            _ => self.enqueued,
+        };
+        // OTLP status codes: 1 is ok, 2 is error
+        let status = match self.delivered {
+            Some(_) => serde_json::json!({ "code": 1 }),
+            None => serde_json::json!({ "code": 2, "message": "never delivered" }),
+        };
+        serde_json::json!({
+            "traceId": format!("{:032x}", self.trace_id),
+            "spanId": format!("{:016x}", self.span_id),
+            "parentSpanId": self
+                .parent_span_id
+                .map(|id| format!("{id:016x}"))
+                .unwrap_or_default(),
+            "name": format!("{} -> {}", self.source.process, self.target.process),
+            // SPAN_KIND_INTERNAL
+            "kind": 1,
+            "startTimeUnixNano": self.enqueued.to_string(),
+            "endTimeUnixNano": end.to_string(),
+            "attributes": [
+                string_attribute("message.source", &self.source.to_string()),
+                string_attribute("message.target", &self.target.to_string()),
+                string_attribute("message.id", &self.message_id.to_string()),
+            ],
+            "events": events,
+            "status": status,
+        })
+    }
+}
+
+fn event(name: &str, time: u64) -> serde_json::Value {
+    serde_json::json!({ "name": name, "timeUnixNano": time.to_string() })
+}
+
+fn string_attribute(key: &str, value: &str) -> serde_json::Value {
+    serde_json::json!({ "key": key, "value": { "stringValue": value } })
+}
+
+/// nanoseconds since the unix epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(name: &str) -> t::Address {
+        t::Address::new("our", t::ProcessId::new(Some(name), "test", "sys"))
+    }
+
+    fn request(id: u64, from: &str, to: &str, expects_response: bool) -> t::KernelMessage {
+        t::KernelMessage::builder()
+            .id(id)
+            .source(address(from))
+            .target(address(to))
+            .message(t::Message::Request(t::Request {
+                inherit: false,
+                expects_response: expects_response.then_some(5),
+                body: vec![],
+                metadata: None,
+                capabilities: vec![],
+            }))
+            .build()
+            .unwrap()
+    }
+
+    fn response(id: u64, from: &str, to: &str) -> t::KernelMessage {
+        t::KernelMessage::builder()
+            .id(id)
+            .source(address(from))
+            .target(address(to))
+            .message(t::Message::Response((
+                t::Response {
+                    inherit: false,
+                    body: vec![],
+                    metadata: None,
+                    capabilities: vec![],
+                },
+                None,
+            )))
+            .build()
+            .unwrap()
+    }
+
+    fn recording_from(root: &str) -> Tracer {
+        let mut tracer = Tracer::default();
+        tracer.start(Some(t::ProcessId::new(Some(root), "test", "sys")));
+        tracer
+    }
+
+    #[test]
+    fn untraced_requests_from_other_processes_are_not_recorded() {
+        let mut tracer = recording_from("root");
+        let mut km = request(1, "other", "target", false);
+        assert_eq!(tracer.enqueue("our", &mut km), None);
+        assert_eq!(km.trace, None);
+        assert!(tracer.spans.is_empty());
+    }
+
+    #[test]
+    fn nothing_is_recorded_when_stopped() {
+        let mut tracer = recording_from("root");
+        tracer.stop(None);
+        let mut km = request(1, "root", "target", false);
+        assert_eq!(tracer.enqueue("our", &mut km), None);
+        assert_eq!(km.trace, None);
+    }
+
+    #[test]
+    fn requests_sent_in_handling_of_a_traced_request_join_its_trace() {
+        let mut tracer = recording_from("root");
+        let mut first = request(1, "root", "middle", false);
+        let first_span = tracer.enqueue("our", &mut first).unwrap();
+        let trace = first.trace.unwrap();
+        assert_eq!(trace.span_id, first_span);
+
+        let mut second = request(2, "middle", "leaf", false);
+        second.trace = first.trace;
+        let second_span = tracer.enqueue("our", &mut second).unwrap();
+        assert_eq!(second.trace.unwrap().trace_id, trace.trace_id);
+        assert_eq!(tracer.spans[&second_span].parent_span_id, Some(first_span));
+        assert_eq!(tracer.spans[&first_span].parent_span_id, None);
+    }
+
+    #[test]
+    fn response_closes_its_request_span() {
+        let mut tracer = recording_from("root");
+        let mut km = request(7, "root", "target", true);
+        let span_id = tracer.enqueue("our", &mut km);
+        tracer.deliver(span_id);
+        let span_id = span_id.unwrap();
+        assert!(tracer.spans[&span_id].delivered.is_some());
+        assert!(tracer.spans[&span_id].responded.is_none());
+
+        // a response to someone else doesn't match
+        let mut stray = response(7, "target", "other");
+        assert_eq!(tracer.enqueue("our", &mut stray), None);
+        assert!(tracer.spans[&span_id].responded.is_none());
+
+        let mut reply = response(7, "target", "root");
+        assert_eq!(tracer.enqueue("our", &mut reply), None);
+        assert!(tracer.spans[&span_id].responded.is_some());
+        assert_eq!(reply.trace.unwrap().span_id, span_id);
+        assert!(tracer.awaiting_response.is_empty());
+    }
+
+    #[test]
+    fn oldest_spans_are_dropped() {
+        let mut tracer = recording_from("root");
+        let first = tracer
+            .enqueue("our", &mut request(0, "root", "target", false))
+            .unwrap();
+        for id in 1..=MAX_SPANS as u64 {
+            tracer.enqueue("our", &mut request(id, "root", "target", false));
+        }
+        assert_eq!(tracer.spans.len(), MAX_SPANS);
+        assert!(!tracer.spans.contains_key(&first));
+    }
+
+    #[tokio::test]
+    async fn export_writes_otlp_and_forgets_spans() {
+        let home = tempfile::tempdir().unwrap();
+        let mut tracer = recording_from("root");
+        let mut km = request(1, "root", "target", false);
+        let span_id = tracer.enqueue("our", &mut km);
+        tracer.deliver(span_id);
+
+        let (path, count) = tracer.export("our", home.path()).await.unwrap();
+        assert_eq!(count, 1);
+        assert!(tracer.spans.is_empty());
+
+        let otlp: serde_json::Value =
+            serde_json::from_slice(&tokio::fs::read(&path).await.unwrap()).unwrap();
+        let span = &otlp["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(span["spanId"], format!("{:016x}", span_id.unwrap()));
+        assert_eq!(
+            span["traceId"],
+            format!("{:032x}", km.trace.unwrap().trace_id)
+        );
+        assert_eq!(span["status"]["code"], 1);
+    }
+}
@@ -0,0 +1,225 @@
+//! The WASI a process gets: what its [`t::WasiPolicy`] allows, narrowed to what its
+//! capabilities allow when it starts. A process that loses a vfs capability is
+//! restarted, so that its preopened drives never outlast the capability.
+
+use lib::types::core::{self as t, VFS_PROCESS_ID};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use wasmtime::component::Linker;
+use wasmtime_wasi::{
+    p2::{WasiCtxBuilder, WasiView},
+    DirPerms, FilePerms, HostWallClock,
+};
+use wasmtime_wasi_http::WasiHttpView;
+
+/// the WASI interface that hands out cryptographic randomness
+const WASI_RANDOM_INTERFACE: &str = "wasi:random/random@0.2.3";
+
+/// WASI granted to one run of a process. The default matches
+/// [`t::WasiPolicy::default()`].
+pub struct WasiAccess {
+    preopens: Vec<Preopen>,
+    wall_clock: bool,
+    random: bool,
+    sockets: bool,
+    http_hosts: Vec<String>,
+}
+
+/// A vfs drive preopened at its vfs path.
+struct Preopen {
+    host_path: PathBuf,
+    guest_path: String,
+    writable: bool,
+}
+
+impl Default for WasiAccess {
+    fn default() -> Self {
+        Self::without_preopens(&t::WasiPolicy::default())
+    }
+}
+
+impl WasiAccess {
+    /// What the process's policy allows, given the capabilities it holds now.
+    pub async fn new(
+        metadata: &t::ProcessMetadata,
+        vfs_path: &Path,
+        caps_oracle: &t::CapMessageSender,
+    ) -> Self {
+        let policy = &metadata.wasi;
+        let mut access = Self::without_preopens(policy);
+        if policy.preopen_drives {
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            caps_oracle
+                .send(t::CapMessage::GetAll {
+                    on: metadata.our.process.clone(),
+                    responder: tx,
+                })
+                .await
+                .expect("fatal: process couldn't access capabilities oracle");
+            let caps = rx.await.unwrap_or_default();
+            access.preopens = drive_preopens(&metadata.our, vfs_path, &caps);
+        }
+        access
+    }
+
+    /// What the policy allows, with no vfs drives preopened.
+    pub fn without_preopens(policy: &t::WasiPolicy) -> Self {
+        Self {
+            preopens: vec![],
+            wall_clock: policy.wall_clock,
+            random: policy.random,
+            sockets: policy.sockets,
+            http_hosts: policy
+                .http_hosts
+                .iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    pub fn apply(&self, builder: &mut WasiCtxBuilder) -> anyhow::Result<()> {
+        for preopen in &self.preopens {
+            let (dir_perms, file_perms) = if preopen.writable {
+                (DirPerms::all(), FilePerms::all())
+            } else {
+                (DirPerms::READ, FilePerms::READ)
+            };
+            builder.preopened_dir(
+                &preopen.host_path,
+                &preopen.guest_path,
+                dir_perms,
+                file_perms,
+            )?;
+        }
+        if !self.wall_clock {
+            builder.wall_clock(EpochClock);
+        }
+        if self.sockets {
+            builder.inherit_network();
+            builder.allow_ip_name_lookup(true);
+        } else {
+            builder.allow_tcp(false);
+            builder.allow_udp(false);
+            builder.allow_ip_name_lookup(false);
+        }
+        Ok(())
+    }
+
+    /// Link the WASI interfaces this access allows. If randomness is denied,
+    /// asking for cryptographic random bytes traps: predictable bytes would be
+    /// worse than none.
+    pub fn link<T: WasiView + WasiHttpView>(&self, linker: &mut Linker<T>) -> anyhow::Result<()> {
+        wasmtime_wasi::p2::add_to_linker_async(linker)?;
+        if !self.http_hosts.is_empty() {
+            wasmtime_wasi_http::add_only_http_to_linker_async(linker)?;
+        }
+        if !self.random {
+            linker.allow_shadowing(true);
+            let mut random = linker.instance(WASI_RANDOM_INTERFACE)?;
+            random.func_wrap("get-random-bytes", |_, _: (u64,)| {
+                Err::<(Vec<u8>,), _>(random_denied())
+            })?;
+            random.func_wrap("get-random-u64", |_, _: ()| {
+                Err::<(u64,), _>(random_denied())
+            })?;
+            linker.allow_shadowing(false);
+        }
+        Ok(())
+    }
+
+    pub fn allows_wall_clock(&self) -> bool {
+        self.wall_clock
+    }
+
+    /// Whether the process may send an HTTP request to `host`.
+    pub fn allows_http_host(&self, host: &str) -> bool {
+        self.http_hosts.contains(&host.to_ascii_lowercase())
+    }
+}
+
+/// Whether losing `caps` can take away drives preopened for a process. Preopens are
+/// fixed when the process starts, so such a process must be restarted to lose them.
+pub fn preopens_depend_on<'a>(
+    our_node: &str,
+    persisted: &t::PersistedProcess,
+    caps: impl IntoIterator<Item = &'a t::Capability>,
+) -> bool {
+    persisted.wasi.preopen_drives
+        && caps
+            .into_iter()
+            .any(|cap| cap.issuer.node == our_node && cap.issuer.process == *VFS_PROCESS_ID)
+}
+
+fn random_denied() -> anyhow::Error {
+    anyhow::anyhow!("process's WASI policy denies randomness")
+}
+
+/// a wall clock stopped at the unix epoch
+struct EpochClock;
+
+impl HostWallClock for EpochClock {
+    fn resolution(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn now(&self) -> Duration {
+        Duration::ZERO
+    }
+}
+
+/// The drives of the process's package that it holds an unexpired vfs capability
+/// for, and that exist.
+fn drive_preopens(
+    our: &t::Address,
+    vfs_path: &Path,
+    caps: &[(t::Capability, Vec<u8>)],
+) -> Vec<Preopen> {
+    let package_prefix = format!("/{}:{}/", our.process.package(), our.process.publisher());
+    let now = super::unix_time_secs();
+    let mut preopens: Vec<Preopen> = vec![];
+    for (cap, _) in caps {
+        if cap.issuer.node != our.node
+            || cap.issuer.process != *VFS_PROCESS_ID
+            || cap.is_expired(now)
+        {
+            continue;
+        }
+        let params = cap.unconstrained_params();
+        let (Some(kind), Some(drive)) = (params["kind"].as_str(), params["drive"].as_str()) else {
+            continue;
+        };
+        let Some(drive_name) = drive.strip_prefix(&package_prefix) else {
+            continue;
+        };
+        // a drive is one path component below the package
+        if drive_name.is_empty() || drive_name.contains(['/', '\\']) || drive_name == ".." {
+            continue;
+        }
+        let writable = kind == "write";
+        if let Some(preopen) = preopens.iter_mut().find(|p| p.guest_path == drive) {
+            preopen.writable |= writable;
+            continue;
+        }
+        let host_path = drive_host_path(vfs_path, drive);
+        if !host_path.is_dir() {
+            continue;
+        }
+        preopens.push(Preopen {
+            host_path,
+            guest_path: drive.to_string(),
+            writable,
+        });
+    }
+    preopens
+}
+
+/// where the vfs keeps a drive, given its vfs path
+fn drive_host_path(vfs_path: &Path, drive: &str) -> PathBuf {
+    let drive = drive.trim_start_matches('/');
+    #[cfg(unix)]
+    return vfs_path.join(drive);
+    #[cfg(target_os = "windows")]
+    return vfs_path.join(drive.replace(":", "_"));
+}
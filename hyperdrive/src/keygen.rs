@@ -12,7 +12,9 @@ use std::{
     net::{IpAddr, Ipv4Addr, Ipv6Addr},
     num::NonZeroU32,
     ops::Add,
+    path::Path,
 };
+use tokio::io::AsyncWriteExt;
 
 type DiskKey = [u8; CREDENTIAL_LEN];
 
@@ -66,6 +68,32 @@ pub fn encode_keyfile(
 }
 
 pub fn decode_keyfile(keyfile: &[u8], password_hash: &str) -> Result<Keyfile, &'static str> {
+    let decrypted = decrypt_keyfile(keyfile, password_hash)?;
+
+    let networking_keypair =
+        signature::Ed25519KeyPair::from_pkcs8(&decrypted.serialized_networking_keypair)
+            .map_err(|_| "failed to parse networking keys")?;
+
+    Ok(Keyfile {
+        username: decrypted.username,
+        routers: decrypted.routers,
+        networking_keypair,
+        jwt_secret_bytes: decrypted.jwt_secret_bytes,
+        file_key: decrypted.file_key,
+    })
+}
+
+/// Keyfile contents with the networking keypair still serialized, so that
+/// the keyfile can be encoded again.
+struct DecryptedKeyfile {
+    username: String,
+    routers: Vec<String>,
+    serialized_networking_keypair: Vec<u8>,
+    jwt_secret_bytes: Vec<u8>,
+    file_key: Vec<u8>,
+}
+
+fn decrypt_keyfile(keyfile: &[u8], password_hash: &str) -> Result<DecryptedKeyfile, &'static str> {
     use generic_array::GenericArray;
 
     let (username, routers, salt, key_enc, jwt_enc, file_enc) =
@@ -100,9 +128,6 @@ pub fn decode_keyfile(keyfile: &[u8], password_hash: &str) -> Result<Keyfile, &'
         .decrypt(net_nonce, &key_enc[12..])
         .map_err(|_| "failed to decrypt networking keys")?;
 
-    let networking_keypair = signature::Ed25519KeyPair::from_pkcs8(&serialized_networking_keypair)
-        .map_err(|_| "failed to parse networking keys")?;
-
     let jwt_secret_bytes: Vec<u8> = cipher
         .decrypt(jwt_nonce, &jwt_enc[12..])
         .map_err(|_| "failed to decrypt jwt secret")?;
@@ -111,15 +136,229 @@ pub fn decode_keyfile(keyfile: &[u8], password_hash: &str) -> Result<Keyfile, &'
         .decrypt(file_nonce, &file_enc[12..])
         .map_err(|_| "failed to decrypt file key")?;
 
-    Ok(Keyfile {
+    Ok(DecryptedKeyfile {
         username,
         routers,
-        networking_keypair,
+        serialized_networking_keypair,
         jwt_secret_bytes,
         file_key,
     })
 }
 
+/// Decrypt a keyfile with the old password hash and encrypt it again under the new one.
+/// If `new_jwt_secret` is given it replaces the JWT secret, invalidating all
+/// existing login sessions. Returns the new encoded keyfile.
+pub fn reencrypt_keyfile(
+    keyfile: &[u8],
+    old_password_hash: &str,
+    new_password_hash: String,
+    new_jwt_secret: Option<&[u8]>,
+) -> Result<Vec<u8>, &'static str> {
+    let decrypted = decrypt_keyfile(keyfile, old_password_hash)?;
+    // make sure the networking key is intact before writing it anywhere
+    signature::Ed25519KeyPair::from_pkcs8(&decrypted.serialized_networking_keypair)
+        .map_err(|_| "failed to parse networking keys")?;
+    Ok(encode_keyfile(
+        new_password_hash,
+        decrypted.username,
+        decrypted.routers,
+        &decrypted.serialized_networking_keypair,
+        new_jwt_secret.unwrap_or(&decrypted.jwt_secret_bytes),
+        &decrypted.file_key,
+    ))
+}
+
+/// Write the keyfile to `home/.keys` with [`write_secret_atomically`].
+pub async fn write_keyfile_atomically(home_directory_path: &Path, keyfile: &[u8]) -> Result<()> {
+    write_secret_atomically(&home_directory_path.join(".keys"), keyfile).await
+}
+
+/// Write a file holding key material by way of a temporary file, so that a
+/// crash midway never leaves a truncated file behind. The temporary file is
+/// readable by our user only, from the moment it's created.
+pub async fn write_secret_atomically(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    // one left behind by a crash may have other permissions
+    let _ = tokio::fs::remove_file(&tmp_path).await;
+    let mut options = tokio::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&tmp_path).await?;
+    file.write_all(bytes).await?;
+    file.sync_all().await?;
+    drop(file);
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// A networking key generated by a key rotation that is not yet published onchain.
+/// Lives next to the keyfile, encrypted with the keyfile's file key.
+pub const PENDING_NETWORKING_KEY_FILE: &str = ".net-key.pending";
+/// A networking key that replaced the keyfile's at runtime after its rotation completed.
+/// Encrypted with the keyfile's file key, and folded into the keyfile at the next login,
+/// since re-encoding the keyfile itself requires the password.
+pub const ROTATED_NETWORKING_KEY_FILE: &str = ".net-key";
+
+/// The networking key files a key rotation may have left next to the keyfile,
+/// read before login so they can be folded into the keyfile.
+#[derive(Clone, Default)]
+pub struct KeyRotationFiles {
+    /// see [`ROTATED_NETWORKING_KEY_FILE`]
+    pub rotated: Option<Vec<u8>>,
+    /// see [`PENDING_NETWORKING_KEY_FILE`]
+    pub pending: Option<Vec<u8>>,
+}
+
+impl KeyRotationFiles {
+    pub async fn read(home_directory_path: &Path) -> Self {
+        Self {
+            rotated: tokio::fs::read(home_directory_path.join(ROTATED_NETWORKING_KEY_FILE))
+                .await
+                .ok(),
+            pending: tokio::fs::read(home_directory_path.join(PENDING_NETWORKING_KEY_FILE))
+                .await
+                .ok(),
+        }
+    }
+}
+
+pub fn encrypt_networking_key(file_key: &[u8], serialized_networking_keypair: &[u8]) -> Vec<u8> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(file_key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, serialized_networking_keypair)
+        .unwrap();
+    [nonce.to_vec(), ciphertext].concat()
+}
+
+pub fn decrypt_networking_key(file_key: &[u8], encrypted: &[u8]) -> Result<Vec<u8>, &'static str> {
+    use generic_array::GenericArray;
+
+    if encrypted.len() < 12 {
+        return Err("networking key file is truncated");
+    }
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(file_key));
+    cipher
+        .decrypt(GenericArray::from_slice(&encrypted[..12]), &encrypted[12..])
+        .map_err(|_| "failed to decrypt networking key file")
+}
+
+/// Swap the networking key of a completed rotation (see [`ROTATED_NETWORKING_KEY_FILE`])
+/// into a freshly decoded keyfile, re-encoding the keyfile under the same password.
+pub fn apply_rotated_networking_key(
+    encoded_keyfile: &mut Vec<u8>,
+    decoded_keyfile: &mut Keyfile,
+    password_hash: &str,
+    encrypted_networking_key: &[u8],
+) -> Result<(), &'static str> {
+    let serialized_networking_keypair =
+        decrypt_networking_key(&decoded_keyfile.file_key, encrypted_networking_key)?;
+    let networking_keypair = signature::Ed25519KeyPair::from_pkcs8(&serialized_networking_keypair)
+        .map_err(|_| "failed to parse rotated networking key")?;
+    swap_networking_key(
+        encoded_keyfile,
+        decoded_keyfile,
+        password_hash,
+        &serialized_networking_keypair,
+        networking_keypair,
+    );
+    Ok(())
+}
+
+/// Swap the networking key of a rotation that was still pending when we shut down
+/// (see [`PENDING_NETWORKING_KEY_FILE`]) into a freshly decoded keyfile, if it is the
+/// key now published onchain. Returns whether it was swapped in.
+pub fn apply_pending_networking_key(
+    encoded_keyfile: &mut Vec<u8>,
+    decoded_keyfile: &mut Keyfile,
+    password_hash: &str,
+    encrypted_networking_key: &[u8],
+    onchain_networking_key: &str,
+) -> Result<bool, &'static str> {
+    let serialized_networking_keypair =
+        decrypt_networking_key(&decoded_keyfile.file_key, encrypted_networking_key)?;
+    let networking_keypair = signature::Ed25519KeyPair::from_pkcs8(&serialized_networking_keypair)
+        .map_err(|_| "failed to parse pending networking key")?;
+    let pending_networking_key = format!(
+        "0x{}",
+        hex::encode(networking_keypair.public_key().as_ref())
+    );
+    if pending_networking_key != onchain_networking_key {
+        return Ok(false);
+    }
+    swap_networking_key(
+        encoded_keyfile,
+        decoded_keyfile,
+        password_hash,
+        &serialized_networking_keypair,
+        networking_keypair,
+    );
+    Ok(true)
+}
+
+fn swap_networking_key(
+    encoded_keyfile: &mut Vec<u8>,
+    decoded_keyfile: &mut Keyfile,
+    password_hash: &str,
+    serialized_networking_keypair: &[u8],
+    networking_keypair: signature::Ed25519KeyPair,
+) {
+    *encoded_keyfile = encode_keyfile(
+        password_hash.to_string(),
+        decoded_keyfile.username.clone(),
+        decoded_keyfile.routers.clone(),
+        serialized_networking_keypair,
+        &decoded_keyfile.jwt_secret_bytes,
+        &decoded_keyfile.file_key,
+    );
+    decoded_keyfile.networking_keypair = networking_keypair;
+}
+
+/// Remove [`ROTATED_NETWORKING_KEY_FILE`] once its key has been folded into the keyfile.
+pub async fn clear_rotated_networking_key(home_directory_path: &Path, keyfile: &Keyfile) {
+    let path = home_directory_path.join(ROTATED_NETWORKING_KEY_FILE);
+    let Ok(encrypted) = tokio::fs::read(&path).await else {
+        return;
+    };
+    let folded = decrypt_networking_key(&keyfile.file_key, &encrypted)
+        .ok()
+        .and_then(|serialized| signature::Ed25519KeyPair::from_pkcs8(&serialized).ok())
+        .is_some_and(|rotated| {
+            rotated.public_key().as_ref() == keyfile.networking_keypair.public_key().as_ref()
+        });
+    if folded {
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+}
+
+/// Hash a password the way the login page does before sending it to us:
+/// argon2 with the node name as salt, hex-encoded with a 0x prefix.
+pub fn hash_password(username: &str, password: &str) -> String {
+    use argon2::Argon2;
+
+    // salt is either node name (if node name is longer than 8 characters)
+    //  or node name repeated enough times to be longer than 8 characters
+    let min_salt_len = 8;
+    let name_len = username.len();
+    let salt = if name_len >= min_salt_len {
+        username.to_string()
+    } else {
+        username.repeat(1 + min_salt_len / name_len)
+    };
+
+    let mut output_key_material = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(
+            password.as_bytes(),
+            salt.as_bytes(),
+            &mut output_key_material,
+        )
+        .expect("password hashing failed");
+    format!("0x{}", hex::encode(output_key_material))
+}
+
 pub fn generate_jwt(
     jwt_secret_bytes: &[u8],
     username: &str,
@@ -230,6 +469,15 @@ pub fn generate_file_key() -> Vec<u8> {
     key.to_vec()
 }
 
+pub fn generate_jwt_secret() -> Vec<u8> {
+    use ring::rand::SecureRandom;
+
+    let mut jwt_secret = [0u8; 32];
+    let rng = SystemRandom::new();
+    rng.fill(&mut jwt_secret).unwrap();
+    jwt_secret.to_vec()
+}
+
 /// # Returns
 /// a pair of (public key (encoded as a hex string), serialized key as a pkcs8 Document)
 pub fn generate_networking_key() -> (String, ring::pkcs8::Document) {
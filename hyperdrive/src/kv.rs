@@ -520,6 +520,14 @@ async fn handle_fd_request(km: KernelMessage, state: &mut KvState) -> anyhow::Re
                     .await;
             }
         }
+        FdManagerRequest::GetFdsUsage => {
+            crate::fd_manager::send_fd_manager_fds_usage(
+                &state.our,
+                state.open_kvs.len() as u64,
+                &state.send_to_loop,
+            )
+            .await;
+        }
         _ => {
             return Err(anyhow::anyhow!("non-Cull FdManagerRequest"));
         }
@@ -548,6 +556,8 @@ async fn add_capability(
         .send(CapMessage::Add {
             on: source.process.clone(),
             caps: vec![cap],
+            actor: our.process.clone(),
+            reason: "kv database opened",
             responder: Some(send_cap_bool),
         })
         .await
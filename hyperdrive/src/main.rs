@@ -83,6 +83,18 @@ async fn main() {
     let home_directory_path = std::fs::canonicalize(&home_directory_path).expect(&format!(
         "specified home directory {home_directory_path} not found"
     ));
+
+    if let Some(recording) = matches.get_one::<String>("replay") {
+        let wasm = matches.get_one::<String>("replay-wasm").map(Path::new);
+        match kernel::replay::replay(&home_directory_path, Path::new(recording), wasm).await {
+            Ok(0) => std::process::exit(0),
+            Ok(_) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("Error: couldn't replay {recording}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
     let http_server_port = set_http_server_port(matches.get_one::<u16>("port")).await;
     let ws_networking_port = matches.get_one::<u16>("ws-port");
     #[cfg(not(feature = "simulation-mode"))]
@@ -102,14 +114,40 @@ async fn main() {
 
     let password = matches.get_one::<String>("password");
 
+    // with --new-password, re-encrypt the keyfile and then log in with the new password
+    #[cfg(not(feature = "simulation-mode"))]
+    let password = match matches.get_one::<String>("new-password") {
+        None => password,
+        Some(new_password) => {
+            let Some(old_password) = password else {
+                eprintln!("Error: --new-password requires the current password in --password");
+                std::process::exit(1);
+            };
+            change_keyfile_password(
+                &home_directory_path,
+                old_password,
+                new_password,
+                *matches.get_one::<bool>("rotate-jwt-secret").unwrap(),
+            )
+            .await;
+            Some(new_password)
+        }
+    };
+
     // logging mode is toggled at runtime by CTRL+L
     let is_logging = !*matches.get_one::<bool>("logging-off").unwrap();
     let max_log_size = matches.get_one::<u64>("max-log-size");
     let number_log_files = matches.get_one::<u64>("number-log-files");
+    let log_format = *matches
+        .get_one::<terminal::utils::LogFormat>("log-format")
+        .unwrap();
 
     // detached determines whether terminal is interactive
     let detached = *matches.get_one::<bool>("detached").unwrap();
 
+    // remote terminal serves the terminal over an authenticated WebSocket
+    let is_remote = *matches.get_one::<bool>("remote-terminal").unwrap();
+
     let process_verbosity = matches.get_one::<String>("process-verbosity").unwrap();
     let process_verbosity: ProcessVerbosity = if process_verbosity.is_empty() {
         HashMap::new()
@@ -259,6 +297,9 @@ async fn main() {
     // terminal receives prints via this channel, all other modules send prints
     let (print_sender, print_receiver): (PrintSender, PrintReceiver) =
         mpsc::channel(TERMINAL_CHANNEL_CAPACITY);
+    // terminal receives responses to its own requests, e.g. for tab completion
+    let (terminal_sender, terminal_receiver): (MessageSender, MessageReceiver) =
+        mpsc::channel(TERMINAL_CHANNEL_CAPACITY);
 
     let our_ip = find_public_ip().await;
     let (ws_tcp_handle, ws_flag_used) = setup_networking("ws", ws_networking_port).await;
@@ -448,6 +489,12 @@ async fn main() {
             None,
             false,
         ),
+        (
+            ProcessId::new(Some("terminal"), "distro", "sys"),
+            terminal_sender,
+            None,
+            false,
+        ),
     ];
 
     /*
@@ -456,6 +503,8 @@ async fn main() {
      *
      *  if any of these modules fail, the program exits with an error.
      */
+    keygen::clear_rotated_networking_key(&home_directory_path, &decoded_keyfile).await;
+
     let networking_keypair_arc = Arc::new(decoded_keyfile.networking_keypair);
     let our_name_arc = Arc::new(our.name.clone());
 
@@ -552,6 +601,7 @@ async fn main() {
                 }
             })
             .collect(),
+        *matches.get_one::<usize>("mailbox-capacity").unwrap(),
     ));
     tasks.spawn(net::networking(
         our.clone(),
@@ -568,6 +618,10 @@ async fn main() {
         *matches
             .get_one::<u64>("max-passthroughs")
             .unwrap_or(&DEFAULT_MAX_PASSTHROUGHS),
+        home_directory_path.clone(),
+        decoded_keyfile.file_key.clone(),
+        #[cfg(feature = "simulation-mode")]
+        fakechain_port.unwrap_or(8545),
     ));
     tasks.spawn(state::state_sender(
         our_name_arc.clone(),
@@ -582,6 +636,7 @@ async fn main() {
         kernel_message_sender.clone(),
         print_sender.clone(),
         fd_manager_receiver,
+        caps_oracle_sender.clone(),
         matches.get_one::<u64>("soft-ulimit").copied(),
     ));
     tasks.spawn(kv::kv(
@@ -609,6 +664,7 @@ async fn main() {
         kernel_message_sender.clone(),
         print_sender.clone(),
         expose_local,
+        home_directory_path.clone(),
     ));
     tasks.spawn(http::client::http_client(
         our.name.clone(),
@@ -667,9 +723,12 @@ async fn main() {
             kernel_debug_message_sender,
             print_sender.clone(),
             print_receiver,
+            terminal_receiver,
             detached,
             verbose_mode,
             is_logging,
+            log_format,
+            is_remote,
             max_log_size.copied(),
             number_log_files.copied(),
             process_verbosity,
@@ -784,11 +843,28 @@ pub async fn simulate_node(
                     panic!("Fake node must be booted with either a --fake-node-name, --password, or both.");
                 }
                 Some(password) => {
-                    let keyfile = tokio::fs::read(home_directory_path.join(".keys"))
+                    let mut keyfile = tokio::fs::read(home_directory_path.join(".keys"))
                         .await
                         .expect("could not read keyfile");
-                    let decoded = keygen::decode_keyfile(&keyfile, &password)
+                    let mut decoded = keygen::decode_keyfile(&keyfile, &password)
                         .expect("could not decode keyfile");
+                    // fold in the networking key of a rotation that completed at runtime
+                    if let Ok(rotated_key) = tokio::fs::read(
+                        home_directory_path.join(keygen::ROTATED_NETWORKING_KEY_FILE),
+                    )
+                    .await
+                    {
+                        keygen::apply_rotated_networking_key(
+                            &mut keyfile,
+                            &mut decoded,
+                            &password,
+                            &rotated_key,
+                        )
+                        .expect("could not apply rotated networking key");
+                        keygen::write_keyfile_atomically(home_directory_path, &keyfile)
+                            .await
+                            .expect("could not write keyfile");
+                    }
                     let mut identity = Identity {
                         name: decoded.username.clone(),
                         networking_key: format!(
@@ -916,6 +992,10 @@ fn build_command() -> Command {
             arg!(-d --detached <IS_DETACHED> "Run in detached mode (don't accept input)")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            arg!(--"remote-terminal" <IS_REMOTE> "Serve the terminal to clients logged in to its secure subdomain, at /terminal:distro:sys")
+                .action(clap::ArgAction::SetTrue),
+        )
         .arg(arg!(--rpc <RPC> "Add a WebSockets RPC URL at boot"))
         .arg(arg!(--"rpc-config" <RPC_CONFIG_PATH> "Add WebSockets RPC URLs specified in config at boot"))
         .arg(arg!(--password <PASSWORD> "Node password (in double quotes)"))
@@ -927,6 +1007,11 @@ fn build_command() -> Command {
             arg!(--"number-log-files" <NUMBER_LOG_FILES> "Number of logs to rotate [default: 4]")
                 .value_parser(value_parser!(u64)),
         )
+        .arg(
+            arg!(--"log-format" <LOG_FORMAT> "Format of the logs in .terminal_logs; json logs can be searched with the `log` script")
+                .default_value("text")
+                .value_parser(value_parser!(terminal::utils::LogFormat)),
+        )
         .arg(
             arg!(--"max-peers" <MAX_PEERS> "Maximum number of peers to hold active connections with [default: 32]")
                 .value_parser(value_parser!(u64)),
@@ -939,15 +1024,33 @@ fn build_command() -> Command {
             arg!(--"soft-ulimit" <SOFT_ULIMIT> "Enforce a static maximum number of file descriptors [default: fetched from system]")
                 .value_parser(value_parser!(u64)),
         )
+        .arg(
+            arg!(--"mailbox-capacity" <CAPACITY> "Messages each priority lane of a process's mailbox holds before senders are told it is full")
+                .default_value("100")
+                .value_parser(value_parser!(usize)),
+        )
         .arg(
             arg!(--"process-verbosity" <JSON_STRING> "ProcessId: verbosity JSON object")
                 .default_value("")
         )
+        .arg(arg!(--replay <RECORDING> "Replay a process recording against its Wasm offline, report divergences, and exit"))
+        .arg(
+            arg!(--"replay-wasm" <WASM> "With --replay, run this Wasm instead of the one the recording was made with")
+                .requires("replay"),
+        )
         .arg(
             arg!(--"expose-local" <EXPOSE_LOCAL> "Expose local-only and RPC endpoints. WARNING: If behind a reverse proxy, ensure proxy is set to put `x-forwarded-for` headers or this will allow your node to be controlled remotely without authentication! (caddy adds these headers by default and so is strongly recommended)")
                 .action(clap::ArgAction::SetTrue),
         );
 
+    #[cfg(not(feature = "simulation-mode"))]
+    let app = app
+        .arg(arg!(--"new-password" <NEW_PASSWORD> "Re-encrypt the keyfile under this password before booting; requires --password"))
+        .arg(
+            arg!(--"rotate-jwt-secret" "With --new-password, also replace the JWT secret, logging out all existing sessions")
+                .action(clap::ArgAction::SetTrue),
+        );
+
     #[cfg(feature = "simulation-mode")]
     let app = app
         .arg(arg!(--"fake-node-name" <NAME> "Name of fake node to boot"))
@@ -1035,6 +1138,7 @@ async fn serve_register_fe(
 
     let keyfile_path = home_directory_path.join(".keys");
     let keyfile: Option<Vec<u8>> = tokio::fs::read(&keyfile_path).await.ok();
+    let key_rotation_files = keygen::KeyRotationFiles::read(home_directory_path).await;
 
     tokio::spawn(async move {
         register::register(
@@ -1045,6 +1149,7 @@ async fn serve_register_fe(
             (tcp_networking.0.as_ref(), tcp_networking.1),
             http_server_port,
             keyfile,
+            key_rotation_files,
             eth_provider_config,
             detached,
             cache_sources_from_file,         // Pass cache sources from config
@@ -1080,11 +1185,10 @@ async fn login_with_password(
     eth_provider_config: lib::eth::SavedConfigs,
     password: &str,
 ) -> (Identity, Vec<u8>, Keyfile, Vec<String>, Vec<String>) {
-    use argon2::Argon2;
     use ring::signature::KeyPair;
 
     let keyfile_path = home_directory_path.join(".keys");
-    let disk_keyfile: Vec<u8> = match tokio::fs::read(&keyfile_path).await {
+    let mut disk_keyfile: Vec<u8> = match tokio::fs::read(&keyfile_path).await {
         Ok(file) => file,
         Err(e) => {
             let path_display = keyfile_path.display();
@@ -1110,31 +1214,24 @@ async fn login_with_password(
         })
         .unwrap();
 
-    // salt is either node name (if node name is longer than 8 characters)
-    //  or node name repeated enough times to be longer than 8 characters
-    let min_salt_len = 8;
-    let name_len = username.len();
-    let salt = if name_len >= min_salt_len {
-        username
-    } else {
-        username.repeat(1 + min_salt_len / name_len)
-    };
+    let password_hash_hex = keygen::hash_password(&username, password);
 
-    let mut output_key_material = [0u8; 32];
-    Argon2::default()
-        .hash_password_into(
-            password.as_bytes(),
-            salt.as_bytes(),
-            &mut output_key_material,
-        )
-        .expect("password hashing failed");
-    let password_hash = hex::encode(output_key_material);
-
-    let password_hash_hex = format!("0x{}", password_hash);
-
-    let k = keygen::decode_keyfile(&disk_keyfile, &password_hash_hex)
+    let mut k = keygen::decode_keyfile(&disk_keyfile, &password_hash_hex)
         .expect("could not decode keyfile, password incorrect");
 
+    // fold in the networking key of a rotation that completed at runtime
+    let key_rotation_files = keygen::KeyRotationFiles::read(home_directory_path).await;
+    if let Some(rotated_key) = key_rotation_files.rotated.as_deref() {
+        if let Err(e) = keygen::apply_rotated_networking_key(
+            &mut disk_keyfile,
+            &mut k,
+            &password_hash_hex,
+            rotated_key,
+        ) {
+            eprintln!("Warning: ignoring rotated networking key: {e}\r");
+        }
+    }
+
     let mut our = Identity {
         name: k.username.clone(),
         networking_key: format!(
@@ -1162,7 +1259,18 @@ async fn login_with_password(
         None => (0, tcp_networking.1),
     };
 
-    if let Err(e) = register::assign_routing(&mut our, &providers, ws_port, tcp_port).await {
+    if let Err(e) = register::assign_routing_after_rotation(
+        &mut our,
+        &mut disk_keyfile,
+        &mut k,
+        &password_hash_hex,
+        key_rotation_files.pending.as_deref(),
+        &providers,
+        ws_port,
+        tcp_port,
+    )
+    .await
+    {
         panic!("information used to boot does not match information onchain: {e}");
     }
 
@@ -1182,6 +1290,48 @@ async fn login_with_password(
     )
 }
 
+/// re-encrypt the keyfile on disk under a new password, for headless password changes
+#[cfg(not(feature = "simulation-mode"))]
+async fn change_keyfile_password(
+    home_directory_path: &Path,
+    old_password: &str,
+    new_password: &str,
+    rotate_jwt_secret: bool,
+) {
+    let keyfile = match tokio::fs::read(home_directory_path.join(".keys")).await {
+        Ok(keyfile) => keyfile,
+        Err(e) => {
+            eprintln!("Error: cannot change password: failed to read keyfile: {e}");
+            std::process::exit(1);
+        }
+    };
+    let Ok((username, _)) = keygen::get_username_and_routers(&keyfile) else {
+        eprintln!("Error: cannot change password: failed to parse keyfile");
+        std::process::exit(1);
+    };
+    let new_jwt_secret = rotate_jwt_secret.then(keygen::generate_jwt_secret);
+    let new_keyfile = match keygen::reencrypt_keyfile(
+        &keyfile,
+        &keygen::hash_password(&username, old_password),
+        keygen::hash_password(&username, new_password),
+        new_jwt_secret.as_deref(),
+    ) {
+        Ok(new_keyfile) => new_keyfile,
+        Err(e) => {
+            eprintln!("Error: cannot change password: {e} (is --password correct?)");
+            std::process::exit(1);
+        }
+    };
+    keygen::write_keyfile_atomically(home_directory_path, &new_keyfile)
+        .await
+        .expect("failed to write keyfile");
+    if rotate_jwt_secret {
+        println!("Password changed; all existing sessions have been logged out.\r");
+    } else {
+        println!("Password changed.\r");
+    }
+}
+
 fn make_remote_link(url: &str, text: &str) -> String {
     format!("\x1B]8;;{}\x1B\\{}\x1B]8;;\x1B\\", url, text)
 }
@@ -2,7 +2,7 @@ use lib::{
     core::Address,
     types::core::{
         Identity, KernelMessage, MessageReceiver, MessageSender, NetAction, NetResponse,
-        NetworkErrorSender, NodeRouting, PrintSender, NET_PROCESS_ID,
+        NetworkErrorSender, NodeRouting, PrintSender, ProcessId, NET_PROCESS_ID,
     },
 };
 use types::{
@@ -12,12 +12,14 @@ use types::{
 use {
     dashmap::DashMap,
     ring::signature::{Ed25519KeyPair, KeyPair},
-    std::sync::Arc,
+    std::path::PathBuf,
+    std::sync::{Arc, RwLock},
     tokio::task::JoinSet,
 };
 
 mod connect;
 mod indirect;
+mod rotate;
 mod tcp;
 mod types;
 mod utils;
@@ -32,6 +34,10 @@ mod ws;
 /// or direct networking in the PKI. If direct, it can be over a number of protocols.
 /// This implementation supports two: `"ws"` and `"tcp"`. These are keys associated
 /// with ports in the `ports` field of a node [`Identity`].
+///
+/// Our networking key can be rotated at runtime with [`NetAction::RotateKey`]: the new
+/// key is saved in the home directory until hns-indexer sends the [`NetAction::HnsUpdate`]
+/// publishing it, at which point we switch over and re-handshake with all peers.
 pub async fn networking(
     our: Identity,
     our_ip: String,
@@ -45,6 +51,9 @@ pub async fn networking(
     max_peers: u64,
     // only used by routers
     max_passthroughs: u64,
+    home_directory_path: PathBuf,
+    file_key: Vec<u8>,
+    #[cfg(feature = "simulation-mode")] fakechain_port: u16,
 ) -> anyhow::Result<()> {
     crate::fd_manager::send_fd_manager_request_fds_limit(
         &Address::new(&our.name, NET_PROCESS_ID.clone()),
@@ -55,11 +64,15 @@ pub async fn networking(
     let ext = IdentityExt {
         our: Arc::new(our),
         our_ip: Arc::new(our_ip),
-        keypair,
+        keypair: Arc::new(RwLock::new(keypair)),
         kernel_message_tx,
         network_error_tx,
         print_tx,
         _reveal_ip,
+        home_directory_path: Arc::new(home_directory_path),
+        file_key: Arc::new(file_key),
+        #[cfg(feature = "simulation-mode")]
+        fakechain_port,
     };
     // start by initializing the structs where we'll store PKI in memory
     // and store a mapping of peers we have an active route for
@@ -76,6 +89,7 @@ pub async fn networking(
         active_passthroughs,
         max_passthroughs,
         fds_limit: 10, // small hardcoded limit that gets replaced by fd-manager soon after boot
+        key_rotation: rotate::load(&ext).await,
     };
 
     let mut tasks = JoinSet::<anyhow::Result<()>>::new();
@@ -177,17 +191,19 @@ async fn handle_local_request(
     match rmp_serde::from_slice::<NetAction>(request_body) {
         Err(_e) => {
             // only other possible message is from fd-manager -- handle here
-            handle_fdman(km, request_body, data).await;
+            handle_fdman(ext, km, request_body, data).await;
         }
         Ok(NetAction::ConnectionRequest(_)) => {
             // we shouldn't get these locally, ignore
         }
         Ok(NetAction::HnsUpdate(log)) => {
-            utils::ingest_log(log, &data.pki);
+            let from_indexer = is_hns_indexer(&km.source.process);
+            rotate::ingest_log(ext, data, log, from_indexer).await;
         }
         Ok(NetAction::HnsBatchUpdate(logs)) => {
+            let from_indexer = is_hns_indexer(&km.source.process);
             for log in logs {
-                utils::ingest_log(log, &data.pki);
+                rotate::ingest_log(ext, data, log, from_indexer).await;
             }
         }
         Ok(gets) => {
@@ -204,7 +220,7 @@ async fn handle_local_request(
                 ),
                 NetAction::GetPeer(peer) => (
                     if peer == ext.our.name {
-                        NetResponse::Peer(Some((*ext.our).clone()))
+                        NetResponse::Peer(Some(ext.current_identity()))
                     } else {
                         NetResponse::Peer(data.pki.get(&peer).map(|p| p.clone()))
                     },
@@ -216,7 +232,13 @@ async fn handle_local_request(
                         "indexing from contract address {}\r\n",
                         crate::HYPERMAP_ADDRESS
                     ));
-                    printout.push_str(&format!("our Identity: {:#?}\r\n", ext.our));
+                    printout.push_str(&format!("our Identity: {:#?}\r\n", ext.current_identity()));
+                    if let Some(pending) = &data.key_rotation {
+                        printout.push_str(&format!(
+                            "rotating networking key: waiting for {} to be published onchain\r\n",
+                            pending.info.new_networking_key
+                        ));
+                    }
                     printout.push_str(&format!(
                         "we have connections with {} peers ({} max):\r\n",
                         data.peers.peers().len(),
@@ -275,7 +297,7 @@ async fn handle_local_request(
                     Some(lib::core::LazyLoadBlob {
                         mime: None,
                         bytes: ext
-                            .keypair
+                            .keypair()
                             .sign(
                                 &[
                                     km.source.to_string().as_bytes(),
@@ -311,11 +333,57 @@ async fn handle_local_request(
                             &signature,
                             &message,
                             &data.pki,
-                            ext.keypair.public_key().as_ref().to_vec(),
+                            ext.keypair().public_key().as_ref().to_vec(),
                         )),
                         None,
                     )
                 }
+                NetAction::RotateKey | NetAction::CancelKeyRotation
+                    if !may_rotate_key(&km.source.process) =>
+                {
+                    utils::print_debug(
+                        &ext.print_tx,
+                        &format!(
+                            "net: {} may not rotate our networking key",
+                            km.source.process
+                        ),
+                    )
+                    .await;
+                    (NetResponse::KeyRotation(None), None)
+                }
+                NetAction::RotateKey => match rotate::start(ext, data).await {
+                    Ok(rotation) => {
+                        utils::print_loud(
+                            &ext.print_tx,
+                            &format!(
+                                "net: generated networking key {}; publish it by sending this calldata to your node's TBA from its owner: {}",
+                                rotation.new_networking_key, rotation.execute_calldata
+                            ),
+                        )
+                        .await;
+                        (NetResponse::KeyRotation(Some(rotation)), None)
+                    }
+                    Err(e) => {
+                        utils::print_loud(
+                            &ext.print_tx,
+                            &format!("net: failed to start networking key rotation: {e}"),
+                        )
+                        .await;
+                        (NetResponse::KeyRotation(None), None)
+                    }
+                },
+                NetAction::GetKeyRotation => (
+                    NetResponse::KeyRotation(
+                        data.key_rotation
+                            .as_ref()
+                            .map(|pending| pending.info.clone()),
+                    ),
+                    None,
+                ),
+                NetAction::CancelKeyRotation => {
+                    rotate::cancel(ext, data).await;
+                    (NetResponse::KeyRotation(None), None)
+                }
                 _ => {
                     // already matched these outcomes
                     return;
@@ -344,7 +412,25 @@ async fn handle_local_request(
     }
 }
 
-async fn handle_fdman(km: &KernelMessage, request_body: &[u8], data: &mut NetData) {
+/// Only the terminal and settings may rotate our networking key: a rotation
+/// nobody publishes leaves the node unable to connect after a restart.
+fn may_rotate_key(process: &ProcessId) -> bool {
+    process.publisher() == "sys" && matches!(process.package(), "terminal" | "settings")
+}
+
+/// Only the indexer's view of our own `~net-key` may switch our networking key.
+fn is_hns_indexer(process: &ProcessId) -> bool {
+    process.process() == "hns-indexer"
+        && process.package() == "hns-indexer"
+        && process.publisher() == "sys"
+}
+
+async fn handle_fdman(
+    ext: &IdentityExt,
+    km: &KernelMessage,
+    request_body: &[u8],
+    data: &mut NetData,
+) {
     if km.source.process != *lib::core::FD_MANAGER_PROCESS_ID {
         return;
     }
@@ -368,6 +454,16 @@ async fn handle_fdman(km: &KernelMessage, request_body: &[u8], data: &mut NetDat
                 data.peers.cull(diff).await;
             }
         }
+        lib::core::FdManagerRequest::GetFdsUsage => {
+            // one connection per peer, and two per passthrough
+            let open = data.peers.peers().len() + 2 * data.active_passthroughs.len();
+            crate::fd_manager::send_fd_manager_fds_usage(
+                &Address::new(ext.our.name.as_str(), NET_PROCESS_ID.clone()),
+                open as u64,
+                &ext.kernel_message_tx,
+            )
+            .await;
+        }
         _ => return,
     }
 }
@@ -0,0 +1,216 @@
+use crate::keygen;
+use crate::net::types::{IdentityExt, NetData, PendingKeyRotation};
+use crate::net::utils::{self, net_key_string_to_hex};
+use crate::sol::{executeCall, noteCall};
+use alloy_primitives::{Address as EthAddress, Bytes, U256};
+use alloy_sol_types::SolCall;
+use lib::types::core::{HnsUpdate, KeyRotation};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use std::{str::FromStr, sync::Arc};
+
+/// Generate a new networking key, save it next to the keyfile and describe the
+/// transaction that publishes it. Refused while another rotation is in progress,
+/// since its key may already be on its way onchain: that one must be cancelled first.
+pub async fn start(ext: &IdentityExt, data: &mut NetData) -> anyhow::Result<KeyRotation> {
+    if let Some(pending) = &data.key_rotation {
+        return Err(anyhow::anyhow!(
+            "rotation to {} is in progress, cancel it first",
+            pending.info.new_networking_key
+        ));
+    }
+    let (_, serialized_keypair) = keygen::generate_networking_key();
+    let pending = pending_rotation(serialized_keypair.as_ref().to_vec())?;
+    keygen::write_secret_atomically(
+        &ext.home_directory_path
+            .join(keygen::PENDING_NETWORKING_KEY_FILE),
+        &keygen::encrypt_networking_key(&ext.file_key, &pending.serialized_keypair),
+    )
+    .await?;
+    let info = pending.info.clone();
+    data.key_rotation = Some(Arc::new(pending));
+
+    #[cfg(feature = "simulation-mode")]
+    {
+        // no owner wallet to sign with: publish the key ourselves
+        let name = ext.our.name.clone();
+        let pubkey = net_key_string_to_hex(&info.new_networking_key);
+        let fakechain_port = ext.fakechain_port;
+        let print_tx = ext.print_tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = crate::fakenet::set_net_key_local(&name, &pubkey, fakechain_port).await
+            {
+                utils::print_loud(
+                    &print_tx,
+                    &format!("net: failed to publish rotated key to fakechain: {e}"),
+                )
+                .await;
+            }
+        });
+    }
+
+    Ok(info)
+}
+
+/// Abandon the rotation in progress, if any.
+pub async fn cancel(ext: &IdentityExt, data: &mut NetData) {
+    if data.key_rotation.take().is_some() {
+        let _ = tokio::fs::remove_file(
+            ext.home_directory_path
+                .join(keygen::PENDING_NETWORKING_KEY_FILE),
+        )
+        .await;
+    }
+}
+
+/// Pick up a rotation that was still waiting for its onchain update when we shut down.
+/// If the update landed while we were offline, login already adopted the key.
+pub async fn load(ext: &IdentityExt) -> Option<Arc<PendingKeyRotation>> {
+    let path = ext
+        .home_directory_path
+        .join(keygen::PENDING_NETWORKING_KEY_FILE);
+    let encrypted = tokio::fs::read(&path).await.ok()?;
+    let pending = keygen::decrypt_networking_key(&ext.file_key, &encrypted)
+        .map_err(|e| anyhow::anyhow!(e))
+        .and_then(pending_rotation);
+    match pending {
+        Ok(pending)
+            if pending.keypair.public_key().as_ref() == ext.keypair().public_key().as_ref() =>
+        {
+            let _ = tokio::fs::remove_file(&path).await;
+            None
+        }
+        Ok(pending) => Some(Arc::new(pending)),
+        Err(e) => {
+            utils::print_loud(
+                &ext.print_tx,
+                &format!("net: ignoring pending networking key rotation: {e}"),
+            )
+            .await;
+            None
+        }
+    }
+}
+
+/// Ingest an HNS update. If it comes from the indexer and carries our pending key,
+/// switch to that key; if it changes the key of a peer we are connected to, drop the
+/// connection so that the next message re-handshakes against the new key.
+pub async fn ingest_log(ext: &IdentityExt, data: &mut NetData, log: HnsUpdate, from_indexer: bool) {
+    if log.name == ext.our.name {
+        if from_indexer {
+            check_our_key(ext, data, &log).await;
+        }
+    } else if data
+        .pki
+        .get(&log.name)
+        .is_some_and(|id| id.networking_key != log.public_key)
+        && data.peers.contains_key(&log.name)
+    {
+        utils::print_debug(
+            &ext.print_tx,
+            &format!(
+                "net: {} rotated their networking key, reconnecting",
+                log.name
+            ),
+        )
+        .await;
+        data.peers.remove(&log.name).await;
+    }
+    utils::ingest_log(log, &data.pki);
+}
+
+async fn check_our_key(ext: &IdentityExt, data: &mut NetData, log: &HnsUpdate) {
+    let onchain_key = net_key_string_to_hex(&log.public_key);
+    if onchain_key == ext.keypair().public_key().as_ref() {
+        return;
+    }
+    let is_pending = data
+        .key_rotation
+        .as_ref()
+        .is_some_and(|pending| onchain_key == pending.keypair.public_key().as_ref());
+    if !is_pending {
+        utils::print_loud(
+            &ext.print_tx,
+            &format!(
+                "net: our networking key onchain changed to {}, which we do not hold! \
+                 Peers will reject our connections until it is rotated again.",
+                log.public_key
+            ),
+        )
+        .await;
+        return;
+    }
+
+    // the new key is live: save it where the next login will fold it into the keyfile.
+    // until it is saved, the pending file is the only copy of it on disk.
+    let pending = data.key_rotation.clone().unwrap();
+    let home = &ext.home_directory_path;
+    if let Err(e) = keygen::write_secret_atomically(
+        &home.join(keygen::ROTATED_NETWORKING_KEY_FILE),
+        &keygen::encrypt_networking_key(&ext.file_key, &pending.serialized_keypair),
+    )
+    .await
+    {
+        utils::print_loud(
+            &ext.print_tx,
+            &format!(
+                "net: failed to save rotated networking key, keeping it pending \
+                 and switching to it at the next login: {e}"
+            ),
+        )
+        .await;
+        return;
+    }
+    data.key_rotation = None;
+    let _ = tokio::fs::remove_file(home.join(keygen::PENDING_NETWORKING_KEY_FILE)).await;
+    *ext.keypair.write().unwrap() = pending.keypair.clone();
+
+    // connections were authenticated with the old key. dropping them lets queued
+    // messages drain before each peer reconnects with a fresh handshake.
+    let peer_names: Vec<String> = data
+        .peers
+        .peers()
+        .iter()
+        .map(|peer| peer.key().clone())
+        .collect();
+    for name in &peer_names {
+        data.peers.remove(name).await;
+    }
+    utils::print_loud(
+        &ext.print_tx,
+        &format!(
+            "net: switched to networking key {}, reconnecting to {} peer(s)",
+            pending.info.new_networking_key,
+            peer_names.len()
+        ),
+    )
+    .await;
+}
+
+fn pending_rotation(serialized_keypair: Vec<u8>) -> anyhow::Result<PendingKeyRotation> {
+    let keypair = Ed25519KeyPair::from_pkcs8(&serialized_keypair)
+        .map_err(|_| anyhow::anyhow!("failed to parse networking key"))?;
+    let hypermap = EthAddress::from_str(crate::HYPERMAP_ADDRESS)?;
+    let note_calldata = noteCall {
+        note: "~net-key".into(),
+        data: Bytes::copy_from_slice(keypair.public_key().as_ref()),
+    }
+    .abi_encode();
+    let execute_calldata = executeCall {
+        to: hypermap,
+        value: U256::from(0),
+        data: note_calldata.clone().into(),
+        operation: 0,
+    }
+    .abi_encode();
+    let info = KeyRotation {
+        new_networking_key: format!("0x{}", hex::encode(keypair.public_key().as_ref())),
+        hypermap: hypermap.to_string(),
+        note_calldata: format!("0x{}", hex::encode(note_calldata)),
+        execute_calldata: format!("0x{}", hex::encode(execute_calldata)),
+    };
+    Ok(PendingKeyRotation {
+        keypair: Arc::new(keypair),
+        serialized_keypair,
+        info,
+    })
+}
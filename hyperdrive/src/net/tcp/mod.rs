@@ -259,7 +259,7 @@ async fn connect_with_handshake(
                 protocol_version: 1,
                 source: ext.our.name.clone(),
                 signature: ext
-                    .keypair
+                    .keypair()
                     .sign(
                         [&peer_id.name, use_router.unwrap().name.as_str()]
                             .concat()
@@ -358,7 +358,7 @@ async fn connect_with_handshake_via_router(
             protocol_version: 1,
             source: ext.our.name.clone(),
             signature: ext
-                .keypair
+                .keypair()
                 .sign(
                     [peer_id.name.as_str(), router_id.name.as_str()]
                         .concat()
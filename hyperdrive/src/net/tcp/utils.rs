@@ -1,7 +1,10 @@
 use crate::net::{
     tcp::PeerConnection,
     types::{HandshakePayload, IdentityExt, Peers},
-    utils::{print_debug, print_loud, IDLE_TIMEOUT, MESSAGE_MAX_SIZE},
+    utils::{
+        deserialize_message, print_debug, print_loud, serialize_message, IDLE_TIMEOUT,
+        MESSAGE_MAX_SIZE,
+    },
 };
 use lib::types::core::{
     check_process_id_hypermap_safe, KernelMessage, MessageSender, NodeId, PrintSender,
@@ -44,7 +47,7 @@ pub async fn maintain_connection(
     let write = async move {
         while let Some(km) = peer_rx.recv().await {
             let Ok(()) =
-                send_protocol_message(&km, &mut our_cipher, write_buf, &mut write_stream).await
+                send_protocol_message(km, &mut our_cipher, write_buf, &mut write_stream).await
             else {
                 break;
             };
@@ -109,12 +112,12 @@ pub async fn maintain_connection(
 }
 
 async fn send_protocol_message(
-    km: &KernelMessage,
+    km: KernelMessage,
     cipher: &mut snow::CipherState,
     buf: &mut [u8],
     stream: &mut OwnedWriteHalf,
 ) -> anyhow::Result<()> {
-    let serialized = rmp_serde::to_vec(km)?;
+    let serialized = serialize_message(&km)?;
     if serialized.len() > MESSAGE_MAX_SIZE as usize {
         return Err(anyhow::anyhow!("message too large"));
     }
@@ -151,7 +154,7 @@ async fn recv_protocol_message(
         let read_len = cipher.decrypt(&buf[..inner_len as usize], &mut msg[ptr..])?;
         ptr += read_len;
     }
-    Ok(rmp_serde::from_slice(&msg)?)
+    deserialize_message(&msg)
 }
 
 pub async fn send_protocol_handshake(
@@ -165,7 +168,7 @@ pub async fn send_protocol_handshake(
     let our_hs = rmp_serde::to_vec(&HandshakePayload {
         protocol_version: 1,
         name: ext.our.name.clone(),
-        signature: ext.keypair.sign(noise_static_key).as_ref().to_vec(),
+        signature: ext.keypair().sign(noise_static_key).as_ref().to_vec(),
         proxy_request,
     })
     .expect("failed to serialize handshake payload");
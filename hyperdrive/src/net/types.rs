@@ -1,13 +1,14 @@
 use lib::types::core::{
-    Address, Identity, KernelMessage, MessageSender, NetworkErrorSender, NodeId, PrintSender,
-    NET_PROCESS_ID,
+    Address, Identity, KernelMessage, KeyRotation, MessageSender, NetworkErrorSender, NodeId,
+    PrintSender, NET_PROCESS_ID,
 };
 use {
     dashmap::DashMap,
     ring::signature::Ed25519KeyPair,
     serde::{Deserialize, Serialize},
+    std::path::PathBuf,
     std::sync::atomic::AtomicU64,
-    std::sync::Arc,
+    std::sync::{Arc, RwLock},
     tokio::net::TcpStream,
     tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender},
     tokio_tungstenite::{MaybeTlsStream, WebSocketStream},
@@ -224,16 +225,51 @@ impl Peer {
         }
     }
 }
+/// Our networking keypair, swapped out when a key rotation completes.
+pub type SharedKeypair = Arc<RwLock<Arc<Ed25519KeyPair>>>;
+
 /// [`Identity`], with additional fields for networking.
 #[derive(Clone)]
 pub struct IdentityExt {
+    /// note that `networking_key` is the key we booted with: use [`IdentityExt::keypair`]
     pub our: Arc<Identity>,
     pub our_ip: Arc<String>,
-    pub keypair: Arc<Ed25519KeyPair>,
+    pub keypair: SharedKeypair,
     pub kernel_message_tx: MessageSender,
     pub network_error_tx: NetworkErrorSender,
     pub print_tx: PrintSender,
     pub _reveal_ip: bool, // TODO use
+    /// where rotated networking keys are saved
+    pub home_directory_path: Arc<PathBuf>,
+    /// encrypts rotated networking keys at rest
+    pub file_key: Arc<Vec<u8>>,
+    /// in simulation mode, we publish rotated networking keys to the fakechain ourselves
+    #[cfg(feature = "simulation-mode")]
+    pub fakechain_port: u16,
+}
+
+impl IdentityExt {
+    /// our current networking keypair
+    pub fn keypair(&self) -> Arc<Ed25519KeyPair> {
+        self.keypair.read().unwrap().clone()
+    }
+
+    /// our [`Identity`], with our current networking key
+    pub fn current_identity(&self) -> Identity {
+        use ring::signature::KeyPair;
+        Identity {
+            networking_key: format!("0x{}", hex::encode(self.keypair().public_key().as_ref())),
+            ..(*self.our).clone()
+        }
+    }
+}
+
+/// A networking key waiting for its `~net-key` note to show up onchain.
+pub struct PendingKeyRotation {
+    pub keypair: Arc<Ed25519KeyPair>,
+    /// pkcs8, as saved to disk
+    pub serialized_keypair: Vec<u8>,
+    pub info: KeyRotation,
 }
 
 #[derive(Clone)]
@@ -246,4 +282,6 @@ pub struct NetData {
     pub active_passthroughs: ActivePassthroughs,
     pub max_passthroughs: u64,
     pub fds_limit: u64,
+    /// only touched by the task handling kernel messages
+    pub key_rotation: Option<Arc<PendingKeyRotation>>,
 }
@@ -10,6 +10,7 @@ use lib::types::core::{
 use {
     futures::{SinkExt, StreamExt},
     ring::signature::{self},
+    serde::Deserialize,
     snow::params::NoiseParams,
     tokio::time,
     tokio_tungstenite::connect_async,
@@ -379,6 +380,29 @@ pub fn make_conn_url(our_ip: &str, ip: &str, port: &u16, protocol: &str) -> anyh
     }
 }
 
+/// Serialize a message to send to another node. A request's trace goes after the
+/// message rather than in it: peers decode the message as a fixed-length array and
+/// ignore what follows, so those that don't trace read it as usual, and the trace
+/// never mixes with metadata the receiving process sees.
+pub fn serialize_message(km: &KernelMessage) -> anyhow::Result<Vec<u8>> {
+    let mut serialized = rmp_serde::to_vec(km)?;
+    if let (Some(trace), Message::Request(_)) = (&km.trace, &km.message) {
+        serialized.extend(rmp_serde::to_vec(trace)?);
+    }
+    Ok(serialized)
+}
+
+/// Undo [`serialize_message`]. Untraced messages end with the message itself,
+/// so they cost no more to read than before traces went over the network.
+pub fn deserialize_message(bytes: &[u8]) -> anyhow::Result<KernelMessage> {
+    let mut rest = bytes;
+    let mut km = KernelMessage::deserialize(&mut rmp_serde::Deserializer::new(&mut rest))?;
+    if !rest.is_empty() && matches!(km.message, Message::Request(_)) {
+        km.trace = rmp_serde::from_slice(rest).ok();
+    }
+    Ok(km)
+}
+
 pub async fn error_offline(km: KernelMessage, network_error_tx: &NetworkErrorSender) {
     network_error_tx
         .send(WrappedSendError {
@@ -455,3 +479,57 @@ pub fn get_now() -> u64 {
         .as_secs();
     now
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib::types::core::TraceContext;
+
+    const TRACE: TraceContext = TraceContext {
+        trace_id: 0x0123_4567_89ab_cdef_0011_2233_4455_6677,
+        span_id: 0xfedc_ba98_7654_3210,
+    };
+
+    fn request(metadata: Option<&str>, trace: Option<TraceContext>) -> KernelMessage {
+        KernelMessage::builder()
+            .id(1)
+            .source(("our", "a", "test", "sys"))
+            .target(("their", "b", "test", "sys"))
+            .message(Message::Request(Request {
+                inherit: false,
+                expects_response: None,
+                body: vec![],
+                metadata: metadata.map(str::to_string),
+                capabilities: vec![],
+            }))
+            .trace(trace)
+            .build()
+            .unwrap()
+    }
+
+    fn metadata(km: &KernelMessage) -> Option<&str> {
+        match &km.message {
+            Message::Request(request) => request.metadata.as_deref(),
+            Message::Response((response, _)) => response.metadata.as_deref(),
+        }
+    }
+
+    #[test]
+    fn trace_goes_over_the_wire_outside_the_metadata() {
+        let serialized = serialize_message(&request(Some("no-revoke"), Some(TRACE))).unwrap();
+        let received = deserialize_message(&serialized).unwrap();
+        assert_eq!(received.trace, Some(TRACE));
+        assert_eq!(metadata(&received), Some("no-revoke"));
+    }
+
+    #[test]
+    fn peers_that_dont_trace_read_traced_messages() {
+        let serialized = serialize_message(&request(None, Some(TRACE))).unwrap();
+        let received: KernelMessage = rmp_serde::from_slice(&serialized).unwrap();
+        assert_eq!(received.trace, None);
+        assert_eq!(metadata(&received), None);
+
+        let untraced = rmp_serde::to_vec(&request(None, None)).unwrap();
+        assert_eq!(deserialize_message(&untraced).unwrap().trace, None);
+    }
+}
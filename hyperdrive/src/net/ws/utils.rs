@@ -1,6 +1,9 @@
 use crate::net::{
     types::{HandshakePayload, IdentityExt, Peers},
-    utils::{print_debug, print_loud, IDLE_TIMEOUT, MESSAGE_MAX_SIZE},
+    utils::{
+        deserialize_message, print_debug, print_loud, serialize_message, IDLE_TIMEOUT,
+        MESSAGE_MAX_SIZE,
+    },
     ws::{PeerConnection, WebSocket},
 };
 use lib::core::{
@@ -42,7 +45,7 @@ pub async fn maintain_connection(
             tokio::select! {
                 Some(km) = peer_rx.recv() => {
                     if let Err(e) =
-                        send_protocol_message(&km, &mut our_cipher, write_buf, &mut write_stream).await
+                        send_protocol_message(km, &mut our_cipher, write_buf, &mut write_stream).await
                     {
                         if e.to_string() == "message too large" {
                             // this will result in a Timeout if the message
@@ -130,12 +133,12 @@ pub async fn maintain_connection(
 }
 
 async fn send_protocol_message(
-    km: &KernelMessage,
+    km: KernelMessage,
     cipher: &mut snow::CipherState,
     buf: &mut [u8],
     stream: &mut WsWriteHalf,
 ) -> anyhow::Result<()> {
-    let serialized = rmp_serde::to_vec(km)?;
+    let serialized = serialize_message(&km)?;
     if serialized.len() > MESSAGE_MAX_SIZE as usize {
         return Err(anyhow::anyhow!("message too large"));
     }
@@ -180,7 +183,7 @@ async fn recv_protocol_message(
         msg.extend_from_slice(&buf[..len]);
     }
 
-    Ok(rmp_serde::from_slice(&msg)?)
+    deserialize_message(&msg)
 }
 
 pub async fn send_protocol_handshake(
@@ -194,7 +197,7 @@ pub async fn send_protocol_handshake(
     let our_hs = rmp_serde::to_vec(&HandshakePayload {
         protocol_version: 1,
         name: ext.our.name.clone(),
-        signature: ext.keypair.sign(noise_static_key).as_ref().to_vec(),
+        signature: ext.keypair().sign(noise_static_key).as_ref().to_vec(),
         proxy_request,
     })
     .expect("failed to serialize handshake payload");
@@ -4,6 +4,8 @@ use lib::types::core::{
     ProcessId, Request, Response, NOTIFICATIONS_PROCESS_ID,
 };
 use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use web_push::WebPushClient;
@@ -15,9 +17,30 @@ use web_push::{
 // Import our types from lib
 use lib::core::StateAction;
 use lib::notifications::{
-    NotificationsAction, NotificationsError, NotificationsResponse, PushSubscription,
+    ChannelConfig, DeliveryChannel, InboxEntry, NotificationPreferences, NotificationPriority,
+    NotificationsAction, NotificationsError, NotificationsResponse, PushSubscription, SmtpConfig,
+    SmtpSecurity, WebhookConfig,
 };
 
+/// how long an app must wait between pushes, unless the notification is urgent
+const PER_APP_PUSH_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(5);
+/// longest the queue processor sleeps before checking for newly queued notifications
+const QUEUE_POLL_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_millis(500);
+/// oldest inbox entries are dropped beyond this many
+const MAX_INBOX_LEN: usize = 200;
+
+lazy_static::lazy_static! {
+    static ref PREFERENCES_STATE_KEY: ProcessId =
+        ProcessId::new(Some("notifications-preferences"), "distro", "sys");
+    static ref INBOX_STATE_KEY: ProcessId =
+        ProcessId::new(Some("notifications-inbox"), "distro", "sys");
+    static ref CHANNELS_STATE_KEY: ProcessId =
+        ProcessId::new(Some("notifications-channels"), "distro", "sys");
+}
+
+/// how long to wait on a webhook or SMTP server before giving up on a delivery
+const CHANNEL_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
 /// VAPID keys for web push notifications
 #[derive(Serialize, Deserialize, Clone)]
 pub struct VapidKeys {
@@ -74,21 +97,37 @@ impl VapidKeys {
     }
 }
 
-use std::collections::VecDeque;
+/// The inbox as persisted. `next_id` is kept apart from the entries so that ids
+/// never repeat, even once the entries are cleared.
+#[derive(Serialize, Deserialize, Default)]
+struct Inbox {
+    next_id: u64,
+    /// newest entries at the back
+    entries: VecDeque<InboxEntry>,
+}
 
 #[derive(Clone)]
 pub struct QueuedNotification {
+    source: ProcessId,
     title: String,
     body: String,
     icon: Option<String>,
     data: Option<serde_json::Value>,
+    topic: Option<String>,
+    priority: NotificationPriority,
 }
 
 pub struct NotificationsState {
     vapid_keys: Option<VapidKeys>,
     subscriptions: Vec<PushSubscription>,
-    last_push_timestamp: Option<tokio::time::Instant>,
-    notification_queue: VecDeque<QueuedNotification>,
+    preferences: NotificationPreferences,
+    channels: ChannelConfig,
+    inbox: Inbox,
+    /// time of the last push from each app, for throttling
+    last_push: HashMap<ProcessId, tokio::time::Instant>,
+    /// ordered by priority, highest first, then by arrival
+    notification_queue: BTreeMap<(Reverse<NotificationPriority>, u64), QueuedNotification>,
+    next_queue_seq: u64,
     queue_processor_handle: Option<tokio::task::JoinHandle<()>>,
 }
 
@@ -110,8 +149,12 @@ pub async fn notifications(
     let state = Arc::new(RwLock::new(NotificationsState {
         vapid_keys: None,
         subscriptions: Vec::new(),
-        last_push_timestamp: None,
-        notification_queue: VecDeque::new(),
+        preferences: NotificationPreferences::default(),
+        channels: ChannelConfig::default(),
+        inbox: Inbox::default(),
+        last_push: HashMap::new(),
+        notification_queue: BTreeMap::new(),
+        next_queue_seq: 0,
         queue_processor_handle: None,
     }));
 
@@ -132,6 +175,45 @@ pub async fn notifications(
         &state,
     )
     .await;
+    if let Some(bytes) = load_from_state(
+        &our_node,
+        &mut recv_notifications,
+        &send_to_state,
+        &send_to_loop,
+        &PREFERENCES_STATE_KEY,
+    )
+    .await
+    {
+        if let Ok(preferences) = serde_json::from_slice(&bytes) {
+            state.write().await.preferences = preferences;
+        }
+    }
+    if let Some(bytes) = load_from_state(
+        &our_node,
+        &mut recv_notifications,
+        &send_to_state,
+        &send_to_loop,
+        &INBOX_STATE_KEY,
+    )
+    .await
+    {
+        if let Ok(inbox) = serde_json::from_slice(&bytes) {
+            state.write().await.inbox = inbox;
+        }
+    }
+    if let Some(bytes) = load_from_state(
+        &our_node,
+        &mut recv_notifications,
+        &send_to_state,
+        &send_to_loop,
+        &CHANNELS_STATE_KEY,
+    )
+    .await
+    {
+        if let Ok(channels) = serde_json::from_slice(&bytes) {
+            state.write().await.channels = channels;
+        }
+    }
     Printout::new(
         2,
         NOTIFICATIONS_PROCESS_ID.clone(),
@@ -345,6 +427,56 @@ async fn load_keys_from_state(
     }
 }
 
+/// Fetch the value stored under `key` in state:distro:sys, if any.
+async fn load_from_state(
+    our_node: &str,
+    recv_notifications: &mut MessageReceiver,
+    send_to_state: &MessageSender,
+    send_to_loop: &MessageSender,
+    key: &ProcessId,
+) -> Option<Vec<u8>> {
+    let request_id = rand::random::<u64>();
+
+    KernelMessage::builder()
+        .id(request_id)
+        .source((our_node, NOTIFICATIONS_PROCESS_ID.clone()))
+        .target((our_node, ProcessId::new(Some("state"), "distro", "sys")))
+        .message(Message::Request(Request {
+            inherit: false,
+            expects_response: Some(5),
+            body: serde_json::to_vec(&StateAction::GetState(key.clone())).unwrap(),
+            metadata: None,
+            capabilities: vec![],
+        }))
+        .build()
+        .unwrap()
+        .send(send_to_state)
+        .await;
+
+    let timeout = tokio::time::sleep(tokio::time::Duration::from_secs(5));
+    tokio::pin!(timeout);
+
+    loop {
+        tokio::select! {
+            _ = &mut timeout => return None,
+            Some(km) = recv_notifications.recv() => {
+                if km.id != request_id {
+                    // Not our response, put it back for main loop to handle
+                    km.send(send_to_loop).await;
+                    continue;
+                }
+                let Message::Response((response, _context)) = km.message else {
+                    return None;
+                };
+                return match serde_json::from_slice::<lib::core::StateResponse>(&response.body) {
+                    Ok(lib::core::StateResponse::GetState) => km.lazy_load_blob.map(|b| b.bytes),
+                    _ => None,
+                };
+            }
+        }
+    }
+}
+
 async fn handle_request(
     our_node: &str,
     km: KernelMessage,
@@ -454,15 +586,50 @@ async fn handle_request(
             body,
             icon,
             data,
+            topic,
+            priority,
         } => {
-            // Add notification to queue
             let mut state_guard = state.write().await;
 
-            // Check if we have keys and subscriptions
-            if state_guard.vapid_keys.is_none() {
+            // Web Push subscriptions need our keys
+            if state_guard.vapid_keys.is_none()
+                && state_guard
+                    .subscriptions
+                    .iter()
+                    .any(|s| s.channel == DeliveryChannel::WebPush)
+            {
                 return Err(NotificationsError::KeysNotInitialized);
             }
 
+            // Record in the inbox whether or not it will be pushed anywhere
+            let id = state_guard.inbox.next_id;
+            state_guard.inbox.next_id += 1;
+            state_guard.inbox.entries.push_back(InboxEntry {
+                id,
+                source: source.process.clone(),
+                title: title.clone(),
+                body: body.clone(),
+                icon: icon.clone(),
+                data: data.clone(),
+                topic: topic.clone(),
+                priority,
+                timestamp: std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap()
+                    .as_millis() as u64,
+                read: false,
+            });
+            while state_guard.inbox.entries.len() > MAX_INBOX_LEN {
+                state_guard.inbox.entries.pop_front();
+            }
+            save_to_state(
+                our_node,
+                send_to_state,
+                &INBOX_STATE_KEY,
+                &state_guard.inbox,
+            )
+            .await?;
+
             if state_guard.subscriptions.is_empty() {
                 Printout::new(
                     2,
@@ -474,18 +641,43 @@ async fn handle_request(
                 return Ok(());
             }
 
+            if let Some(app) = state_guard.preferences.apps.get(&source.process) {
+                if app.muted
+                    || topic
+                        .as_ref()
+                        .is_some_and(|topic| app.muted_topics.contains(topic))
+                {
+                    Printout::new(
+                        2,
+                        NOTIFICATIONS_PROCESS_ID.clone(),
+                        format!(
+                            "notifications: {} is muted, not pushing notification",
+                            source.process
+                        ),
+                    )
+                    .send(send_to_terminal)
+                    .await;
+                    return Ok(());
+                }
+            }
+
             // Create queued notification
             let queued_notification = QueuedNotification {
+                source: source.process.clone(),
                 title,
                 body,
                 icon,
                 data,
+                topic,
+                priority,
             };
 
             // Add to queue
+            let seq = state_guard.next_queue_seq;
+            state_guard.next_queue_seq += 1;
             state_guard
                 .notification_queue
-                .push_back(queued_notification);
+                .insert((Reverse(priority), seq), queued_notification);
             Printout::new(
                 2,
                 NOTIFICATIONS_PROCESS_ID.clone(),
@@ -536,6 +728,11 @@ async fn handle_request(
             NotificationsResponse::NotificationSent
         }
         NotificationsAction::AddSubscription { mut subscription } => {
+            if !manages_subscription(&source.process, &subscription) {
+                return Err(NotificationsError::Unauthorized {
+                    process: source.process,
+                });
+            }
             let mut state_guard = state.write().await;
 
             // Set created_at timestamp if not provided (for backward compatibility)
@@ -591,7 +788,8 @@ async fn handle_request(
                 }
             }
 
-            // Clean up old subscriptions (older than 1 month)
+            // Clean up old browser subscriptions (older than 1 month);
+            // webhooks and email addresses don't go stale the same way
             let now = std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
@@ -602,7 +800,7 @@ async fn handle_request(
             let mut removed_subscriptions = Vec::new();
             state_guard.subscriptions.retain(|s| {
                 let age = now.saturating_sub(s.created_at);
-                if age > one_month_ms {
+                if s.channel == DeliveryChannel::WebPush && age > one_month_ms {
                     removed_subscriptions.push((age, s.endpoint.clone()));
                     false
                 } else {
@@ -633,6 +831,15 @@ async fn handle_request(
         }
         NotificationsAction::RemoveSubscription { endpoint } => {
             let mut state_guard = state.write().await;
+            if state_guard
+                .subscriptions
+                .iter()
+                .any(|s| s.endpoint == endpoint && !manages_subscription(&source.process, s))
+            {
+                return Err(NotificationsError::Unauthorized {
+                    process: source.process,
+                });
+            }
             let initial_len = state_guard.subscriptions.len();
             state_guard.subscriptions.retain(|s| s.endpoint != endpoint);
 
@@ -664,11 +871,17 @@ async fn handle_request(
         }
         NotificationsAction::ClearSubscriptions => {
             let mut state_guard = state.write().await;
-            state_guard.subscriptions.clear();
+            // leave the subscriptions this process couldn't have added
+            state_guard
+                .subscriptions
+                .retain(|s| !manages_subscription(&source.process, s));
             Printout::new(
                 2,
                 NOTIFICATIONS_PROCESS_ID.clone(),
-                "notifications: Cleared all subscriptions".to_string(),
+                format!(
+                    "notifications: Cleared subscriptions, remaining: {}",
+                    state_guard.subscriptions.len()
+                ),
             )
             .send(send_to_terminal)
             .await;
@@ -689,6 +902,162 @@ async fn handle_request(
 
             NotificationsResponse::SubscriptionInfo(subscription)
         }
+        NotificationsAction::GetPreferences => {
+            let state_guard = state.read().await;
+            if manages_notifications(&source.process) {
+                NotificationsResponse::Preferences(state_guard.preferences.clone())
+            } else {
+                NotificationsResponse::Preferences(NotificationPreferences {
+                    apps: state_guard
+                        .preferences
+                        .apps
+                        .get(&source.process)
+                        .map(|preference| (source.process.clone(), preference.clone()))
+                        .into_iter()
+                        .collect(),
+                    devices: HashMap::new(),
+                })
+            }
+        }
+        NotificationsAction::SetAppPreference {
+            process,
+            preference,
+        } => {
+            if process != source.process && !manages_notifications(&source.process) {
+                return Err(NotificationsError::Unauthorized {
+                    process: source.process,
+                });
+            }
+            let mut state_guard = state.write().await;
+            match preference {
+                Some(preference) => state_guard.preferences.apps.insert(process, preference),
+                None => state_guard.preferences.apps.remove(&process),
+            };
+            save_to_state(
+                our_node,
+                send_to_state,
+                &PREFERENCES_STATE_KEY,
+                &state_guard.preferences,
+            )
+            .await?;
+            NotificationsResponse::PreferencesUpdated
+        }
+        NotificationsAction::SetDevicePreference {
+            endpoint,
+            preference,
+        } => {
+            if !manages_notifications(&source.process) {
+                return Err(NotificationsError::Unauthorized {
+                    process: source.process,
+                });
+            }
+            let mut state_guard = state.write().await;
+            match preference {
+                Some(preference) => state_guard.preferences.devices.insert(endpoint, preference),
+                None => state_guard.preferences.devices.remove(&endpoint),
+            };
+            save_to_state(
+                our_node,
+                send_to_state,
+                &PREFERENCES_STATE_KEY,
+                &state_guard.preferences,
+            )
+            .await?;
+            NotificationsResponse::PreferencesUpdated
+        }
+        NotificationsAction::GetInbox { since, limit } => {
+            let state_guard = state.read().await;
+            let all = manages_notifications(&source.process);
+            NotificationsResponse::Inbox(
+                state_guard
+                    .inbox
+                    .entries
+                    .iter()
+                    .rev()
+                    .take_while(|entry| since.is_none_or(|since| entry.id > since))
+                    .filter(|entry| all || entry.source == source.process)
+                    .take(limit.unwrap_or(usize::MAX))
+                    .cloned()
+                    .collect(),
+            )
+        }
+        NotificationsAction::MarkRead { ids } => {
+            let all = manages_notifications(&source.process);
+            let mut state_guard = state.write().await;
+            for entry in state_guard.inbox.entries.iter_mut() {
+                if ids.contains(&entry.id) && (all || entry.source == source.process) {
+                    entry.read = true;
+                }
+            }
+            save_to_state(
+                our_node,
+                send_to_state,
+                &INBOX_STATE_KEY,
+                &state_guard.inbox,
+            )
+            .await?;
+            NotificationsResponse::InboxUpdated
+        }
+        NotificationsAction::ClearInbox => {
+            let all = manages_notifications(&source.process);
+            let mut state_guard = state.write().await;
+            state_guard
+                .inbox
+                .entries
+                .retain(|entry| !all && entry.source != source.process);
+            save_to_state(
+                our_node,
+                send_to_state,
+                &INBOX_STATE_KEY,
+                &state_guard.inbox,
+            )
+            .await?;
+            NotificationsResponse::InboxUpdated
+        }
+        NotificationsAction::SetWebhookConfig(config) => {
+            if !configures_channels(&source.process) {
+                return Err(NotificationsError::Unauthorized {
+                    process: source.process,
+                });
+            }
+            let mut state_guard = state.write().await;
+            state_guard.channels.webhook = config;
+            save_to_state(
+                our_node,
+                send_to_state,
+                &CHANNELS_STATE_KEY,
+                &state_guard.channels,
+            )
+            .await?;
+            NotificationsResponse::ChannelConfigUpdated
+        }
+        NotificationsAction::SetSmtpConfig(config) => {
+            if !configures_channels(&source.process) {
+                return Err(NotificationsError::Unauthorized {
+                    process: source.process,
+                });
+            }
+            let mut state_guard = state.write().await;
+            state_guard.channels.smtp = config;
+            save_to_state(
+                our_node,
+                send_to_state,
+                &CHANNELS_STATE_KEY,
+                &state_guard.channels,
+            )
+            .await?;
+            NotificationsResponse::ChannelConfigUpdated
+        }
+        NotificationsAction::GetChannelConfig => {
+            let mut channels = state.read().await.channels.clone();
+            if let Some(webhook) = channels.webhook.as_mut() {
+                webhook.secret = String::new();
+            }
+            if let Some(smtp) = channels.smtp.as_mut() {
+                smtp.password = None;
+            }
+            NotificationsResponse::ChannelConfig(channels)
+        }
     };
 
     // Send response if expected
@@ -719,6 +1088,27 @@ async fn handle_request(
     Ok(())
 }
 
+/// Whether `process` may manage notifications on behalf of the user: read and
+/// clear every app's inbox entries and set any preference. Other apps may only
+/// touch their own.
+fn manages_notifications(process: &ProcessId) -> bool {
+    process.publisher() == "sys" && matches!(process.package(), "homepage" | "settings")
+}
+
+/// Whether `process` may configure the webhook and email channels and subscribe
+/// webhooks or email addresses. Only settings may: these send notifications, signed
+/// with our webhook secret, to any host it names.
+fn configures_channels(process: &ProcessId) -> bool {
+    process.publisher() == "sys" && process.package() == "settings"
+}
+
+/// Whether `process` may add or remove `subscription`: any process may manage web
+/// push subscriptions, but webhooks and emails go wherever the subscription says,
+/// with our signature, so only processes that configure channels may manage them.
+fn manages_subscription(process: &ProcessId, subscription: &PushSubscription) -> bool {
+    subscription.channel == DeliveryChannel::WebPush || configures_channels(process)
+}
+
 async fn save_keys_to_state(
     our_node: &str,
     send_to_state: &MessageSender,
@@ -790,6 +1180,40 @@ async fn save_subscriptions_to_state(
     Ok(())
 }
 
+/// Store `value` under `key` in state:distro:sys.
+async fn save_to_state<T: Serialize>(
+    our_node: &str,
+    send_to_state: &MessageSender,
+    key: &ProcessId,
+    value: &T,
+) -> Result<(), NotificationsError> {
+    let bytes = serde_json::to_vec(value).map_err(|e| NotificationsError::StateError {
+        error: format!("Failed to serialize {key}: {:?}", e),
+    })?;
+
+    KernelMessage::builder()
+        .id(rand::random())
+        .source((our_node, NOTIFICATIONS_PROCESS_ID.clone()))
+        .target((our_node, ProcessId::new(Some("state"), "distro", "sys")))
+        .message(Message::Request(Request {
+            inherit: false,
+            expects_response: None, // Don't expect a response to avoid polluting the main loop
+            body: serde_json::to_vec(&StateAction::SetState(key.clone())).unwrap(),
+            metadata: None,
+            capabilities: vec![],
+        }))
+        .lazy_load_blob(Some(LazyLoadBlob {
+            mime: Some("application/octet-stream".into()),
+            bytes,
+        }))
+        .build()
+        .unwrap()
+        .send(send_to_state)
+        .await;
+
+    Ok(())
+}
+
 async fn process_notification_queue(
     our_node: &str,
     send_to_terminal: &PrintSender,
@@ -797,37 +1221,65 @@ async fn process_notification_queue(
     state: &Arc<RwLock<NotificationsState>>,
 ) {
     loop {
-        // Check if we should process the next notification
-        let should_process = {
-            let state_guard = state.read().await;
-
-            // Check if enough time has passed since last push
-            match state_guard.last_push_timestamp {
-                None => true, // No previous push, can send immediately
-                Some(last_timestamp) => {
-                    let elapsed = tokio::time::Instant::now().duration_since(last_timestamp);
-                    elapsed >= tokio::time::Duration::from_secs(5)
+        // Take the highest-priority notification whose app is not throttled,
+        // or learn when the next one will be
+        let next = {
+            let mut state_guard = state.write().await;
+            if state_guard.notification_queue.is_empty() {
+                Printout::new(
+                    2,
+                    NOTIFICATIONS_PROCESS_ID.clone(),
+                    "notifications: Queue now empty, exiting processor".to_string(),
+                )
+                .send(send_to_terminal)
+                .await;
+                state_guard.queue_processor_handle = None;
+                return;
+            }
+            let now = tokio::time::Instant::now();
+            let mut next_ready: Option<tokio::time::Instant> = None;
+            let ready_key =
+                state_guard
+                    .notification_queue
+                    .iter()
+                    .find_map(|(key, notification)| {
+                        let ready_at = match state_guard.last_push.get(&notification.source) {
+                            Some(last) if notification.priority < NotificationPriority::Urgent => {
+                                *last + PER_APP_PUSH_INTERVAL
+                            }
+                            _ => now,
+                        };
+                        if ready_at <= now {
+                            return Some(*key);
+                        }
+                        next_ready = Some(next_ready.map_or(ready_at, |n| n.min(ready_at)));
+                        None
+                    });
+            match ready_key {
+                Some(key) => {
+                    let notification = state_guard.notification_queue.remove(&key).unwrap();
+                    state_guard
+                        .last_push
+                        .insert(notification.source.clone(), now);
+                    Ok(notification)
                 }
+                None => Err(next_ready.unwrap_or(now)),
             }
         };
 
-        if should_process {
-            // Process one notification from the queue
-            let notification = {
-                let mut state_guard = state.write().await;
-                state_guard.notification_queue.pop_front()
-            };
-
-            if let Some(notification) = notification {
+        match next {
+            Ok(notification) => {
                 Printout::new(
                     2,
                     NOTIFICATIONS_PROCESS_ID.clone(),
-                    "notifications: Processing notification from queue".to_string(),
+                    format!(
+                        "notifications: Processing {:?} notification from {}",
+                        notification.priority, notification.source
+                    ),
                 )
                 .send(send_to_terminal)
                 .await;
 
-                // Send the notification
                 if let Err(e) = send_notification_to_all(
                     our_node,
                     send_to_terminal,
@@ -845,54 +1297,11 @@ async fn process_notification_queue(
                     .send(send_to_terminal)
                     .await;
                 }
-
-                // Update timestamp and check if we should exit
-                let mut state_guard = state.write().await;
-                state_guard.last_push_timestamp = Some(tokio::time::Instant::now());
-
-                // Check if queue is now empty and clean up if so
-                if state_guard.notification_queue.is_empty() {
-                    Printout::new(
-                        2,
-                        NOTIFICATIONS_PROCESS_ID.clone(),
-                        "notifications: Queue now empty, exiting processor".to_string(),
-                    )
-                    .send(send_to_terminal)
-                    .await;
-                    state_guard.queue_processor_handle = None;
-                    return;
-                } else {
-                    Printout::new(
-                        2,
-                        NOTIFICATIONS_PROCESS_ID.clone(),
-                        format!(
-                            "notifications: {} more notifications in queue, waiting 5 seconds",
-                            state_guard.notification_queue.len()
-                        ),
-                    )
-                    .send(send_to_terminal)
-                    .await;
-                }
             }
-        } else {
-            // Wait until 5 seconds have passed since last push
-            let wait_duration = {
-                let state_guard = state.read().await;
-                match state_guard.last_push_timestamp {
-                    Some(last_timestamp) => {
-                        let elapsed = tokio::time::Instant::now().duration_since(last_timestamp);
-                        if elapsed < tokio::time::Duration::from_secs(5) {
-                            tokio::time::Duration::from_secs(5) - elapsed
-                        } else {
-                            tokio::time::Duration::from_secs(0)
-                        }
-                    }
-                    None => tokio::time::Duration::from_secs(0),
-                }
-            };
-
-            if wait_duration > tokio::time::Duration::from_secs(0) {
-                tokio::time::sleep(wait_duration).await;
+            Err(next_ready) => {
+                // wake early in case a more urgent notification arrives meanwhile
+                let poll_at = tokio::time::Instant::now() + QUEUE_POLL_INTERVAL;
+                tokio::time::sleep_until(next_ready.min(poll_at)).await;
             }
         }
     }
@@ -905,14 +1314,36 @@ async fn send_notification_to_all(
     state: &Arc<RwLock<NotificationsState>>,
     notification: QueuedNotification,
 ) -> Result<(), NotificationsError> {
-    let state_guard = state.read().await;
-
-    let keys = state_guard
-        .vapid_keys
-        .as_ref()
-        .ok_or(NotificationsError::KeysNotInitialized)?;
+    // take what sending needs and let go of the lock: deliveries can be slow, and
+    // must not hold up the requests that change subscriptions
+    let (subscription_count, subscriptions, vapid_keys, channels) = {
+        let state_guard = state.read().await;
+        let subscriptions: Vec<PushSubscription> = state_guard
+            .subscriptions
+            .iter()
+            .filter(|subscription| {
+                // skip devices that have muted this notification
+                state_guard
+                    .preferences
+                    .devices
+                    .get(&subscription.endpoint)
+                    .is_none_or(|device| {
+                        !device.muted
+                            && !device.muted_apps.contains(&notification.source)
+                            && notification.priority >= device.min_priority
+                    })
+            })
+            .cloned()
+            .collect();
+        (
+            state_guard.subscriptions.len(),
+            subscriptions,
+            state_guard.vapid_keys.clone(),
+            state_guard.channels.clone(),
+        )
+    };
 
-    if state_guard.subscriptions.is_empty() {
+    if subscription_count == 0 {
         Printout::new(
             2,
             NOTIFICATIONS_PROCESS_ID.clone(),
@@ -929,6 +1360,9 @@ async fn send_notification_to_all(
         "body": notification.body,
         "icon": notification.icon,
         "data": notification.data,
+        "source": notification.source,
+        "topic": notification.topic,
+        "priority": notification.priority,
     });
 
     Printout::new(
@@ -936,7 +1370,7 @@ async fn send_notification_to_all(
         NOTIFICATIONS_PROCESS_ID.clone(),
         format!(
             "notifications: Sending notification to {} devices",
-            state_guard.subscriptions.len()
+            subscription_count
         ),
     )
     .send(send_to_terminal)
@@ -947,89 +1381,37 @@ async fn send_notification_to_all(
     let mut send_count = 0;
     let mut invalid_endpoints = Vec::new();
 
-    for subscription in &state_guard.subscriptions {
-        // Create subscription info for web-push
-        let subscription_info = SubscriptionInfo::new(
-            &subscription.endpoint,
-            &subscription.keys.p256dh,
-            &subscription.keys.auth,
-        );
-
-        // Convert raw private key bytes to PEM format for web-push
-        let private_key_bytes = URL_SAFE_NO_PAD.decode(&keys.private_key).map_err(|e| {
-            NotificationsError::WebPushError {
-                error: format!("Failed to decode private key: {:?}", e),
-            }
-        })?;
-
-        // Convert Vec to fixed-size array
-        let private_key_array: [u8; 32] =
-            private_key_bytes
-                .try_into()
-                .map_err(|_| NotificationsError::WebPushError {
-                    error: "Invalid private key length".to_string(),
-                })?;
-
-        // Create PEM from raw bytes using p256
-        use p256::ecdsa::SigningKey;
-        use p256::pkcs8::EncodePrivateKey;
-
-        let signing_key = SigningKey::from_bytes(&private_key_array.into()).map_err(|e| {
-            NotificationsError::WebPushError {
-                error: format!("Failed to create signing key: {:?}", e),
-            }
-        })?;
-
-        let pem_content = signing_key
-            .to_pkcs8_pem(p256::pkcs8::LineEnding::LF)
-            .map_err(|e| NotificationsError::WebPushError {
-                error: format!("Failed to convert to PEM: {:?}", e),
-            })?
-            .to_string();
-
-        // Create VAPID signature from PEM
-        let mut sig_builder =
-            VapidSignatureBuilder::from_pem(pem_content.as_bytes(), &subscription_info).map_err(
-                |e| NotificationsError::WebPushError {
-                    error: format!("Failed to create VAPID signature: {:?}", e),
-                },
-            )?;
-
-        // Add required subject claim for VAPID
-        sig_builder.add_claim("sub", "mailto:admin@hyperware.ai");
-
-        let sig_builder = sig_builder
-            .build()
-            .map_err(|e| NotificationsError::WebPushError {
-                error: format!("Failed to build VAPID signature: {:?}", e),
-            })?;
-
-        // Build the web push message
-        let mut message_builder = WebPushMessageBuilder::new(&subscription_info);
-        let payload_str = payload.to_string();
-        message_builder.set_payload(ContentEncoding::Aes128Gcm, payload_str.as_bytes());
-        message_builder.set_vapid_signature(sig_builder);
-
-        let message = message_builder
-            .build()
-            .map_err(|e| NotificationsError::WebPushError {
-                error: format!("Failed to build message: {:?}", e),
-            })?;
-
-        // Send the notification using IsahcWebPushClient
-        let client = IsahcWebPushClient::new().map_err(|e| NotificationsError::WebPushError {
-            error: format!("Failed to create web push client: {:?}", e),
-        })?;
+    for subscription in &subscriptions {
+        let result = match subscription.channel {
+            DeliveryChannel::WebPush => match &vapid_keys {
+                Some(keys) => send_web_push(keys, subscription, &payload).await,
+                None => Err(NotificationsError::KeysNotInitialized),
+            },
+            DeliveryChannel::Webhook => match &channels.webhook {
+                Some(config) => send_webhook(config, subscription, &payload).await,
+                None => Err(NotificationsError::ChannelNotConfigured {
+                    channel: DeliveryChannel::Webhook,
+                }),
+            },
+            DeliveryChannel::Email => match &channels.smtp {
+                Some(config) => send_email(config, subscription, &notification).await,
+                None => Err(NotificationsError::ChannelNotConfigured {
+                    channel: DeliveryChannel::Email,
+                }),
+            },
+        };
 
-        match client.send(message).await {
-            Ok(_) => {
+        match result {
+            Ok(()) => {
                 send_count += 1;
             }
             Err(e) => {
                 let error_str = format!("{:?}", e);
 
                 // Check if this is an EndpointNotValid error
-                if error_str.contains("EndpointNotValid") || error_str.contains("410") {
+                if subscription.channel == DeliveryChannel::WebPush
+                    && (error_str.contains("EndpointNotValid") || error_str.contains("410"))
+                {
                     Printout::new(
                         0,
                         NOTIFICATIONS_PROCESS_ID.clone(),
@@ -1046,7 +1428,7 @@ async fn send_notification_to_all(
                         0,
                         NOTIFICATIONS_PROCESS_ID.clone(),
                         format!(
-                            "notifications: Failed to send to {}: {:?}",
+                            "notifications: Failed to send to {}: {}",
                             subscription.endpoint, e
                         ),
                     )
@@ -1058,9 +1440,6 @@ async fn send_notification_to_all(
         }
     }
 
-    // Drop the read guard before attempting to write
-    drop(state_guard);
-
     // Remove invalid endpoints if any were found
     if !invalid_endpoints.is_empty() {
         let mut state_guard = state.write().await;
@@ -1114,3 +1493,407 @@ async fn send_notification_to_all(
 
     Ok(())
 }
+
+async fn send_web_push(
+    keys: &VapidKeys,
+    subscription: &PushSubscription,
+    payload: &serde_json::Value,
+) -> Result<(), NotificationsError> {
+    // Create subscription info for web-push
+    let subscription_info = SubscriptionInfo::new(
+        &subscription.endpoint,
+        &subscription.keys.p256dh,
+        &subscription.keys.auth,
+    );
+
+    // Convert raw private key bytes to PEM format for web-push
+    let private_key_bytes = URL_SAFE_NO_PAD.decode(&keys.private_key).map_err(|e| {
+        NotificationsError::WebPushError {
+            error: format!("Failed to decode private key: {:?}", e),
+        }
+    })?;
+
+    // Convert Vec to fixed-size array
+    let private_key_array: [u8; 32] =
+        private_key_bytes
+            .try_into()
+            .map_err(|_| NotificationsError::WebPushError {
+                error: "Invalid private key length".to_string(),
+            })?;
+
+    // Create PEM from raw bytes using p256
+    use p256::ecdsa::SigningKey;
+    use p256::pkcs8::EncodePrivateKey;
+
+    let signing_key = SigningKey::from_bytes(&private_key_array.into()).map_err(|e| {
+        NotificationsError::WebPushError {
+            error: format!("Failed to create signing key: {:?}", e),
+        }
+    })?;
+
+    let pem_content = signing_key
+        .to_pkcs8_pem(p256::pkcs8::LineEnding::LF)
+        .map_err(|e| NotificationsError::WebPushError {
+            error: format!("Failed to convert to PEM: {:?}", e),
+        })?
+        .to_string();
+
+    // Create VAPID signature from PEM
+    let mut sig_builder =
+        VapidSignatureBuilder::from_pem(pem_content.as_bytes(), &subscription_info).map_err(
+            |e| NotificationsError::WebPushError {
+                error: format!("Failed to create VAPID signature: {:?}", e),
+            },
+        )?;
+
+    // Add required subject claim for VAPID
+    sig_builder.add_claim("sub", "mailto:admin@hyperware.ai");
+
+    let sig_builder = sig_builder
+        .build()
+        .map_err(|e| NotificationsError::WebPushError {
+            error: format!("Failed to build VAPID signature: {:?}", e),
+        })?;
+
+    // Build the web push message
+    let mut message_builder = WebPushMessageBuilder::new(&subscription_info);
+    let payload_str = payload.to_string();
+    message_builder.set_payload(ContentEncoding::Aes128Gcm, payload_str.as_bytes());
+    message_builder.set_vapid_signature(sig_builder);
+
+    let message = message_builder
+        .build()
+        .map_err(|e| NotificationsError::WebPushError {
+            error: format!("Failed to build message: {:?}", e),
+        })?;
+
+    // Send the notification using IsahcWebPushClient
+    let client = IsahcWebPushClient::new().map_err(|e| NotificationsError::WebPushError {
+        error: format!("Failed to create web push client: {:?}", e),
+    })?;
+
+    client
+        .send(message)
+        .await
+        .map_err(|e| NotificationsError::WebPushError {
+            error: format!("{:?}", e),
+        })
+}
+
+/// POST the payload to the subscription's URL, signed as described on [`WebhookConfig`].
+async fn send_webhook(
+    config: &WebhookConfig,
+    subscription: &PushSubscription,
+    payload: &serde_json::Value,
+) -> Result<(), NotificationsError> {
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    let body = payload.to_string();
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let mut mac = Hmac::<Sha256>::new_from_slice(config.secret.as_bytes()).map_err(|e| {
+        NotificationsError::WebhookError {
+            error: format!("bad secret: {e}"),
+        }
+    })?;
+    mac.update(format!("{timestamp}.{body}").as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+
+    let response = reqwest::Client::new()
+        .post(&subscription.endpoint)
+        .timeout(CHANNEL_TIMEOUT)
+        .header("Content-Type", "application/json")
+        .header("X-Hyperware-Timestamp", timestamp.to_string())
+        .header("X-Hyperware-Signature", format!("sha256={signature}"))
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| NotificationsError::WebhookError {
+            error: e.to_string(),
+        })?;
+    if !response.status().is_success() {
+        return Err(NotificationsError::WebhookError {
+            error: format!("{} returned {}", subscription.endpoint, response.status()),
+        });
+    }
+    Ok(())
+}
+
+/// Email the notification to the subscription's address through the configured SMTP server.
+async fn send_email(
+    config: &SmtpConfig,
+    subscription: &PushSubscription,
+    notification: &QueuedNotification,
+) -> Result<(), NotificationsError> {
+    use lettre::transport::smtp::authentication::Credentials;
+    use lettre::{AsyncSmtpTransport, AsyncTransport, Message as Email, Tokio1Executor};
+
+    let to_error = |e: String| NotificationsError::EmailError { error: e };
+
+    let email = Email::builder()
+        .from(
+            config
+                .from
+                .parse()
+                .map_err(|e| to_error(format!("bad from address: {e}")))?,
+        )
+        .to(subscription
+            .endpoint
+            .parse()
+            .map_err(|e| to_error(format!("bad to address: {e}")))?)
+        .subject(&notification.title)
+        .body(format!(
+            "{}\n\n-- sent by {}",
+            notification.body, notification.source
+        ))
+        .map_err(|e| to_error(e.to_string()))?;
+
+    let builder = match config.security {
+        SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+        SmtpSecurity::StartTls => {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)
+                .map_err(|e| to_error(e.to_string()))?
+        }
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)
+            .map_err(|e| to_error(e.to_string()))?,
+    };
+    let mut builder = builder.port(config.port).timeout(Some(CHANNEL_TIMEOUT));
+    if let (Some(username), Some(password)) = (&config.username, &config.password) {
+        builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+
+    builder
+        .build()
+        .send(email)
+        .await
+        .map_err(|e| to_error(e.to_string()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    fn subscription(channel: DeliveryChannel, endpoint: String) -> PushSubscription {
+        PushSubscription {
+            endpoint,
+            keys: Default::default(),
+            created_at: 0,
+            channel,
+        }
+    }
+
+    /// Answer one HTTP request with `status`, returning the request's head and body.
+    async fn serve_one_http(listener: TcpListener, status: &str) -> (String, String) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        let mut head = String::new();
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await.unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            head.push_str(&line);
+        }
+        let length: usize = head
+            .lines()
+            .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name.eq_ignore_ascii_case("content-length")
+                    .then(|| value.trim().parse().unwrap())
+            })
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await.unwrap();
+        stream
+            .write_all(format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+        (head, String::from_utf8(body).unwrap())
+    }
+
+    fn header<'a>(head: &'a str, name: &str) -> &'a str {
+        head.lines()
+            .find_map(|line| {
+                let (n, value) = line.split_once(':')?;
+                n.eq_ignore_ascii_case(name).then(|| value.trim())
+            })
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn webhook_is_signed_with_the_secret() {
+        use hmac::{Hmac, Mac};
+        use sha2::Sha256;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/hook", listener.local_addr().unwrap());
+        let server = tokio::spawn(serve_one_http(listener, "200 OK"));
+
+        let config = WebhookConfig {
+            secret: "shh".to_string(),
+        };
+        let payload = serde_json::json!({ "title": "hi" });
+        send_webhook(
+            &config,
+            &subscription(DeliveryChannel::Webhook, endpoint),
+            &payload,
+        )
+        .await
+        .unwrap();
+
+        let (head, body) = server.await.unwrap();
+        assert!(head.starts_with("POST /hook "));
+        assert_eq!(body, payload.to_string());
+        let timestamp = header(&head, "X-Hyperware-Timestamp");
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"shh").unwrap();
+        mac.update(format!("{timestamp}.{body}").as_bytes());
+        assert_eq!(
+            header(&head, "X-Hyperware-Signature"),
+            format!("sha256={}", hex::encode(mac.finalize().into_bytes())),
+        );
+    }
+
+    #[tokio::test]
+    async fn webhook_error_status_fails_delivery() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(serve_one_http(listener, "500 Internal Server Error"));
+
+        let result = send_webhook(
+            &WebhookConfig {
+                secret: "shh".to_string(),
+            },
+            &subscription(DeliveryChannel::Webhook, endpoint),
+            &serde_json::json!({}),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(NotificationsError::WebhookError { .. })
+        ));
+    }
+
+    /// Accept one plaintext SMTP session, returning the envelope commands and message.
+    async fn serve_one_smtp(listener: TcpListener) -> (Vec<String>, String) {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut stream = BufReader::new(stream);
+        stream.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+        let mut commands = vec![];
+        let mut message = String::new();
+        loop {
+            let mut line = String::new();
+            if stream.read_line(&mut line).await.unwrap() == 0 {
+                break;
+            }
+            let command = line.trim_end().to_string();
+            let reply: &[u8] = match command
+                .split(' ')
+                .next()
+                .unwrap()
+                .to_ascii_uppercase()
+                .as_str()
+            {
+                "EHLO" | "HELO" => b"250 localhost\r\n",
+                "DATA" => {
+                    stream.write_all(b"354 go ahead\r\n").await.unwrap();
+                    loop {
+                        let mut line = String::new();
+                        stream.read_line(&mut line).await.unwrap();
+                        if line == ".\r\n" {
+                            break;
+                        }
+                        message.push_str(&line);
+                    }
+                    b"250 OK\r\n"
+                }
+                "QUIT" => {
+                    stream.write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                }
+                _ => b"250 OK\r\n",
+            };
+            commands.push(command);
+            stream.write_all(reply).await.unwrap();
+        }
+        (commands, message)
+    }
+
+    #[tokio::test]
+    async fn email_is_sent_through_the_smtp_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(serve_one_smtp(listener));
+
+        let config = SmtpConfig {
+            host: "127.0.0.1".to_string(),
+            port,
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: "node@example.com".to_string(),
+        };
+        let notification = QueuedNotification {
+            source: ProcessId::new(Some("chat"), "chat", "sys"),
+            title: "new message".to_string(),
+            body: "hello there".to_string(),
+            icon: None,
+            data: None,
+            topic: None,
+            priority: NotificationPriority::Normal,
+        };
+        send_email(
+            &config,
+            &subscription(DeliveryChannel::Email, "user@example.com".to_string()),
+            &notification,
+        )
+        .await
+        .unwrap();
+
+        let (commands, message) = server.await.unwrap();
+        assert!(commands.contains(&"MAIL FROM:<node@example.com>".to_string()));
+        assert!(commands.contains(&"RCPT TO:<user@example.com>".to_string()));
+        assert!(message.contains("Subject: new message"));
+        assert!(message.contains("hello there"));
+        assert!(message.contains("sent by chat:chat:sys"));
+    }
+
+    #[test]
+    fn only_settings_configures_channels() {
+        assert!(configures_channels(&ProcessId::new(
+            Some("settings"),
+            "settings",
+            "sys"
+        )));
+        assert!(!configures_channels(&ProcessId::new(
+            Some("homepage"),
+            "homepage",
+            "sys"
+        )));
+        assert!(!configures_channels(&ProcessId::new(
+            Some("settings"),
+            "settings",
+            "evil.os"
+        )));
+    }
+
+    #[test]
+    fn only_settings_manages_webhook_and_email_subscriptions() {
+        let settings = ProcessId::new(Some("settings"), "settings", "sys");
+        let homepage = ProcessId::new(Some("homepage"), "homepage", "sys");
+        let web_push = subscription(DeliveryChannel::WebPush, "https://push".into());
+        for channel in [DeliveryChannel::Webhook, DeliveryChannel::Email] {
+            let subscription = subscription(channel, "somewhere".into());
+            assert!(manages_subscription(&settings, &subscription));
+            assert!(!manages_subscription(&homepage, &subscription));
+        }
+        assert!(manages_subscription(&settings, &web_push));
+        assert!(manages_subscription(&homepage, &web_push));
+    }
+}
@@ -55,6 +55,7 @@ pub async fn register(
     tcp_networking: (Option<&tokio::net::TcpListener>, bool),
     http_port: u16,
     keyfile: Option<Vec<u8>>,
+    key_rotation_files: keygen::KeyRotationFiles,
     eth_provider_config: lib::eth::SavedConfigs,
     detached: bool,
     initial_cache_sources: Option<Vec<String>>,
@@ -109,6 +110,7 @@ pub async fn register(
     let providers = Arc::new(connect_to_providers(&eth_provider_config).await);
 
     let keyfile = warp::any().map(move || keyfile.clone());
+    let key_rotation_files = warp::any().map(move || key_rotation_files.clone());
     let our_temp_id = warp::any().map(move || our_temp_id.clone());
     let net_keypair = warp::any().map(move || net_keypair.clone());
     let tx = warp::any().map(move || tx.clone());
@@ -234,18 +236,22 @@ pub async fn register(
                 .and(tcp_port.clone())
                 .and(tx.clone())
                 .and(keyfile.clone())
-                .and_then(move |boot_info, ip, ws_port, tcp_port, tx, keyfile| {
-                    let login_providers = login_providers.clone();
-                    handle_login(
-                        boot_info,
-                        ip,
-                        ws_port,
-                        tcp_port,
-                        tx,
-                        keyfile,
-                        login_providers,
-                    )
-                }),
+                .and(key_rotation_files)
+                .and_then(
+                    move |boot_info, ip, ws_port, tcp_port, tx, keyfile, key_rotation_files| {
+                        let login_providers = login_providers.clone();
+                        handle_login(
+                            boot_info,
+                            ip,
+                            ws_port,
+                            tcp_port,
+                            tx,
+                            keyfile,
+                            key_rotation_files,
+                            login_providers,
+                        )
+                    },
+                ),
         ));
 
     let mut headers = HeaderMap::new();
@@ -657,6 +663,7 @@ async fn handle_login(
     tcp_networking_port: (u16, bool),
     sender: Arc<RegistrationSender>,
     encoded_keyfile: Option<Vec<u8>>,
+    key_rotation_files: keygen::KeyRotationFiles,
     providers: Arc<Vec<RootProvider<PubSubFrontend>>>,
 ) -> Result<impl Reply, Rejection> {
     if encoded_keyfile.is_none() {
@@ -666,11 +673,22 @@ async fn handle_login(
         )
         .into_response());
     }
-    let encoded_keyfile = encoded_keyfile.unwrap();
+    let mut encoded_keyfile = encoded_keyfile.unwrap();
 
-    let (decoded_keyfile, mut our) =
+    let (mut decoded_keyfile, mut our) =
         match keygen::decode_keyfile(&encoded_keyfile, &info.password_hash) {
-            Ok(k) => {
+            Ok(mut k) => {
+                // fold in the networking key of a rotation that completed at runtime
+                if let Some(rotated_key) = key_rotation_files.rotated.as_deref() {
+                    if let Err(e) = keygen::apply_rotated_networking_key(
+                        &mut encoded_keyfile,
+                        &mut k,
+                        &info.password_hash,
+                        rotated_key,
+                    ) {
+                        println!("ignoring rotated networking key: {e}\r");
+                    }
+                }
                 let our = Identity {
                     name: k.username.clone(),
                     networking_key: format!(
@@ -722,8 +740,12 @@ async fn handle_login(
             Vec::new()
         };
 
-    if let Err(e) = assign_routing(
+    if let Err(e) = assign_routing_after_rotation(
         &mut our,
+        &mut encoded_keyfile,
+        &mut decoded_keyfile,
+        &info.password_hash,
+        key_rotation_files.pending.as_deref(),
         &providers,
         ws_networking_port,
         tcp_networking_port,
@@ -747,6 +769,66 @@ async fn handle_login(
     .await
 }
 
+/// The networking key published onchain is not the one in our keyfile.
+#[derive(Debug)]
+pub struct NetworkingKeyMismatch {
+    pub onchain: String,
+    pub saved: String,
+}
+
+impl std::fmt::Display for NetworkingKeyMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Networking key from PKI ({}) does not match our saved networking key ({})",
+            self.onchain, self.saved
+        )
+    }
+}
+
+impl std::error::Error for NetworkingKeyMismatch {}
+
+/// [`assign_routing`], first adopting the networking key of a rotation that was
+/// published onchain while we were offline, if that is why our saved key is stale.
+pub async fn assign_routing_after_rotation(
+    our: &mut Identity,
+    encoded_keyfile: &mut Vec<u8>,
+    decoded_keyfile: &mut Keyfile,
+    password_hash: &str,
+    pending_networking_key: Option<&[u8]>,
+    providers: &[RootProvider<PubSubFrontend>],
+    ws_networking_port: (u16, bool),
+    tcp_networking_port: (u16, bool),
+) -> anyhow::Result<()> {
+    let Err(e) = assign_routing(our, providers, ws_networking_port, tcp_networking_port).await
+    else {
+        return Ok(());
+    };
+    let (Some(mismatch), Some(pending_networking_key)) = (
+        e.downcast_ref::<NetworkingKeyMismatch>(),
+        pending_networking_key,
+    ) else {
+        return Err(e);
+    };
+    let onchain = mismatch.onchain.clone();
+    match keygen::apply_pending_networking_key(
+        encoded_keyfile,
+        decoded_keyfile,
+        password_hash,
+        pending_networking_key,
+        &onchain,
+    ) {
+        Ok(true) => {}
+        Ok(false) => return Err(e),
+        Err(pending_error) => {
+            return Err(e.context(format!("ignored pending networking key: {pending_error}")))
+        }
+    }
+    println!("Adopted networking key {onchain}, published onchain while we were offline\r");
+    our.networking_key = onchain;
+    assign_routing(our, providers, ws_networking_port, tcp_networking_port).await
+}
+
 pub async fn assign_routing(
     our: &mut Identity,
     providers: &[RootProvider<PubSubFrontend>],
@@ -841,11 +923,11 @@ pub async fn assign_routing(
         }
 
         if netkey.data.to_string() != our.networking_key {
-            return Err(anyhow::anyhow!(
-                "Networking key from PKI ({}) does not match our saved networking key ({})",
-                netkey.data.to_string(),
-                our.networking_key
-            ));
+            return Err(NetworkingKeyMismatch {
+                onchain: netkey.data.to_string(),
+                saved: our.networking_key.clone(),
+            }
+            .into());
         }
 
         // Successfully validated netkey, now decode the rest
@@ -537,6 +537,14 @@ async fn handle_fd_request(km: KernelMessage, state: &mut SqliteState) -> anyhow
                     .await;
             }
         }
+        FdManagerRequest::GetFdsUsage => {
+            crate::fd_manager::send_fd_manager_fds_usage(
+                &state.our,
+                state.open_dbs.len() as u64,
+                &state.send_to_loop,
+            )
+            .await;
+        }
         _ => {
             return Err(anyhow::anyhow!("non-Cull FdManagerRequest"));
         }
@@ -565,6 +573,8 @@ async fn add_capability(
         .send(CapMessage::Add {
             on: source.process.clone(),
             caps: vec![cap],
+            actor: our.process.clone(),
+            reason: "sqlite database opened",
             responder: Some(send_cap_bool),
         })
         .await
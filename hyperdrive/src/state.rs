@@ -1,9 +1,10 @@
 use lib::types::core::{
-    check_process_id_hypermap_safe, Address, Capability, Erc721Metadata, KernelMessage,
-    LazyLoadBlob, Message, MessageReceiver, MessageSender, NetworkErrorSender, OnExit,
-    PackageManifestEntry, PersistedProcess, PrintSender, Printout, ProcessId, ProcessMap, Request,
-    Response, ReverseCapIndex, StateAction, StateError, StateResponse, KERNEL_PROCESS_ID,
-    STATE_PROCESS_ID, VFS_PROCESS_ID,
+    check_process_id_hypermap_safe, Address, CapAuditEntry, Capability, Erc721Metadata,
+    KernelMessage, LazyLoadBlob, LegacyProcessMap, Message, MessageReceiver, MessageSender,
+    NetworkErrorSender, OnExit, PackageId, PackageManifestEntry, PersistedProcess, PrintSender,
+    Printout, ProcessId, ProcessMap, Request, Response, ReverseCapIndex, StateAction, StateError,
+    StateResponse, SupervisionGroup, WasiPolicy, KERNEL_PROCESS_ID, STATE_PROCESS_ID,
+    VFS_PROCESS_ID,
 };
 use ring::signature;
 use rocksdb::{checkpoint::Checkpoint, Options, DB};
@@ -44,8 +45,15 @@ pub async fn load_state(
     let kernel_id_vec = process_to_vec(KERNEL_PROCESS_ID.clone());
     match db.get(&kernel_id_vec) {
         Ok(Some(value)) => {
-            process_map = bincode::deserialize::<ProcessMap>(&value)
-                .expect("failed to deserialize kernel process map");
+            process_map = match bincode::deserialize::<ProcessMap>(&value) {
+                Ok(process_map) => process_map,
+                // saved before processes had WASI policies
+                Err(_) => bincode::deserialize::<LegacyProcessMap>(&value)
+                    .expect("failed to deserialize kernel process map")
+                    .into_iter()
+                    .map(|(id, process)| (id, process.into()))
+                    .collect(),
+            };
             // if our networking key changed, we need to re-sign all local caps
             process_map.iter_mut().for_each(|(_id, process)| {
                 process.capabilities.iter_mut().for_each(|(cap, sig)| {
@@ -377,40 +385,58 @@ async fn bootstrap(
 
     // finally, save runtime modules in state map as well, somewhat fakely
     // special cases for kernel and net
+    // the capabilities newly granted, for the audit log
+    let mut granted: Vec<CapAuditEntry> = vec![];
+    let kernel_id = ProcessId::new(Some("kernel"), "distro", "sys");
     let current_kernel = process_map
-        .entry(ProcessId::new(Some("kernel"), "distro", "sys"))
+        .entry(kernel_id.clone())
         .or_insert(PersistedProcess {
             wasm_bytes_handle: "".into(),
             wit_version: Some(crate::kernel::LATEST_WIT_VERSION),
             on_exit: OnExit::Restart,
-            capabilities: runtime_caps.clone(),
+            capabilities: HashMap::new(),
             public: false,
+            wasi: WasiPolicy::default(),
         });
-    current_kernel.capabilities.extend(runtime_caps.clone());
+    grant_caps(
+        current_kernel,
+        &kernel_id,
+        runtime_caps.clone(),
+        &mut granted,
+    );
+    let net_id = ProcessId::new(Some("net"), "distro", "sys");
     let current_net = process_map
-        .entry(ProcessId::new(Some("net"), "distro", "sys"))
+        .entry(net_id.clone())
         .or_insert(PersistedProcess {
             wasm_bytes_handle: "".into(),
             wit_version: Some(crate::kernel::LATEST_WIT_VERSION),
             on_exit: OnExit::Restart,
-            capabilities: runtime_caps.clone(),
+            capabilities: HashMap::new(),
             public: false,
+            wasi: WasiPolicy::default(),
         });
-    current_net.capabilities.extend(runtime_caps.clone());
+    grant_caps(current_net, &net_id, runtime_caps.clone(), &mut granted);
     for runtime_module in runtime_extensions {
         let current = process_map
-            .entry(runtime_module.0)
+            .entry(runtime_module.0.clone())
             .or_insert(PersistedProcess {
                 wasm_bytes_handle: "".into(),
                 wit_version: Some(crate::kernel::LATEST_WIT_VERSION),
                 on_exit: OnExit::Restart,
-                capabilities: runtime_caps.clone(),
+                capabilities: HashMap::new(),
                 public: runtime_module.3,
+                wasi: WasiPolicy::default(),
             });
-        current.capabilities.extend(runtime_caps.clone());
+        grant_caps(
+            current,
+            &runtime_module.0,
+            runtime_caps.clone(),
+            &mut granted,
+        );
     }
 
     let packages = get_zipped_packages();
+    let mut supervision_groups: Vec<SupervisionGroup> = vec![];
 
     for (package_metadata, mut package) in packages.clone() {
         let package_name = package_metadata.properties.package_name.as_str();
@@ -512,6 +538,13 @@ async fn bootstrap(
         let package_manifest = String::from_utf8(manifest_content)?;
         let package_manifest = serde_json::from_str::<Vec<PackageManifestEntry>>(&package_manifest)
             .expect("fs: manifest parse error");
+        match SupervisionGroup::from_manifest(
+            &PackageId::new(package_name, package_publisher),
+            &package_manifest,
+        ) {
+            Ok(groups) => supervision_groups.extend(groups),
+            Err(e) => println!("fs: {package_name}: supervision groups not set: {e}"),
+        }
 
         for mut entry in package_manifest {
             let wasm_bytes = &mut Vec::new();
@@ -611,27 +644,28 @@ async fn bootstrap(
 
             let wasm_bytes_handle = format!("{}/{}", &drive_path, &file_path);
 
-            match process_map.entry(ProcessId::new(
-                Some(&entry.process_name),
-                package_name,
-                package_publisher,
-            )) {
+            let process_id =
+                ProcessId::new(Some(&entry.process_name), package_name, package_publisher);
+            match process_map.entry(process_id.clone()) {
                 std::collections::hash_map::Entry::Occupied(p) => {
                     let p = p.into_mut();
                     p.wasm_bytes_handle = wasm_bytes_handle.clone();
                     p.wit_version = package_metadata.properties.wit_version;
                     p.on_exit = entry.on_exit;
-                    p.capabilities.extend(requested_caps);
+                    grant_caps(p, &process_id, requested_caps, &mut granted);
                     p.public = public_process;
+                    p.wasi = entry.wasi;
                 }
                 std::collections::hash_map::Entry::Vacant(v) => {
-                    v.insert(PersistedProcess {
+                    let p = v.insert(PersistedProcess {
                         wasm_bytes_handle: wasm_bytes_handle.clone(),
                         wit_version: package_metadata.properties.wit_version,
                         on_exit: entry.on_exit,
-                        capabilities: requested_caps,
+                        capabilities: HashMap::new(),
                         public: public_process,
+                        wasi: entry.wasi,
                     });
+                    grant_caps(p, &process_id, requested_caps, &mut granted);
                 }
             }
         }
@@ -684,9 +718,14 @@ async fn bootstrap(
                                     },
                                     params: "\"messaging\"".into(),
                                 };
-                                process
-                                    .capabilities
-                                    .insert(cap.clone(), sign_cap(cap.clone(), keypair.clone()));
+                                let sig = sign_cap(cap.clone(), keypair.clone());
+                                if process.capabilities.insert(cap.clone(), sig).is_none() {
+                                    granted.push(crate::kernel::cap_audit::bootstrap_entry(
+                                        &cap.issuer.process,
+                                        &parsed_process_id,
+                                        &cap,
+                                    ));
+                                }
                                 reverse_cap_index
                                     .entry(cap.clone().issuer.process)
                                     .or_insert_with(HashMap::new)
@@ -710,10 +749,16 @@ async fn bootstrap(
                                             },
                                             params: params.to_string(),
                                         };
-                                        process.capabilities.insert(
-                                            cap.clone(),
-                                            sign_cap(cap.clone(), keypair.clone()),
-                                        );
+                                        let sig = sign_cap(cap.clone(), keypair.clone());
+                                        if process.capabilities.insert(cap.clone(), sig).is_none() {
+                                            granted.push(
+                                                crate::kernel::cap_audit::bootstrap_entry(
+                                                    &cap.issuer.process,
+                                                    &parsed_process_id,
+                                                    &cap,
+                                                ),
+                                            );
+                                        }
                                         reverse_cap_index
                                             .entry(cap.clone().issuer.process)
                                             .or_insert_with(HashMap::new)
@@ -732,9 +777,31 @@ async fn bootstrap(
             }
         }
     }
+    crate::kernel::supervision::save_bootstrapped_groups(&home_directory_path, supervision_groups)
+        .await?;
+    crate::kernel::cap_audit::record_bootstrap(&home_directory_path, granted).await?;
     Ok(())
 }
 
+/// Add `caps` to `process`, noting those it didn't already hold in `granted`.
+fn grant_caps(
+    process: &mut PersistedProcess,
+    process_id: &ProcessId,
+    caps: HashMap<Capability, Vec<u8>>,
+    granted: &mut Vec<CapAuditEntry>,
+) {
+    for (cap, sig) in caps {
+        if !process.capabilities.contains_key(&cap) {
+            granted.push(crate::kernel::cap_audit::bootstrap_entry(
+                &KERNEL_PROCESS_ID,
+                process_id,
+                &cap,
+            ));
+        }
+        process.capabilities.insert(cap, sig);
+    }
+}
+
 fn sign_cap(cap: Capability, keypair: Arc<signature::Ed25519KeyPair>) -> Vec<u8> {
     keypair
         .sign(&rmp_serde::to_vec(&cap).unwrap())
@@ -8,9 +8,11 @@ use crossterm::{
 };
 use futures::{future::FutureExt, StreamExt};
 use lib::types::core::{
-    DebugCommand, DebugSender, Identity, KernelMessage, Message, MessageSender, PrintReceiver,
-    PrintSender, Printout, ProcessId, ProcessVerbosity, ProcessVerbosityVal, Request, VfsAction,
-    VfsRequest, TERMINAL_PROCESS_ID,
+    CompleteRequest, DebugCommand, DebugSender, Identity, KernelMessage, Message, MessageReceiver,
+    MessageSender, PrintReceiver, PrintSender, Printout, ProcessId, ProcessVerbosity,
+    ProcessVerbosityVal, Request, Response, RuntimeTerminalRequest, RuntimeTerminalResponse,
+    TerminalRequest, TerminalResponse, VfsAction, VfsRequest, HTTP_SERVER_PROCESS_ID,
+    RUNTIME_TERMINAL_PROCESS_ID, TERMINAL_PROCESS_ID,
 };
 use std::{
     collections::{HashMap, HashSet, VecDeque},
@@ -22,6 +24,7 @@ use std::{
 use tokio::signal::unix::{signal, SignalKind};
 use unicode_segmentation::UnicodeSegmentation;
 
+mod remote;
 pub mod utils;
 
 // TODO: add a flag & `terminal::terminal()` arg so can be set at run time
@@ -61,6 +64,11 @@ struct State {
     pub printout_queue_number_dropped_printouts: u64,
     /// request ids suppressed at verbosity 3 so their responses can be dropped too
     pub suppressed_event_loop_ids: HashSet<u64>,
+    /// id of the outstanding tab completion request, with the line and cursor position
+    /// it was made for: the response is dropped if either has changed since
+    pub pending_completion: Option<(u64, String, usize)>,
+    /// clients of the terminal served over WebSocket
+    pub remote: remote::Remote,
 }
 
 impl State {
@@ -318,9 +326,12 @@ pub async fn terminal(
     mut debug_event_loop: DebugSender,
     mut print_tx: PrintSender,
     mut print_rx: PrintReceiver,
+    mut recv_from_loop: MessageReceiver,
     is_detached: bool,
     verbose_mode: u8,
     is_logging: bool,
+    log_format: utils::LogFormat,
+    is_remote: bool,
     max_log_size: Option<u64>,
     number_log_files: Option<u64>,
     process_verbosity: ProcessVerbosity,
@@ -358,7 +369,7 @@ pub async fn terminal(
     // will also be written with their full timestamp to the .terminal_log file.
     // logging mode is always on by default
     let log_dir_path = home_directory_path.join(".terminal_logs");
-    let logger = utils::Logger::new(log_dir_path, max_log_size, number_log_files);
+    let logger = utils::Logger::new(log_dir_path, max_log_size, number_log_files, log_format);
 
     let process_verbosity_mode = false;
     let saved_line = None;
@@ -367,6 +378,11 @@ pub async fn terminal(
     let max_printout_queue_len = MAX_PRINTOUT_QUEUE_LEN_DEFAULT.clone();
     let printout_queue_number_dropped_printouts = 0;
     let suppressed_event_loop_ids = HashSet::new();
+    let pending_completion = None;
+    let remote = remote::Remote::new(&our.name, event_loop.clone());
+    if is_remote {
+        remote.bind().await;
+    }
 
     let mut state = State {
         stdout,
@@ -393,6 +409,8 @@ pub async fn terminal(
         max_printout_queue_len,
         printout_queue_number_dropped_printouts,
         suppressed_event_loop_ids,
+        pending_completion,
+        remote,
     };
 
     // use to trigger cleanup if receive signal to kill process
@@ -454,6 +472,9 @@ pub async fn terminal(
                         break;
                     }
                 }
+                Some(km) = recv_from_loop.recv() => {
+                    handle_message(&our, km, &mut state, &mut event_loop, &mut debug_event_loop, &mut print_tx).await?;
+                }
                 _ = sigalrm.recv() => return Err(anyhow::anyhow!("exiting due to SIGALRM")),
                 _ = sighup.recv() =>  return Err(anyhow::anyhow!("exiting due to SIGHUP")),
                 _ = sigint.recv() =>  return Err(anyhow::anyhow!("exiting due to SIGINT")),
@@ -473,6 +494,9 @@ pub async fn terminal(
                         break;
                     }
                 }
+                Some(km) = recv_from_loop.recv() => {
+                    handle_message(&our, km, &mut state, &mut event_loop, &mut debug_event_loop, &mut print_tx).await?;
+                }
             }
         }
     } else {
@@ -482,6 +506,9 @@ pub async fn terminal(
                 Some(printout) = print_rx.recv() => {
                     handle_printout(printout, &mut state)?;
                 }
+                Some(km) = recv_from_loop.recv() => {
+                    handle_message(&our, km, &mut state, &mut event_loop, &mut debug_event_loop, &mut print_tx).await?;
+                }
                 _ = sigalrm.recv() => return Err(anyhow::anyhow!("exiting due to SIGALRM")),
                 _ = sighup.recv() =>  return Err(anyhow::anyhow!("exiting due to SIGHUP")),
                 _ = sigint.recv() =>  return Err(anyhow::anyhow!("exiting due to SIGINT")),
@@ -492,9 +519,14 @@ pub async fn terminal(
                 _ = sigusr2.recv() => return Err(anyhow::anyhow!("exiting due to SIGUSR2")),
             }
             #[cfg(target_os = "windows")]
-            if let Some(printout) = print_rx.recv().await {
-                handle_printout(printout, &mut state)?;
-            };
+            tokio::select! {
+                Some(printout) = print_rx.recv() => {
+                    handle_printout(printout, &mut state)?;
+                }
+                Some(km) = recv_from_loop.recv() => {
+                    handle_message(&our, km, &mut state, &mut event_loop, &mut debug_event_loop, &mut print_tx).await?;
+                }
+            }
         }
     };
     Ok(())
@@ -515,7 +547,7 @@ fn handle_printout(printout: Printout, state: &mut State) -> anyhow::Result<()>
     let mut stdout = state.stdout.lock();
     // always write print to log if in logging mode
     if state.logging_mode {
-        state.logger.write(&printout.content)?;
+        state.logger.write(&printout)?;
     }
     // skip writing print to terminal if it's of a greater
     // verbosity level than our current mode
@@ -534,6 +566,13 @@ fn handle_printout(printout: Printout, state: &mut State) -> anyhow::Result<()>
             return Ok(());
         }
     }
+    if state.remote.wants(&printout) {
+        state.remote.broadcast(&remote::RemoteEvent::Printout {
+            verbosity: printout.verbosity,
+            source: printout.source.to_string(),
+            content: printout.content.clone(),
+        });
+    }
     let now = Local::now();
     execute!(
         stdout,
@@ -640,6 +679,220 @@ fn path_has_log_second_segment(path: &str) -> bool {
     matches!(segments.next(), Some(_)) && matches!(segments.next(), Some("log"))
 }
 
+/// handle a message addressed to the runtime terminal: either the response to a
+/// tab completion request, or an event from a client of the remote terminal
+async fn handle_message(
+    our: &Identity,
+    km: KernelMessage,
+    state: &mut State,
+    event_loop: &mut MessageSender,
+    debug_event_loop: &mut DebugSender,
+    print_tx: &mut PrintSender,
+) -> anyhow::Result<()> {
+    match km.message {
+        Message::Response(_) => handle_completion(km, state, print_tx).await,
+        Message::Request(_) if km.source.process == *HTTP_SERVER_PROCESS_ID => {
+            remote::handle_request(our, km, state, event_loop, debug_event_loop, print_tx).await
+        }
+        Message::Request(_) => handle_log_query(our, km, state, event_loop),
+    }
+}
+
+/// answer a query from the `log` script. Searching the logs can take a while,
+/// so it happens off the terminal loop.
+fn handle_log_query(
+    our: &Identity,
+    km: KernelMessage,
+    state: &mut State,
+    event_loop: &MessageSender,
+) -> anyhow::Result<()> {
+    let Message::Request(request) = km.message else {
+        return Ok(());
+    };
+    if km.source.node != our.name || request.expects_response.is_none() {
+        return Ok(());
+    }
+    let Ok(RuntimeTerminalRequest::QueryLogs(query)) =
+        serde_json::from_slice::<RuntimeTerminalRequest>(&request.body)
+    else {
+        return Ok(());
+    };
+    state.logger.flush()?;
+    let log_dir_path = state.logger.log_dir_path.clone();
+    let log_format = state.logger.format;
+    let our_name = our.name.clone();
+    let event_loop = event_loop.clone();
+    tokio::spawn(async move {
+        let response = tokio::task::spawn_blocking(move || {
+            if log_format != utils::LogFormat::Json {
+                return Err(anyhow::anyhow!(
+                    "terminal logs are not being written as JSON: restart with `--log-format json` to query them"
+                ));
+            }
+            utils::query_logs(&log_dir_path, &query)
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|result| result);
+        let response = match response {
+            Ok(entries) => RuntimeTerminalResponse::QueryLogs(entries),
+            Err(e) => RuntimeTerminalResponse::Err(e.to_string()),
+        };
+        KernelMessage::builder()
+            .id(km.id)
+            .source((our_name.as_str(), RUNTIME_TERMINAL_PROCESS_ID.clone()))
+            .target(km.rsvp.unwrap_or(km.source))
+            .message(Message::Response((
+                Response {
+                    inherit: false,
+                    body: serde_json::to_vec(&response).unwrap(),
+                    metadata: None,
+                    capabilities: vec![],
+                },
+                None,
+            )))
+            .build()
+            .unwrap()
+            .send(&event_loop)
+            .await;
+    });
+    Ok(())
+}
+
+/// record a command in history, show it to remote clients, and send it to the
+/// terminal process to run
+async fn run_command(
+    our: &Identity,
+    command: String,
+    state: &mut State,
+    event_loop: &mut MessageSender,
+) {
+    state.command_history.add(command.clone());
+    state
+        .remote
+        .broadcast(&remote::RemoteEvent::Command(command.clone()));
+    KernelMessage::builder()
+        .id(rand::random())
+        .source((our.name.as_str(), TERMINAL_PROCESS_ID.clone()))
+        .target((our.name.as_str(), TERMINAL_PROCESS_ID.clone()))
+        .message(Message::Request(Request {
+            inherit: false,
+            expects_response: None,
+            body: command.into_bytes(),
+            metadata: None,
+            capabilities: vec![],
+        }))
+        .build()
+        .unwrap()
+        .send(event_loop)
+        .await;
+}
+
+/// set the verbosity mode, turning the kernel's event loop printouts on when
+/// entering modes 3 and 4 and off when leaving them
+async fn set_verbose_mode(
+    state: &mut State,
+    verbose_mode: u8,
+    debug_event_loop: &mut DebugSender,
+    print_tx: &PrintSender,
+) {
+    if (state.verbose_mode >= 3) != (verbose_mode >= 3) {
+        debug_event_loop
+            .send(DebugCommand::ToggleEventLoop)
+            .await
+            .expect("failed to toggle event loop");
+    }
+    state.verbose_mode = verbose_mode;
+    state
+        .remote
+        .broadcast(&remote::RemoteEvent::VerboseMode(verbose_mode));
+    Printout::new(
+        0,
+        TERMINAL_PROCESS_ID.clone(),
+        format!(
+            "verbose mode: {}",
+            match verbose_mode {
+                0 => "off",
+                1 => "debug",
+                2 => "super-debug",
+                3 => "event loop (no logging)",
+                4 => "event loop",
+                _ => unreachable!(),
+            }
+        ),
+    )
+    .send(print_tx)
+    .await;
+}
+
+/// apply a tab completion response from the terminal process: extend the word
+/// before the cursor as far as all candidates agree, and if that gets no further,
+/// list the candidates
+async fn handle_completion(
+    km: KernelMessage,
+    state: &mut State,
+    print_tx: &PrintSender,
+) -> anyhow::Result<()> {
+    let Message::Response((response, _)) = km.message else {
+        return Ok(());
+    };
+    match &state.pending_completion {
+        Some((id, line, line_col))
+            if *id == km.id
+                && *line == state.current_line.line
+                && *line_col == state.current_line.line_col => {}
+        _ => return Ok(()),
+    }
+    state.pending_completion = None;
+    if state.search_mode || state.process_verbosity_mode {
+        return Ok(());
+    }
+    let Ok(TerminalResponse::Complete(completion)) =
+        serde_json::from_slice::<TerminalResponse>(&response.body)
+    else {
+        return Ok(());
+    };
+    let Some(first) = completion.candidates.first() else {
+        // the "no-no" ding
+        print!("\x07");
+        return Ok(());
+    };
+    let common = completion
+        .candidates
+        .iter()
+        .fold(first.as_str(), |common, candidate| {
+            utils::common_prefix(common, candidate)
+        });
+    let mut insert = common
+        .strip_prefix(completion.word.as_str())
+        .unwrap_or_default()
+        .to_string();
+    if completion.candidates.len() == 1 && !insert.ends_with('/') {
+        insert.push(' ');
+    }
+    if insert.is_empty() {
+        Printout::new(
+            0,
+            TERMINAL_PROCESS_ID.clone(),
+            completion.candidates.join("  "),
+        )
+        .send(print_tx)
+        .await;
+        return Ok(());
+    }
+    let current_line = &mut state.current_line;
+    current_line.insert_str(&insert);
+    current_line.line_col += insert.graphemes(true).count();
+    current_line.cursor_col = std::cmp::min(
+        current_line.cursor_col + utils::display_width(&insert) as u16,
+        state
+            .win_cols
+            .saturating_sub(current_line.prompt_len as u16),
+    );
+    state.display_current_input_line(false)?;
+    Ok(())
+}
+
 /// returns true if runtime should exit due to CTRL+C or CTRL+D
 async fn handle_event(
     our: &Identity,
@@ -788,45 +1041,8 @@ async fn handle_key_event(
                 return Ok(Some(false));
             }
             // go from low to high, then reset to 0
-            match verbose_mode {
-                0 => *verbose_mode = 1,
-                1 => *verbose_mode = 2,
-                2 => {
-                    *verbose_mode = 3;
-                    debug_event_loop
-                        .send(DebugCommand::ToggleEventLoop)
-                        .await
-                        .expect("failed to toggle ON event loop");
-                }
-                3 => {
-                    *verbose_mode = 4;
-                }
-                4 => {
-                    *verbose_mode = 0;
-                    debug_event_loop
-                        .send(DebugCommand::ToggleEventLoop)
-                        .await
-                        .expect("failed to toggle OFF event loop");
-                }
-                _ => unreachable!(),
-            }
-            Printout::new(
-                0,
-                TERMINAL_PROCESS_ID.clone(),
-                format!(
-                    "verbose mode: {}",
-                    match verbose_mode {
-                        0 => "off",
-                        1 => "debug",
-                        2 => "super-debug",
-                        3 => "event loop (no logging)",
-                        4 => "event loop",
-                        _ => unreachable!(),
-                    }
-                ),
-            )
-            .send(&print_tx)
-            .await;
+            let next_mode = (*verbose_mode + 1) % 5;
+            set_verbose_mode(state, next_mode, debug_event_loop, print_tx).await;
             return Ok(Some(false));
         }
         //
@@ -1149,6 +1365,37 @@ async fn handle_key_event(
                     }
                 }
                 //
+                //  TAB: ask terminal process to complete the word before the cursor
+                //
+                KeyCode::Tab => {
+                    if state.search_mode || state.process_verbosity_mode {
+                        return Ok(Some(false));
+                    }
+                    let id: u64 = rand::random();
+                    let line = current_line.line[..current_line.byte_index()].to_string();
+                    state.pending_completion =
+                        Some((id, current_line.line.clone(), current_line.line_col));
+                    KernelMessage::builder()
+                        .id(id)
+                        .source((our.name.as_str(), RUNTIME_TERMINAL_PROCESS_ID.clone()))
+                        .target((our.name.as_str(), TERMINAL_PROCESS_ID.clone()))
+                        .message(Message::Request(Request {
+                            inherit: false,
+                            expects_response: Some(5),
+                            body: serde_json::to_vec(&TerminalRequest::Complete(CompleteRequest {
+                                line,
+                            }))
+                            .unwrap(),
+                            metadata: None,
+                            capabilities: vec![],
+                        }))
+                        .build()
+                        .unwrap()
+                        .send(event_loop)
+                        .await;
+                    return Ok(Some(false));
+                }
+                //
                 //  ENTER: send current input to terminal process, clearing input line
                 //
                 KeyCode::Enter => {
@@ -1226,23 +1473,8 @@ async fn handle_key_event(
                     *search_depth = 0;
                     current_line.cursor_col = 0;
                     current_line.line_col = 0;
-                    command_history.add(command.to_string());
-                    KernelMessage::builder()
-                        .id(rand::random())
-                        .source((our.name.as_str(), TERMINAL_PROCESS_ID.clone()))
-                        .target((our.name.as_str(), TERMINAL_PROCESS_ID.clone()))
-                        .message(Message::Request(Request {
-                            inherit: false,
-                            expects_response: None,
-                            body: command.into_bytes(),
-                            metadata: None,
-                            capabilities: vec![],
-                        }))
-                        .build()
-                        .unwrap()
-                        .send(&event_loop)
-                        .await;
                     current_line.line = "".to_string();
+                    run_command(our, command, state, event_loop).await;
                 }
                 _ => {
                     // some keycode we don't care about, yet
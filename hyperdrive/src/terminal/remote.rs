@@ -0,0 +1,261 @@
+//! The terminal, served over an authenticated WebSocket on http-server so that a
+//! node run with `--detached` can still be driven interactively. Opt-in with
+//! `--remote-terminal`, and only on the terminal's secure subdomain, so neither a
+//! cookie for the main domain nor an app's frontend can reach it.
+//!
+//! Every logged-in client sees the printouts the local terminal would show. One
+//! client at a time holds input: it can run commands and change the verbosity mode.
+
+use super::State;
+use lib::types::core::{
+    DebugSender, Identity, KernelMessage, LazyLoadBlob, Message, MessageSender, PrintSender,
+    Printout, Request, HTTP_SERVER_PROCESS_ID, RUNTIME_TERMINAL_PROCESS_ID,
+};
+use lib::types::http_server::{HttpServerAction, HttpServerRequest, WsMessageType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// served at `/terminal:distro:sys`
+const WEB_CLIENT: &str = include_str!("remote.html");
+/// bound at `/terminal:distro:sys/ws`
+const WS_PATH: &str = "/ws";
+/// number of history lines a client is sent when it connects
+const HELLO_HISTORY_LEN: usize = 100;
+
+/// Sent to clients as JSON in text frames.
+#[derive(Debug, Serialize)]
+pub enum RemoteEvent {
+    /// first message on every connection
+    Hello {
+        node: String,
+        verbose_mode: u8,
+        has_input: bool,
+        /// most recent last
+        history: Vec<String>,
+    },
+    Printout {
+        verbosity: u8,
+        source: String,
+        content: String,
+    },
+    /// a command run locally or by the client holding input
+    Command(String),
+    /// whether this client now holds input
+    Input(bool),
+    VerboseMode(u8),
+    Error(String),
+}
+
+/// Received from clients as JSON in text frames.
+#[derive(Debug, Deserialize)]
+pub enum RemoteRequest {
+    /// take input, from whichever client holds it
+    TakeInput,
+    ReleaseInput,
+    Command(String),
+    SetVerboseMode(u8),
+}
+
+pub struct Remote {
+    our: String,
+    send_to_loop: MessageSender,
+    /// WebSocket channel IDs of connected clients
+    clients: HashSet<u32>,
+    input: Option<u32>,
+}
+
+impl Remote {
+    pub fn new(our: &str, send_to_loop: MessageSender) -> Self {
+        Self {
+            our: our.to_string(),
+            send_to_loop,
+            clients: HashSet::new(),
+            input: None,
+        }
+    }
+
+    /// Serve the web client and accept WebSocket connections on our secure subdomain.
+    /// Both require login to that subdomain.
+    pub async fn bind(&self) {
+        let bindings = [
+            (
+                HttpServerAction::SecureBind {
+                    path: "/".to_string(),
+                    cache: true,
+                },
+                Some(LazyLoadBlob {
+                    mime: Some("text/html".to_string()),
+                    bytes: WEB_CLIENT.as_bytes().to_vec(),
+                }),
+            ),
+            (
+                HttpServerAction::WebSocketSecureBind {
+                    path: WS_PATH.to_string(),
+                    extension: false,
+                },
+                None,
+            ),
+        ];
+        for (action, blob) in bindings {
+            self.http_server_request(action, blob)
+                .send(&self.send_to_loop)
+                .await;
+        }
+    }
+
+    /// Whether a printout should go to clients. Event loop printouts of our own
+    /// traffic are left out: pushing them would print more of them, without end.
+    pub fn wants(&self, printout: &Printout) -> bool {
+        !self.clients.is_empty()
+            && printout.source != *RUNTIME_TERMINAL_PROCESS_ID
+            && !(printout.verbosity >= 3
+                && printout
+                    .content
+                    .contains(&RUNTIME_TERMINAL_PROCESS_ID.to_string()))
+    }
+
+    pub fn broadcast(&self, event: &RemoteEvent) {
+        for channel_id in &self.clients {
+            self.push(*channel_id, event);
+        }
+    }
+
+    /// Does not wait: the kernel may itself be waiting to hand us a printout.
+    /// If the event loop is that backed up, clients miss this event.
+    fn push(&self, channel_id: u32, event: &RemoteEvent) {
+        let _ = self.send_to_loop.try_send(self.http_server_request(
+            HttpServerAction::WebSocketPush {
+                channel_id,
+                message_type: WsMessageType::Text,
+            },
+            Some(LazyLoadBlob {
+                mime: Some("application/json".to_string()),
+                bytes: serde_json::to_vec(event).unwrap(),
+            }),
+        ));
+    }
+
+    fn http_server_request(
+        &self,
+        action: HttpServerAction,
+        blob: Option<LazyLoadBlob>,
+    ) -> KernelMessage {
+        KernelMessage::builder()
+            .id(rand::random())
+            .source((self.our.as_str(), RUNTIME_TERMINAL_PROCESS_ID.clone()))
+            .target((self.our.as_str(), HTTP_SERVER_PROCESS_ID.clone()))
+            .message(Message::Request(Request {
+                inherit: false,
+                expects_response: None,
+                body: serde_json::to_vec(&action).unwrap(),
+                metadata: None,
+                capabilities: vec![],
+            }))
+            .lazy_load_blob(blob)
+            .build()
+            .unwrap()
+    }
+}
+
+/// Handle a WebSocket event from http-server.
+pub async fn handle_request(
+    our: &Identity,
+    km: KernelMessage,
+    state: &mut State,
+    event_loop: &mut MessageSender,
+    debug_event_loop: &mut DebugSender,
+    print_tx: &mut PrintSender,
+) -> anyhow::Result<()> {
+    if km.source.node != our.name || km.source.process != *HTTP_SERVER_PROCESS_ID {
+        return Ok(());
+    }
+    let Message::Request(request) = km.message else {
+        return Ok(());
+    };
+    let Ok(request) = serde_json::from_slice::<HttpServerRequest>(&request.body) else {
+        return Ok(());
+    };
+    let remote = &mut state.remote;
+    match request {
+        HttpServerRequest::WebSocketOpen { channel_id, .. } => {
+            remote.clients.insert(channel_id);
+            // the first client in gets input
+            let has_input = remote.input.is_none();
+            if has_input {
+                remote.input = Some(channel_id);
+            }
+            remote.push(
+                channel_id,
+                &RemoteEvent::Hello {
+                    node: our.name.clone(),
+                    verbose_mode: state.verbose_mode,
+                    has_input,
+                    history: state.command_history.recent(HELLO_HISTORY_LEN),
+                },
+            );
+        }
+        HttpServerRequest::WebSocketClose(channel_id) => {
+            remote.clients.remove(&channel_id);
+            if remote.input == Some(channel_id) {
+                remote.input = None;
+            }
+        }
+        HttpServerRequest::WebSocketPush {
+            channel_id,
+            message_type: WsMessageType::Text | WsMessageType::Binary,
+        } => {
+            let Some(request) = km
+                .lazy_load_blob
+                .and_then(|blob| serde_json::from_slice::<RemoteRequest>(&blob.bytes).ok())
+            else {
+                remote.push(channel_id, &RemoteEvent::Error("malformed request".into()));
+                return Ok(());
+            };
+            match request {
+                RemoteRequest::TakeInput => {
+                    if let Some(previous) = remote.input.replace(channel_id) {
+                        if previous != channel_id {
+                            remote.push(previous, &RemoteEvent::Input(false));
+                        }
+                    }
+                    remote.push(channel_id, &RemoteEvent::Input(true));
+                }
+                RemoteRequest::ReleaseInput => {
+                    if remote.input == Some(channel_id) {
+                        remote.input = None;
+                        remote.push(channel_id, &RemoteEvent::Input(false));
+                    }
+                }
+                _ if remote.input != Some(channel_id) => {
+                    remote.push(
+                        channel_id,
+                        &RemoteEvent::Error("another client holds input".into()),
+                    );
+                }
+                RemoteRequest::Command(command) => {
+                    Printout::new(
+                        0,
+                        RUNTIME_TERMINAL_PROCESS_ID.clone(),
+                        format!("(remote) {}{command}", state.current_line.prompt),
+                    )
+                    .send(print_tx)
+                    .await;
+                    super::run_command(our, command, state, event_loop).await;
+                }
+                RemoteRequest::SetVerboseMode(mode) => {
+                    if mode > 4 {
+                        remote.push(
+                            channel_id,
+                            &RemoteEvent::Error("verbose mode must be 0 to 4".into()),
+                        );
+                        return Ok(());
+                    }
+                    super::set_verbose_mode(state, mode, debug_event_loop, print_tx).await;
+                }
+            }
+        }
+        // pings, pongs and close frames; http-server follows a close with WebSocketClose
+        HttpServerRequest::WebSocketPush { .. } | HttpServerRequest::Http(_) => {}
+    }
+    Ok(())
+}
@@ -1,9 +1,9 @@
 use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
-use lib::types::core::Identity;
+use lib::types::core::{Identity, LogEntry, LogQuery, Printout};
 use std::{
     collections::VecDeque,
     fs::{File, OpenOptions},
-    io::{BufWriter, Stdout, Write},
+    io::{BufRead, BufReader, BufWriter, Stdout, Write},
     path::{Path, PathBuf},
 };
 use unicode_segmentation::UnicodeSegmentation;
@@ -220,6 +220,17 @@ pub fn display_width(s: &str) -> usize {
     UnicodeWidthStr::width(s)
 }
 
+/// longest prefix shared by both strings, in whole characters
+pub fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len = a
+        .char_indices()
+        .zip(b.chars())
+        .take_while(|((_, x), y)| x == y)
+        .last()
+        .map_or(0, |((i, c), _)| i + c.len_utf8());
+    &a[..len]
+}
+
 /// produce command line prompt and its length
 pub fn make_prompt(our_name: &str) -> (&'static str, usize) {
     let prompt = Box::leak(format!("{} > ", our_name).into_boxed_str());
@@ -278,6 +289,11 @@ impl CommandHistory {
         }
     }
 
+    /// the most recent `n` lines, oldest first
+    pub fn recent(&self, n: usize) -> Vec<String> {
+        self.lines.iter().take(n).rev().cloned().collect()
+    }
+
     pub fn get_prev(&mut self, working_line: &str) -> Option<String> {
         if self.lines.is_empty() || self.index == self.lines.len() {
             return None;
@@ -426,9 +442,27 @@ pub fn truncate_in_place(
 pub struct Logger {
     pub log_dir_path: PathBuf,
     pub strategy: LoggerStrategy,
+    pub format: LogFormat,
     log_writer: BufWriter<std::fs::File>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    /// `[timestamp] content` lines in `.log` files
+    Text,
+    /// one [`LogEntry`] per line in `.jsonl` files: these can be searched with `log`
+    Json,
+}
+
+impl LogFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            LogFormat::Text => "log",
+            LogFormat::Json => "jsonl",
+        }
+    }
+}
+
 pub enum LoggerStrategy {
     Rotating {
         max_log_dir_bytes: u64,
@@ -457,18 +491,28 @@ impl Logger {
         log_dir_path: PathBuf,
         max_log_size: Option<u64>,
         number_log_files: Option<u64>,
+        format: LogFormat,
     ) -> Self {
-        let log_writer = make_log_writer(&log_dir_path).unwrap();
+        let log_writer = make_log_writer(&log_dir_path, format).unwrap();
         Self {
             log_dir_path,
             log_writer,
             strategy: LoggerStrategy::new(max_log_size, number_log_files),
+            format,
         }
     }
 
-    pub fn write(&mut self, line: &str) -> anyhow::Result<()> {
+    pub fn write(&mut self, printout: &Printout) -> anyhow::Result<()> {
         let now = chrono::Local::now();
-        let line = &format!("[{}] {}", now.to_rfc2822(), line);
+        let line = &match self.format {
+            LogFormat::Text => format!("[{}] {}", now.to_rfc2822(), printout.content),
+            LogFormat::Json => serde_json::to_string(&LogEntry {
+                timestamp: now.to_rfc3339(),
+                verbosity: printout.verbosity,
+                source: printout.source.clone(),
+                content: strip_ansi(&printout.content),
+            })?,
+        };
         match self.strategy {
             LoggerStrategy::Infinite => {}
             LoggerStrategy::Rotating {
@@ -480,7 +524,7 @@ impl Logger {
                 let file_bytes = self.log_writer.get_ref().metadata()?.len() as usize;
                 if line_bytes + file_bytes >= (max_log_dir_bytes / number_log_files) as usize {
                     // rotate
-                    self.log_writer = make_log_writer(&self.log_dir_path)?;
+                    self.log_writer = make_log_writer(&self.log_dir_path, self.format)?;
 
                     // clean up oldest if necessary
                     remove_oldest_if_exceeds(&self.log_dir_path, number_log_files as usize)?;
@@ -492,17 +536,104 @@ impl Logger {
 
         Ok(())
     }
+
+    /// write out buffered lines so that a query sees them
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.log_writer.flush()
+    }
 }
 
-fn make_log_writer(log_dir_path: &Path) -> anyhow::Result<BufWriter<std::fs::File>> {
+/// Search the JSON-lines logs in `log_dir_path`, newest first, for entries
+/// matching `query`. Returns them oldest first.
+pub fn query_logs(log_dir_path: &Path, query: &LogQuery) -> anyhow::Result<Vec<LogEntry>> {
+    if query.limit == 0 {
+        return Ok(vec![]);
+    }
+    let cutoff = query
+        .since_secs
+        .map(|secs| chrono::Local::now() - chrono::Duration::seconds(secs as i64));
+    // log names are timestamps, so they sort oldest first
+    let mut log_paths: Vec<PathBuf> = std::fs::read_dir(log_dir_path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == LogFormat::Json.extension())
+        })
+        .collect();
+    log_paths.sort();
+
+    let mut entries = vec![];
+    'files: for log_path in log_paths.iter().rev() {
+        let lines: Vec<String> = BufReader::new(File::open(log_path)?)
+            .lines()
+            .collect::<Result<_, _>>()?;
+        for line in lines.iter().rev() {
+            // a line may be cut short if we were killed mid-write
+            let Ok(entry) = serde_json::from_str::<LogEntry>(line) else {
+                continue;
+            };
+            if let Some(cutoff) = cutoff {
+                match chrono::DateTime::parse_from_rfc3339(&entry.timestamp) {
+                    Ok(time) if time < cutoff => break 'files,
+                    _ => {}
+                }
+            }
+            if query
+                .process
+                .as_ref()
+                .is_some_and(|process| *process != entry.source)
+                || query
+                    .max_verbosity
+                    .is_some_and(|max_verbosity| entry.verbosity > max_verbosity)
+            {
+                continue;
+            }
+            entries.push(entry);
+            if entries.len() >= query.limit {
+                break 'files;
+            }
+        }
+    }
+    entries.reverse();
+    Ok(entries)
+}
+
+/// remove ANSI escape sequences, as used for color, from a printout
+pub fn strip_ansi(s: &str) -> String {
+    let mut stripped = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            stripped.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'[').is_some() {
+            // CSI: parameters and intermediates, then one final byte in @..~
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        } else {
+            // two-character sequence
+            chars.next();
+        }
+    }
+    stripped
+}
+
+fn make_log_writer(
+    log_dir_path: &Path,
+    format: LogFormat,
+) -> anyhow::Result<BufWriter<std::fs::File>> {
     if !log_dir_path.exists() {
         std::fs::create_dir(log_dir_path)?;
     }
     let now = chrono::Local::now();
     #[cfg(unix)]
-    let log_name = format!("{}.log", now.format("%Y-%m-%d-%H:%M:%S"));
+    let log_name = format!("{}.{}", now.format("%Y-%m-%d-%H:%M:%S"), format.extension());
     #[cfg(target_os = "windows")]
-    let log_name = format!("{}.log", now.format("%Y-%m-%d-%H_%M_%S"));
+    let log_name = format!("{}.{}", now.format("%Y-%m-%d-%H_%M_%S"), format.extension());
 
     let log_path = log_dir_path.join(log_name);
     let log_handle = OpenOptions::new()
@@ -536,3 +667,113 @@ fn remove_oldest_if_exceeds<P: AsRef<Path>>(path: P, max_items: usize) -> anyhow
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lib::types::core::ProcessId;
+
+    fn entry(secs_ago: i64, verbosity: u8, source: &str, content: &str) -> String {
+        serde_json::to_string(&LogEntry {
+            timestamp: (chrono::Local::now() - chrono::Duration::seconds(secs_ago)).to_rfc3339(),
+            verbosity,
+            source: source.parse().unwrap(),
+            content: content.to_string(),
+        })
+        .unwrap()
+    }
+
+    fn query(limit: usize) -> LogQuery {
+        LogQuery {
+            process: None,
+            max_verbosity: None,
+            since_secs: None,
+            limit,
+        }
+    }
+
+    fn contents(entries: Vec<LogEntry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.content).collect()
+    }
+
+    /// two log files, oldest first, and a text log that should be ignored
+    fn log_dir() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("2024-01-01-00_00_00.jsonl"),
+            [
+                entry(300, 0, "a:pkg:sys", "one"),
+                entry(200, 2, "b:pkg:sys", "two"),
+            ]
+            .join("\n")
+                + "\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("2024-01-02-00_00_00.jsonl"),
+            [
+                entry(100, 1, "a:pkg:sys", "three"),
+                // cut short mid-write
+                "{\"timestamp\":".to_string(),
+                entry(0, 0, "b:pkg:sys", "four"),
+            ]
+            .join("\n")
+                + "\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("2024-01-03-00_00_00.log"), "five\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn query_logs_keeps_most_recent_oldest_first() {
+        let dir = log_dir();
+        assert_eq!(
+            contents(query_logs(dir.path(), &query(10)).unwrap()),
+            ["one", "two", "three", "four"]
+        );
+        assert_eq!(
+            contents(query_logs(dir.path(), &query(3)).unwrap()),
+            ["two", "three", "four"]
+        );
+        assert!(query_logs(dir.path(), &query(0)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn query_logs_filters() {
+        let dir = log_dir();
+        let by_process = LogQuery {
+            process: Some(ProcessId::new(Some("a"), "pkg", "sys")),
+            ..query(10)
+        };
+        assert_eq!(
+            contents(query_logs(dir.path(), &by_process).unwrap()),
+            ["one", "three"]
+        );
+        let by_verbosity = LogQuery {
+            max_verbosity: Some(0),
+            ..query(10)
+        };
+        assert_eq!(
+            contents(query_logs(dir.path(), &by_verbosity).unwrap()),
+            ["one", "four"]
+        );
+        let by_time = LogQuery {
+            since_secs: Some(150),
+            ..query(10)
+        };
+        assert_eq!(
+            contents(query_logs(dir.path(), &by_time).unwrap()),
+            ["three", "four"]
+        );
+    }
+
+    #[test]
+    fn strip_ansi_removes_escape_sequences() {
+        assert_eq!(strip_ansi("plain"), "plain");
+        assert_eq!(strip_ansi("\x1b[1;31mred\x1b[0m text"), "red text");
+        // two-character sequences, and a sequence cut short at the end
+        assert_eq!(strip_ansi("a\x1bcb\x1b[3"), "ab");
+        assert_eq!(strip_ansi("ünï\x1b[38;5;208mcødé"), "ünïcødé");
+    }
+}
@@ -907,6 +907,8 @@ async fn add_capability(
         .send(CapMessage::Add {
             on: source.process.clone(),
             caps: vec![cap],
+            actor: VFS_PROCESS_ID.clone(),
+            reason: "vfs drive created",
             responder: Some(send_cap_bool),
         })
         .await
@@ -1062,6 +1064,14 @@ async fn handle_fd_request(km: KernelMessage, files: &mut Files) -> anyhow::Resu
                     .await?;
             }
         }
+        FdManagerRequest::GetFdsUsage => {
+            crate::fd_manager::send_fd_manager_fds_usage(
+                &files.our,
+                files.open_files.len() as u64,
+                &files.send_to_loop,
+            )
+            .await;
+        }
         _ => {
             return Err(anyhow::anyhow!("non-Cull FdManagerRequest"));
         }
@@ -14,6 +14,7 @@ lazy_static::lazy_static! {
     pub static ref KV_PROCESS_ID: ProcessId = ProcessId::new(Some("kv"), "distro", "sys");
     pub static ref NET_PROCESS_ID: ProcessId = ProcessId::new(Some("net"), "distro", "sys");
     pub static ref NOTIFICATIONS_PROCESS_ID: ProcessId = ProcessId::new(Some("notifications"), "distro", "sys");
+    pub static ref RUNTIME_TERMINAL_PROCESS_ID: ProcessId = ProcessId::new(Some("terminal"), "distro", "sys");
     pub static ref STATE_PROCESS_ID: ProcessId = ProcessId::new(Some("state"), "distro", "sys");
     pub static ref SQLITE_PROCESS_ID: ProcessId = ProcessId::new(Some("sqlite"), "distro", "sys");
     pub static ref TERMINAL_PROCESS_ID: ProcessId = ProcessId::new(Some("terminal"), "terminal", "sys");
@@ -482,23 +483,55 @@ pub struct Capability {
     pub params: String,
 }
 
+/// Conditions on holding a capability.
+///
+/// They are carried inside the params, as `{"__constraints": <constraints>, "params": <params>}`,
+/// so that a constrained capability is signed along with its constraints and keeps the
+/// shape of any other capability over the network, across WIT and in persisted state.
+/// Capabilities are compared by their unconstrained params: a process holds at most one
+/// version of each.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapConstraints {
+    /// seconds since the unix epoch at which the capability stops being held
+    pub expires: Option<u64>,
+    /// whether the holder may attach the capability to messages it sends
+    pub delegable: bool,
+}
+
+impl CapConstraints {
+    /// The constraints that hold only where both `self` and `other` do: the earlier
+    /// expiry, and delegable only if both are.
+    pub fn intersect(&self, other: &CapConstraints) -> CapConstraints {
+        CapConstraints {
+            expires: match (self.expires, other.expires) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            },
+            delegable: self.delegable && other.delegable,
+        }
+    }
+}
+
+/// an unconstrained capability: held forever, and delegable
+const NO_CONSTRAINTS: CapConstraints = CapConstraints {
+    expires: None,
+    delegable: true,
+};
+
+const CONSTRAINTS_KEY: &str = "__constraints";
+
 impl Eq for Capability {}
 
 impl PartialEq for Capability {
     fn eq(&self, other: &Self) -> bool {
-        let self_json_params: serde_json::Value =
-            serde_json::from_str(&self.params).unwrap_or_default();
-        let other_json_params: serde_json::Value =
-            serde_json::from_str(&other.params).unwrap_or_default();
-        self.issuer == other.issuer && self_json_params == other_json_params
+        self.issuer == other.issuer && self.unconstrained_params() == other.unconstrained_params()
     }
 }
 
 impl Hash for Capability {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.issuer.hash(state);
-        let params: serde_json::Value = serde_json::from_str(&self.params).unwrap_or_default();
-        params.hash(state);
+        self.unconstrained_params().hash(state);
     }
 }
 
@@ -523,6 +556,70 @@ impl Capability {
             params: "\"messaging\"".into(),
         }
     }
+
+    /// This capability, limited by `constraints` in place of any it had.
+    pub fn constrained(&self, constraints: CapConstraints) -> Self {
+        if constraints == NO_CONSTRAINTS {
+            return Capability {
+                issuer: self.issuer.clone(),
+                params: self.unconstrained_params().to_string(),
+            };
+        }
+        Capability {
+            issuer: self.issuer.clone(),
+            params: serde_json::json!({
+                CONSTRAINTS_KEY: constraints,
+                "params": self.unconstrained_params(),
+            })
+            .to_string(),
+        }
+    }
+
+    pub fn constraints(&self) -> Option<CapConstraints> {
+        let params: serde_json::Value = serde_json::from_str(&self.params).ok()?;
+        let (constraints, _) = split_constrained(&params)?;
+        serde_json::from_value(constraints.clone()).ok()
+    }
+
+    /// The params without constraints, as JSON.
+    pub fn unconstrained_params(&self) -> serde_json::Value {
+        let params: serde_json::Value = serde_json::from_str(&self.params).unwrap_or_default();
+        match split_constrained(&params) {
+            Some((_, inner)) => inner.clone(),
+            None => params,
+        }
+    }
+
+    /// Whether the capability has expired as of `now`, in seconds since the unix epoch.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.constraints()
+            .and_then(|constraints| constraints.expires)
+            .is_some_and(|expires| expires <= now)
+    }
+
+    pub fn is_delegable(&self) -> bool {
+        self.constraints()
+            .is_none_or(|constraints| constraints.delegable)
+    }
+
+    /// This capability, limited by the constraints of `held` as well as its own, so
+    /// that replacing `held` with it can't lengthen its expiry or make it delegable.
+    pub fn no_looser_than(&self, held: &Capability) -> Self {
+        let ours = self.constraints().unwrap_or(NO_CONSTRAINTS);
+        let theirs = held.constraints().unwrap_or(NO_CONSTRAINTS);
+        self.constrained(ours.intersect(&theirs))
+    }
+}
+
+/// Split constrained params into their constraints and the params they constrain.
+fn split_constrained(
+    params: &serde_json::Value,
+) -> Option<(&serde_json::Value, &serde_json::Value)> {
+    let object = params.as_object()?;
+    if object.len() != 2 {
+        return None;
+    }
+    Some((object.get(CONSTRAINTS_KEY)?, object.get("params")?))
 }
 
 impl std::fmt::Display for Capability {
@@ -549,8 +646,17 @@ pub struct SendError {
 pub enum SendErrorKind {
     Offline,
     Timeout,
+    /// The target process's mailbox was full, so the message was not delivered.
+    /// Processes see this as `Offline`, with the metadata of the request handed back
+    /// to them marked with [`QUEUE_FULL_METADATA`].
+    QueueFull,
 }
 
+/// Metadata of a request handed back in a queue-full send error. The WIT has no
+/// queue-full kind, so this is how a process tells a full mailbox from an offline one.
+/// If the request had metadata of its own, it follows after a `:`.
+pub const QUEUE_FULL_METADATA: &str = "queue-full";
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum OnExit {
     None,
@@ -817,10 +923,18 @@ pub fn en_wit_message(message: Message) -> wit::Message {
 }
 
 pub fn en_wit_send_error(error: SendError) -> wit::SendError {
+    let mut message = en_wit_message(error.message);
+    if let (SendErrorKind::QueueFull, wit::Message::Request(request)) = (&error.kind, &mut message)
+    {
+        request.metadata = Some(match request.metadata.take() {
+            None => QUEUE_FULL_METADATA.to_string(),
+            Some(metadata) => format!("{QUEUE_FULL_METADATA}:{metadata}"),
+        });
+    }
     wit::SendError {
         kind: en_wit_send_error_kind(error.kind),
         target: error.target.en_wit(),
-        message: en_wit_message(error.message),
+        message,
         lazy_load_blob: en_wit_blob(error.lazy_load_blob),
     }
 }
@@ -829,5 +943,110 @@ pub fn en_wit_send_error_kind(kind: SendErrorKind) -> wit::SendErrorKind {
     match kind {
         SendErrorKind::Offline => wit::SendErrorKind::Offline,
         SendErrorKind::Timeout => wit::SendErrorKind::Timeout,
+        // the WIT has no such kind: like `Offline`, the message was never delivered
+        SendErrorKind::QueueFull => wit::SendErrorKind::Offline,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cap(params: &str) -> Capability {
+        Capability::new(
+            ("our", ProcessId::new(Some("vfs"), "distro", "sys")),
+            params,
+        )
+    }
+
+    fn constraints(expires: Option<u64>, delegable: bool) -> CapConstraints {
+        CapConstraints { expires, delegable }
+    }
+
+    #[test]
+    fn constrained_cap_keeps_its_params() {
+        let plain = cap(r#"{"kind":"read","drive":"/foo:bar.os/data"}"#);
+        let constrained = plain.constrained(constraints(Some(100), false));
+        assert_eq!(constrained, plain);
+        assert_eq!(
+            constrained.unconstrained_params(),
+            plain.unconstrained_params()
+        );
+        assert_eq!(
+            constrained.constraints(),
+            Some(constraints(Some(100), false))
+        );
+        assert_eq!(plain.constraints(), None);
+    }
+
+    #[test]
+    fn unconstrained_cap_has_plain_params() {
+        let plain = cap("\"messaging\"");
+        assert_eq!(plain.constrained(NO_CONSTRAINTS).params, plain.params);
+    }
+
+    #[test]
+    fn params_that_only_look_constrained_are_plain() {
+        // a third key means these are the issuer's own params
+        let odd = cap(r#"{"__constraints":{"expires":1,"delegable":false},"params":1,"x":2}"#);
+        assert_eq!(odd.constraints(), None);
+        assert!(!odd.is_expired(u64::MAX));
+        assert!(odd.is_delegable());
+        // unparseable constraints constrain nothing
+        let bad = cap(r#"{"__constraints":"soon","params":1}"#);
+        assert_eq!(bad.constraints(), None);
+    }
+
+    #[test]
+    fn expiry_is_inclusive() {
+        let expiring = cap("\"messaging\"").constrained(constraints(Some(100), true));
+        assert!(!expiring.is_expired(99));
+        assert!(expiring.is_expired(100));
+        assert!(!cap("\"messaging\"").is_expired(u64::MAX));
+    }
+
+    #[test]
+    fn replacement_is_no_looser_than_held() {
+        let plain = cap("\"messaging\"");
+        let held = plain.constrained(constraints(Some(100), false));
+        let loosened = plain.no_looser_than(&held);
+        assert_eq!(loosened.constraints(), Some(constraints(Some(100), false)));
+
+        let tighter = plain
+            .constrained(constraints(Some(50), true))
+            .no_looser_than(&held);
+        assert_eq!(tighter.constraints(), Some(constraints(Some(50), false)));
+
+        let later = plain
+            .constrained(constraints(Some(200), false))
+            .no_looser_than(&plain.constrained(constraints(None, true)));
+        assert_eq!(later.constraints(), Some(constraints(Some(200), false)));
+
+        assert_eq!(plain.no_looser_than(&plain).params, plain.params);
+    }
+
+    #[test]
+    fn queue_full_errors_keep_the_request_metadata() {
+        let error = |metadata: Option<&str>| SendError {
+            kind: SendErrorKind::QueueFull,
+            target: Address::new("our", ProcessId::new(Some("chat"), "chat", "sys")),
+            message: Message::Request(Request {
+                inherit: false,
+                expects_response: Some(5),
+                body: vec![],
+                metadata: metadata.map(str::to_string),
+                capabilities: vec![],
+            }),
+            lazy_load_blob: None,
+        };
+        let metadata = |error| match en_wit_send_error(error).message {
+            wit::Message::Request(request) => request.metadata,
+            wit::Message::Response(_) => panic!("expected a request"),
+        };
+        assert_eq!(metadata(error(None)).as_deref(), Some("queue-full"));
+        assert_eq!(
+            metadata(error(Some("mine"))).as_deref(),
+            Some("queue-full:mine")
+        );
     }
 }
@@ -48,11 +48,25 @@ pub enum SubscriptionKind {
 pub enum EthAction {
     /// Subscribe to logs with a custom filter. ID is to be used to unsubscribe.
     /// Logs come in as JSON value which can be parsed to [`alloy::rpc::types::eth::pubsub::SubscriptionResult`]
+    ///
+    /// For [`SubscriptionKind::Logs`] only, `confirmations` and `from_block` make the
+    /// subscription reorg-safe: see [`EthSubEvent`].
     SubscribeLogs {
         sub_id: u64,
         chain_id: u64,
         kind: SubscriptionKind,
         params: serde_json::Value,
+        /// Hold each log until its block is this many blocks deep before delivering it.
+        /// Logs are delivered with [`EthSubEvent::Confirmed`], and a reorg that removes
+        /// an already-delivered log produces an [`EthSubEvent::Rollback`].
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        confirmations: Option<u64>,
+        /// Deliver matching logs from this block onward before switching to live
+        /// updates, signalled by [`EthSubEvent::Live`]. Pass the block after the last
+        /// one processed to resume a subscription without gaps, including one that
+        /// failed because too many live logs arrived during its backfill.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        from_block: Option<u64>,
     },
     /// Kill a SubscribeLogs subscription of a given ID, to stop getting updates.
     UnsubscribeLogs(u64),
@@ -74,8 +88,29 @@ pub type EthSubResult = Result<EthSub, EthSubError>;
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct EthSub {
     pub id: u64,
-    /// can be parsed to [`alloy::rpc::types::eth::pubsub::SubscriptionResult`]
+    /// can be parsed to [`alloy::rpc::types::eth::pubsub::SubscriptionResult`],
+    /// except for [`EthSubEvent::Live`] updates
     pub result: serde_json::Value,
+    /// Only set for subscriptions made with `confirmations` or `from_block`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<EthSubEvent>,
+}
+
+/// What an [`EthSub`] update means, for subscriptions made with `confirmations`
+/// or `from_block` in [`EthAction::SubscribeLogs`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EthSubEvent {
+    /// `result` is a log that is now at least `confirmations` blocks deep,
+    /// or a backfilled log from before the subscription went live.
+    Confirmed,
+    /// `result` is a previously confirmed log that a reorg has removed from the
+    /// chain. Consumers should undo whatever they did for it.
+    Rollback,
+    /// Backfill from `from_block` is complete and live updates follow.
+    /// `result` is the chain head when the subscription started, the last block
+    /// backfilled. Logs of its last `confirmations` blocks are delivered once
+    /// they are confirmed.
+    Live,
 }
 
 /// If your subscription is closed unexpectedly, you will receive this.
@@ -154,6 +189,21 @@ pub enum EthConfigAction {
     GetAccessSettings,
     /// Get the state of calls and subscriptions. Used for debugging.
     GetState,
+    /// Delete all cached RPC responses, both in memory and on disk, along with the
+    /// chain heads that decide which responses are final.
+    ClearCache,
+    /// Limit the RPC usage of a local process.
+    SetProcessQuota((crate::core::ProcessId, RpcQuota)),
+    /// Remove the quota of a local process.
+    RemoveProcessQuota(crate::core::ProcessId),
+    /// Limit the RPC usage of a remote node using us as a provider.
+    SetNodeQuota((String, RpcQuota)),
+    /// Remove the quota of a remote node.
+    RemoveNodeQuota(String),
+    /// Set the quota for remote nodes that do not have their own, or None to remove it.
+    SetDefaultNodeQuota(Option<RpcQuota>),
+    /// Get the current quotas as an [`RpcQuotas`] object.
+    GetQuotas,
 }
 
 /// Response type from an [`EthConfigAction`] request.
@@ -166,17 +216,80 @@ pub enum EthConfigResponse {
     Providers(SavedConfigs),
     /// Response from a GetAccessSettings request.
     AccessSettings(AccessSettings),
+    /// Response from a GetQuotas request.
+    Quotas(RpcQuotas),
     /// Permission denied due to missing capability
     PermissionDenied,
     /// Response from a GetState request
     State {
         active_subscriptions: HashMap<crate::core::Address, HashMap<u64, Option<String>>>, // None if local, Some(node_provider_name) if remote
         outstanding_requests: HashSet<u64>,
+        #[serde(default)]
+        cache_stats: CacheStats,
+        /// RPC usage of every local process and remote node that has made a request
+        #[serde(default)]
+        usage: HashMap<crate::core::Address, RpcUsage>,
     },
     /// Provider was not found
     ProviderNotFound,
 }
 
+/// Counters for the eth:distro:sys response cache, reported in [`EthConfigResponse::State`].
+/// Counters reset when the node restarts; cached entries on disk do not.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CacheStats {
+    /// requests answered from the short-lived in-memory cache
+    pub memory_hits: u64,
+    /// requests for immutable data answered from the on-disk cache
+    pub disk_hits: u64,
+    /// requests that waited on an identical request already in flight
+    pub coalesced: u64,
+    /// requests that had to go to a provider
+    pub misses: u64,
+    /// estimated number of responses stored on disk
+    pub disk_entries: u64,
+}
+
+/// Limits on how much of our RPC capacity a process or remote node may use.
+/// Requests or subscriptions beyond a limit get [`EthError::RateLimited`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcQuota {
+    /// maximum [`EthAction::Request`]s per minute
+    pub requests_per_minute: Option<u64>,
+    /// maximum number of subscriptions open at once
+    pub max_subscriptions: Option<u64>,
+}
+
+/// All quotas configured on eth:distro:sys. Persisted across restarts.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RpcQuotas {
+    /// quotas for local processes
+    #[serde(default)]
+    pub processes: HashMap<crate::core::ProcessId, RpcQuota>,
+    /// quotas for remote nodes, counted across all of the node's processes
+    #[serde(default)]
+    pub nodes: HashMap<String, RpcQuota>,
+    /// quota for any remote node not in `nodes`
+    #[serde(default)]
+    pub default_node: Option<RpcQuota>,
+}
+
+/// RPC usage of a single [`crate::core::Address`], reported in [`EthConfigResponse::State`].
+/// Counters reset when the node restarts.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RpcUsage {
+    /// total [`EthAction::Request`]s made
+    pub requests: u64,
+    /// requests made in the current one-minute window
+    pub requests_this_minute: u64,
+    /// total subscriptions opened
+    pub subscriptions: u64,
+    /// subscriptions currently open
+    pub active_subscriptions: u64,
+    /// requests and subscriptions refused for exceeding a quota
+    pub rate_limited: u64,
+}
+
 /// Settings for our ETH provider
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct AccessSettings {
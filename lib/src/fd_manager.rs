@@ -15,6 +15,12 @@ pub enum FdManagerRequest {
 
     /// fd-manager -> other process
     FdsLimit(u64),
+    /// fd-manager -> other process
+    /// ask the process to report how many fds it has open with [`FdManagerRequest::FdsUsage`]
+    GetFdsUsage,
+    /// other process -> fd-manager
+    /// number of file descriptors the process currently has open
+    FdsUsage(u64),
 
     /// administrative
     UpdateMaxFdsAsFractionOfUlimitPercentage(u64),
@@ -22,8 +28,20 @@ pub enum FdManagerRequest {
     UpdateUpdateUlimitSecs(u64),
     /// administrative
     UpdateCullFractionDenominator(u64),
+    /// administrative, requires root capability
+    /// set the weight of a process when dividing file descriptors between processes.
+    /// Processes have weight 1 unless set otherwise.
+    SetFdsWeight { process: ProcessId, weight: u64 },
+    /// administrative, requires root capability
+    /// keep the limit of a process within `min` and `max`, whatever its weight and demand.
+    /// `None` removes that bound.
+    PinFdsLimit {
+        process: ProcessId,
+        min: Option<u64>,
+        max: Option<u64>,
+    },
 
-    /// get a `HashMap` of all `ProcessId`s to their number of allocated file descriptors.
+    /// get a `HashMap` of all `ProcessId`s to their number of allocated and open file descriptors.
     GetState,
     /// get the `u64` number of file descriptors allocated to `ProcessId`.
     GetProcessFdLimit(ProcessId),
@@ -41,6 +59,30 @@ pub enum FdManagerResponse {
 pub struct FdsLimit {
     pub limit: u64,
     pub hit_count: u64,
+    /// file descriptors open as of the process' last [`FdManagerRequest::FdsUsage`]
+    #[serde(default)]
+    pub open: u64,
+    #[serde(default)]
+    pub budget: FdsBudget,
+}
+
+/// How a process' limit is set, as configured by [`FdManagerRequest::SetFdsWeight`]
+/// and [`FdManagerRequest::PinFdsLimit`].
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct FdsBudget {
+    pub weight: u64,
+    pub min: Option<u64>,
+    pub max: Option<u64>,
+}
+
+impl Default for FdsBudget {
+    fn default() -> Self {
+        Self {
+            weight: 1,
+            min: None,
+            max: None,
+        }
+    }
 }
 
 #[derive(Debug, Error)]
@@ -51,4 +93,6 @@ pub enum FdManagerError {
     BadRequest,
     #[error("fd-manager: received a FdManagerRequest::FdsLimit, but I am the one who sets limits")]
     FdManagerWasSentLimit,
+    #[error("fd-manager: {0} tried to set fd budgets without root capability")]
+    NoRootCap(ProcessId),
 }
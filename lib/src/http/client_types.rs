@@ -28,6 +28,8 @@ pub enum HttpClientAction {
 /// BODY is stored in the lazy_load_blob, as bytes
 ///
 /// TIMEOUT is stored in the message's `expects_response` value
+///
+/// All fields after `headers` are optional and may be omitted from the JSON.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OutgoingHttpRequest {
     /// must parse to [`http::Method`]
@@ -37,6 +39,56 @@ pub struct OutgoingHttpRequest {
     /// must parse to [`url::Url`]
     pub url: String,
     pub headers: HashMap<String, String>,
+    /// If set, the response body is streamed into this vfs file rather than
+    /// being returned as a blob. The body is written by the vfs as if the
+    /// requesting process had asked, so that process must be able to message
+    /// the vfs and have write capability for the drive the path lives in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_to: Option<DownloadTarget>,
+    /// Retry the request on connection errors and retryable statuses.
+    /// If not set, the request is attempted exactly once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry: Option<RetryPolicy>,
+    /// Maximum number of redirects to follow. Defaults to 10; 0 disables redirects.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_redirects: Option<usize>,
+    /// Maximum response body size in bytes. Requests whose body exceeds
+    /// this are aborted with [`HttpClientError::BodyTooLarge`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_body_size: Option<u64>,
+    /// Proxy URL through which to send the request, e.g. `socks5://127.0.0.1:9050`.
+    /// must parse to [`url::Url`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+}
+
+/// Destination in the vfs for a response body, used in
+/// [`OutgoingHttpRequest::download_to`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DownloadTarget {
+    /// vfs path of the file to write, e.g. `/my-package:publisher.os/downloads/file.zip`.
+    /// The file is created if it does not exist and truncated if it does.
+    pub path: String,
+    /// If true, [`DownloadProgress`] requests are sent to the requester as the
+    /// body is written.
+    #[serde(default)]
+    pub progress: bool,
+}
+
+/// Retry policy for [`OutgoingHttpRequest::retry`].
+///
+/// The delay between attempts starts at `initial_backoff_ms` and doubles
+/// after each failed attempt, capped at `max_backoff_ms`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// total number of attempts, including the first
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// response statuses that trigger a retry. If empty, 429 and all
+    /// 5xx statuses are retried.
+    #[serde(default)]
+    pub retry_on_status: Vec<u16>,
 }
 
 /// Request that comes from an open WebSocket client connection in the
@@ -53,12 +105,31 @@ pub enum HttpClientRequest {
     },
 }
 
+/// Request sent by the `http-client:distro:sys` service to the requester of
+/// an [`OutgoingHttpRequest`] whose [`DownloadTarget::progress`] is set, each
+/// time a part of the body is written to the vfs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DownloadProgress {
+    /// the vfs path given in [`DownloadTarget::path`]
+    pub path: String,
+    pub downloaded: u64,
+    /// taken from the response's `Content-Length` header, if present
+    pub total: Option<u64>,
+}
+
 /// Response type received from the `http-client:distro:sys` service after
 /// sending a successful [`HttpClientAction`] to it.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum HttpClientResponse {
     Http(HttpResponse),
     WebSocketAck,
+    /// Response to an [`OutgoingHttpRequest`] with a [`DownloadTarget`]:
+    /// the body was written to `path` and no blob is attached.
+    Download {
+        response: HttpResponse,
+        path: String,
+        bytes_written: u64,
+    },
 }
 
 #[derive(Clone, Debug, Error, Serialize, Deserialize)]
@@ -76,6 +147,14 @@ pub enum HttpClientError {
     BuildRequestFailed(String),
     #[error("client failed to execute request: {0}")]
     ExecuteRequestFailed(String),
+    #[error("proxy url could not be parsed: {proxy}")]
+    BadProxy { proxy: String },
+    #[error("response body exceeded max size of {max_body_size} bytes")]
+    BodyTooLarge { max_body_size: u64 },
+    #[error("no write capability for download path {path}")]
+    DownloadNoWriteCap { path: String },
+    #[error("failed to write download to {path}: {error}")]
+    DownloadFailed { path: String, error: String },
 
     // WebSocket errors
     #[error("could not open connection to {url}")]
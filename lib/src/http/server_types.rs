@@ -217,6 +217,7 @@ pub type HttpSender = tokio::sync::oneshot::Sender<(HttpResponse, Vec<u8>)>;
 
 /// mapping from an open websocket connection to a channel that will ingest
 /// WebSocketPush messages from the app that handles the connection, and
-/// send them to the connection.
-pub type WebSocketSenders = Arc<DashMap<u32, (ProcessId, WebSocketSender)>>;
+/// send them to the connection. also notes whether the connection was
+/// authenticated with a login cookie, so it can be closed when the login ends.
+pub type WebSocketSenders = Arc<DashMap<u32, (ProcessId, WebSocketSender, bool)>>;
 pub type WebSocketSender = tokio::sync::mpsc::Sender<warp::ws::Message>;
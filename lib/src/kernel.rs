@@ -1,6 +1,6 @@
 use crate::types::core::{
-    display_message, Address, Capability, LazyLoadBlob, Message, NodeId, OnExit, ProcessId,
-    SendError,
+    display_message, Address, CapConstraints, Capability, LazyLoadBlob, Message, NodeId, OnExit,
+    PackageId, ProcessId, SendError,
 };
 use ring::signature;
 use serde::{Deserialize, Serialize};
@@ -69,6 +69,16 @@ pub struct LoginInfo {
     pub custom_base_l2_access_providers: Option<Vec<String>>,
 }
 
+/// Body of a POST to `/change-password`. Hashes are computed as for [`LoginInfo`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangePasswordInfo {
+    pub old_password_hash: String,
+    pub new_password_hash: String,
+    /// replace the JWT secret, logging out every existing session
+    #[serde(default)]
+    pub rotate_jwt_secret: bool,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Identity {
     pub name: NodeId,
@@ -198,6 +208,7 @@ pub struct ProcessMetadata {
     pub wit_version: Option<u32>,
     pub on_exit: OnExit,
     pub public: bool,
+    pub wasi: WasiPolicy,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -208,6 +219,19 @@ pub struct KernelMessage {
     pub rsvp: Rsvp,
     pub message: Message,
     pub lazy_load_blob: Option<LazyLoadBlob>,
+    /// Set on messages that are part of a trace. Never serialized: a peer decodes
+    /// messages as a fixed-length array, so over the network a request carries its
+    /// trace after the message instead.
+    #[serde(skip)]
+    pub trace: Option<TraceContext>,
+}
+
+/// Carried by a message that is part of a trace: the trace, and the span of the
+/// message this one was sent in handling of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
 }
 
 impl KernelMessage {
@@ -241,6 +265,7 @@ pub struct KernelMessageBuilder {
     rsvp: Rsvp,
     message: Option<Message>,
     lazy_load_blob: Option<LazyLoadBlob>,
+    trace: Option<TraceContext>,
 }
 
 impl KernelMessageBuilder {
@@ -280,6 +305,11 @@ impl KernelMessageBuilder {
         self
     }
 
+    pub fn trace(mut self, trace: Option<TraceContext>) -> Self {
+        self.trace = trace;
+        self
+    }
+
     pub fn build(self) -> Result<KernelMessage, String> {
         Ok(KernelMessage {
             id: self.id,
@@ -288,6 +318,7 @@ impl KernelMessageBuilder {
             rsvp: self.rsvp,
             message: self.message.ok_or("Message is required")?,
             lazy_load_blob: self.lazy_load_blob,
+            trace: self.trace,
         })
     }
 }
@@ -409,6 +440,69 @@ pub enum DebugCommand {
     ToggleEventLoopForProcess(ProcessId),
 }
 
+/// IPC format for requests from the runtime terminal to terminal:terminal:sys.
+/// Mirrors the `terminal:sys` API; keep the two in sync.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum TerminalRequest {
+    Complete(CompleteRequest),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompleteRequest {
+    /// the command line up to the cursor
+    pub line: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum TerminalResponse {
+    Complete(CompleteResponse),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompleteResponse {
+    /// the word at the end of the line that the candidates would replace
+    pub word: String,
+    pub candidates: Vec<String>,
+}
+
+/// IPC format for requests sent to the runtime terminal, terminal:distro:sys
+#[derive(Debug, Serialize, Deserialize)]
+pub enum RuntimeTerminalRequest {
+    /// Search the JSON-lines logs in `.terminal_logs`. Logs written in the
+    /// text format are not searched.
+    QueryLogs(LogQuery),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LogQuery {
+    /// only entries printed by this process
+    pub process: Option<ProcessId>,
+    /// only entries at this verbosity or lower
+    pub max_verbosity: Option<u8>,
+    /// only entries from the last this many seconds
+    pub since_secs: Option<u64>,
+    /// return at most this many entries, keeping the most recent
+    pub limit: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum RuntimeTerminalResponse {
+    /// matching entries, oldest first
+    QueryLogs(Vec<LogEntry>),
+    Err(String),
+}
+
+/// One line of a JSON-lines terminal log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    /// RFC 3339, in local time
+    pub timestamp: String,
+    pub verbosity: u8,
+    pub source: ProcessId,
+    /// printout content with ANSI escape codes removed
+    pub content: String,
+}
+
 /// IPC format for requests sent to kernel runtime module
 #[derive(Debug, Serialize, Deserialize)]
 pub enum KernelCommand {
@@ -431,11 +525,19 @@ pub enum KernelCommand {
         on_exit: OnExit,
         initial_capabilities: HashSet<Capability>,
         public: bool,
+        /// left out by callers that predate WASI policies, giving the default
+        #[serde(default)]
+        wasi: WasiPolicy,
     },
-    /// Create an arbitrary capability and grant it to a process.
+    /// Create an arbitrary capability and grant it to a process, limited by
+    /// `constraints` if given. A grant never loosens the constraints of a capability
+    /// the process already holds, unless it comes from the capability's issuer: to
+    /// extend one, drop it first.
     GrantCapabilities {
         target: ProcessId,
         capabilities: Vec<Capability>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        constraints: Option<CapConstraints>,
     },
     /// Drop capabilities. Does nothing if process doesn't have these caps
     DropCapabilities {
@@ -450,11 +552,213 @@ pub enum KernelCommand {
     RunProcess(ProcessId),
     /// Kill a running process immediately. This may result in the dropping / mishandling of messages!
     KillProcess(ProcessId),
+    /// Replace the code of a running process with the Wasm bytes in the blob, keeping
+    /// its capabilities and on-exit behavior. Messages for the process are held while
+    /// it is replaced and delivered to the new code afterwards.
+    ///
+    /// Code opts in to handing off its state by exporting a function named
+    /// [`HANDOFF_EXPORT`] from its component, e.g. `export handoff: func();` in its
+    /// world. The kernel doesn't call the export, since the old code is busy in
+    /// `init`: it sends the old code a request with body [`UPGRADE_REQUEST_BODY`],
+    /// and if the old code responds within [`UPGRADE_HANDOFF_TIMEOUT_SECS`], the blob
+    /// of its response is handed to the new code, before any other message, in a
+    /// request with body [`HANDOFF_REQUEST_BODY`]. Only requests are held meanwhile:
+    /// responses still reach the old code. Code without the export, or that doesn't
+    /// respond in time, is replaced without a handoff, as with `KillProcess` and
+    /// `InitializeProcess`.
+    UpgradeProcess {
+        id: ProcessId,
+        wasm_bytes_handle: String,
+    },
     /// RUNTIME ONLY: notify the kernel that the runtime is shutting down and it
     /// should gracefully stop and persist the running processes.
     Shutdown,
+    /// RUNTIME ONLY: record a crash of a process and pass it on to subscribers.
+    ReportCrash {
+        process: ProcessId,
+        report: CrashReport,
+    },
+    /// Receive a [`CrashEvent`] request from the kernel whenever a process crashes.
+    SubscribeCrashes,
+    /// Stop receiving [`CrashEvent`]s.
+    UnsubscribeCrashes,
     /// Ask kernel to produce debugging information
     Debug(KernelPrint),
+    /// Record spans for traced messages passing through this node. Given a process,
+    /// also start a new trace for each request it sends that is not already traced.
+    StartTracing(Option<ProcessId>),
+    /// Stop starting traces at a process, or given `None`, stop tracing altogether.
+    StopTracing(Option<ProcessId>),
+    /// Write the spans recorded so far to an OTLP JSON file in the `.traces`
+    /// directory of the node's home, and forget them.
+    ExportTraces,
+    /// Remove all compiled components from the on-disk cache. Processes started
+    /// afterwards are compiled from their Wasm bytes again.
+    PurgeComponentCache,
+    /// Create or replace a supervision group. A process may be in at most one group.
+    /// Replacing a group that gave up restarts its stopped members.
+    SetSupervisionGroup(SupervisionGroup),
+    /// Remove a supervision group by name. Its members keep running.
+    RemoveSupervisionGroup(String),
+    /// Set a package's supervision groups to those its manifest declares (see
+    /// [`SupervisionGroup::from_manifest`]), removing its groups no longer declared.
+    /// An empty manifest removes them all.
+    SetPackageSupervision {
+        package_id: PackageId,
+        manifest: Vec<PackageManifestEntry>,
+    },
+    /// Set how many messages each lane of a process's mailbox holds, or with `None`
+    /// return it to the `--mailbox-capacity` default. Takes effect the next time the
+    /// process starts.
+    SetMailboxCapacity {
+        target: ProcessId,
+        capacity: Option<usize>,
+    },
+    /// RUNTIME ONLY: a process returned from init or crashed, and is about to carry
+    /// out its on-exit behavior.
+    ProcessExited(ProcessId),
+    /// Restart a process and record every message it takes in and sends during the
+    /// new run to a file in the `.recordings` directory of the node's home. The
+    /// recording ends when the process exits or on `StopRecording`, and can be
+    /// replayed offline against the process's Wasm with `hyperdrive --replay`.
+    RecordProcess(ProcessId),
+    /// End the recording of a process started by `RecordProcess`.
+    StopRecording(ProcessId),
+}
+
+/// The first line of a process recording: who the process was.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RecordingHeader {
+    pub process: Address,
+    pub wasm_bytes_handle: String,
+    pub wit_version: Option<u32>,
+    pub on_exit: OnExit,
+    pub public: bool,
+    /// seconds since the unix epoch
+    pub started: u64,
+    /// absent from recordings made before processes had a [`WasiPolicy`]
+    #[serde(default)]
+    pub wasi: WasiPolicy,
+}
+
+/// Each line of a process recording after the header, in the order the process
+/// took in and sent messages and read from the host.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum RecordedEvent {
+    Received(Result<KernelMessage, WrappedSendError>),
+    Sent(KernelMessage),
+    /// the capabilities the process held when it asked for all of them
+    Capabilities(Vec<(Capability, Vec<u8>)>),
+    /// a read of the wall clock: the time since the unix epoch
+    WallClock(std::time::Duration),
+    /// a read of the monotonic clock, in nanoseconds
+    MonotonicClock(u64),
+    /// bytes the process drew from the host's insecure randomness
+    InsecureRandom(Vec<u8>),
+    /// the seed the process got for its hash maps and the like
+    InsecureRandomSeed(u128),
+}
+
+/// Processes whose lifecycles are linked: when a member exits, the kernel restarts
+/// or kills other members according to `strategy`. Members that restart on exit are
+/// restarted; others are killed.
+///
+/// If the group restarts more than `max_restarts` times within `within_secs`, it
+/// gives up: its members are stopped, and not started again until the next boot or
+/// until the group is set again.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SupervisionGroup {
+    pub name: String,
+    pub strategy: SupervisionStrategy,
+    /// in start order, which [`SupervisionStrategy::RestForOne`] follows
+    pub members: Vec<ProcessId>,
+    pub max_restarts: u32,
+    pub within_secs: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SupervisionStrategy {
+    /// only the exited member is affected
+    OneForOne,
+    /// every other member is restarted or killed
+    OneForAll,
+    /// the members after the exited one are restarted or killed
+    RestForOne,
+}
+
+pub const DEFAULT_SUPERVISION_MAX_RESTARTS: u32 = 5;
+pub const DEFAULT_SUPERVISION_WITHIN_SECS: u64 = 60;
+
+impl SupervisionGroup {
+    /// The groups declared in a package manifest, named `<group>:<package-id>`,
+    /// with members in manifest order. A group's strategy and limits may be declared
+    /// on any of its members, but members that declare them must agree.
+    pub fn from_manifest(
+        package_id: &PackageId,
+        manifest: &[PackageManifestEntry],
+    ) -> Result<Vec<Self>, String> {
+        let mut groups: Vec<Self> = vec![];
+        // the settings declared so far for each group, by index
+        let mut declared: Vec<ManifestSupervision> = vec![];
+        for entry in manifest {
+            let Some(supervision) = &entry.supervision else {
+                continue;
+            };
+            let name = format!("{}:{package_id}", supervision.group);
+            let member = ProcessId::new(
+                Some(&entry.process_name),
+                package_id._package(),
+                package_id._publisher(),
+            );
+            let index = match groups.iter().position(|group| group.name == name) {
+                Some(index) => index,
+                None => {
+                    groups.push(Self {
+                        name,
+                        strategy: SupervisionStrategy::OneForOne,
+                        members: vec![],
+                        max_restarts: DEFAULT_SUPERVISION_MAX_RESTARTS,
+                        within_secs: DEFAULT_SUPERVISION_WITHIN_SECS,
+                    });
+                    declared.push(ManifestSupervision {
+                        group: supervision.group.clone(),
+                        strategy: None,
+                        max_restarts: None,
+                        within_secs: None,
+                    });
+                    groups.len() - 1
+                }
+            };
+            let group = &mut groups[index];
+            let declared = &mut declared[index];
+            let conflict = |setting: &str, group: &str| {
+                format!("{member} declares a different {setting} for supervision group {group} than another member")
+            };
+            if let Some(strategy) = supervision.strategy {
+                if declared.strategy.is_some_and(|s| s != strategy) {
+                    return Err(conflict("strategy", &group.name));
+                }
+                declared.strategy = Some(strategy);
+                group.strategy = strategy;
+            }
+            if let Some(max_restarts) = supervision.max_restarts {
+                if declared.max_restarts.is_some_and(|m| m != max_restarts) {
+                    return Err(conflict("max_restarts", &group.name));
+                }
+                declared.max_restarts = Some(max_restarts);
+                group.max_restarts = max_restarts;
+            }
+            if let Some(within_secs) = supervision.within_secs {
+                if declared.within_secs.is_some_and(|w| w != within_secs) {
+                    return Err(conflict("within_secs", &group.name));
+                }
+                declared.within_secs = Some(within_secs);
+                group.within_secs = within_secs;
+            }
+            group.members.push(member);
+        }
+        Ok(groups)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -462,6 +766,33 @@ pub enum KernelPrint {
     ProcessMap,
     Process(ProcessId),
     HasCap { on: ProcessId, cap: Capability },
+    // recent crash reports of one process, or of all processes
+    Crashes(Option<ProcessId>),
+    // every process holding a capability issued by this process, with those capabilities
+    CapHolders(ProcessId),
+}
+
+/// A process ending with an error, as recorded by the kernel.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CrashReport {
+    /// seconds since the unix epoch
+    pub timestamp: u64,
+    pub trap: String,
+    pub backtrace: Option<String>,
+    /// the end of what the process wrote to stderr
+    pub stderr: String,
+    /// the message the process was handling when it crashed; only sent to the
+    /// crashed process itself, and not kept by the kernel
+    pub last_message: Option<String>,
+    /// for processes that restart on exit, the number of consecutive unhealthy runs
+    pub restart_attempt: Option<u32>,
+}
+
+/// Body of the request sent to processes subscribed with [`KernelCommand::SubscribeCrashes`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CrashEvent {
+    pub process: ProcessId,
+    pub report: CrashReport,
 }
 
 /// IPC format for all KernelCommand responses
@@ -472,28 +803,104 @@ pub enum KernelResponse {
     StartedProcess,
     RunProcessError,
     KilledProcess(ProcessId),
+    /// response to [`KernelCommand::UpgradeProcess`]: whether the old code handed off state
+    UpgradedProcess {
+        id: ProcessId,
+        handoff: bool,
+    },
+    UpgradeProcessError(String),
     Debug(KernelPrintResponse),
+    /// response to [`KernelCommand::StartTracing`] and [`KernelCommand::StopTracing`]
+    Tracing {
+        recording: bool,
+        roots: Vec<ProcessId>,
+    },
+    /// response to [`KernelCommand::ExportTraces`]
+    ExportedTraces {
+        path: String,
+        spans: usize,
+    },
+    ExportTracesError(String),
+    /// response to [`KernelCommand::PurgeComponentCache`]: the number of entries removed
+    PurgedComponentCache(usize),
+    PurgeComponentCacheError(String),
+    /// response to [`KernelCommand::SetSupervisionGroup`],
+    /// [`KernelCommand::RemoveSupervisionGroup`], and
+    /// [`KernelCommand::SetPackageSupervision`], which is answered with the names of
+    /// the package's groups
+    SupervisionGroupSet(String),
+    PackageSupervisionSet(Vec<String>),
+    SupervisionGroupRemoved(String),
+    SupervisionGroupError(String),
+    MailboxCapacitySet(ProcessId),
+    MailboxCapacityError(String),
+    /// response to [`KernelCommand::RecordProcess`] and [`KernelCommand::StopRecording`]:
+    /// the path of the recording
+    RecordingProcess(String),
+    StoppedRecording(String),
+    RecordingError(String),
 }
 
+/// the component export by which a process declares it answers [`UPGRADE_REQUEST_BODY`]
+pub const HANDOFF_EXPORT: &str = "handoff";
+/// body of the request asking a process about to be upgraded for its handoff blob
+pub const UPGRADE_REQUEST_BODY: &[u8] = b"upgrade";
+/// body of the request carrying the handoff blob to the upgraded process
+pub const HANDOFF_REQUEST_BODY: &[u8] = b"handoff";
+pub const UPGRADE_HANDOFF_TIMEOUT_SECS: u64 = 5;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub enum KernelPrintResponse {
     ProcessMap(UserspaceProcessMap),
     Process(Option<UserspacePersistedProcess>),
     HasCap(Option<bool>),
+    /// most recent last
+    Crashes(Vec<CrashEvent>),
+    CapHolders(HashMap<ProcessId, Vec<Capability>>),
+}
+
+/// A line of the capability audit log, `.cap_audit.jsonl` in the home directory.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CapAuditEntry {
+    /// seconds since the unix epoch
+    pub timestamp: u64,
+    pub action: CapAuditAction,
+    /// the process that made the change
+    pub actor: ProcessId,
+    /// the process whose capabilities changed
+    pub target: ProcessId,
+    pub cap: Capability,
+    pub reason: String,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum CapAuditAction {
+    Add,
+    Drop,
+    /// dropped because its issuer's capabilities were revoked
+    Revoke,
+    /// dropped because it expired
+    Expire,
 }
 
 #[derive(Debug)]
 pub enum CapMessage {
-    /// root access: uncritically sign and add all `caps` to `on`
+    /// root access: uncritically sign and add all `caps` to `on`. A cap `on` already
+    /// holds is replaced, but its constraints are only loosened if `actor` issued it.
     Add {
         on: ProcessId,
         caps: Vec<Capability>,
+        /// the process making the change and why, for the audit log
+        actor: ProcessId,
+        reason: &'static str,
         responder: Option<tokio::sync::oneshot::Sender<bool>>,
     },
     /// root delete: uncritically remove all `caps` from `on`
     Drop {
         on: ProcessId,
         caps: Vec<Capability>,
+        actor: ProcessId,
+        reason: &'static str,
         responder: Option<tokio::sync::oneshot::Sender<bool>>,
     },
     /// does `on` have `cap` in its store?
@@ -511,6 +918,8 @@ pub enum CapMessage {
     /// Remove all caps issued by `on` from every process on the entire system
     RevokeAll {
         on: ProcessId,
+        actor: ProcessId,
+        reason: &'static str,
         responder: Option<tokio::sync::oneshot::Sender<bool>>,
     },
     /// before `on` sends a message, filter out any bogus caps it may have attached, sign any new
@@ -571,6 +980,98 @@ pub struct PersistedProcess {
     pub capabilities: HashMap<Capability, Vec<u8>>,
     /// marks if a process allows messages from any process
     pub public: bool,
+    #[serde(with = "wasi_policy_bytes")]
+    pub wasi: WasiPolicy,
+}
+
+/// The process map as persisted before processes had a [`WasiPolicy`]. Read once
+/// at boot if the persisted map doesn't parse as a [`ProcessMap`].
+pub type LegacyProcessMap = HashMap<ProcessId, LegacyPersistedProcess>;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LegacyPersistedProcess {
+    pub wasm_bytes_handle: String,
+    pub wit_version: Option<u32>,
+    pub on_exit: OnExit,
+    pub capabilities: HashMap<Capability, Vec<u8>>,
+    pub public: bool,
+}
+
+impl From<LegacyPersistedProcess> for PersistedProcess {
+    fn from(p: LegacyPersistedProcess) -> Self {
+        PersistedProcess {
+            wasm_bytes_handle: p.wasm_bytes_handle,
+            wit_version: p.wit_version,
+            on_exit: p.on_exit,
+            capabilities: p.capabilities,
+            public: p.public,
+            wasi: WasiPolicy::default(),
+        }
+    }
+}
+
+/// Which parts of WASI a process may use, beyond the stderr that captures its
+/// stack traces. Declared per process in `manifest.json` as `"wasi"`. The wall clock
+/// and host randomness are allowed by default, as the Rust standard library relies
+/// on them; everything else is denied by default.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WasiPolicy {
+    /// Preopen the vfs drives of the process's own package that it holds vfs
+    /// capabilities for, at their vfs paths (e.g. `/my-package:publisher.os/data`),
+    /// so that `std::fs` works on them. A drive is writable if the process holds the
+    /// drive's write capability, and read-only otherwise. Capabilities are read when
+    /// the process starts, and a process that loses a vfs capability is restarted.
+    pub preopen_drives: bool,
+    /// Read the time of day. Otherwise the wall clock stays at the unix epoch.
+    pub wall_clock: bool,
+    /// Get cryptographic random bytes from the host. Otherwise asking for them traps;
+    /// insecure randomness, e.g. for hash map seeds, is still available.
+    pub random: bool,
+    /// Open TCP and UDP sockets to any address, and look up names.
+    pub sockets: bool,
+    /// Hosts the process may send HTTP requests to with wasi-http, e.g.
+    /// `"api.example.com"`. wasi-http is only linked for a process with some.
+    pub http_hosts: Vec<String>,
+}
+
+impl Default for WasiPolicy {
+    fn default() -> Self {
+        WasiPolicy {
+            preopen_drives: false,
+            wall_clock: true,
+            random: true,
+            sockets: false,
+            http_hosts: vec![],
+        }
+    }
+}
+
+/// The persisted process map is bincode, which can't skip or default fields, so a
+/// [`WasiPolicy`] is kept in it as JSON: fields can then be added to the policy
+/// without breaking saved state. Human-readable formats get the policy as is.
+mod wasi_policy_bytes {
+    use super::WasiPolicy;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(policy: &WasiPolicy, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            policy.serialize(serializer)
+        } else {
+            serde_json::to_vec(policy)
+                .map_err(serde::ser::Error::custom)?
+                .serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<WasiPolicy, D::Error> {
+        if deserializer.is_human_readable() {
+            WasiPolicy::deserialize(deserializer)
+        } else {
+            let bytes = Vec::<u8>::deserialize(deserializer)?;
+            serde_json::from_slice(&bytes).map_err(serde::de::Error::custom)
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -580,6 +1081,8 @@ pub struct UserspacePersistedProcess {
     pub on_exit: OnExit,
     pub capabilities: HashSet<Capability>,
     pub public: bool,
+    #[serde(default)]
+    pub wasi: WasiPolicy,
 }
 
 impl From<PersistedProcess> for UserspacePersistedProcess {
@@ -590,6 +1093,7 @@ impl From<PersistedProcess> for UserspacePersistedProcess {
             on_exit: p.on_exit,
             capabilities: p.capabilities.into_keys().collect(),
             public: p.public,
+            wasi: p.wasi,
         }
     }
 }
@@ -651,4 +1155,110 @@ pub struct PackageManifestEntry {
     pub request_capabilities: Vec<serde_json::Value>,
     pub grant_capabilities: Vec<serde_json::Value>,
     pub public: bool,
+    /// the package's supervision group that this process is a member of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supervision: Option<ManifestSupervision>,
+    #[serde(default)]
+    pub wasi: WasiPolicy,
+}
+
+/// Membership of a process in a supervision group declared in `manifest.json`.
+/// See [`SupervisionGroup::from_manifest`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestSupervision {
+    pub group: String,
+    #[serde(default)]
+    pub strategy: Option<SupervisionStrategy>,
+    #[serde(default)]
+    pub max_restarts: Option<u32>,
+    #[serde(default)]
+    pub within_secs: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::core::Request;
+
+    fn request(metadata: Option<&str>, trace: Option<TraceContext>) -> KernelMessage {
+        KernelMessage::builder()
+            .id(1)
+            .source(("our", ProcessId::new(Some("a"), "test", "sys")))
+            .target(("their", ProcessId::new(Some("b"), "test", "sys")))
+            .message(Message::Request(Request {
+                inherit: false,
+                expects_response: None,
+                body: vec![],
+                metadata: metadata.map(str::to_string),
+                capabilities: vec![],
+            }))
+            .trace(trace)
+            .build()
+            .unwrap()
+    }
+
+    const TRACE: TraceContext = TraceContext {
+        trace_id: 0x0123_4567_89ab_cdef_0011_2233_4455_6677,
+        span_id: 0xfedc_ba98_7654_3210,
+    };
+
+    #[test]
+    fn trace_is_not_serialized() {
+        let traced = serde_json::to_vec(&request(None, Some(TRACE))).unwrap();
+        let untraced = serde_json::to_vec(&request(None, None)).unwrap();
+        assert_eq!(traced, untraced);
+    }
+
+    fn manifest(entries: serde_json::Value) -> Vec<PackageManifestEntry> {
+        let entries = entries.as_array().unwrap().iter().map(|entry| {
+            let mut full = serde_json::json!({
+                "process_wasm_path": "/p.wasm",
+                "on_exit": "Restart",
+                "request_networking": false,
+                "request_capabilities": [],
+                "grant_capabilities": [],
+                "public": false,
+            });
+            full.as_object_mut()
+                .unwrap()
+                .extend(entry.as_object().unwrap().clone());
+            full
+        });
+        serde_json::from_value(entries.collect()).unwrap()
+    }
+
+    #[test]
+    fn supervision_groups_come_from_any_member() {
+        let manifest = manifest(serde_json::json!([
+            { "process_name": "a", "supervision": { "group": "g" } },
+            { "process_name": "b" },
+            {
+                "process_name": "c",
+                "supervision": { "group": "g", "strategy": "RestForOne", "max_restarts": 2 },
+            },
+        ]));
+        let groups =
+            SupervisionGroup::from_manifest(&PackageId::new("pkg", "sys"), &manifest).unwrap();
+        assert_eq!(groups.len(), 1);
+        let group = &groups[0];
+        assert_eq!(group.name, "g:pkg:sys");
+        assert_eq!(group.strategy, SupervisionStrategy::RestForOne);
+        assert_eq!(group.max_restarts, 2);
+        assert_eq!(group.within_secs, DEFAULT_SUPERVISION_WITHIN_SECS);
+        let members: Vec<String> = group.members.iter().map(|m| m.to_string()).collect();
+        assert_eq!(members, ["a:pkg:sys", "c:pkg:sys"]);
+    }
+
+    #[test]
+    fn supervision_group_members_must_agree() {
+        let manifest = manifest(serde_json::json!([
+            { "process_name": "a", "supervision": { "group": "g", "within_secs": 10 } },
+            { "process_name": "b", "supervision": { "group": "g", "within_secs": 20 } },
+        ]));
+        let Err(e) = SupervisionGroup::from_manifest(&PackageId::new("pkg", "sys"), &manifest)
+        else {
+            panic!("expected a conflict");
+        };
+        assert!(e.contains("within_secs"), "{e}");
+    }
 }
@@ -29,6 +29,18 @@ pub enum NetAction {
     /// the PKI, will not verify.
     /// **the `from` [`Address`] will always be prepended to the payload**
     Verify { from: Address, signature: Vec<u8> },
+    /// generate a new networking key and wait for it to be published as our `~net-key`
+    /// note in hypermap. once the [`HnsUpdate`] carrying it arrives, we switch to it
+    /// and re-handshake with all of our peers. refused while another rotation is in
+    /// progress: cancel that one with [`NetAction::CancelKeyRotation`] first.
+    /// if the update lands while the node is offline, the new key is adopted at login.
+    /// **only accepted from the terminal and settings packages of our own node**
+    RotateKey,
+    /// get the networking key rotation in progress, if any
+    GetKeyRotation,
+    /// abandon the networking key rotation in progress, discarding the new key.
+    /// **only accepted from the terminal and settings packages of our own node**
+    CancelKeyRotation,
 }
 
 /// Must be parsed from message pack vector
@@ -51,6 +63,24 @@ pub enum NetResponse {
     /// cannot be found in our representation of PKI, this will return false,
     /// because we cannot find the networking public key to verify with.
     Verified(bool),
+    /// response to [`NetAction::RotateKey`], [`NetAction::GetKeyRotation`]
+    /// and [`NetAction::CancelKeyRotation`]
+    KeyRotation(Option<KeyRotation>),
+}
+
+/// A networking key rotation waiting for its onchain update.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeyRotation {
+    /// the new networking public key, hex-encoded with 0x prefix
+    pub new_networking_key: String,
+    /// address of the hypermap contract the `~net-key` note is written to
+    pub hypermap: String,
+    /// hex calldata of `note("~net-key", new_networking_key)`, to be called on
+    /// hypermap by our node's TBA
+    pub note_calldata: String,
+    /// hex calldata of `execute(hypermap, 0, note_calldata, 0)`: sign and send
+    /// this to our node's TBA from its owner to publish the new key
+    pub execute_calldata: String,
 }
 
 //
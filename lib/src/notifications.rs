@@ -1,41 +1,216 @@
 use crate::types::core::ProcessId;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use thiserror::Error;
 
 /// IPC Requests for the notifications:distro:sys runtime module.
 #[derive(Serialize, Deserialize, Debug)]
 pub enum NotificationsAction {
-    /// Send a push notification to all registered devices
+    /// Send a push notification to all registered devices that have not muted it.
+    /// Every notification is also recorded in the inbox, muted or not.
     SendNotification {
         title: String,
         body: String,
         icon: Option<String>,
         data: Option<serde_json::Value>,
+        /// app-defined category, which users can mute per app
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        topic: Option<String>,
+        #[serde(default)]
+        priority: NotificationPriority,
     },
     /// Get the public key for VAPID authentication
     GetPublicKey,
     /// Initialize or regenerate VAPID keys
     InitializeKeys,
-    /// Add a push subscription for a device
+    /// Add a push subscription for a device. Only settings may add a
+    /// [`DeliveryChannel::Webhook`] or [`DeliveryChannel::Email`] subscription.
     AddSubscription { subscription: PushSubscription },
-    /// Remove a push subscription
+    /// Remove a push subscription. As with adding, only settings may remove a
+    /// [`DeliveryChannel::Webhook`] or [`DeliveryChannel::Email`] subscription.
     RemoveSubscription { endpoint: String },
-    /// Clear all subscriptions
+    /// Clear all subscriptions the requester may remove
     ClearSubscriptions,
     /// Get subscription info by endpoint
     GetSubscription { endpoint: String },
+    /// Get the user's notification preferences. Apps other than the homepage and
+    /// settings get only their own app preference.
+    GetPreferences,
+    /// Set the preference for an app, or remove it with None. Apps other than the
+    /// homepage and settings may only set their own.
+    SetAppPreference {
+        process: ProcessId,
+        preference: Option<AppPreference>,
+    },
+    /// Set the preference for a device (by subscription endpoint), or remove it with None.
+    /// Only the homepage and settings may do this.
+    SetDevicePreference {
+        endpoint: String,
+        preference: Option<DevicePreference>,
+    },
+    /// Get inbox entries, newest first. `since` is an exclusive lower bound on entry id;
+    /// ids only ever increase, even across [`NotificationsAction::ClearInbox`].
+    /// Apps other than the homepage and settings see only their own entries, and
+    /// likewise only mark read or clear their own.
+    GetInbox {
+        since: Option<u64>,
+        limit: Option<usize>,
+    },
+    /// Mark inbox entries as read
+    MarkRead { ids: Vec<u64> },
+    /// Delete all inbox entries
+    ClearInbox,
+    /// Configure, or with None disable, the [`DeliveryChannel::Webhook`] channel.
+    /// Only settings may do this.
+    SetWebhookConfig(Option<WebhookConfig>),
+    /// Configure, or with None disable, the [`DeliveryChannel::Email`] channel.
+    /// Only settings may do this.
+    SetSmtpConfig(Option<SmtpConfig>),
+    /// Get the channel configuration, with secrets and passwords blanked out
+    GetChannelConfig,
+}
+
+/// Priority of a notification. Higher priorities are pushed first, and
+/// [`NotificationPriority::Urgent`] is never held back by per-app throttling.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub enum NotificationPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+/// User preferences deciding which notifications get pushed where.
+/// Apps and devices without an entry receive everything.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NotificationPreferences {
+    #[serde(default)]
+    pub apps: HashMap<ProcessId, AppPreference>,
+    /// keyed by [`PushSubscription::endpoint`]
+    #[serde(default)]
+    pub devices: HashMap<String, DevicePreference>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct AppPreference {
+    /// push nothing from this app
+    #[serde(default)]
+    pub muted: bool,
+    /// push nothing from this app with one of these topics
+    #[serde(default)]
+    pub muted_topics: HashSet<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DevicePreference {
+    /// push nothing to this device
+    #[serde(default)]
+    pub muted: bool,
+    /// push nothing from these apps to this device
+    #[serde(default)]
+    pub muted_apps: HashSet<ProcessId>,
+    /// push only notifications of at least this priority to this device
+    #[serde(default = "lowest_priority")]
+    pub min_priority: NotificationPriority,
+}
+
+/// A device without a preference receives everything, so the default doesn't
+/// filter by priority either.
+impl Default for DevicePreference {
+    fn default() -> Self {
+        Self {
+            muted: false,
+            muted_apps: HashSet::new(),
+            min_priority: lowest_priority(),
+        }
+    }
+}
+
+fn lowest_priority() -> NotificationPriority {
+    NotificationPriority::Low
+}
+
+/// A notification as recorded in the inbox.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InboxEntry {
+    pub id: u64,
+    pub source: ProcessId,
+    pub title: String,
+    pub body: String,
+    pub icon: Option<String>,
+    pub data: Option<serde_json::Value>,
+    pub topic: Option<String>,
+    pub priority: NotificationPriority,
+    /// milliseconds since epoch
+    pub timestamp: u64,
+    pub read: bool,
 }
 
 /// Push subscription information from the client
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PushSubscription {
+    /// Where to deliver: a Web Push endpoint, a webhook URL or an email address,
+    /// depending on `channel`
     pub endpoint: String,
+    /// Only used by [`DeliveryChannel::WebPush`]
+    #[serde(default)]
     pub keys: SubscriptionKeys,
     /// Timestamp when the subscription was created (milliseconds since epoch)
     pub created_at: u64,
+    #[serde(default)]
+    pub channel: DeliveryChannel,
+}
+
+/// How notifications reach a subscription.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeliveryChannel {
+    /// VAPID Web Push to a browser
+    #[default]
+    WebPush,
+    /// JSON POST to a URL, signed with the [`WebhookConfig`] secret
+    Webhook,
+    /// Email sent through the [`SmtpConfig`] server
+    Email,
+}
+
+/// Webhook requests carry an `X-Hyperware-Timestamp` header (seconds since epoch)
+/// and an `X-Hyperware-Signature` header of `sha256=` followed by the hex
+/// HMAC-SHA256, keyed with `secret`, of `{timestamp}.{body}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    pub secret: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// sender address, e.g. `Hyperware <node@example.com>`
+    pub from: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    /// unencrypted; only for local relays and testing
+    None,
+    StartTls,
+    Tls,
+}
+
+/// Configuration of the delivery channels other than Web Push.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ChannelConfig {
+    pub webhook: Option<WebhookConfig>,
+    pub smtp: Option<SmtpConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct SubscriptionKeys {
     pub p256dh: String,
     pub auth: String,
@@ -51,6 +226,12 @@ pub enum NotificationsResponse {
     SubscriptionRemoved,
     SubscriptionsCleared,
     SubscriptionInfo(Option<PushSubscription>),
+    Preferences(NotificationPreferences),
+    PreferencesUpdated,
+    Inbox(Vec<InboxEntry>),
+    InboxUpdated,
+    ChannelConfigUpdated,
+    ChannelConfig(ChannelConfig),
     Err(NotificationsError),
 }
 
@@ -72,6 +253,12 @@ pub enum NotificationsError {
     WebPushError { error: String },
     #[error("unauthorized request from {process}")]
     Unauthorized { process: ProcessId },
+    #[error("delivery channel {channel:?} is not configured")]
+    ChannelNotConfigured { channel: DeliveryChannel },
+    #[error("webhook error: {error}")]
+    WebhookError { error: String },
+    #[error("email error: {error}")]
+    EmailError { error: String },
 }
 
 impl NotificationsError {
@@ -85,6 +272,9 @@ impl NotificationsError {
             NotificationsError::KeysNotInitialized => "KeysNotInitialized",
             NotificationsError::WebPushError { .. } => "WebPushError",
             NotificationsError::Unauthorized { .. } => "Unauthorized",
+            NotificationsError::ChannelNotConfigured { .. } => "ChannelNotConfigured",
+            NotificationsError::WebhookError { .. } => "WebhookError",
+            NotificationsError::EmailError { .. } => "EmailError",
         }
     }
 }